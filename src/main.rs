@@ -1,15 +1,40 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use bitcoin::consensus::Encodable;
-use bitcoin::{Amount, OutPoint, TxOut};
+use anyhow::{anyhow, Result};
+use bitcoin::consensus::{deserialize, serialize, Encodable};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::key::Keypair;
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{Address, Amount, FeeRate, Network, OutPoint, TapSighashType, Transaction, TxOut};
+use bitcoincore_rpc::jsonrpc::serde_json;
 use bitcoincore_rpc::{RawTx, RpcApi};
 use clap::Parser;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use crate::settings::Settings;
+use crate::vault::batch::{spend_many, spend_many_fee};
+use crate::vault::broadcast::validate_with_node;
+use crate::vault::builder::VaultCovenantBuilder;
+use crate::vault::bundle::SignedTxBundle;
 use crate::vault::contract::VaultState::{Completed, Inactive, Triggered};
-use crate::vault::contract::{VaultCovenant, VaultState, VaultType};
+use crate::vault::contract::{covenant_filename, SpendPath, VaultCovenant, VaultType, SECP};
+use crate::vault::cpfp::{
+    cpfp_fee_input_amount, create_cpfp_child, ephemeral_anchor, ephemeral_anchor_vout,
+};
+use crate::vault::error::VaultError;
+use crate::vault::fee_key::{fee_key_address, sign_fee_input};
+use crate::vault::fees::{effective_fee_rate, estimate_fee_rate, input_weight_breakdown};
+use crate::vault::inputs::arrange_inputs;
+use crate::vault::metrics::{CountingSink, LogSink};
+use crate::vault::psbt::finalize_psbt;
+use crate::vault::session::VaultBuildSession;
+use crate::vault::sync::{Balance, ChainState};
+use crate::vault::timelock::{timelock_for_target_date, TimelockKind, SECONDS_PER_INTERVAL};
+use crate::vault::watchtower::LogWatchHook;
 use crate::wallet::Wallet;
 
 mod settings;
@@ -21,19 +46,230 @@ struct Cli {
     #[arg(short, long, default_value = "settings.toml")]
     settings_file: PathBuf,
 
+    /// A BIP39 mnemonic or BIP32 xprv the vault keys are derived from
+    #[arg(long)]
+    seed: Option<String>,
+
+    /// A secret to key the vault file's integrity check with, instead of the seed
+    #[arg(long)]
+    integrity_secret: Option<String>,
+
+    /// The network to use instead of the one in the settings file: bitcoin, testnet, signet or regtest
+    #[arg(long)]
+    network: Option<Network>,
+
     #[command(subcommand)]
     action: Action,
 }
 
+/// Secret keys, in hex, a trigger signs with besides the vault's own
+#[derive(clap::Args, Default)]
+struct TriggerKeys {
+    /// Fund the fee input to this key and sign it here instead of with the fee wallet
+    #[arg(long)]
+    fee_key: Option<String>,
+    /// A trigger cosigner's key, repeat it for every cosigner signing here
+    #[arg(long = "cosigner-key")]
+    cosigner_keys: Vec<String>,
+}
+
+/// How a trigger is built and whether it's broadcast
+#[derive(Default)]
+struct TriggerOptions {
+    anchor: bool,
+    dry_run: bool,
+    overfund: Option<Amount>,
+}
+
 #[derive(Parser)]
 enum Action {
-    Deposit,
-    Trigger,
+    Deposit {
+        /// Commit a CTV vault to only ever paying out to these outputs, as <address>:<sats>
+        #[arg(short, long = "payout", value_parser = parse_recipient)]
+        payout: Vec<(String, u64)>,
+        /// Where a CTV vault's transactions put the vault input, 0 or 1
+        #[arg(long, default_value_t = 0)]
+        vault_input_index: usize,
+        /// Commit a CTV vault's trigger to an OP_RETURN output carrying this hex data
+        #[arg(long, value_parser = parse_hex)]
+        trigger_metadata: Option<Vec<u8>>,
+        /// Commit a CTV vault's trigger to paying change out of its fee input, as <address>:<sats>
+        #[arg(long, value_parser = parse_recipient)]
+        trigger_change: Option<(String, u64)>,
+        /// Commit a CTV vault's trigger to paying this many sats of fee out of the vault, without a fee input
+        #[arg(long)]
+        trigger_fee: Option<u64>,
+        /// Without --seed, draw the vault keys from this number so the same value rebuilds the same vault.
+        /// Anyone who knows it has the keys, so it is only accepted on regtest.
+        #[arg(long)]
+        key_seed: Option<u64>,
+    },
+    Trigger {
+        /// Withdraw only this many sats of a CTV vault, the rest goes back into a vault
+        #[arg(short, long)]
+        amount: Option<u64>,
+        /// Pay part of a CTV withdrawal to another address, as <address>:<sats>, the rest goes to the withdrawal address
+        #[arg(short, long = "recipient", value_parser = parse_recipient)]
+        recipients: Vec<(String, u64)>,
+        /// Send the last marker of a CAT trigger to a keyless anchor, so cpfp-trigger can fee bump it
+        #[arg(long)]
+        anchor: bool,
+        /// Only check the signed trigger against the node's mempool policy instead of broadcasting it
+        #[arg(long)]
+        dry_run: bool,
+        /// Fund the fee input with this many sats more than needed and pay them back as change, needs a
+        /// CAT vault built with fee_change
+        #[arg(long)]
+        overfund: Option<u64>,
+        #[command(flatten)]
+        keys: TriggerKeys,
+    },
     Steal,
     Complete,
-    Cancel,
-    Status,
+    Watch {
+        /// How many seconds to guard the vault for before giving up
+        #[arg(short, long, default_value_t = 3600)]
+        timeout: u64,
+        /// A withdrawal address the owner's own CAT triggers commit to, they aren't cancelled
+        #[arg(long)]
+        expect: Option<String>,
+    },
+    Cancel {
+        /// Also save the signed cancel transaction and the outputs it spends to this file
+        #[arg(long)]
+        save_tx: Option<String>,
+        /// Sign a CTV cancel with this sighash type, SIGHASH_ALL or "SIGHASH_ALL|SIGHASH_ANYONECANPAY"
+        #[arg(long)]
+        sighash: Option<TapSighashType>,
+        /// Fund the fee input with this many sats more than needed and pay them back as change, needs a
+        /// CAT vault built with fee_change
+        #[arg(long)]
+        overfund: Option<u64>,
+    },
+    Status {
+        /// Wait up to this many seconds for the latest vault transaction to get enough confirmations to be final
+        #[arg(long)]
+        wait: Option<u64>,
+    },
     Switch,
+    Monitor {
+        /// How many seconds to watch the vault for before giving up
+        #[arg(short, long, default_value_t = 600)]
+        timeout: u64,
+    },
+    MerkleRoot {
+        /// A merkle root computed by another implementation to compare against
+        #[arg(short, long)]
+        expected: Option<String>,
+    },
+    Audit,
+    /// Print every tapleaf's script, leaf hash and control block as JSON
+    DumpLeaves,
+    /// Print a watch-only descriptor for the deposit address
+    Descriptor,
+    /// Print the vsize each vault transaction will have with a taproot fee input, without building it
+    PredictVsize,
+    CancelAndRotate {
+        /// The timelock for the new vault in blocks, the settings' timelock if unset
+        #[arg(short, long)]
+        timelock: Option<u16>,
+        /// Require the new vault's timelock to be longer than this one's, making it a cold vault
+        #[arg(long)]
+        cold: bool,
+    },
+    /// Cancel a CTV withdrawal into a new vault that also sweeps in other outputs the fee wallet can sign for
+    CancelConsolidate {
+        /// An output to fold into the new vault, as txid:vout
+        #[arg(short, long = "input", required = true)]
+        inputs: Vec<OutPoint>,
+    },
+    /// Fetch a lost trigger transaction from the node
+    RecoverTrigger,
+    SetOutpoint {
+        /// The output now holding the vault, as txid:vout
+        outpoint: String,
+    },
+    SweepMarker {
+        /// The marker output of a CAT trigger transaction, as txid:vout
+        marker: OutPoint,
+    },
+    Timelock {
+        /// The unix timestamp the withdrawal should unlock at
+        unlock_at: u64,
+        /// The expected average number of seconds between blocks
+        #[arg(short, long, default_value_t = 600)]
+        avg_block_secs: u64,
+    },
+    ChunkMap,
+    DelegateCancel {
+        /// Where to write the cancel delegation package for a watchtower, CAT vaults only
+        #[arg(short, long, default_value = "cancel_delegation.json")]
+        output: String,
+    },
+    CancelMany {
+        /// The files of the triggered CTV vaults to cancel together
+        #[arg(required = true)]
+        vault_files: Vec<String>,
+    },
+    Recover,
+    KeypathSweep {
+        /// The hex secret key of the vault's cold internal key
+        cold_key: String,
+        /// Where to sweep the funds, a new withdrawal wallet address if not given
+        #[arg(short, long)]
+        destination: Option<String>,
+    },
+    BumpTrigger {
+        /// The fee rate in sat/vB the replacement trigger should pay
+        fee_rate: u64,
+    },
+    CpfpTrigger {
+        /// The fee rate in sat/vB the trigger and its child should pay together
+        fee_rate: u64,
+    },
+    VerifyTx {
+        /// A transaction bundle saved by cancel --save-tx
+        bundle_file: String,
+    },
+    /// Print the template hash a CTV vault's deposit leaf commits to
+    CtvHash {
+        /// Also check this raw trigger transaction, in hex, against the committed template
+        #[arg(long, value_parser = parse_hex)]
+        tx: Option<Vec<u8>>,
+    },
+    /// Print the deposit address and the trigger, complete and cancel a CTV vault would produce, without a node
+    Simulate {
+        /// The address the simulated complete withdraws to
+        withdrawal_address: String,
+    },
+    /// Print which of trigger, complete and cancel the vault can take right now
+    Actions,
+    /// Summarize every vault file in a directory
+    Summary {
+        dir: PathBuf,
+    },
+    /// Move the untriggered vault's funds into a new vault with fresh keys, the same timelock and amount
+    RotateKey,
+}
+
+/// Set by the first Ctrl-C, the waits of monitor, watch and deposit give up once it is
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The flag a Ctrl-C sets, installing the handler on first use. Commands that don't wait on the node never
+/// install it and stop on Ctrl-C as usual, a second Ctrl-C stops the ones that do.
+fn interrupt_flag() -> Result<&'static AtomicBool> {
+    static INSTALL: Once = Once::new();
+    let mut installed = Ok(());
+    INSTALL.call_once(|| {
+        installed = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+            warn!("Interrupted, stopping at the next poll. Press Ctrl-C again to quit right away.");
+        });
+    });
+    installed?;
+    Ok(&INTERRUPTED)
 }
 
 fn main() -> Result<()> {
@@ -41,7 +277,7 @@ fn main() -> Result<()> {
 
     let args = Cli::parse();
 
-    let settings = match Settings::from_toml_file(&args.settings_file) {
+    let mut settings = match Settings::from_toml_file(&args.settings_file) {
         Ok(settings) => settings,
         Err(e) => {
             error!("Error reading settings file: {}", e);
@@ -54,23 +290,105 @@ fn main() -> Result<()> {
             settings
         }
     };
+    settings.path = Some(args.settings_file.clone());
+    settings.seed = args.seed;
+    settings.integrity_secret = args.integrity_secret;
+    if let Some(network) = args.network {
+        settings.network = network;
+    }
 
     println!("A simple vault built with {}", settings.vault_type);
 
     match args.action {
-        Action::Deposit => deposit(&settings)?,
-        Action::Trigger => trigger(false, &settings)?,
-        Action::Steal => trigger(true, &settings)?,
+        Action::Deposit {
+            payout,
+            vault_input_index,
+            trigger_metadata,
+            trigger_change,
+            trigger_fee,
+            key_seed,
+        } => deposit(
+            &settings,
+            &payout,
+            vault_input_index,
+            trigger_metadata.as_deref(),
+            trigger_change,
+            trigger_fee.map(Amount::from_sat),
+            key_seed,
+        )?,
+        Action::Trigger {
+            amount,
+            recipients,
+            anchor,
+            dry_run,
+            overfund,
+            keys,
+        } => trigger(
+            false,
+            amount.map(Amount::from_sat),
+            &recipients,
+            TriggerOptions {
+                anchor,
+                dry_run,
+                overfund: overfund.map(Amount::from_sat),
+            },
+            &keys,
+            &settings,
+        )?,
+        Action::Steal => trigger(
+            true,
+            None,
+            &[],
+            TriggerOptions::default(),
+            &TriggerKeys::default(),
+            &settings,
+        )?,
         Action::Complete => complete(&settings)?,
-        Action::Cancel => cancel(&settings)?,
-        Action::Status => status(&settings)?,
+        Action::Watch { timeout, expect } => watch(&settings, timeout, expect)?,
+        Action::Cancel {
+            save_tx,
+            sighash,
+            overfund,
+        } => cancel(&settings, save_tx, sighash, overfund.map(Amount::from_sat))?,
+        Action::Status { wait } => status(&settings, wait)?,
         Action::Switch => switch(&settings, &args.settings_file)?,
+        Action::Monitor { timeout } => monitor(&settings, timeout)?,
+        Action::MerkleRoot { expected } => merkle_root(&settings, expected)?,
+        Action::Audit => audit(&settings)?,
+        Action::DumpLeaves => dump_leaves(&settings)?,
+        Action::Descriptor => descriptor(&settings)?,
+        Action::PredictVsize => predict_vsize(&settings)?,
+        Action::CancelAndRotate { timelock, cold } => cancel_and_rotate(&settings, timelock, cold)?,
+        Action::CancelConsolidate { inputs } => cancel_consolidate(&settings, &inputs)?,
+        Action::RecoverTrigger => recover_trigger(&settings)?,
+        Action::SetOutpoint { outpoint } => set_outpoint(&settings, &outpoint)?,
+        Action::SweepMarker { marker } => sweep_marker(&settings, marker)?,
+        Action::Timelock {
+            unlock_at,
+            avg_block_secs,
+        } => timelock(&settings, unlock_at, avg_block_secs)?,
+        Action::ChunkMap => chunk_map(&settings)?,
+        Action::DelegateCancel { output } => delegate_cancel(&settings, &output)?,
+        Action::CancelMany { vault_files } => cancel_many(&settings, &vault_files)?,
+        Action::Recover => recover(&settings)?,
+        Action::KeypathSweep {
+            cold_key,
+            destination,
+        } => keypath_sweep(&settings, &cold_key, destination)?,
+        Action::BumpTrigger { fee_rate } => bump_trigger(&settings, fee_rate)?,
+        Action::CpfpTrigger { fee_rate } => cpfp_trigger(&settings, fee_rate)?,
+        Action::VerifyTx { bundle_file } => verify_tx(&bundle_file)?,
+        Action::CtvHash { tx } => ctv_hash(&settings, tx.as_deref())?,
+        Action::Simulate { withdrawal_address } => simulate(&settings, &withdrawal_address)?,
+        Action::Actions => actions(&settings)?,
+        Action::Summary { dir } => summary(&settings, &dir)?,
+        Action::RotateKey => rotate_key(&settings)?,
     }
     Ok(())
 }
 
 fn switch(settings: &Settings, settings_file: &PathBuf) -> Result<()> {
-    if VaultCovenant::from_file(&settings.vault_file).is_ok() {
+    if VaultCovenant::file_exists(&settings.vault_file) {
         info!("Vault already exists. Delete the vault file to start over if you want to switch vault type.");
         return Ok(());
     }
@@ -89,29 +407,68 @@ fn switch(settings: &Settings, settings_file: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn status(settings: &Settings) -> Result<()> {
-    let vault = VaultCovenant::from_file(&settings.vault_file).map_err(|e| {
+fn status(settings: &Settings, wait: Option<u64>) -> Result<()> {
+    let vault = load_vault(settings).map_err(|e| {
         error!("No vault found: {}.", e);
         error!("You can create a vault with the deposit command.");
         e
     })?;
+    VaultCovenant::verify_file_integrity(
+        &settings.vault_file,
+        settings.master_key()?.as_ref(),
+        settings.file_key()?.as_ref(),
+    )?;
     let client = Wallet::create_rpc_client(settings, None);
-    debug!(
-        "Vault current outpoint is {}",
-        &vault.get_current_outpoint().unwrap()
-    );
-    let latest_vault_transaction =
-        client.get_raw_transaction(&vault.get_current_outpoint()?.txid, None)?;
-    debug!(
-        "Vault last transaction is {}",
-        &latest_vault_transaction.raw_hex()
-    );
-    let latest_state_onchain: VaultState =
-        (latest_vault_transaction, vault.address()?, vault.get_type()).into();
+    info!("Vault id is {}", vault.vault_id());
+    if vault.get_state() != Completed {
+        match vault.balance(&client) {
+            Ok(Balance::Matches(amount)) => info!("Vault output holds {}", amount),
+            Ok(Balance::Differs { onchain, recorded }) => error!(
+                "Vault output holds {} but the vault recorded {}, its covenant spends will fail",
+                onchain, recorded
+            ),
+            Ok(Balance::Pending {
+                onchain,
+                confirmations,
+            }) => info!(
+                "Vault output holds {} but only has {} of the {} confirmations to count as final",
+                onchain,
+                confirmations,
+                vault.min_confirmations()
+            ),
+            Err(e) => warn!("Couldn't check the vault output: {}", e),
+        }
+    }
+    match vault.completed_txid() {
+        Some(txid) => debug!("Vault was completed by {}", txid),
+        None => debug!(
+            "Vault current outpoint is {}",
+            vault.current_outpoint_string()?
+        ),
+    }
+    let chain_state = match wait {
+        Some(wait) => {
+            let (state, confirmations) =
+                vault.sync_state(&client, Duration::from_secs(wait), interrupt_flag()?)?;
+            (ChainState::Final(state), confirmations)
+        }
+        None => vault.verify_state(&client)?,
+    };
+    let (latest_state_onchain, confirmations) = match chain_state {
+        (ChainState::Final(state), confirmations) => (state, confirmations),
+        (ChainState::Pending(state), confirmations) => {
+            info!(
+                "The latest vault transaction only has {} of the {} confirmations to count as final",
+                confirmations,
+                vault.min_confirmations()
+            );
+            (state, confirmations)
+        }
+    };
     if latest_state_onchain == vault.get_state() {
         info!(
-            "Vault state is consistent with the latest on-chain transaction: {:?}",
-            latest_state_onchain
+            "Vault state is consistent with the latest on-chain transaction: {:?} ({} confirmations)",
+            latest_state_onchain, confirmations
         );
     } else if latest_state_onchain == Triggered {
         error!("Onchain state is Triggered, but the internal vault state is not. YOU MIGHT BE GETTING ROBBED! Run the `cancel` command to cancel the withdrawal and SAVE YOUR MONEY!");
@@ -126,111 +483,998 @@ fn status(settings: &Settings) -> Result<()> {
     Ok(())
 }
 
-fn cancel(settings: &Settings) -> Result<()> {
-    info!("Cancelling the withdrawal");
+fn recover_trigger(settings: &Settings) -> Result<()> {
+    let mut vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    let trigger_tx = vault.recover_trigger_transaction(&client)?;
+    info!(
+        "Recovered trigger {}, the vault is at {}",
+        trigger_tx.txid(),
+        vault.current_outpoint_string()?
+    );
+    vault.to_file(&settings.vault_file)?;
+    Ok(())
+}
+
+fn set_outpoint(settings: &Settings, outpoint: &str) -> Result<()> {
+    let mut vault = load_vault(settings)?;
+    vault.set_current_outpoint_from_str(outpoint)?;
+    info!(
+        "Vault current outpoint is now {}",
+        vault.current_outpoint_string()?
+    );
+    vault.to_file(&settings.vault_file)?;
+    Ok(())
+}
+
+fn sweep_marker(settings: &Settings, marker: OutPoint) -> Result<()> {
+    info!("Sweeping the trigger marker output {}", marker);
     let miner_wallet = Wallet::new("miner", settings);
     let fee_wallet = Wallet::new("fee_payment", settings);
-    let mut vault = VaultCovenant::from_file(&settings.vault_file)?;
+    let withdrawal_wallet = Wallet::new("withdrawal", settings);
+    let vault = load_vault(settings)?;
 
+    let destination = withdrawal_wallet.get_new_address()?;
     let fee_paying_address = fee_wallet.get_new_address()?;
     let fee_paying_utxo = miner_wallet.send(&fee_paying_address, Amount::from_sat(10_000))?;
-    let cancel_tx = if vault.get_type() == VaultType::CAT {
-        vault.create_cancel_tx(
-            &fee_paying_utxo,
-            TxOut {
-                script_pubkey: fee_paying_address.script_pubkey(),
-                value: Amount::from_sat(10_000),
-            },
-        )?
-    } else {
-        vault.create_ctv_cancel_tx(
+    miner_wallet.mine_blocks(Some(1))?;
+    let fee_paying_output = TxOut {
+        script_pubkey: fee_paying_address.script_pubkey(),
+        value: Amount::from_sat(10_000),
+    };
+    let sweep_tx = vault.sweep_marker(
+        marker,
+        &destination,
+        (fee_paying_utxo, fee_paying_output),
+        Amount::from_sat(1_000),
+    )?;
+
+    let signed_tx = fee_wallet.sign_tx(&withdrawal_wallet.sign_tx(&sweep_tx)?)?;
+    let mut serialized_tx = Vec::new();
+    signed_tx.consensus_encode(&mut serialized_tx).unwrap();
+    debug!("serialized tx: {:?}", serialized_tx.raw_hex());
+    let txid = fee_wallet.broadcast_tx(&serialized_tx, None)?;
+    info!("sent marker sweep txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+
+    Ok(())
+}
+
+fn audit(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    VaultCovenant::verify_file_integrity(
+        &settings.vault_file,
+        settings.master_key()?.as_ref(),
+        settings.file_key()?.as_ref(),
+    )?;
+    let report = vault.security_report()?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    println!("{}", serde_json::to_string_pretty(&vault.compare_types()?)?);
+    for risk in report.risks() {
+        warn!("{}", risk);
+    }
+    Ok(())
+}
+
+fn dump_leaves(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    println!("{}", serde_json::to_string_pretty(&vault.dump_leaves()?)?);
+    Ok(())
+}
+
+fn descriptor(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    println!("{}", vault.descriptor()?);
+    Ok(())
+}
+
+fn predict_vsize(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    // the wallets pay from and withdraw to taproot addresses, the same size as the vault's
+    let taproot = vault.address()?;
+    let destination = match vault.get_withdrawal_address() {
+        Ok(address) => address,
+        Err(_) => taproot.clone(),
+    };
+    for path in [SpendPath::Trigger, SpendPath::Complete, SpendPath::Cancel] {
+        match vault.predicted_vsize(path, taproot.script_pubkey(), Some(&destination)) {
+            Ok(vsize) => println!("{:?}: {} vB", path, vsize),
+            Err(e) => println!("{:?}: can't be sized yet, {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+fn chunk_map(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    let trigger_tx = vault.get_trigger_transaction()?;
+    // the chunk map indexes into the serialization without witnesses, as hashed for the txid
+    let mut stripped_tx = trigger_tx.clone();
+    stripped_tx
+        .input
+        .iter_mut()
+        .for_each(|input| input.witness.clear());
+    let serialized = serialize(&stripped_tx);
+    for (chunk, (start, end)) in vault
+        .complete_witness_chunk_map(&trigger_tx)
+        .into_iter()
+        .enumerate()
+    {
+        info!(
+            "chunk {}: bytes {}..{} <0x{}>",
+            chunk,
+            start,
+            end,
+            serialized[start..end].to_lower_hex_string()
+        );
+    }
+    Ok(())
+}
+
+fn delegate_cancel(settings: &Settings, output: &str) -> Result<()> {
+    let vault = load_vault(settings)?;
+    let delegation = vault.cancel_delegation_package()?;
+    std::fs::write(output, serde_json::to_string_pretty(&delegation)?)?;
+    info!(
+        "Wrote cancel delegation to {}, it holds the cancel key, which can only send the funds back into the vault",
+        output
+    );
+    Ok(())
+}
+
+fn bump_trigger(settings: &Settings, fee_rate: u64) -> Result<()> {
+    info!("Replacing the trigger transaction at {} sat/vB", fee_rate);
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let mut vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    let fee_rate =
+        FeeRate::from_sat_per_vb(fee_rate).ok_or(anyhow!("fee rate {} overflows", fee_rate))?;
+
+    let original = vault.get_trigger_transaction()?;
+    let original_fee_inputs = original.input[1..]
+        .iter()
+        .map(|input| {
+            let funding_tx = client.get_raw_transaction(&input.previous_output.txid, None)?;
+            funding_tx
+                .output
+                .get(input.previous_output.vout as usize)
+                .cloned()
+                .ok_or(anyhow!("fee input {} doesn't exist", input.previous_output))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let fee_paying_address = fee_wallet.get_new_address()?;
+    let fee_amount = vault.bump_trigger_fee_input(
+        &original,
+        &original_fee_inputs,
+        fee_rate,
+        fee_paying_address.script_pubkey(),
+    )?;
+    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, fee_amount)?;
+    let fee_paying_output = TxOut {
+        script_pubkey: fee_paying_address.script_pubkey(),
+        value: fee_amount,
+    };
+    let replacement = vault.bump_trigger_fee(
+        &original,
+        &original_fee_inputs,
+        fee_rate,
+        (fee_paying_utxo, fee_paying_output),
+    )?;
+    let signed_tx = fee_wallet.sign_tx(&replacement)?;
+    vault.broadcast(&signed_tx, SpendPath::Trigger, &client)?;
+    vault.to_file(&settings.vault_file)?;
+
+    Ok(())
+}
+
+fn cpfp_trigger(settings: &Settings, fee_rate: u64) -> Result<()> {
+    info!(
+        "Fee bumping the trigger transaction through its anchor at {} sat/vB",
+        fee_rate
+    );
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    let fee_rate =
+        FeeRate::from_sat_per_vb(fee_rate).ok_or(anyhow!("fee rate {} overflows", fee_rate))?;
+
+    let parent = vault.get_trigger_transaction()?;
+    let anchor_vout =
+        ephemeral_anchor_vout(&parent).ok_or(anyhow!("the trigger has no anchor output"))?;
+    let parent_prevouts = parent
+        .input
+        .iter()
+        .map(|input| {
+            let funding_tx = client.get_raw_transaction(&input.previous_output.txid, None)?;
+            funding_tx
+                .output
+                .get(input.previous_output.vout as usize)
+                .cloned()
+                .ok_or(anyhow!("input {} doesn't exist", input.previous_output))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let change = fee_wallet.get_new_address()?;
+    let fee_input = fee_wallet.get_utxo(cpfp_fee_input_amount(
+        &parent,
+        &parent_prevouts,
+        anchor_vout,
+        &change,
+        fee_rate,
+    )?)?;
+    let child = create_cpfp_child(
+        &parent,
+        &parent_prevouts,
+        anchor_vout,
+        fee_input,
+        &change,
+        fee_rate,
+    )?;
+    // the keyless anchor needs no witness, the wallet only signs the fee input
+    let signed_child = fee_wallet.sign_tx(&child)?;
+    for result in client.test_mempool_accept(&[&signed_child])? {
+        if !result.allowed {
+            return Err(anyhow!(
+                "the node won't accept the child: {}",
+                result.reject_reason.unwrap_or_default()
+            ));
+        }
+    }
+    let txid = client.send_raw_transaction(&signed_child)?;
+    info!("sent child transaction txid: {}", txid);
+
+    Ok(())
+}
+
+fn verify_tx(bundle_file: &str) -> Result<()> {
+    let bundle = SignedTxBundle::load_tx(bundle_file)?;
+    bundle.verify()?;
+    info!(
+        "{:?} transaction {} checks out against its prevouts",
+        bundle.path,
+        bundle.tx.txid()
+    );
+    Ok(())
+}
+
+fn simulate(settings: &Settings, withdrawal_address: &str) -> Result<()> {
+    let vault = load_vault(settings)?;
+    let withdrawal_address =
+        Address::from_str(withdrawal_address)?.require_network(settings.network)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&vault.simulate_full_flow(&withdrawal_address)?)?
+    );
+    Ok(())
+}
+
+fn actions(settings: &Settings) -> Result<()> {
+    let vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&vault.available_actions(&client)?)?
+    );
+    Ok(())
+}
+
+fn summary(settings: &Settings, dir: &Path) -> Result<()> {
+    let loaded = VaultCovenant::load_all(dir, settings.master_key()?.as_ref())?;
+    for (path, e) in &loaded.failures {
+        error!("Couldn't load {}: {}", path.display(), e);
+    }
+    let summaries = loaded
+        .vaults
+        .iter()
+        .map(|(path, vault)| vault.summary(path))
+        .collect::<Result<Vec<_>>>()?;
+    println!("{}", serde_json::to_string_pretty(&summaries)?);
+    Ok(())
+}
+
+fn ctv_hash(settings: &Settings, tx: Option<&[u8]>) -> Result<()> {
+    let vault = load_vault(settings)?;
+    if vault.get_type() != VaultType::CTV {
+        return Err(anyhow!("only a CTV vault commits to a trigger template"));
+    }
+    info!(
+        "The deposit leaf commits to template hash {}",
+        vault.ctv_template_hash()?.to_lower_hex_string()
+    );
+    if let Some(tx) = tx {
+        let trigger_tx: Transaction = deserialize(tx)?;
+        vault.verify_ctv_spend(&trigger_tx)?;
+        info!("{} matches the committed template", trigger_tx.txid());
+    }
+    Ok(())
+}
+
+fn timelock(settings: &Settings, unlock_at: u64, avg_block_secs: u64) -> Result<()> {
+    let target = UNIX_EPOCH + Duration::from_secs(unlock_at);
+    let client = Wallet::create_rpc_client(settings, None);
+    let current_height = client.get_block_count()? as u32;
+    let timelock =
+        timelock_for_target_date(target, current_height, SystemTime::now(), avg_block_secs)?;
+    info!(
+        "A withdrawal triggered now would need a timelock of {:?} (nSequence {})",
+        timelock,
+        timelock.to_sequence()
+    );
+    Ok(())
+}
+
+fn merkle_root(settings: &Settings, expected: Option<String>) -> Result<()> {
+    let vault = load_vault(settings)?;
+    info!("Vault taproot merkle root is {}", vault.merkle_root_hex()?);
+    if let Some(expected) = expected {
+        vault.verify_merkle_root(&expected)?;
+        info!("Merkle root matches the expected value");
+    }
+    Ok(())
+}
+
+fn monitor(settings: &Settings, timeout: u64) -> Result<()> {
+    let vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    let spending_tx = vault.monitor(&client, Duration::from_secs(timeout), interrupt_flag()?)?;
+    let state_onchain = vault.spent_leaf_state(&spending_tx)?;
+    info!(
+        "Vault outpoint was spent by {}, on-chain state is now {:?}",
+        spending_tx.txid(),
+        state_onchain
+    );
+    if state_onchain == Triggered && vault.get_state() != Triggered {
+        error!("A withdrawal was triggered that this vault did not initiate. YOU MIGHT BE GETTING ROBBED! Run the `cancel` command to cancel the withdrawal and SAVE YOUR MONEY!");
+    }
+    Ok(())
+}
+
+fn watch(settings: &Settings, timeout: u64, expect: Option<String>) -> Result<()> {
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let mut builder = VaultCovenantBuilder::new(load_vault(settings)?);
+    if let Some(expect) = expect {
+        builder = builder
+            .withdrawal_address(Address::from_str(&expect)?.require_network(settings.network)?);
+    }
+    let mut vault = builder.build_for(SpendPath::Cancel)?;
+    let events = Arc::new(CountingSink::new(LogSink));
+    vault.set_metrics_sink(events.clone());
+    // fund the cancel up front so it can go out the moment a trigger shows up
+    let fee_input = fund_fee_input(
+        settings,
+        &VaultBuildSession::new(&vault),
+        SpendPath::Cancel,
+        None,
+        &miner_wallet,
+        &fee_wallet,
+    )?;
+    miner_wallet.mine_blocks(Some(1))?;
+    let client = Wallet::create_rpc_client(settings, None);
+    info!("Watching the vault for unauthorized triggers");
+    let cancelled = vault.watch(
+        &client,
+        Duration::from_secs(timeout),
+        interrupt_flag()?,
+        &LogWatchHook,
+        fee_input,
+        |cancel_tx| fee_wallet.sign_tx(cancel_tx),
+    )?;
+    info!("Vault events while watching: {:?}", events.counts());
+    if cancelled.is_some() {
+        miner_wallet.mine_blocks(Some(1))?;
+        vault.to_file(&settings.vault_file)?;
+    }
+    Ok(())
+}
+
+fn cancel(
+    settings: &Settings,
+    save_tx: Option<String>,
+    sighash: Option<TapSighashType>,
+    overfund: Option<Amount>,
+) -> Result<()> {
+    info!("Cancelling the withdrawal");
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let mut vault =
+        VaultCovenantBuilder::new(load_vault(settings)?).build_for(SpendPath::Cancel)?;
+    vault.set_metrics_sink(Arc::new(LogSink));
+    if let Some(sighash) = sighash {
+        vault.set_sighash_type(sighash)?;
+    }
+    if overfund.is_some() {
+        vault.require_fee_change(SpendPath::Cancel)?;
+    }
+
+    let session = VaultBuildSession::new(&vault);
+    let (fee_paying_utxo, fee_paying_output) = fund_fee_input_to(
+        settings,
+        &session,
+        SpendPath::Cancel,
+        None,
+        &miner_wallet,
+        &fee_wallet.get_new_address()?,
+        overfund.unwrap_or(Amount::ZERO),
+    )?;
+    let client = Wallet::create_rpc_client(settings, None);
+    vault.validate_fee_input_live(&client, &fee_paying_utxo, &fee_paying_output)?;
+    let cancel_tx = match overfund {
+        Some(_) => vault
+            .create_cancel_tx_with_change(
+                &fee_paying_utxo,
+                fee_paying_output.clone(),
+                &fee_wallet.get_new_address()?,
+                path_fee_rate(settings, SpendPath::Cancel),
+            )?
+            .into_transaction(),
+        None => session.create_path_tx(
+            SpendPath::Cancel,
             &fee_paying_utxo,
-            TxOut {
-                script_pubkey: fee_paying_address.script_pubkey(),
-                value: Amount::from_sat(10_000),
-            },
-        )?
+            fee_paying_output.clone(),
+            None,
+        )?,
     };
 
     let signed_tx = fee_wallet.sign_tx(&cancel_tx)?;
+    log_fee_rate(
+        &signed_tx,
+        &[vault.current_txout()?, fee_paying_output.clone()],
+    )?;
+    if let Some(save_tx) = save_tx {
+        vault
+            .tx_bundle(&signed_tx, SpendPath::Cancel, &[fee_paying_output])?
+            .save_tx(&save_tx)?;
+    }
+    debug!("serialized tx: {:?}", serialize(&signed_tx).raw_hex());
+    vault.broadcast(&signed_tx, SpendPath::Cancel, &client)?;
+    miner_wallet.mine_blocks(Some(1))?;
+    vault.to_file(&settings.vault_file)?;
+
+    Ok(())
+}
+
+fn keypath_sweep(settings: &Settings, cold_key: &str, destination: Option<String>) -> Result<()> {
+    info!("Sweeping the vault through the key path");
+    let miner_wallet = Wallet::new("miner", settings);
+    let withdrawal_wallet = Wallet::new("withdrawal", settings);
+    let mut vault = load_vault(settings)?;
+    let cold_keypair = parse_keypair(cold_key)?;
+    let destination = match destination {
+        Some(destination) => Address::from_str(&destination)?.require_network(settings.network)?,
+        None => withdrawal_wallet.get_new_address()?,
+    };
+
+    let fee = vault.keypath_sweep_fee(&destination, path_fee_rate(settings, SpendPath::Cancel))?;
+    let sweep_tx = vault.create_keypath_sweep_tx(&cold_keypair, &destination, fee)?;
+    log_fee_rate(&sweep_tx, &[vault.current_txout()?])?;
+    let mut serialized_tx = Vec::new();
+    sweep_tx.consensus_encode(&mut serialized_tx).unwrap();
+    debug!("serialized tx: {:?}", serialized_tx.raw_hex());
+    let txid = miner_wallet.broadcast_tx(&serialized_tx, None)?;
+    info!("sent txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+    info!("funds are now at {}", destination);
+    vault.complete_with(txid);
+    vault.to_file(&settings.vault_file)?;
+
+    Ok(())
+}
+
+fn recover(settings: &Settings) -> Result<()> {
+    info!("Moving the funds to the recovery address");
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let mut vault = load_vault(settings)?;
+    let recovery_height = vault
+        .recovery_height
+        .ok_or(anyhow!("the vault has no recovery leaf"))?;
+    let client = Wallet::create_rpc_client(settings, None);
+    // the next block is the first one the recovery transaction can be mined in
+    let next_height = client.get_block_count()? + 1;
+    if next_height < recovery_height as u64 {
+        return Err(anyhow!(
+            "recovery unlocks at height {}, the next block is {}",
+            recovery_height,
+            next_height
+        ));
+    }
+
+    let fee_paying_address = fee_wallet.get_new_address()?;
+    let fee_amount = vault.recovery_fee(path_fee_rate(settings, SpendPath::Cancel))?;
+    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, fee_amount)?;
+    let fee_paying_output = TxOut {
+        script_pubkey: fee_paying_address.script_pubkey(),
+        value: fee_amount,
+    };
+    let recovery_tx = vault.create_recovery_tx(&fee_paying_utxo)?;
+
+    let signed_tx = fee_wallet.sign_tx(&recovery_tx)?;
+    log_fee_rate(&signed_tx, &[vault.current_txout()?, fee_paying_output])?;
     let mut serialized_tx = Vec::new();
     signed_tx.consensus_encode(&mut serialized_tx).unwrap();
     debug!("serialized tx: {:?}", serialized_tx.raw_hex());
     let txid = fee_wallet.broadcast_tx(&serialized_tx, None)?;
     info!("sent txid: {}", txid);
     miner_wallet.mine_blocks(Some(1))?;
-    vault.set_current_outpoint(OutPoint { txid, vout: 0 });
-    vault.set_state(Inactive);
+    info!("funds are now at {}", vault.get_recovery_address()?);
+    vault.complete_with(txid);
     vault.to_file(&settings.vault_file)?;
 
     Ok(())
 }
 
-fn complete(settings: &Settings) -> Result<()> {
-    info!("Completing the withdrawal");
+fn cancel_many(settings: &Settings, vault_files: &[String]) -> Result<()> {
+    info!(
+        "Cancelling {} withdrawals in one transaction",
+        vault_files.len()
+    );
     let miner_wallet = Wallet::new("miner", settings);
     let fee_wallet = Wallet::new("fee_payment", settings);
-    let mut vault = VaultCovenant::from_file(&settings.vault_file)?;
-    let timelock_in_blocks = vault.timelock_in_blocks;
-    let withdrawal_address = vault.get_withdrawal_address()?;
-    let trigger_tx = vault.get_trigger_transaction()?;
+    let master_key = settings.master_key()?;
+    let file_key = settings.file_key()?;
+    let mut vaults = vault_files
+        .iter()
+        .map(|file| {
+            let mut vault = VaultCovenant::from_file(&Some(file.clone()), master_key.as_ref())?;
+            vault.set_file_key(file_key);
+            Ok(vault)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
+    let outputs = vaults
+        .iter()
+        .map(|vault| {
+            Ok(TxOut {
+                script_pubkey: vault.address()?.script_pubkey(),
+                value: vault.get_amount(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let spends = vaults
+        .iter()
+        .map(|vault| (vault, SpendPath::Cancel))
+        .collect::<Vec<_>>();
     let fee_paying_address = fee_wallet.get_new_address()?;
-    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, Amount::from_sat(10_000))?;
-    info!("need to mine {timelock_in_blocks} blocks for the timelock");
-    miner_wallet.mine_blocks(Some(timelock_in_blocks as u64))?;
+    let fee_amount = spend_many_fee(
+        &spends,
+        fee_paying_address.script_pubkey(),
+        &outputs,
+        path_fee_rate(settings, SpendPath::Cancel),
+    )?;
+    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, fee_amount)?;
     let fee_paying_output = TxOut {
         script_pubkey: fee_paying_address.script_pubkey(),
-        value: Amount::from_sat(10_000),
+        value: fee_amount,
     };
-    let compete_tx = if vault.get_type() == VaultType::CAT {
-        vault.create_complete_tx(
-            &fee_paying_utxo,
-            fee_paying_output,
-            &withdrawal_address,
-            &trigger_tx,
-        )?
+    let cancel_tx = spend_many(
+        &spends,
+        Some((&fee_paying_utxo, fee_paying_output.clone())),
+        &outputs,
+    )?;
+
+    let signed_tx = fee_wallet.sign_tx(&cancel_tx)?;
+    let mut prevouts = vaults
+        .iter()
+        .map(|vault| vault.current_txout())
+        .collect::<Result<Vec<_>>>()?;
+    prevouts.push(fee_paying_output);
+    log_fee_rate(&signed_tx, &prevouts)?;
+    let mut serialized_tx = Vec::new();
+    signed_tx.consensus_encode(&mut serialized_tx).unwrap();
+    debug!("serialized tx: {:?}", serialized_tx.raw_hex());
+    let txid = fee_wallet.broadcast_tx(&serialized_tx, None)?;
+    info!("sent txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+    for (vout, (vault, file)) in vaults.iter_mut().zip(vault_files).enumerate() {
+        vault.set_current_outpoint(OutPoint {
+            txid,
+            vout: vout as u32,
+        });
+        vault.set_state(Inactive);
+        vault.to_file(&Some(file.clone()))?;
+    }
+
+    Ok(())
+}
+
+fn cancel_and_rotate(settings: &Settings, timelock: Option<u16>, cold: bool) -> Result<()> {
+    info!("Cancelling the withdrawal into a new vault");
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let vault = load_vault(settings)?;
+    let timelock = timelock.map_or(settings.timelock(), TimelockKind::Blocks);
+
+    let (new_vault, signed_tx) = if vault.fee_reserve > Amount::ZERO {
+        if cold {
+            vault.require_colder_timelock(timelock)?;
+        }
+        let (new_vault, cancel_tx) = vault.cancel_and_rotate_from_reserve(
+            timelock,
+            path_fee_rate(settings, SpendPath::Cancel),
+            settings,
+        )?;
+        log_fee_rate(&cancel_tx, &[vault.current_txout()?])?;
+        info!("{} left in the fee reserve", new_vault.fee_reserve);
+        (new_vault, cancel_tx)
     } else {
-        vault.create_ctv_complete_tx(&fee_paying_utxo, fee_paying_output)?
+        let (fee_paying_utxo, fee_paying_output) = fund_fee_input(
+            settings,
+            &VaultBuildSession::new(&vault),
+            SpendPath::Cancel,
+            None,
+            &miner_wallet,
+            &fee_wallet,
+        )?;
+        let client = Wallet::create_rpc_client(settings, None);
+        vault.validate_fee_input_live(&client, &fee_paying_utxo, &fee_paying_output)?;
+        let fee_input = (fee_paying_utxo, fee_paying_output.clone());
+        let (new_vault, cancel_tx) = if cold {
+            vault.cancel_to_cold_vault(timelock, fee_input, settings)?
+        } else {
+            vault.cancel_and_rotate(timelock, fee_input, settings)?
+        };
+        let signed_tx = fee_wallet.sign_tx(&cancel_tx)?;
+        log_fee_rate(&signed_tx, &[vault.current_txout()?, fee_paying_output])?;
+        (new_vault, signed_tx)
     };
-    let signed_tx = fee_wallet.sign_tx(&compete_tx)?;
     let mut serialized_tx = Vec::new();
     signed_tx.consensus_encode(&mut serialized_tx).unwrap();
     debug!("serialized tx: {:?}", serialized_tx.raw_hex());
     let txid = fee_wallet.broadcast_tx(&serialized_tx, None)?;
     info!("sent txid: {}", txid);
     miner_wallet.mine_blocks(Some(1))?;
-    vault.set_current_outpoint(OutPoint { txid, vout: 0 });
-    vault.set_state(Completed);
-    vault.to_file(&settings.vault_file)?;
+    info!("funds are now in the new vault at {}", new_vault.address()?);
+    new_vault.to_file(&settings.vault_file)?;
 
     Ok(())
 }
 
-fn trigger(steal: bool, settings: &Settings) -> Result<()> {
-    info!("Triggering a withdrawal");
+fn rotate_key(settings: &Settings) -> Result<()> {
+    info!("Moving the vault to fresh keys");
     let miner_wallet = Wallet::new("miner", settings);
     let fee_wallet = Wallet::new("fee_payment", settings);
     let withdrawal_wallet = Wallet::new("withdrawal", settings);
-    let mut vault = VaultCovenant::from_file(&settings.vault_file)?;
+    let mut vault = load_vault(settings)?;
+    if vault.get_type() == VaultType::CAT && vault.trigger_markers() > 1 {
+        let marker_addresses = (1..vault.trigger_markers())
+            .map(|_| withdrawal_wallet.get_new_address())
+            .collect::<Result<Vec<_>>>()?;
+        vault.set_marker_addresses(&marker_addresses)?;
+    }
+    let timelock = vault.timelock;
+    let trigger_pays_fee = vault.trigger_fee.is_none();
+    let rotation = vault.rotate_key(settings, |vault, path, destination| {
+        let fee_input = fund_fee_input(
+            settings,
+            &VaultBuildSession::new(vault),
+            path,
+            destination,
+            &miner_wallet,
+            &fee_wallet,
+        )?;
+        miner_wallet.mine_blocks(Some(1))?;
+        Ok(fee_input)
+    })?;
+
+    let trigger_tx = if trigger_pays_fee {
+        fee_wallet.sign_tx(&rotation.trigger_tx)?
+    } else {
+        rotation.trigger_tx
+    };
+    // both vaults are on disk before anything is broadcast, an interruption leaves the old one Triggered and the
+    // keys of the new one saved
+    let vault_file = covenant_filename(&settings.vault_file);
+    let pending_file = format!("{}.new", vault_file);
+    rotation.new_vault.to_file(&Some(pending_file.clone()))?;
+    vault.to_file(&settings.vault_file)?;
+    info!(
+        "The new vault is in {} until the funds have moved into it",
+        pending_file
+    );
+
+    let txid = fee_wallet.broadcast_tx(&serialize(&trigger_tx), None)?;
+    info!("sent trigger transaction txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+    let transfer_tx = fee_wallet.sign_tx(&rotation.transfer_tx)?;
+    if rotation.new_vault.get_type() == VaultType::CAT {
+        wait_for_timelock(&miner_wallet, timelock)?;
+    }
+    let txid = fee_wallet.broadcast_tx(&serialize(&transfer_tx), None)?;
+    info!("sent txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+    info!(
+        "{} are now in the new vault at {}",
+        rotation.new_vault.get_amount(),
+        rotation.new_vault.address()?
+    );
+    vault.complete_with(txid);
+    let rotated_file = format!("{}.rotated", vault_file);
+    vault.to_file(&Some(rotated_file.clone()))?;
+    info!("The old vault is kept in {}", rotated_file);
+    rotation.new_vault.to_file(&settings.vault_file)?;
+    for file in [pending_file.clone(), format!("{}.hmac", pending_file)] {
+        if Path::new(&file).exists() {
+            std::fs::remove_file(file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cancel_consolidate(settings: &Settings, inputs: &[OutPoint]) -> Result<()> {
+    info!(
+        "Cancelling the withdrawal and consolidating {} outputs",
+        inputs.len()
+    );
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let vault = load_vault(settings)?;
+    let client = Wallet::create_rpc_client(settings, None);
+    let extra_inputs = inputs
+        .iter()
+        .map(|outpoint| {
+            let funding_tx = client.get_raw_transaction(&outpoint.txid, None)?;
+            let output = funding_tx
+                .output
+                .get(outpoint.vout as usize)
+                .cloned()
+                .ok_or(anyhow!("{} doesn't exist", outpoint))?;
+            Ok((*outpoint, output))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    let withdrawal_address = withdrawal_wallet.get_new_address()?;
     let fee_paying_address = fee_wallet.get_new_address()?;
-    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, Amount::from_sat(10_000))?;
+    let fee_amount = vault.consolidating_cancel_fee_input_amount(
+        &extra_inputs,
+        path_fee_rate(settings, SpendPath::Cancel),
+        fee_paying_address.script_pubkey(),
+    )?;
+    let fee_paying_utxo = miner_wallet.send(&fee_paying_address, fee_amount)?;
+    let fee_paying_output = TxOut {
+        script_pubkey: fee_paying_address.script_pubkey(),
+        value: fee_amount,
+    };
     miner_wallet.mine_blocks(Some(1))?;
-    let trigger_tx = if vault.get_type() == VaultType::CAT {
-        vault.create_trigger_tx(
+    let (new_vault, cancel_tx) = vault.cancel_consolidating(
+        &extra_inputs,
+        (fee_paying_utxo, fee_paying_output.clone()),
+        settings,
+    )?;
+    let signed_tx = fee_wallet.sign_tx(&cancel_tx)?;
+    let mut prevouts = vec![vault.current_txout()?, fee_paying_output];
+    prevouts.extend(extra_inputs.into_iter().map(|(_, output)| output));
+    log_fee_rate(&signed_tx, &prevouts)?;
+    let mut serialized_tx = Vec::new();
+    signed_tx.consensus_encode(&mut serialized_tx).unwrap();
+    debug!("serialized tx: {:?}", serialized_tx.raw_hex());
+    let txid = fee_wallet.broadcast_tx(&serialized_tx, None)?;
+    info!("sent txid: {}", txid);
+    miner_wallet.mine_blocks(Some(1))?;
+    info!(
+        "{} are now in the new vault at {}",
+        new_vault.get_amount(),
+        new_vault.address()?
+    );
+    new_vault.to_file(&settings.vault_file)?;
+
+    Ok(())
+}
+
+fn complete(settings: &Settings) -> Result<()> {
+    info!("Completing the withdrawal");
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let mut vault =
+        VaultCovenantBuilder::new(load_vault(settings)?).build_for(SpendPath::Complete)?;
+    vault.set_metrics_sink(Arc::new(LogSink));
+    vault.normalize_withdrawal_address()?;
+    vault.verify_trigger_consistency()?;
+    let timelock = vault.timelock;
+
+    let signed_tx = if vault.fee_reserve > Amount::ZERO {
+        wait_for_timelock(&miner_wallet, timelock)?;
+        let complete_tx =
+            vault.create_reserve_complete_tx(path_fee_rate(settings, SpendPath::Complete))?;
+        log_fee_rate(&complete_tx, &[vault.current_txout()?])?;
+        vault.draw_fee_reserve(vault.reserve_fee_paid(&complete_tx)?)?;
+        complete_tx
+    } else {
+        let session = VaultBuildSession::new(&vault);
+        let (fee_paying_utxo, fee_paying_output) = fund_fee_input(
+            settings,
+            &session,
+            SpendPath::Complete,
+            None,
+            &miner_wallet,
+            &fee_wallet,
+        )?;
+        wait_for_timelock(&miner_wallet, timelock)?;
+        let client = Wallet::create_rpc_client(settings, None);
+        vault.validate_fee_input_live(&client, &fee_paying_utxo, &fee_paying_output)?;
+        let compete_tx = session.create_path_tx(
+            SpendPath::Complete,
             &fee_paying_utxo,
-            TxOut {
-                script_pubkey: fee_paying_address.script_pubkey(),
-                value: Amount::from_sat(10_000),
-            },
-            &withdrawal_address,
-        )?
+            fee_paying_output.clone(),
+            None,
+        )?;
+        let signed_tx = fee_wallet.sign_tx(&compete_tx)?;
+        log_fee_rate(&signed_tx, &[vault.current_txout()?, fee_paying_output])?;
+        signed_tx
+    };
+    // completing clears the withdrawal amount, so the residual vault has to be built first
+    let residual_vault = match vault.withdrawal_amount {
+        Some(_) => Some(vault.residual_vault()?),
+        None => None,
+    };
+    debug!("serialized tx: {:?}", serialize(&signed_tx).raw_hex());
+    let client = Wallet::create_rpc_client(settings, None);
+    let txid = vault.broadcast(&signed_tx, SpendPath::Complete, &client)?;
+    miner_wallet.mine_blocks(Some(1))?;
+    if let Some(mut residual_vault) = residual_vault {
+        residual_vault.set_current_outpoint(OutPoint { txid, vout: 1 });
+        info!(
+            "{} left in the vault at {}",
+            residual_vault.get_amount(),
+            residual_vault.address()?
+        );
+        residual_vault.to_file(&settings.vault_file)?;
+        return Ok(());
+    }
+    vault.to_file(&settings.vault_file)?;
+
+    Ok(())
+}
+
+fn wait_for_timelock(miner_wallet: &Wallet, timelock: TimelockKind) -> Result<()> {
+    info!("need to wait {} for the timelock", timelock);
+    match timelock {
+        TimelockKind::Blocks(blocks) => miner_wallet.mine_blocks(Some(blocks as u64)),
+        TimelockKind::Seconds(intervals) => {
+            miner_wallet.advance_median_time(intervals as u64 * SECONDS_PER_INTERVAL)
+        }
+    }
+}
+
+fn parse_recipient(recipient: &str) -> Result<(String, u64)> {
+    let (address, sats) = recipient
+        .rsplit_once(':')
+        .ok_or(anyhow!("expected <address>:<sats>, got {}", recipient))?;
+    Ok((address.to_string(), sats.parse()?))
+}
+
+fn parse_keypair(secret_key: &str) -> Result<Keypair> {
+    Ok(Keypair::from_secret_key(
+        &SECP,
+        &SecretKey::from_str(secret_key)?,
+    ))
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    Ok(Vec::<u8>::from_hex(hex)?)
+}
+
+fn parse_outputs(outputs: &[(String, u64)], network: Network) -> Result<Vec<(Address, Amount)>> {
+    outputs
+        .iter()
+        .map(|(address, sats)| {
+            Ok((
+                Address::from_str(address)?.require_network(network)?,
+                Amount::from_sat(*sats),
+            ))
+        })
+        .collect()
+}
+
+fn trigger(
+    steal: bool,
+    amount: Option<Amount>,
+    recipients: &[(String, u64)],
+    options: TriggerOptions,
+    keys: &TriggerKeys,
+    settings: &Settings,
+) -> Result<()> {
+    info!("Triggering a withdrawal");
+    let miner_wallet = Wallet::new("miner", settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
+    let withdrawal_wallet = Wallet::new("withdrawal", settings);
+    let mut vault = load_vault(settings)?;
+    vault.set_metrics_sink(Arc::new(LogSink));
+    let client = Wallet::create_rpc_client(settings, None);
+    if let Balance::Differs { onchain, recorded } = vault.balance(&client)? {
+        return Err(anyhow!(
+            "the trigger would commit to {} but the vault output holds {}",
+            recorded,
+            onchain
+        ));
+    }
+
+    let withdrawal_address = withdrawal_wallet.get_new_address()?;
+    if let Some(amount) = amount {
+        vault.set_withdrawal_amount(amount)?;
+    }
+    if !recipients.is_empty() {
+        vault.set_recipients(&parse_outputs(recipients, settings.network)?)?;
+    }
+    if vault.get_type() == VaultType::CAT && vault.trigger_markers() > 1 {
+        let marker_addresses = (1..vault.trigger_markers())
+            .map(|_| withdrawal_wallet.get_new_address())
+            .collect::<Result<Vec<_>>>()?;
+        vault.set_marker_addresses(&marker_addresses)?;
+    }
+    if options.overfund.is_some() {
+        vault.require_fee_change(SpendPath::Trigger)?;
+    }
+    if options.anchor {
+        vault.set_trigger_anchor(&ephemeral_anchor(settings.network)?)?;
+    }
+    for cosigner_key in &keys.cosigner_keys {
+        vault.add_trigger_signer(parse_keypair(cosigner_key)?)?;
+    }
+    let (trigger_tx, signed_tx, prevouts) = if vault.trigger_fee.is_some() {
+        // the template pays the fee out of the vault, there is no fee input to fund or sign
+        let trigger_tx = vault.create_ctv_trigger_tx(None)?;
+        (trigger_tx.clone(), trigger_tx, vec![vault.current_txout()?])
     } else {
-        vault.create_ctv_trigger_tx(&fee_paying_utxo)?
+        let fee_keypair = keys.fee_key.as_deref().map(parse_keypair).transpose()?;
+        let session = VaultBuildSession::new(&vault);
+        let (fee_paying_utxo, fee_paying_output) = fund_fee_input_to(
+            settings,
+            &session,
+            SpendPath::Trigger,
+            Some(&withdrawal_address),
+            &miner_wallet,
+            &match &fee_keypair {
+                Some(keypair) => fee_key_address(keypair, settings.network, false)?,
+                None => fee_wallet.get_new_address()?,
+            },
+            options.overfund.unwrap_or(Amount::ZERO),
+        )?;
+        miner_wallet.mine_blocks(Some(1))?;
+        vault.validate_fee_input_live(&client, &fee_paying_utxo, &fee_paying_output)?;
+        let trigger_tx = match options.overfund {
+            Some(_) => vault
+                .create_trigger_tx_with_change(
+                    &[(fee_paying_utxo, fee_paying_output.clone())],
+                    &std::iter::once(withdrawal_address.clone())
+                        .chain(vault.marker_addresses()?)
+                        .collect::<Vec<_>>(),
+                    &fee_wallet.get_new_address()?,
+                    path_fee_rate(settings, SpendPath::Trigger),
+                )?
+                .into_transaction(),
+            None => session.create_path_tx(
+                SpendPath::Trigger,
+                &fee_paying_utxo,
+                fee_paying_output.clone(),
+                Some(&withdrawal_address),
+            )?,
+        };
+        let (prevouts, _) = arrange_inputs(
+            vault.current_txout()?,
+            Some(fee_paying_output.clone()),
+            vault.vault_input_index,
+        );
+        let signed_tx = match &fee_keypair {
+            Some(keypair) => sign_fee_input(&trigger_tx, &prevouts, keypair)?,
+            None => {
+                let psbt = vault.to_psbt(&trigger_tx, std::slice::from_ref(&fee_paying_output))?;
+                finalize_psbt(fee_wallet.sign_psbt(&psbt)?)?
+            }
+        };
+        (trigger_tx, signed_tx, prevouts)
     };
-    let signed_tx = fee_wallet.sign_tx(&trigger_tx)?;
+    log_fee_rate(&signed_tx, &prevouts)?;
+    if options.dry_run {
+        let accepted = validate_with_node(&signed_tx, &client)?;
+        info!(
+            "the node would accept trigger {} (vsize {:?}, fee {:?}), not broadcasting it",
+            signed_tx.txid(),
+            accepted.vsize,
+            accepted.fee
+        );
+        return Ok(());
+    }
     let mut serialized_tx = Vec::new();
     signed_tx.consensus_encode(&mut serialized_tx).unwrap();
     debug!("serialized tx: {:?}", serialized_tx.raw_hex());
@@ -240,7 +1484,7 @@ fn trigger(steal: bool, settings: &Settings) -> Result<()> {
 
     vault.set_current_outpoint(OutPoint { txid, vout: 0 });
     if !steal {
-        vault.set_withdrawal_address(Some(withdrawal_address));
+        vault.set_withdrawal_address(Some(withdrawal_address))?;
         vault.set_trigger_transaction(Some(trigger_tx));
         vault.set_state(Triggered);
     }
@@ -249,19 +1493,143 @@ fn trigger(steal: bool, settings: &Settings) -> Result<()> {
     Ok(())
 }
 
-fn deposit(settings: &Settings) -> Result<()> {
-    if VaultCovenant::from_file(&settings.vault_file).is_ok() {
+fn load_vault(settings: &Settings) -> Result<VaultCovenant> {
+    let mut vault =
+        VaultCovenant::from_file(&settings.vault_file, settings.master_key()?.as_ref())?;
+    vault.set_min_fee_rate(settings.min_fee_rate);
+    vault.set_grind_bounds(settings.grind_bounds);
+    vault.set_min_confirmations(settings.min_confirmations);
+    vault.set_file_key(settings.file_key()?);
+    if vault.get_network() != settings.network {
+        return Err(VaultError::NetworkMismatch {
+            vault: vault.get_network(),
+            settings: settings.network,
+        }
+        .into());
+    }
+    Ok(vault)
+}
+
+/// Fund a fee input big enough for the `path` transaction to pay the fee rate configured for it
+fn fund_fee_input(
+    settings: &Settings,
+    session: &VaultBuildSession,
+    path: SpendPath,
+    destination: Option<&Address>,
+    miner_wallet: &Wallet,
+    fee_wallet: &Wallet,
+) -> Result<(OutPoint, TxOut)> {
+    fund_fee_input_to(
+        settings,
+        session,
+        path,
+        destination,
+        miner_wallet,
+        &fee_wallet.get_new_address()?,
+        Amount::ZERO,
+    )
+}
+
+/// [fund_fee_input], paying the fee input and `extra` on top of it to `fee_paying_address`
+fn fund_fee_input_to(
+    settings: &Settings,
+    session: &VaultBuildSession,
+    path: SpendPath,
+    destination: Option<&Address>,
+    miner_wallet: &Wallet,
+    fee_paying_address: &Address,
+    extra: Amount,
+) -> Result<(OutPoint, TxOut)> {
+    let vault = session.vault();
+    let configured_fee = || {
+        session.fee_input_amount(
+            path,
+            settings.fee_rates.for_path(path),
+            fee_paying_address.script_pubkey(),
+            destination,
+        )
+    };
+    let fee_amount = match settings.fee_conf_target {
+        Some(conf_target) => {
+            let client = Wallet::create_rpc_client(settings, None);
+            match vault.estimate_path_fee(
+                &client,
+                path,
+                conf_target,
+                fee_paying_address.script_pubkey(),
+                destination,
+            ) {
+                Ok(fee) => fee,
+                Err(e) => {
+                    warn!("falling back to the configured fee rate: {}", e);
+                    configured_fee()?
+                }
+            }
+        }
+        None => configured_fee()?,
+    };
+    let fee_amount = fee_amount + extra;
+    let fee_paying_utxo = miner_wallet.send(fee_paying_address, fee_amount)?;
+    Ok((
+        fee_paying_utxo,
+        TxOut {
+            script_pubkey: fee_paying_address.script_pubkey(),
+            value: fee_amount,
+        },
+    ))
+}
+
+/// The fee rate the `path` transaction should pay, from the node's estimate when `fee_conf_target` is set
+fn path_fee_rate(settings: &Settings, path: SpendPath) -> FeeRate {
+    let Some(conf_target) = settings.fee_conf_target else {
+        return settings.fee_rates.for_path(path);
+    };
+    let client = Wallet::create_rpc_client(settings, None);
+    estimate_fee_rate(&client, conf_target).unwrap_or_else(|e| {
+        warn!("falling back to the configured fee rate: {}", e);
+        settings.fee_rates.for_path(path)
+    })
+}
+
+fn log_fee_rate(signed_tx: &Transaction, prevouts: &[TxOut]) -> Result<()> {
+    let fee_rate = effective_fee_rate(signed_tx, prevouts)?;
+    info!(
+        "transaction pays {} sat/vB (vsize {})",
+        fee_rate.to_sat_per_vb_floor(),
+        signed_tx.vsize()
+    );
+    for (index, weight) in input_weight_breakdown(signed_tx, prevouts)? {
+        debug!("input {} weighs {}", index, weight);
+    }
+    Ok(())
+}
+
+fn deposit(
+    settings: &Settings,
+    payout: &[(String, u64)],
+    vault_input_index: usize,
+    trigger_metadata: Option<&[u8]>,
+    trigger_change: Option<(String, u64)>,
+    trigger_fee: Option<Amount>,
+    key_seed: Option<u64>,
+) -> Result<()> {
+    if key_seed.is_some() && settings.seed.is_some() {
+        return Err(anyhow!(
+            "the vault keys come from either --seed or --key-seed, not both"
+        ));
+    }
+    if VaultCovenant::file_exists(&settings.vault_file) {
         info!("Vault already exists. Delete the vault file if you want to start over.");
         return Ok(());
     }
     info!("Getting miner wallet all set up");
-    let miner_wallet = Wallet::new("miner", &settings);
+    let miner_wallet = Wallet::new("miner", settings);
     while miner_wallet.get_balance()? < Amount::from_btc(1.0f64)? {
         debug!("Mining some blocks to get some coins");
         miner_wallet.mine_blocks(Some(1))?;
     }
 
-    let fee_wallet = Wallet::new("fee_payment", &settings);
+    let fee_wallet = Wallet::new("fee_payment", settings);
     while fee_wallet.get_balance()? < Amount::from_sat(50_000) {
         let fee_address = fee_wallet.get_new_address()?;
         miner_wallet.send(&fee_address, Amount::from_sat(10_000))?;
@@ -269,22 +1637,96 @@ fn deposit(settings: &Settings) -> Result<()> {
     }
 
     println!("lets make a vault");
-    let timelock_in_blocks = 20;
+    let timelock = settings.timelock();
     let amount = Amount::from_sat(100_000_000);
-    let mut vault = if &settings.vault_type == "CAT" {
-        VaultCovenant::new(timelock_in_blocks, &settings)?
-    } else {
-        VaultCovenant::new_ctv(timelock_in_blocks, amount, &settings)?
+    let vault_type = match settings.vault_type.as_str() {
+        "CAT" => VaultType::CAT,
+        #[cfg(feature = "op-vault")]
+        "OP_VAULT" => VaultType::OpVault,
+        _ => VaultType::CTV,
     };
-    //let mut vault = VaultCovenant::new(timelock_in_blocks, amount, &settings)?;
 
     info!("depositing into vault");
-    let vault_address = vault.address()?;
-    let deposit_tx = miner_wallet.send(&vault_address, amount)?;
-    vault.set_amount(amount);
-    vault.set_current_outpoint(deposit_tx);
-    info!("deposit txid: {}", deposit_tx.txid);
-    miner_wallet.mine_blocks(Some(1))?;
+    let (source_utxo, source_output) = miner_wallet.get_utxo(amount + Amount::from_sat(10_000))?;
+    let change_address = miner_wallet.get_new_address()?;
+    let payout = parse_outputs(payout, settings.network)?;
+    if key_seed.is_none() && settings.seed.is_none() {
+        warn!("No seed supplied, the vault keys will only exist in the vault file. Pass --seed to derive them from a seed you can back up.");
+    }
+    let mut new_vault = match (key_seed, vault_type) {
+        (Some(key_seed), vault_type) => {
+            warn!("The vault keys come from key seed {}, anyone who knows it can spend the vault. Only use this for tests.", key_seed);
+            let mut vault =
+                VaultCovenant::new_seeded(vault_type, timelock, amount, settings, key_seed)?;
+            if !payout.is_empty() {
+                vault.set_payout(&payout)?;
+            }
+            vault
+        }
+        (None, VaultType::CAT) => VaultCovenant::new(timelock, settings)?,
+        (None, VaultType::CTV) if !payout.is_empty() => {
+            VaultCovenant::new_ctv_multi(timelock, amount, &payout, settings)?
+        }
+        (None, VaultType::CTV) => VaultCovenant::new_ctv(timelock, amount, settings)?,
+        #[cfg(feature = "op-vault")]
+        (None, VaultType::OpVault) => VaultCovenant::new_op_vault(timelock, amount, settings)?,
+    };
+    if let Some((recovery_address, recovery_height)) = settings.recovery()? {
+        // the recovery template commits to the amount, which a CAT vault otherwise only gets when funded
+        new_vault.set_amount(amount);
+        new_vault.set_recovery(recovery_address, recovery_height)?;
+    }
+    if vault_input_index != 0 {
+        new_vault.set_vault_input_index(vault_input_index)?;
+    }
+    if let Some(data) = trigger_metadata {
+        new_vault.set_trigger_metadata(data)?;
+    }
+    if let Some(change) = trigger_change {
+        let (address, amount) = parse_outputs(&[change], settings.network)?.remove(0);
+        new_vault.set_trigger_change(&address, amount)?;
+    }
+    if let Some(fee) = trigger_fee {
+        new_vault.set_trigger_fee(fee)?;
+    }
+    if let Some(cold_internal_key) = settings.cold_internal_key()? {
+        new_vault.set_cold_internal_key(cold_internal_key)?;
+    }
+    let trigger_cosigners = settings.trigger_cosigners()?;
+    if !trigger_cosigners.is_empty() {
+        // every trigger key has to sign unless the settings lower the threshold
+        let threshold = settings
+            .trigger_threshold
+            .unwrap_or(trigger_cosigners.len() + 1);
+        new_vault.set_trigger_cosigners(trigger_cosigners, threshold)?;
+    }
+    if let Some(parity) = settings.output_key_parity()? {
+        new_vault.set_output_key_parity(parity)?;
+    }
+    let (mut vault, funding_tx) = VaultCovenant::create_and_fund(
+        new_vault,
+        amount,
+        source_utxo,
+        source_output,
+        &change_address,
+        FeeRate::from_sat_per_vb_unchecked(2),
+    )?;
+    vault.set_metrics_sink(Arc::new(LogSink));
+    vault.set_fee_reserve(settings.fee_reserve)?;
+    let signed_tx = miner_wallet.sign_tx(&funding_tx)?;
+    let mut serialized_tx = Vec::new();
+    signed_tx.consensus_encode(&mut serialized_tx).unwrap();
+    debug!("serialized tx: {:?}", serialized_tx.raw_hex());
+    let txid = miner_wallet.broadcast_tx(&serialized_tx, None)?;
+    info!("deposit txid: {}", txid);
+    miner_wallet.mine_blocks(Some(settings.min_confirmations.max(1) as u64))?;
+    let client = Wallet::create_rpc_client(settings, None);
+    vault.await_deposit(
+        &client,
+        settings.min_confirmations,
+        Duration::from_secs(30),
+        interrupt_flag()?,
+    )?;
     vault.to_file(&settings.vault_file)?;
 
     Ok(())
@@ -1,20 +1,131 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 
-use anyhow::Result;
-use bitcoin::Network;
+use anyhow::{anyhow, Result};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::Parity;
+use bitcoin::{Address, Amount, FeeRate, Network, XOnlyPublicKey};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
+use crate::vault::fees::PathFeeRates;
+use crate::vault::signature_building::{GrindBounds, MARKER_AMOUNT};
+use crate::vault::timelock::TimelockKind;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) struct Settings {
     pub network: Network,
     pub bitcoin_rpc_username: String,
     pub bitcoin_rpc_password: String,
+    #[serde(default = "default_rpc_host")]
+    pub rpc_host: String,
+    /// The node's RPC port, the network's default port if unset
+    #[serde(default)]
+    pub rpc_port: Option<u16>,
     pub create_wallets: bool,
     pub miner_wallet_name: String,
     pub fee_wallet_name: String,
     pub withdrawal_wallet_name: String,
     pub vault_type: String,
     pub vault_file: Option<String>,
+    /// How many 80 byte chunks the CAT complete script expects the trigger transaction's inputs in.
+    /// Two fits a trigger spending the vault and a segwit fee input.
+    #[serde(default = "default_trigger_input_chunks")]
+    pub trigger_input_chunks: usize,
+    /// How many dust marker outputs a CAT trigger pays, one per eventual withdrawal destination
+    #[serde(default = "default_trigger_markers")]
+    pub trigger_markers: usize,
+    /// What each CAT trigger marker pays its destination, at least the dust limit of every marker destination.
+    /// The fee inputs fund it, the vault output keeps the whole vault amount.
+    #[serde(default = "default_marker_amount")]
+    pub marker_amount: Amount,
+    /// Build CAT vaults whose trigger and cancel can send what's left of their fee inputs back as change.
+    /// The leaves take the change output as one more witness element, so it changes the vault address.
+    #[serde(default)]
+    pub fee_change: bool,
+    /// When set, size fee inputs from the node's estimatesmartfee for this many blocks instead of `fee_rates`
+    #[serde(default)]
+    pub fee_conf_target: Option<u16>,
+    /// The account path each new vault gets its own hardened index under, m/86'/0'/0' on mainnet and
+    /// m/86'/1'/0' on the other networks if unset
+    #[serde(default)]
+    pub derivation_path: Option<String>,
+    /// The index under `derivation_path` the next vault with derived keys gets
+    #[serde(default)]
+    pub next_vault_index: u32,
+    /// Where these settings were read from, for recording the vault indexes handed out
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// A BIP39 mnemonic or BIP32 xprv to derive vault keys from.
+    /// Only ever supplied on the command line, never written to the settings file.
+    #[serde(skip)]
+    pub seed: Option<String>,
+    /// A secret to key the vault file's integrity check with instead of the seed.
+    /// Only ever supplied on the command line, never written to the settings file.
+    #[serde(skip)]
+    pub integrity_secret: Option<String>,
+    #[serde(default)]
+    pub fee_rates: PathFeeRates,
+    /// CAT transactions whose fee inputs can't pay this rate are refused before any grinding
+    #[serde(default = "default_min_fee_rate")]
+    pub min_fee_rate: FeeRate,
+    /// Where CAT signature grinding starts and how many values it tries, to reproduce or resume a grind
+    #[serde(default)]
+    pub grind_bounds: GrindBounds,
+    /// How many confirmations a vault transaction needs before the state it puts the vault in is final
+    #[serde(default = "default_min_confirmations")]
+    pub min_confirmations: u32,
+    /// How much of a new CTV vault's amount to set aside for its complete and cancel fees
+    #[serde(default)]
+    pub fee_reserve: Amount,
+    /// The relative timelock of new vaults, in blocks or 512 second intervals. Unset, it depends on the network,
+    /// see [Settings::timelock].
+    #[serde(default)]
+    pub timelock: Option<TimelockKind>,
+    /// When set to "even" or "odd", new vaults pick a NUMS internal key giving their output key that parity
+    #[serde(default)]
+    pub output_key_parity: Option<String>,
+    /// Where the recovery leaf of new vaults sends the funds once the chain reaches `recovery_height`
+    #[serde(default)]
+    pub recovery_address: Option<String>,
+    #[serde(default)]
+    pub recovery_height: Option<u32>,
+    /// A hex x-only public key to use as the internal key of new vaults instead of a NUMS point,
+    /// letting whoever holds its secret sweep the vault through the key path
+    #[serde(default)]
+    pub cold_internal_key: Option<String>,
+    /// Hex x-only public keys that have to co-sign the triggers of new CAT vaults along with the vault key
+    #[serde(default)]
+    pub trigger_cosigners: Vec<String>,
+    /// How many of the vault key and `trigger_cosigners` have to sign a trigger, all of them if unset
+    #[serde(default)]
+    pub trigger_threshold: Option<usize>,
+}
+
+fn default_min_fee_rate() -> FeeRate {
+    FeeRate::BROADCAST_MIN
+}
+
+fn default_min_confirmations() -> u32 {
+    1
+}
+
+fn default_rpc_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+pub(crate) fn default_trigger_input_chunks() -> usize {
+    2
+}
+
+pub(crate) fn default_trigger_markers() -> usize {
+    1
+}
+
+pub(crate) fn default_marker_amount() -> Amount {
+    MARKER_AMOUNT
 }
 
 impl Settings {
@@ -31,12 +142,36 @@ impl Default for Settings {
             network: Network::Regtest,
             bitcoin_rpc_username: "user".to_string(),
             bitcoin_rpc_password: "password".to_string(),
+            rpc_host: default_rpc_host(),
+            rpc_port: None,
             create_wallets: true,
             miner_wallet_name: "miner".to_string(),
             fee_wallet_name: "fee_payment".to_string(),
             withdrawal_wallet_name: "withdrawal".to_string(),
             vault_type: "CAT".to_string(),
             vault_file: None,
+            trigger_input_chunks: default_trigger_input_chunks(),
+            trigger_markers: default_trigger_markers(),
+            marker_amount: default_marker_amount(),
+            fee_change: false,
+            fee_conf_target: None,
+            derivation_path: None,
+            next_vault_index: 0,
+            path: None,
+            seed: None,
+            integrity_secret: None,
+            fee_rates: PathFeeRates::default(),
+            min_fee_rate: default_min_fee_rate(),
+            grind_bounds: GrindBounds::default(),
+            min_confirmations: default_min_confirmations(),
+            fee_reserve: Amount::ZERO,
+            timelock: None,
+            output_key_parity: None,
+            recovery_address: None,
+            recovery_height: None,
+            cold_internal_key: None,
+            trigger_cosigners: Vec::new(),
+            trigger_threshold: None,
         }
     }
 }
@@ -46,4 +181,233 @@ impl Settings {
         let toml = std::fs::read_to_string(path)?;
         Ok(toml::from_str(&toml)?)
     }
+
+    /// The master key parsed from the supplied seed, if there is one
+    pub(crate) fn master_key(&self) -> Result<Option<Xpriv>> {
+        let Some(seed) = self.seed.as_ref() else {
+            return Ok(None);
+        };
+        let seed = seed.trim();
+        if seed.contains(char::is_whitespace) {
+            let mnemonic =
+                bip39::Mnemonic::parse(seed).map_err(|e| anyhow!("invalid mnemonic: {}", e))?;
+            Ok(Some(Xpriv::new_master(
+                self.network,
+                &mnemonic.to_seed(""),
+            )?))
+        } else {
+            Ok(Some(Xpriv::from_str(seed)?))
+        }
+    }
+
+    /// The key of the vault file's integrity check: derived from the integrity secret if one is supplied,
+    /// otherwise from the seed's master key. None without either, the file is then written unchecked.
+    pub(crate) fn file_key(&self) -> Result<Option<[u8; 32]>> {
+        let secret = match (&self.integrity_secret, self.master_key()?) {
+            (Some(secret), _) => secret.as_bytes().to_vec(),
+            (None, Some(master_key)) => master_key.private_key.secret_bytes().to_vec(),
+            (None, None) => return Ok(None),
+        };
+        let mut engine = HmacEngine::<sha256::Hash>::new(b"simple_covenant_vault file integrity");
+        engine.input(&secret);
+        Ok(Some(Hmac::from_engine(engine).to_byte_array()))
+    }
+
+    /// The relative timelock of new vaults: the configured one, or a day on mainnet, a few hours on the test
+    /// networks and 20 blocks on regtest
+    pub(crate) fn timelock(&self) -> TimelockKind {
+        self.timelock.unwrap_or(match self.network {
+            Network::Bitcoin => TimelockKind::Blocks(144),
+            Network::Testnet | Network::Signet => TimelockKind::Blocks(24),
+            _ => TimelockKind::Blocks(20),
+        })
+    }
+
+    /// The configured RPC port, or the network's default one
+    pub(crate) fn rpc_port(&self) -> u16 {
+        self.rpc_port.unwrap_or(match self.network {
+            Network::Bitcoin => 8332,
+            Network::Testnet => 18332,
+            Network::Regtest => 18443,
+            Network::Signet => 38332,
+            _ => {
+                unreachable!("unsupported network")
+            }
+        })
+    }
+
+    /// The configured account path, or BIP86's first account for the network's coin type
+    pub(crate) fn account_path(&self) -> Result<DerivationPath> {
+        let coin_type = match self.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let path = match &self.derivation_path {
+            Some(path) => DerivationPath::from_str(path)?,
+            None => DerivationPath::from(vec![
+                ChildNumber::from_hardened_idx(86)?,
+                ChildNumber::from_hardened_idx(coin_type)?,
+                ChildNumber::from_hardened_idx(0)?,
+            ]),
+        };
+        if let Some(ChildNumber::Hardened { index }) = path.into_iter().nth(1) {
+            if *index != coin_type {
+                warn!(
+                    "derivation path {} has coin type {}' but {} uses {}'",
+                    path, index, self.network, coin_type
+                );
+            }
+        }
+        Ok(path)
+    }
+
+    /// Hand out the next vault index by bumping `next_vault_index` in the settings file, so no two vaults ever
+    /// share keys. The file is re-read first so command line overrides aren't written to it.
+    pub(crate) fn allocate_vault_index(&self) -> Result<u32> {
+        let path = self.path.as_ref().ok_or(anyhow!(
+            "the settings weren't read from a file, there is nowhere to record the vault index"
+        ))?;
+        let mut stored = Self::from_toml_file(path)?;
+        let index = stored.next_vault_index;
+        stored.next_vault_index = index
+            .checked_add(1)
+            .filter(|next| *next < 1 << 31)
+            .ok_or(anyhow!("every hardened vault index has been handed out"))?;
+        stored.to_toml_file(path)?;
+        Ok(index)
+    }
+
+    /// Where the keys of a new vault are derived: a freshly allocated hardened index under the account path
+    pub(crate) fn new_vault_derivation_path(&self) -> Result<DerivationPath> {
+        let index = self.allocate_vault_index()?;
+        Ok(self
+            .account_path()?
+            .child(ChildNumber::from_hardened_idx(index)?))
+    }
+
+    /// The recovery address and height for new vaults, both or neither have to be set
+    pub(crate) fn recovery(&self) -> Result<Option<(Address, u32)>> {
+        match (self.recovery_address.as_ref(), self.recovery_height) {
+            (None, None) => Ok(None),
+            (Some(address), Some(height)) => Ok(Some((
+                Address::from_str(address)?.require_network(self.network)?,
+                height,
+            ))),
+            _ => Err(anyhow!(
+                "recovery_address and recovery_height have to be set together"
+            )),
+        }
+    }
+
+    pub(crate) fn cold_internal_key(&self) -> Result<Option<XOnlyPublicKey>> {
+        self.cold_internal_key
+            .as_deref()
+            .map(|key| {
+                XOnlyPublicKey::from_str(key)
+                    .map_err(|e| anyhow!("invalid cold internal key {:?}: {}", key, e))
+            })
+            .transpose()
+    }
+
+    pub(crate) fn trigger_cosigners(&self) -> Result<Vec<XOnlyPublicKey>> {
+        self.trigger_cosigners
+            .iter()
+            .map(|key| {
+                XOnlyPublicKey::from_str(key)
+                    .map_err(|e| anyhow!("invalid trigger cosigner {:?}: {}", key, e))
+            })
+            .collect()
+    }
+
+    pub(crate) fn output_key_parity(&self) -> Result<Option<Parity>> {
+        match self.output_key_parity.as_deref() {
+            None => Ok(None),
+            Some("even") => Ok(Some(Parity::Even)),
+            Some("odd") => Ok(Some(Parity::Odd)),
+            Some(other) => Err(anyhow!(
+                "output key parity must be \"even\" or \"odd\", not {:?}",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::contract::{VaultCovenant, VaultType};
+
+    #[test]
+    fn account_path_defaults_to_the_network_coin_type() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.account_path().unwrap().to_string(), "m/86'/1'/0'");
+        settings.network = Network::Bitcoin;
+        assert_eq!(settings.account_path().unwrap().to_string(), "m/86'/0'/0'");
+        settings.derivation_path = Some("m/86'/0'/5'".to_string());
+        assert_eq!(settings.account_path().unwrap().to_string(), "m/86'/0'/5'");
+    }
+
+    #[test]
+    fn vault_indexes_are_handed_out_once() {
+        let dir = std::env::temp_dir().join(format!("scv-test-{}-settings", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.toml");
+        Settings::default().to_toml_file(&path).unwrap();
+        let mut settings = Settings::from_toml_file(&path).unwrap();
+        settings.path = Some(path.clone());
+        settings.network = Network::Signet;
+
+        assert_eq!(settings.allocate_vault_index().unwrap(), 0);
+        assert_eq!(settings.allocate_vault_index().unwrap(), 1);
+        assert_eq!(
+            settings.new_vault_derivation_path().unwrap().to_string(),
+            "m/86'/1'/0'/2'"
+        );
+        let stored = Settings::from_toml_file(&path).unwrap();
+        assert_eq!(stored.next_vault_index, 3);
+        assert_eq!(stored.network, Network::Regtest);
+    }
+
+    #[test]
+    fn vaults_inherit_custom_defaults() {
+        let dir = std::env::temp_dir().join(format!("scv-test-{}-defaults", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.toml");
+        Settings {
+            timelock: Some(TimelockKind::Blocks(50)),
+            marker_amount: Amount::from_sat(1_000),
+            rpc_host: "10.0.0.2".to_string(),
+            rpc_port: Some(18000),
+            ..Settings::default()
+        }
+        .to_toml_file(&path)
+        .unwrap();
+        let settings = Settings::from_toml_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(settings.rpc_host, "10.0.0.2");
+        assert_eq!(settings.rpc_port(), 18000);
+
+        let vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            settings.timelock(),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        assert_eq!(vault.timelock, TimelockKind::Blocks(50));
+        assert_eq!(vault.marker_amount(), Amount::from_sat(1_000));
+    }
+
+    #[test]
+    fn unset_timelock_and_port_follow_the_network() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.timelock(), TimelockKind::Blocks(20));
+        assert_eq!(settings.rpc_port(), 18443);
+        settings.network = Network::Bitcoin;
+        assert_eq!(settings.timelock(), TimelockKind::Blocks(144));
+        assert_eq!(settings.rpc_port(), 8332);
+        settings.network = Network::Signet;
+        assert_eq!(settings.timelock(), TimelockKind::Blocks(24));
+    }
 }
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+
+use crate::vault::contract::{VaultCovenant, VaultState};
+use crate::vault::timelock::{TimelockKind, SECONDS_PER_INTERVAL};
+
+/// Which spend paths the vault can take right now, for a front-end deciding what to offer
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub(crate) struct AvailableActions {
+    pub(crate) trigger: bool,
+    pub(crate) complete: bool,
+    /// blocks left until the complete's relative timelock expires, estimated at 600 seconds a block for a
+    /// time-based timelock. None unless the vault is triggered.
+    pub(crate) complete_in_blocks: Option<u32>,
+    pub(crate) cancel: bool,
+}
+
+impl VaultCovenant {
+    /// Check the vault outpoint against the node and work out which actions a spend of it would be accepted for.
+    /// Nothing is available once the outpoint is spent, the vault file has to be synced with the chain first.
+    pub(crate) fn available_actions(&self, client: &Client) -> Result<AvailableActions> {
+        if self.get_state() == VaultState::Completed || !self.is_funded() {
+            return Ok(AvailableActions::default());
+        }
+        let outpoint = self.get_current_outpoint()?;
+        let Some(utxo) = client.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))? else {
+            return Ok(AvailableActions::default());
+        };
+        self.unspent_actions(utxo.confirmations, || {
+            let tip = client.get_block_header_info(&utxo.bestblock)?;
+            // BIP68 counts from the median time past of the block before the one confirming the trigger
+            let trigger_height = tip.height as u64 + 1 - utxo.confirmations as u64;
+            let before_trigger =
+                client.get_block_header_info(&client.get_block_hash(trigger_height - 1)?)?;
+            Ok(median_time(tip.median_time)?
+                .saturating_sub(median_time(before_trigger.median_time)?))
+        })
+    }
+
+    /// The actions open to the vault while its outpoint is unspent with `confirmations`. `median_time_elapsed`
+    /// gives the seconds of median time past the trigger has aged, it is only asked for a confirmed trigger
+    /// behind a time-based timelock.
+    fn unspent_actions(
+        &self,
+        confirmations: u32,
+        median_time_elapsed: impl FnOnce() -> Result<u64>,
+    ) -> Result<AvailableActions> {
+        if self.get_state() == VaultState::Inactive {
+            return Ok(AvailableActions {
+                trigger: true,
+                ..Default::default()
+            });
+        }
+        let complete_in_blocks = match self.timelock {
+            TimelockKind::Blocks(blocks) => (blocks as u32).saturating_sub(confirmations),
+            // an unconfirmed trigger has no median time past to count from yet
+            TimelockKind::Seconds(_) if confirmations == 0 => self.timelock.approx_blocks() as u32,
+            TimelockKind::Seconds(intervals) => {
+                let remaining = (intervals as u64 * SECONDS_PER_INTERVAL)
+                    .saturating_sub(median_time_elapsed()?);
+                remaining.div_ceil(600) as u32
+            }
+        };
+        Ok(AvailableActions {
+            trigger: false,
+            complete: complete_in_blocks == 0,
+            complete_in_blocks: Some(complete_in_blocks),
+            cancel: true,
+        })
+    }
+}
+
+fn median_time(median_time: Option<usize>) -> Result<u64> {
+    median_time
+        .map(|time| time as u64)
+        .ok_or(anyhow!("the node didn't report a median time past"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::VaultType;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, Txid};
+
+    fn vault(timelock: TimelockKind, state: VaultState) -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CTV,
+            timelock,
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault.set_state(state);
+        vault
+    }
+
+    fn no_median_time() -> Result<u64> {
+        panic!("a block-based timelock doesn't need the median time past")
+    }
+
+    #[test]
+    fn inactive_vault_can_only_be_triggered() {
+        let vault = vault(TimelockKind::Blocks(10), VaultState::Inactive);
+        assert_eq!(
+            vault.unspent_actions(3, no_median_time).unwrap(),
+            AvailableActions {
+                trigger: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn complete_opens_exactly_timelock_blocks_after_the_trigger_confirms() {
+        let vault = vault(TimelockKind::Blocks(10), VaultState::Triggered);
+        for confirmations in 0..10 {
+            let actions = vault
+                .unspent_actions(confirmations, no_median_time)
+                .unwrap();
+            assert!(!actions.complete && actions.cancel && !actions.trigger);
+            assert_eq!(actions.complete_in_blocks, Some(10 - confirmations));
+        }
+        let actions = vault.unspent_actions(10, no_median_time).unwrap();
+        assert_eq!(
+            actions,
+            AvailableActions {
+                trigger: false,
+                complete: true,
+                complete_in_blocks: Some(0),
+                cancel: true,
+            }
+        );
+    }
+
+    #[test]
+    fn time_based_complete_counts_down_the_median_time_past() {
+        let vault = vault(TimelockKind::Seconds(2), VaultState::Triggered);
+        let timelock = 2 * SECONDS_PER_INTERVAL;
+        // an unconfirmed trigger can only be estimated from the timelock
+        let actions = vault.unspent_actions(0, no_median_time).unwrap();
+        assert_eq!(
+            actions.complete_in_blocks,
+            Some(vault.timelock.approx_blocks() as u32)
+        );
+        let actions = vault.unspent_actions(4, || Ok(timelock - 601)).unwrap();
+        assert_eq!(actions.complete_in_blocks, Some(2));
+        assert!(!actions.complete);
+        let actions = vault.unspent_actions(4, || Ok(timelock)).unwrap();
+        assert!(actions.complete);
+    }
+
+    #[test]
+    fn unfunded_vault_has_no_actions() {
+        let vault = VaultCovenant::new_seeded(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        // it doesn't get as far as asking the node
+        let client =
+            bitcoincore_rpc::Client::new("http://127.0.0.1:1", bitcoincore_rpc::Auth::None)
+                .unwrap();
+        assert_eq!(
+            vault.available_actions(&client).unwrap(),
+            AvailableActions::default()
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Result};
+use bitcoin::hex::{Case, DisplayHex};
+use bitcoin::taproot::LeafVersion;
+use bitcoin::{Network, TapLeafHash};
+use serde::Serialize;
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::timelock::TimelockKind;
+
+/// Timelocks shorter than roughly a day of blocks leave little time to notice and cancel a theft
+pub(crate) const MIN_SAFE_TIMELOCK_BLOCKS: u16 = 144;
+
+/// A summary of a vault's security properties for reviewers
+#[derive(Serialize, Debug)]
+pub(crate) struct SecurityReport {
+    pub(crate) vault_type: VaultType,
+    pub(crate) network: Network,
+    pub(crate) address: String,
+    pub(crate) key_derivation: String,
+    pub(crate) internal_key_scheme: String,
+    pub(crate) output_key_parity: String,
+    pub(crate) timelock: TimelockKind,
+    /// the timelock is shorter than [MIN_SAFE_TIMELOCK_BLOCKS]
+    pub(crate) short_timelock: bool,
+    /// cancelling sends the funds back into a vault the same (possibly compromised) trigger key can spend
+    pub(crate) cancel_rearms_hot_key: bool,
+    /// there is no path that moves the funds somewhere safe without going through trigger and complete
+    pub(crate) no_recovery_path: bool,
+    /// the opcodes each leaf needs that mainnet doesn't enforce
+    pub(crate) leaf_opcode_requirements: Vec<(String, Vec<&'static str>)>,
+}
+
+/// The deposit addresses a vault's keys and parameters produce under each vault type
+#[derive(Serialize, Debug)]
+pub(crate) struct TypeComparison {
+    pub(crate) cat_address: String,
+    pub(crate) cat_leaf_count: usize,
+    pub(crate) ctv_address: String,
+    pub(crate) ctv_leaf_count: usize,
+}
+
+/// One tapleaf as it appears on chain, hex encoded for diffing against a block explorer
+#[derive(Serialize, Debug)]
+pub(crate) struct LeafDump {
+    pub(crate) leaf: &'static str,
+    /// `deposit` for the tree the vault address commits to, `triggered` for the output a CTV trigger creates
+    pub(crate) tree: &'static str,
+    pub(crate) script: String,
+    pub(crate) leaf_hash: String,
+    pub(crate) leaf_version: u8,
+    pub(crate) control_block: String,
+}
+
+impl VaultCovenant {
+    /// Each tapleaf's script, leaf hash and the control block spending it from the tree it sits in
+    pub(crate) fn dump_leaves(&self) -> Result<Vec<LeafDump>> {
+        let trees = [
+            ("deposit", self.deposit_spend_info()?),
+            ("triggered", self.triggered_spend_info()?),
+        ];
+        self.named_leaves()?
+            .into_iter()
+            .map(|(leaf, script)| {
+                let script_ver = (script, LeafVersion::TapScript);
+                let (tree, control_block) = trees
+                    .iter()
+                    .find_map(|(tree, spend_info)| {
+                        Some((*tree, spend_info.control_block(&script_ver)?))
+                    })
+                    .ok_or(anyhow!("the {} leaf is in none of the vault's trees", leaf))?;
+                let (script, leaf_version) = script_ver;
+                Ok(LeafDump {
+                    leaf,
+                    tree,
+                    leaf_hash: TapLeafHash::from_script(&script, leaf_version).to_string(),
+                    script: script.as_bytes().to_hex_string(Case::Lower),
+                    leaf_version: leaf_version.to_consensus(),
+                    control_block: control_block.serialize().to_hex_string(Case::Lower),
+                })
+            })
+            .collect()
+    }
+
+    /// Compare the CAT and CTV deposit addresses for this vault's keys, timelock, amount and network.
+    /// The addresses differ because the CAT tree holds the trigger, complete and cancel leaves while
+    /// the CTV deposit tree holds a single leaf committing to the trigger template.
+    pub(crate) fn compare_types(&self) -> Result<TypeComparison> {
+        let cat = self.counterpart(VaultType::CAT);
+        let ctv = self.counterpart(VaultType::CTV);
+        Ok(TypeComparison {
+            cat_address: cat.address()?.to_string(),
+            cat_leaf_count: cat.deposit_leaf_count()?,
+            ctv_address: ctv.address()?.to_string(),
+            ctv_leaf_count: ctv.deposit_leaf_count()?,
+        })
+    }
+
+    pub(crate) fn security_report(&self) -> Result<SecurityReport> {
+        Ok(SecurityReport {
+            vault_type: self.get_type(),
+            network: self.get_network(),
+            address: self.address()?.to_string(),
+            key_derivation: match self.derivation() {
+                Some((path, true)) => format!(
+                    "derived from the seed as the hardened children {}/0', 1' and 2'",
+                    path
+                ),
+                Some((path, false)) => format!(
+                    "derived from the seed as the normal children {}/0, 1 and 2, one leaked key and the xpub give away the others",
+                    path
+                ),
+                None => {
+                    "random keypairs generated at vault creation, stored only in the vault file"
+                        .to_string()
+                }
+            },
+            internal_key_scheme: match self.cold_internal_key() {
+                Some(key) => format!("cold key {}, key path spendable by its holder", key),
+                None => format!(
+                    "NUMS point: x-only lift of sha256(G uncompressed) plus {}G, key path disabled",
+                    self.nums_offset()
+                ),
+            },
+            output_key_parity: format!("{:?}", self.output_key_parity()?),
+            timelock: self.timelock,
+            short_timelock: self.timelock.approx_blocks() < MIN_SAFE_TIMELOCK_BLOCKS as u64,
+            // only the CAT cancel leaf pins the destination to the vault's own address, a CTV cancel can rotate
+            // to new keys and an OP_VAULT recovery pays the cancel key
+            cancel_rearms_hot_key: self.get_type() == VaultType::CAT,
+            no_recovery_path: self.recovery_height.is_none(),
+            leaf_opcode_requirements: self.leaf_opcode_requirements()?,
+        })
+    }
+}
+
+impl SecurityReport {
+    /// Human readable descriptions of each risk flagged in the report
+    pub(crate) fn risks(&self) -> Vec<String> {
+        let mut risks = Vec::new();
+        if self.short_timelock {
+            risks.push(format!(
+                "timelock of {} is shorter than the recommended {} blocks",
+                self.timelock, MIN_SAFE_TIMELOCK_BLOCKS
+            ));
+        }
+        if self.cancel_rearms_hot_key {
+            risks.push("cancelling re-vaults to the same keys, a stolen trigger key can keep re-triggering".to_string());
+        }
+        if self.no_recovery_path {
+            risks.push("there is no recovery path to cold storage".to_string());
+        }
+        for (leaf, opcodes) in &self.leaf_opcode_requirements {
+            if !opcodes.is_empty() {
+                risks.push(format!(
+                    "the {} leaf relies on {}, which mainnet doesn't enforce",
+                    leaf,
+                    opcodes.join(", ")
+                ));
+            }
+        }
+        risks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use bitcoin::Amount;
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    fn report(vault_type: VaultType) -> SecurityReport {
+        seeded_vault(vault_type).security_report().unwrap()
+    }
+
+    #[test]
+    fn report_reflects_the_vault() {
+        let cat = report(VaultType::CAT);
+        assert!(cat.cancel_rearms_hot_key);
+        assert!(cat.key_derivation.starts_with("random keypairs"));
+        assert!(!report(VaultType::CTV).cancel_rearms_hot_key);
+    }
+
+    #[test]
+    fn cat_and_ctv_addresses_differ() {
+        let vault = seeded_vault(VaultType::CAT);
+        let comparison = vault.compare_types().unwrap();
+        assert_ne!(comparison.cat_address, comparison.ctv_address);
+        assert_eq!(comparison.cat_address, vault.address().unwrap().to_string());
+        assert_eq!(comparison.cat_leaf_count, 3);
+        assert_eq!(comparison.ctv_leaf_count, 1);
+    }
+
+    #[test]
+    fn dumped_leaf_hashes_match_their_scripts() {
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let dump = seeded_vault(vault_type).dump_leaves().unwrap();
+            assert!(!dump.is_empty());
+            for leaf in dump {
+                let script = bitcoin::ScriptBuf::from_hex(&leaf.script).unwrap();
+                let expected = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+                assert_eq!(leaf.leaf_hash, expected.to_string(), "{} leaf", leaf.leaf);
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::taproot::LeafVersion;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, FeeRate, OutPoint, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxIn, TxOut,
+};
+
+use crate::vault::contract::{sign_transaction_input, SpendPath, VaultCovenant, VaultType};
+use crate::vault::fees::signed_fee;
+use crate::vault::timelock::{csv_sequence, require_csv_satisfied};
+
+/// Build one transaction spending the triggered outputs of several vaults to `outputs`, with an optional
+/// fee input last. Each vault input is signed for its own path. Only CTV complete and cancel spends can be
+/// batched, the CAT leaves and the CTV trigger template commit to the exact shape of their transaction.
+pub(crate) fn spend_many(
+    spends: &[(&VaultCovenant, SpendPath)],
+    fee_input: Option<(&OutPoint, TxOut)>,
+    outputs: &[TxOut],
+) -> Result<Transaction> {
+    if spends.is_empty() {
+        return Err(anyhow!("no vaults to spend"));
+    }
+    let mut seen = HashSet::new();
+    let mut input = Vec::with_capacity(spends.len() + 1);
+    let mut prevouts = Vec::with_capacity(spends.len() + 1);
+    for (vault, path) in spends {
+        if vault.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "a {:?} vault can't be batched, its leaves fix the input index and outputs",
+                vault.get_type()
+            ));
+        }
+        if !vault.payout.is_empty() {
+            return Err(anyhow!(
+                "a vault with a committed payout can't be batched, its template fixes the transaction"
+            ));
+        }
+        let sequence = match path {
+            SpendPath::Complete => csv_sequence(vault.timelock)?,
+            SpendPath::Cancel => Default::default(),
+            SpendPath::Trigger => {
+                return Err(anyhow!(
+                    "a trigger can't be batched, the CTV template fixes its inputs"
+                ))
+            }
+        };
+        let previous_output = vault.get_current_outpoint()?;
+        if !seen.insert(previous_output) {
+            return Err(anyhow!("{} is spent twice", previous_output));
+        }
+        input.push(TxIn {
+            previous_output,
+            sequence,
+            ..Default::default()
+        });
+        prevouts.push(vault.triggered_output()?);
+    }
+    if let Some((fee_paying_utxo, fee_paying_output)) = fee_input {
+        input.push(TxIn {
+            previous_output: *fee_paying_utxo,
+            ..Default::default()
+        });
+        prevouts.push(fee_paying_output);
+    }
+    let mut txn = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output: outputs.to_vec(),
+    };
+
+    for (index, (vault, path)) in spends.iter().enumerate() {
+        let ((script, control_block), keypair) = match path {
+            SpendPath::Complete => {
+                require_csv_satisfied(&txn, index, vault.timelock)?;
+                (vault.ctv_complete_leaf()?, vault.withdraw_keypair())
+            }
+            _ => (vault.cancel_leaf()?, vault.cancel_keypair()),
+        };
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        let sig = sign_transaction_input(
+            &txn,
+            index,
+            &prevouts,
+            leaf_hash,
+            keypair,
+            TapSighashType::All,
+        )?;
+        let witness = &mut txn.input[index].witness;
+        witness.push(sig);
+        witness.push(script.to_bytes());
+        witness.push(control_block.serialize());
+    }
+    #[cfg(feature = "verify")]
+    for index in 0..spends.len() {
+        crate::vault::interpreter::verify_input(&txn, index, &prevouts)?;
+    }
+
+    Ok(txn)
+}
+
+/// The fee input value a `spend_many` transaction needs to pay `fee_rate` with no change,
+/// sized from a dry run with a placeholder fee input paying to `fee_script_pubkey`
+pub(crate) fn spend_many_fee(
+    spends: &[(&VaultCovenant, SpendPath)],
+    fee_script_pubkey: ScriptBuf,
+    outputs: &[TxOut],
+    fee_rate: FeeRate,
+) -> Result<Amount> {
+    // big enough that the dry run never pays out more than it spends
+    let placeholder = TxOut {
+        script_pubkey: fee_script_pubkey,
+        value: Amount::MAX_MONEY,
+    };
+    let dry_run = spend_many(spends, Some((&OutPoint::null(), placeholder)), outputs)?;
+    let vault_value = spends
+        .iter()
+        .map(|(vault, _)| vault.get_amount())
+        .sum::<Amount>();
+    let output_value = outputs.iter().map(|output| output.value).sum::<Amount>();
+    let shortfall = output_value
+        .checked_sub(vault_value)
+        .unwrap_or(Amount::ZERO);
+    let surplus = vault_value
+        .checked_sub(output_value)
+        .unwrap_or(Amount::ZERO);
+    let fee = signed_fee(&dry_run, fee_rate)? + shortfall;
+    Ok(fee.checked_sub(surplus).unwrap_or(Amount::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+
+    fn triggered_vault(vault_type: VaultType, seed: u64) -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            seed,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([seed as u8; 32]), 0));
+        vault
+    }
+
+    #[test]
+    fn cancels_of_two_vaults_spend_together() {
+        let first = triggered_vault(VaultType::CTV, 7);
+        let second = triggered_vault(VaultType::CTV, 8);
+        let output = TxOut {
+            script_pubkey: first.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(150_000),
+        };
+        let spends = [(&first, SpendPath::Cancel), (&second, SpendPath::Cancel)];
+        let txn = spend_many(&spends, None, std::slice::from_ref(&output)).unwrap();
+        assert_eq!(txn.input.len(), 2);
+        for (index, vault) in [&first, &second].into_iter().enumerate() {
+            assert_eq!(
+                txn.input[index].previous_output,
+                vault.get_current_outpoint().unwrap()
+            );
+            let (cancel_script, _) = vault.cancel_leaf().unwrap();
+            assert_eq!(
+                txn.input[index].witness.nth(1).unwrap(),
+                cancel_script.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn cat_cancel_is_refused_in_a_batch() {
+        // the CAT cancel leaf fixes its input index and outputs, so it can't share a transaction
+        let cat = triggered_vault(VaultType::CAT, 7);
+        let ctv = triggered_vault(VaultType::CTV, 8);
+        let output = TxOut {
+            script_pubkey: ctv.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(150_000),
+        };
+        let spends = [(&cat, SpendPath::Cancel), (&ctv, SpendPath::Cancel)];
+        let error = spend_many(&spends, None, &[output]).unwrap_err();
+        assert!(error.to_string().contains("can't be batched"), "{}", error);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Amount, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::json::TestMempoolAcceptResult;
+use bitcoincore_rpc::jsonrpc::error::RpcError;
+use bitcoincore_rpc::{jsonrpc, Client, RpcApi};
+use log::info;
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultState};
+use crate::vault::sync::require_final;
+
+/// Why the node refused a vault transaction, for the rejections a caller can do something about
+#[derive(Debug)]
+pub(crate) enum BroadcastError {
+    /// pays less than the minimum relay or mempool fee, bump the fee input and rebuild
+    FeeTooLow(String),
+    /// an input is already spent or not known to the node yet
+    MissingInputs(String),
+    /// a timelock hasn't expired, wait for more blocks and broadcast again
+    NonFinal(String),
+    Rejected(i32, String),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastError::FeeTooLow(message) => write!(f, "fee too low: {}", message),
+            BroadcastError::MissingInputs(message) => write!(f, "missing inputs: {}", message),
+            BroadcastError::NonFinal(message) => write!(f, "timelock not expired: {}", message),
+            BroadcastError::Rejected(code, message) => {
+                write!(f, "rejected with code {}: {}", code, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+/// The code bitcoind rejects a transaction that fails validation or policy with
+const RPC_VERIFY_REJECTED: i32 = -26;
+
+impl From<&RpcError> for BroadcastError {
+    fn from(error: &RpcError) -> Self {
+        BroadcastError::classify(error.code, error.message.clone())
+    }
+}
+
+impl BroadcastError {
+    fn classify(code: i32, message: String) -> Self {
+        if message.contains("non-final") || message.contains("non-BIP68-final") {
+            BroadcastError::NonFinal(message)
+        } else if message.contains("fee") {
+            BroadcastError::FeeTooLow(message)
+        } else if message.contains("missingorspent") || message.contains("Missing inputs") {
+            BroadcastError::MissingInputs(message)
+        } else {
+            BroadcastError::Rejected(code, message)
+        }
+    }
+}
+
+/// What the node computed for a transaction it would accept
+pub(crate) struct MempoolAcceptance {
+    pub(crate) vsize: Option<u64>,
+    pub(crate) fee: Option<Amount>,
+}
+
+/// Ask the node whether it would accept `txn` into its mempool, fee and standardness policy included, without
+/// broadcasting it. A rejection comes back as a [BroadcastError] carrying the node's reject reason.
+pub(crate) fn validate_with_node(txn: &Transaction, client: &Client) -> Result<MempoolAcceptance> {
+    let result = client
+        .test_mempool_accept(&[txn])?
+        .pop()
+        .ok_or(anyhow!("the node returned no result for {}", txn.txid()))?;
+    mempool_acceptance(result)
+}
+
+/// The node's verdict from one testmempoolaccept result, a rejection as a [BroadcastError]
+fn mempool_acceptance(result: TestMempoolAcceptResult) -> Result<MempoolAcceptance> {
+    if !result.allowed {
+        let reason = result
+            .reject_reason
+            .unwrap_or_else(|| "no reason given".to_string());
+        return Err(BroadcastError::classify(RPC_VERIFY_REJECTED, reason).into());
+    }
+    Ok(MempoolAcceptance {
+        vsize: result.vsize,
+        fee: result.fees.map(|fees| fees.base),
+    })
+}
+
+impl VaultCovenant {
+    /// Send the signed `path` transaction to the node and move the vault onto its first output.
+    /// A trigger is recorded so the complete can be built from it. Node rejections come back as a [BroadcastError].
+    pub(crate) fn broadcast(
+        &mut self,
+        txn: &Transaction,
+        path: SpendPath,
+        client: &Client,
+    ) -> Result<Txid> {
+        require_final(client, txn)?;
+        let txid = match client.send_raw_transaction(txn) {
+            Ok(txid) => txid,
+            Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(error))) => {
+                return Err(BroadcastError::from(&error).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        info!("sent {:?} transaction txid: {}", path, txid);
+
+        self.set_current_outpoint(OutPoint { txid, vout: 0 });
+        match path {
+            SpendPath::Trigger => {
+                self.set_trigger_transaction(Some(txn.clone()));
+                self.set_state(VaultState::Triggered);
+            }
+            SpendPath::Complete => self.complete_with(txid),
+            // an OP_VAULT recovery pays the cancel key outside the vault
+            #[cfg(feature = "op-vault")]
+            SpendPath::Cancel if self.get_type() == crate::vault::contract::VaultType::OpVault => {
+                self.complete_with(txid)
+            }
+            SpendPath::Cancel => self.set_state(VaultState::Inactive),
+        }
+        Ok(txid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::jsonrpc::serde_json;
+
+    const TXID: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    /// A testmempoolaccept result as bitcoind returns it
+    fn node_result(json: &str) -> TestMempoolAcceptResult {
+        serde_json::from_str(&json.replace("TXID", TXID)).unwrap()
+    }
+
+    #[test]
+    fn accepted_trigger_reports_the_nodes_vsize_and_fee() {
+        let accepted = mempool_acceptance(node_result(
+            r#"{"txid": "TXID", "allowed": true, "vsize": 213, "fees": {"base": 0.00000426}}"#,
+        ))
+        .unwrap();
+        assert_eq!(accepted.vsize, Some(213));
+        assert_eq!(accepted.fee, Some(Amount::from_sat(426)));
+    }
+
+    #[test]
+    fn under_fee_trigger_is_rejected_as_fee_too_low() {
+        let error = mempool_acceptance(node_result(
+            r#"{"txid": "TXID", "allowed": false, "reject-reason": "min relay fee not met, 100 < 213"}"#,
+        ))
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<BroadcastError>(),
+            Some(BroadcastError::FeeTooLow(reason)) if reason == "min relay fee not met, 100 < 213"
+        ));
+
+        let error = mempool_acceptance(node_result(
+            r#"{"txid": "TXID", "allowed": false, "reject-reason": "scriptpubkey"}"#,
+        ))
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<BroadcastError>(),
+            Some(BroadcastError::Rejected(RPC_VERIFY_REJECTED, reason)) if reason == "scriptpubkey"
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Amount};
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultState};
+
+/// Fills in a vault and checks it has everything a spend path needs before any fee input is funded,
+/// instead of the first missing field surfacing deep inside a builder
+pub(crate) struct VaultCovenantBuilder {
+    vault: VaultCovenant,
+    /// the first setter that failed, reported by the build
+    error: Option<anyhow::Error>,
+}
+
+impl VaultCovenantBuilder {
+    pub(crate) fn new(vault: VaultCovenant) -> Self {
+        Self { vault, error: None }
+    }
+
+    pub(crate) fn withdrawal_address(mut self, address: Address) -> Self {
+        if let Err(e) = self.vault.set_withdrawal_address(Some(address)) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// The vault, once it has everything a `path` transaction needs. Otherwise the error lists all that is missing.
+    pub(crate) fn build_for(self, path: SpendPath) -> Result<VaultCovenant> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let vault = self.vault;
+        let mut missing = Vec::new();
+        if vault.get_current_outpoint().is_err() {
+            missing.push("the current outpoint");
+        }
+        if vault.get_amount() == Amount::ZERO {
+            missing.push("the amount");
+        }
+        match path {
+            SpendPath::Trigger => {
+                if vault.get_withdrawal_address().is_err() {
+                    missing.push("the withdrawal address");
+                }
+            }
+            SpendPath::Complete => {
+                if vault.get_state() != VaultState::Triggered {
+                    missing.push("a triggered withdrawal");
+                }
+                if vault.get_withdrawal_address().is_err() {
+                    missing.push("the withdrawal address");
+                }
+                if vault.get_trigger_transaction().is_err() {
+                    missing.push("the trigger transaction");
+                }
+            }
+            // the watchtower prepares a cancel before anything is triggered
+            SpendPath::Cancel => {}
+        }
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "the vault can't build a {:?} transaction, it is missing {}",
+                path,
+                missing.join(", ")
+            ));
+        }
+        Ok(vault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultType, SECP};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    fn test_address(byte: u8, network: Network) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, network)
+    }
+
+    fn seeded_vault() -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    fn funded_vault() -> VaultCovenant {
+        let mut vault = seeded_vault();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    #[test]
+    fn trigger_without_a_withdrawal_address_is_refused() {
+        let error = VaultCovenantBuilder::new(funded_vault())
+            .build_for(SpendPath::Trigger)
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .ends_with("missing the withdrawal address"),
+            "{}",
+            error
+        );
+        VaultCovenantBuilder::new(funded_vault())
+            .withdrawal_address(test_address(2, Network::Regtest))
+            .build_for(SpendPath::Trigger)
+            .unwrap();
+    }
+
+    #[test]
+    fn every_missing_field_is_listed() {
+        let error = VaultCovenantBuilder::new(seeded_vault())
+            .build_for(SpendPath::Trigger)
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .ends_with("missing the current outpoint, the withdrawal address"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn withdrawal_address_from_another_network_fails_the_build() {
+        assert!(VaultCovenantBuilder::new(funded_vault())
+            .withdrawal_address(test_address(2, Network::Testnet))
+            .build_for(SpendPath::Trigger)
+            .is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Transaction, TxOut};
+use bitcoincore_rpc::jsonrpc::serde_json;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::vault::contract::{SpendPath, VaultCovenant};
+use crate::vault::fees::fee_paid;
+
+/// A built vault transaction saved with the outputs it spends, so it can be checked again
+/// later without the vault file. The vault input is always input 0.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct SignedTxBundle {
+    pub(crate) tx: Transaction,
+    /// the outputs spent by each input, in input order
+    pub(crate) prevouts: Vec<TxOut>,
+    pub(crate) path: SpendPath,
+    /// the address of the vault output the transaction spends
+    pub(crate) vault_address: String,
+}
+
+impl VaultCovenant {
+    /// Bundle the `path` transaction with the current vault output and the outputs spent by its other inputs
+    pub(crate) fn tx_bundle(
+        &self,
+        txn: &Transaction,
+        path: SpendPath,
+        other_prevouts: &[TxOut],
+    ) -> Result<SignedTxBundle> {
+        let vault_output = self.current_txout()?;
+        let vault_address = Address::from_script(&vault_output.script_pubkey, self.get_network())?;
+        Ok(SignedTxBundle {
+            tx: txn.clone(),
+            prevouts: std::iter::once(vault_output)
+                .chain(other_prevouts.iter().cloned())
+                .collect(),
+            path,
+            vault_address: vault_address.to_string(),
+        })
+    }
+}
+
+impl SignedTxBundle {
+    pub(crate) fn save_tx(&self, filename: &str) -> Result<()> {
+        info!(
+            "writing {:?} transaction bundle to file: {}",
+            self.path, filename
+        );
+        std::fs::write(filename, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn load_tx(filename: &str) -> Result<Self> {
+        info!("reading transaction bundle from file: {}", filename);
+        Ok(serde_json::from_slice(&std::fs::read(filename)?)?)
+    }
+
+    /// Check the transaction against its stored prevouts: one per input, the vault input spending
+    /// the vault address, and no more value out than in. With the `verify` feature the vault
+    /// input's script is run as well.
+    pub(crate) fn verify(&self) -> Result<()> {
+        fee_paid(&self.tx, &self.prevouts)?;
+        let vault_address = Address::from_str(&self.vault_address)?.assume_checked();
+        let vault_prevout = self
+            .prevouts
+            .first()
+            .ok_or(anyhow!("transaction has no vault input"))?;
+        if vault_prevout.script_pubkey != vault_address.script_pubkey() {
+            return Err(anyhow!(
+                "input 0 spends {}, not the vault address {}",
+                vault_prevout.script_pubkey,
+                vault_address
+            ));
+        }
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&self.tx, 0, &self.prevouts)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultType, SECP};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Amount, Network, OutPoint, Txid};
+
+    #[test]
+    fn loaded_bundle_verifies_against_its_prevouts() {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[9; 32]).unwrap();
+        let address = Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest);
+        let fee_output = TxOut {
+            script_pubkey: address.script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault
+            .create_trigger_tx(
+                &[(
+                    OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+                    fee_output.clone(),
+                )],
+                &[address],
+            )
+            .unwrap()
+            .transaction;
+        let bundle = vault
+            .tx_bundle(&trigger_tx, SpendPath::Trigger, &[fee_output])
+            .unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("scv-test-{}-bundle.json", std::process::id()));
+        let filename = path.to_str().unwrap();
+        bundle.save_tx(filename).unwrap();
+        let loaded = SignedTxBundle::load_tx(filename);
+        std::fs::remove_file(&path).unwrap();
+        let mut loaded = loaded.unwrap();
+        assert_eq!(loaded.tx, trigger_tx);
+        assert_eq!(loaded.prevouts, bundle.prevouts);
+        assert_eq!(loaded.vault_address, vault.address().unwrap().to_string());
+        loaded.verify().unwrap();
+
+        loaded.prevouts[0].script_pubkey = loaded.prevouts[1].script_pubkey.clone();
+        assert!(loaded.verify().is_err());
+    }
+}
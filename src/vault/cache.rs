@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anyhow::Result;
+use bitcoin::taproot::TaprootSpendInfo;
+use bitcoin::{Address, Amount, Network, XOnlyPublicKey};
+
+use crate::vault::contract::VaultType;
+use crate::vault::timelock::TimelockKind;
+
+/// The script trees a vault builds its addresses and control blocks from
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum SpendTree {
+    Deposit,
+    CtvDeposit,
+    CtvTrigger,
+}
+
+/// Everything the script trees and the vault address are built from.
+/// A cached value is only reused while these are unchanged.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct TreeParams {
+    pub(crate) vault_type: VaultType,
+    pub(crate) network: Network,
+    pub(crate) timelock: TimelockKind,
+    pub(crate) amount: Amount,
+    pub(crate) keys: [XOnlyPublicKey; 3],
+    pub(crate) trigger_input_chunks: usize,
+    pub(crate) trigger_markers: usize,
+    pub(crate) marker_amount: Amount,
+    pub(crate) fee_change: bool,
+    pub(crate) nums_offset: u32,
+    pub(crate) cold_internal_key: Option<XOnlyPublicKey>,
+    pub(crate) trigger_cosigners: Vec<XOnlyPublicKey>,
+    pub(crate) trigger_threshold: usize,
+    pub(crate) recovery_address: Option<String>,
+    pub(crate) recovery_height: Option<u32>,
+    pub(crate) payout: Vec<(String, Amount)>,
+    pub(crate) vault_input_index: usize,
+    pub(crate) trigger_metadata: Option<Vec<u8>>,
+    pub(crate) trigger_change: Option<(String, Amount)>,
+    pub(crate) trigger_fee: Option<Amount>,
+}
+
+/// Memoized spend infos and the vault address, so repeated builds don't finalize the same tree again.
+/// Each entry remembers the parameters it was built from and is rebuilt once they change.
+#[derive(Default)]
+pub(crate) struct SpendInfoCache {
+    spend_infos: RefCell<HashMap<SpendTree, (TreeParams, TaprootSpendInfo)>>,
+    address: RefCell<Option<(TreeParams, Address)>>,
+}
+
+impl SpendInfoCache {
+    pub(crate) fn get_or_build(
+        &self,
+        tree: SpendTree,
+        params: TreeParams,
+        build: impl FnOnce() -> Result<TaprootSpendInfo>,
+    ) -> Result<TaprootSpendInfo> {
+        if let Some((cached_params, spend_info)) = self.spend_infos.borrow().get(&tree) {
+            if *cached_params == params {
+                return Ok(spend_info.clone());
+            }
+        }
+        // building a tree can build others, so the cache isn't borrowed while it runs
+        let spend_info = build()?;
+        self.spend_infos
+            .borrow_mut()
+            .insert(tree, (params, spend_info.clone()));
+        Ok(spend_info)
+    }
+
+    pub(crate) fn get_or_build_address(
+        &self,
+        params: TreeParams,
+        build: impl FnOnce() -> Result<Address>,
+    ) -> Result<Address> {
+        if let Some((cached_params, address)) = self.address.borrow().as_ref() {
+            if *cached_params == params {
+                return Ok(address.clone());
+            }
+        }
+        let address = build()?;
+        *self.address.borrow_mut() = Some((params, address.clone()));
+        Ok(address)
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Amount, FeeRate, OutPoint, ScriptBuf, Transaction, TxOut};
+
+use crate::settings::Settings;
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::fees::signed_fee;
+
+impl VaultCovenant {
+    /// Cancel a triggered withdrawal into a new vault that also takes in `extra_inputs`, e.g. to sweep dust back
+    /// into cold storage. The new vault holds the vault amount plus the extra inputs and keeps this vault's
+    /// timelock, the fee input pays the whole fee. Returns the new vault and the cancel transaction, whose fee
+    /// input and extra inputs the caller still has to sign.
+    pub(crate) fn cancel_consolidating(
+        &self,
+        extra_inputs: &[(OutPoint, TxOut)],
+        fee_input: (OutPoint, TxOut),
+        settings: &Settings,
+    ) -> Result<(VaultCovenant, Transaction)> {
+        self.require_consolidation(extra_inputs)?;
+        let (fee_paying_utxo, fee_paying_output) = fee_input;
+        let mut new_vault = self.rotated_vault(
+            VaultType::CTV,
+            self.timelock,
+            self.consolidated_amount(extra_inputs),
+            settings,
+        )?;
+        let txn = self.cancel_signer()?.create_consolidating_cancel_tx_to(
+            self.get_current_outpoint()?,
+            (&fee_paying_utxo, fee_paying_output),
+            extra_inputs,
+            new_vault.address()?.script_pubkey(),
+        )?;
+        new_vault.set_current_outpoint(OutPoint {
+            txid: txn.txid(),
+            vout: 0,
+        });
+        Ok((new_vault, txn))
+    }
+
+    /// The fee input [Self::cancel_consolidating] needs to pay `fee_rate`, sized from a dry run with a placeholder
+    /// fee input paying from `fee_script_pubkey`
+    pub(crate) fn consolidating_cancel_fee_input_amount(
+        &self,
+        extra_inputs: &[(OutPoint, TxOut)],
+        fee_rate: FeeRate,
+        fee_script_pubkey: ScriptBuf,
+    ) -> Result<Amount> {
+        self.require_consolidation(extra_inputs)?;
+        let placeholder = TxOut {
+            script_pubkey: fee_script_pubkey,
+            value: Amount::ZERO,
+        };
+        let dry_run = self.cancel_signer()?.create_consolidating_cancel_tx_to(
+            self.get_current_outpoint()?,
+            (&OutPoint::null(), placeholder),
+            extra_inputs,
+            self.address()?.script_pubkey(),
+        )?;
+        signed_fee(&dry_run, fee_rate)
+    }
+
+    fn consolidated_amount(&self, extra_inputs: &[(OutPoint, TxOut)]) -> Amount {
+        self.get_amount()
+            + extra_inputs
+                .iter()
+                .map(|(_, output)| output.value)
+                .sum::<Amount>()
+    }
+
+    fn require_consolidation(&self, extra_inputs: &[(OutPoint, TxOut)]) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "the CAT cancel leaf only spends the vault and one fee input and pays back the vault amount"
+            ));
+        }
+        if extra_inputs.is_empty() {
+            return Err(anyhow!("there are no inputs to consolidate"));
+        }
+        let current = self.get_current_outpoint()?;
+        if let Some((outpoint, _)) = extra_inputs
+            .iter()
+            .find(|(outpoint, _)| *outpoint == current)
+        {
+            return Err(anyhow!("{} is the vault output itself", outpoint));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    fn utxo(byte: u8, sats: u64, script_pubkey: &ScriptBuf) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([byte; 32]), 0),
+            TxOut {
+                script_pubkey: script_pubkey.clone(),
+                value: Amount::from_sat(sats),
+            },
+        )
+    }
+
+    #[test]
+    fn consolidating_cancel_pays_the_vault_plus_the_extra_inputs() {
+        let vault = seeded_vault(VaultType::CTV);
+        let script_pubkey = vault.address().unwrap().script_pubkey();
+        let extra_inputs = [
+            utxo(2, 1_000, &script_pubkey),
+            utxo(3, 2_500, &script_pubkey),
+        ];
+        let (new_vault, cancel_tx) = vault
+            .cancel_consolidating(
+                &extra_inputs,
+                utxo(9, 50_000, &script_pubkey),
+                &Settings::default(),
+            )
+            .unwrap();
+
+        assert_eq!(cancel_tx.input.len(), 4);
+        assert_eq!(cancel_tx.output.len(), 1);
+        assert_eq!(cancel_tx.output[0].value, Amount::from_sat(103_500));
+        assert_eq!(new_vault.get_amount(), Amount::from_sat(103_500));
+        assert_eq!(
+            cancel_tx.output[0].script_pubkey,
+            new_vault.address().unwrap().script_pubkey()
+        );
+        for (outpoint, _) in &extra_inputs {
+            assert!(cancel_tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == *outpoint));
+        }
+    }
+
+    #[test]
+    fn consolidation_is_refused_for_cat_and_without_extra_inputs() {
+        let vault = seeded_vault(VaultType::CTV);
+        let script_pubkey = vault.address().unwrap().script_pubkey();
+        let fee_input = utxo(9, 50_000, &script_pubkey);
+        assert!(vault
+            .cancel_consolidating(&[], fee_input.clone(), &Settings::default())
+            .is_err());
+        let vault_output = (vault.get_current_outpoint().unwrap(), fee_input.1.clone());
+        assert!(vault
+            .cancel_consolidating(&[vault_output], fee_input.clone(), &Settings::default())
+            .is_err());
+        assert!(seeded_vault(VaultType::CAT)
+            .cancel_consolidating(
+                &[utxo(2, 1_000, &script_pubkey)],
+                fee_input,
+                &Settings::default()
+            )
+            .is_err());
+    }
+}
@@ -1,31 +1,69 @@
 use anyhow::{anyhow, Result};
 use bitcoin::absolute::LockTime;
-use bitcoin::consensus::Encodable;
-use bitcoin::hashes::{sha256, Hash};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use bitcoin::blockdata::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::consensus::{serialize, Encodable};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use bitcoin::hex::{Case, DisplayHex};
-use bitcoin::key::{Keypair, Secp256k1};
-use bitcoin::secp256k1::{rand, Message, ThirtyTwoByteHash};
+use bitcoin::key::{Keypair, Parity, Secp256k1};
+use bitcoin::secp256k1::rand::rngs::StdRng;
+use bitcoin::secp256k1::rand::{CryptoRng, RngCore, SeedableRng};
+use bitcoin::secp256k1::{rand, All, Message, Scalar, ThirtyTwoByteHash};
 use bitcoin::sighash::{Prevouts, SighashCache};
-use bitcoin::taproot::{LeafVersion, Signature, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::taproot::{ControlBlock, LeafVersion, Signature, TaprootBuilder, TaprootSpendInfo};
 use bitcoin::transaction::Version;
 use bitcoin::{
-    Address, Amount, Network, OutPoint, Sequence, TapLeafHash, TapSighashType, Transaction, TxIn,
-    TxOut, XOnlyPublicKey,
+    Address, Amount, FeeRate, Network, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType,
+    Transaction, TxIn, TxOut, Txid, XOnlyPublicKey,
 };
 use bitcoincore_rpc::jsonrpc::serde_json::{self};
-use log::{debug, info};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
 use secp256kfun::marker::{EvenY, NonZero, Public};
 use secp256kfun::{Point, G};
 use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::settings::Settings;
+use crate::settings::{
+    default_marker_amount, default_trigger_input_chunks, default_trigger_markers, Settings,
+};
+use crate::vault::cache::{SpendInfoCache, SpendTree, TreeParams};
+use crate::vault::error::VaultError;
+use crate::vault::fee_change::change_witness_element;
+use crate::vault::fees::{
+    check_fee_floor, dust_limit, fee_paid, key_spend_fee, signed_fee, BuiltTx,
+};
+use crate::vault::inputs::arrange_inputs;
+use crate::vault::metrics::{MetricsSink, NoopSink, VaultEvent};
+use crate::vault::schema;
 use crate::vault::script::{
-    ctv_vault_cancel_withdrawal, ctv_vault_complete_withdrawal, ctv_vault_deposit,
-    vault_cancel_withdrawal, vault_complete_withdrawal, vault_trigger_withdrawal,
+    checksig_keys, ctv_vault_cancel_withdrawal, ctv_vault_deposit, inactive_opcodes,
+    vault_cancel_withdrawal, vault_complete_withdrawal, witness_chunk_count, witness_chunk_ranges,
+    MAX_TRIGGER_MARKERS,
 };
 use crate::vault::signature_building;
-use crate::vault::signature_building::{get_sigmsg_components, TxCommitmentSpec};
+use crate::vault::signature_building::{
+    get_sigmsg_components, grind_field_for_path, GrindBounds, TxCommitmentSpec,
+};
+use crate::vault::timelock::{
+    csv_sequence, deserialize_timelock, require_csv_satisfied, TimelockKind,
+};
+use crate::vault::trace::witness_component;
+
+lazy_static! {
+    /// The one signing and verification context every vault shares, randomized against side channels when
+    /// first used instead of on each call
+    pub(crate) static ref SECP: Secp256k1<All> = {
+        let mut secp = Secp256k1::new();
+        secp.randomize(&mut rand::thread_rng());
+        secp
+    };
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub(crate) enum VaultState {
@@ -34,36 +72,184 @@ pub(crate) enum VaultState {
     Completed,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub(crate) enum VaultType {
     CAT,
     CTV,
+    /// BIP 345 vault, the trigger leaf commits to the withdrawal through OP_VAULT instead of grinding or templates
+    #[cfg(feature = "op-vault")]
+    OpVault,
 }
 
-/// Get the vault state from the transaction and the vault address
-impl From<(Transaction, Address, VaultType)> for VaultState {
-    fn from(spec: (Transaction, Address, VaultType)) -> Self {
-        let (tx, address, vault_type) = spec;
-        if vault_type == VaultType::CAT {
-            if tx.output.len() == 2 && tx.output.get(1).unwrap().value == Amount::from_sat(546) {
-                VaultState::Triggered
-            } else if tx.output.len() == 1
-                && tx.output.first().unwrap().script_pubkey != address.script_pubkey()
-            {
-                VaultState::Completed
-            } else {
-                VaultState::Inactive
-            }
-        } else {
-            if tx.output.first().unwrap().script_pubkey == address.script_pubkey() {
-                VaultState::Inactive
-            } else if tx.input.first().unwrap().witness.len() == 2 {
-                VaultState::Triggered
-            } else {
-                VaultState::Completed
-            }
+/// The BIP119 standard template hash of `txn` as spent by input `input_index`
+pub(crate) fn template_hash(txn: &Transaction, input_index: usize) -> Result<[u8; 32]> {
+    let components = get_sigmsg_components(
+        &TxCommitmentSpec::for_ctv_template(),
+        txn,
+        input_index,
+        &[],
+        None,
+        TapLeafHash::all_zeros(),
+        TapSighashType::Default,
+    )
+    .map_err(|e| anyhow!("cannot compute the CTV template hash: {}", e))?;
+
+    let mut buffer = Vec::new();
+    buffer.extend(components[0].clone()); // version
+    buffer.extend(components[1].clone()); // locktime
+    buffer.extend((txn.input.len() as u32).to_le_bytes()); // inputs len
+    buffer.extend(components[2].clone()); // sequences
+    buffer.extend((txn.output.len() as u32).to_le_bytes()); // outputs len
+    buffer.extend(components[3].clone()); // outputs hash
+    buffer.extend(components[4].clone()); // input index
+
+    let hash = sha256::Hash::hash(&buffer);
+
+    Ok(hash.to_byte_array())
+}
+
+/// Check that each leaf has a control block proving it is committed to by the spend info's output key,
+/// a leaf missing from the tree would leave its spend path unspendable
+fn verify_leaves_reachable(
+    spend_info: &TaprootSpendInfo,
+    leaves: &[(&str, ScriptBuf)],
+) -> Result<()> {
+    for (name, script) in leaves {
+        let reachable = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .is_some_and(|control_block| {
+                control_block.verify_taproot_commitment(
+                    &SECP,
+                    spend_info.output_key().to_inner(),
+                    script,
+                )
+            });
+        if !reachable {
+            return Err(anyhow!(
+                "the {} leaf is not reachable in the script tree",
+                name
+            ));
         }
     }
+    Ok(())
+}
+
+/// Check that the leaves `spend_info` commits to check signatures against exactly the `expected` keys, one entry
+/// per leaf that checks any. A leaf built with the wrong key would leave its spend path unspendable.
+pub(crate) fn verify_leaf_keys_consistent(
+    spend_info: &TaprootSpendInfo,
+    expected: &[Vec<XOnlyPublicKey>],
+) -> Result<()> {
+    let mut committed = spend_info
+        .script_map()
+        .keys()
+        .map(|(script, _)| checksig_keys(script))
+        .filter(|keys| !keys.is_empty())
+        .collect::<Vec<_>>();
+    let mut expected = expected.to_vec();
+    committed.sort();
+    expected.sort();
+    if committed != expected {
+        return Err(anyhow!(
+            "the script tree's leaves check signatures against {:?}, expected {:?}",
+            committed,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Each offset flips the output key parity with even odds, so one of these is all but certain to work
+const MAX_NUMS_OFFSET: u32 = 256;
+
+pub(crate) fn covenant_filename(filename: &Option<String>) -> String {
+    filename
+        .clone()
+        .unwrap_or("vault_covenant.json".to_string())
+}
+
+/// Replace `filename` with `contents` so it's never seen half written: the contents go to a temporary file in
+/// the same directory, are synced to disk and then renamed over the target. An interrupted write leaves the old
+/// file in place.
+fn write_atomically(filename: &str, contents: &[u8]) -> Result<()> {
+    let path = Path::new(filename);
+    let temp_path = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name()
+            .ok_or(anyhow!("{} is not a file name", filename))?
+            .to_string_lossy()
+    ));
+    let mut temp_file = File::create(&temp_path)?;
+    temp_file.write_all(contents)?;
+    temp_file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    // the rename only survives a crash once the directory entry is on disk too
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn file_hmac(file_key: &[u8; 32], contents: &[u8]) -> String {
+    let mut engine = HmacEngine::<sha256::Hash>::new(file_key);
+    engine.input(contents);
+    Hmac::from_engine(engine).to_string()
+}
+
+/// Sign a tapscript spend of input 0 with `sighash_type`
+pub(crate) fn sign_transaction(
+    txn: &Transaction,
+    prevouts: &[TxOut],
+    leaf_hash: TapLeafHash,
+    keypair: Keypair,
+    sighash_type: TapSighashType,
+) -> Result<Vec<u8>> {
+    sign_transaction_input(txn, 0, prevouts, leaf_hash, keypair, sighash_type)
+}
+
+/// Sign a tapscript spend of `input_index` with `sighash_type`
+pub(crate) fn sign_transaction_input(
+    txn: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    leaf_hash: TapLeafHash,
+    keypair: Keypair,
+    sighash_type: TapSighashType,
+) -> Result<Vec<u8>> {
+    // the signature commits to the prevout amounts, refuse to sign a transaction the network would reject for them
+    fee_paid(txn, prevouts)?;
+    let mut sighashcache = SighashCache::new(txn);
+    let sighash = sighashcache
+        .taproot_script_spend_signature_hash(
+            input_index,
+            &Prevouts::All(prevouts),
+            leaf_hash,
+            sighash_type,
+        )
+        .map_err(|e| anyhow!("cannot compute the sighash of input {}: {}", input_index, e))?;
+    let message = Message::from_digest_slice(sighash.as_byte_array())?;
+    // no aux randomness so that building the same transaction twice gives byte-identical results
+    let signature = SECP.sign_schnorr_no_aux_rand(&message, &keypair);
+    let final_sig = Signature {
+        sig: signature,
+        hash_ty: sighash_type,
+    };
+    Ok(final_sig.to_vec())
+}
+
+/// The tapleaf a vault transaction spends through
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) enum SpendPath {
+    Trigger,
+    Complete,
+    Cancel,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -71,120 +257,853 @@ pub(crate) struct VaultCovenant {
     current_outpoint: Option<OutPoint>,
     amount: Amount,
     network: Network,
-    pub(crate) timelock_in_blocks: u16,
+    #[serde(
+        alias = "timelock_in_blocks",
+        deserialize_with = "deserialize_timelock"
+    )]
+    pub(crate) timelock: TimelockKind,
     withdrawal_address: Option<String>,
     trigger_transaction: Option<Transaction>,
     state: VaultState,
+    /// the transaction that took the funds out of the vault, set in place of the current outpoint on completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_txid: Option<Txid>,
     vault_keypair: Keypair,
     withdraw_keypair: Keypair,
     cancel_keypair: Keypair,
     vault_type: VaultType,
+    /// where the keys were derived from the seed, the keys of a derived vault are never written to the vault file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    derivation_path: Option<DerivationPath>,
+    /// whether the keys are the hardened children of `derivation_path`, vaults from before schema 3 aren't
+    #[serde(default)]
+    hardened_keys: bool,
+    #[serde(default = "default_trigger_input_chunks")]
+    trigger_input_chunks: usize,
+    /// how many dust marker outputs a CAT trigger pays, one per eventual withdrawal destination
+    #[serde(default = "default_trigger_markers")]
+    trigger_markers: usize,
+    /// what each marker output pays, the trigger and complete leaves commit to it
+    #[serde(default = "default_marker_amount")]
+    marker_amount: Amount,
+    /// whether the CAT trigger and cancel leaves let those transactions pay change back out of their fee inputs
+    #[serde(default)]
+    fee_change: bool,
+    /// where the markers after the first pay, the first marker pays the withdrawal destination
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    marker_addresses: Vec<String>,
+    /// how many times G is added to the NUMS internal key, picked to get a particular output key parity
+    #[serde(default)]
+    nums_offset: u32,
+    /// a cold key that replaces the NUMS internal key, letting its holder sweep the vault through the key path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cold_internal_key: Option<XOnlyPublicKey>,
+    /// keys that co-sign a CAT trigger after the vault key, empty when the vault key triggers alone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) trigger_cosigners: Vec<XOnlyPublicKey>,
+    /// how many of the vault key and `trigger_cosigners` have to sign a CAT trigger
+    #[serde(default = "default_trigger_threshold")]
+    pub(crate) trigger_threshold: usize,
+    /// the part of `amount` set aside to pay a CTV vault's complete and cancel fees
+    #[serde(default)]
+    pub(crate) fee_reserve: Amount,
+    /// how much of `amount` a partial CTV withdrawal pays out, the rest goes back into a vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) withdrawal_amount: Option<Amount>,
+    /// addresses a CTV complete pays set amounts to before the rest goes to the withdrawal address
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) recipients: Vec<(String, Amount)>,
+    /// outputs a CTV vault's complete leaf commits to, the only payout the vault can ever make
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) payout: Vec<(String, Amount)>,
+    /// where a CTV vault's transactions put the vault input, the fee input takes the other slot
+    #[serde(default)]
+    pub(crate) vault_input_index: usize,
+    /// data a CTV trigger carries in an OP_RETURN output, committed to by the deposit leaf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trigger_metadata: Option<Vec<u8>>,
+    /// change a CTV trigger pays back out of its fee input, committed to by the deposit leaf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trigger_change: Option<(String, Amount)>,
+    /// fee a CTV trigger pays out of the vault amount instead of from a fee input, committed to by the deposit leaf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trigger_fee: Option<Amount>,
+    /// where the recovery leaf sends the funds once the chain reaches `recovery_height`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) recovery_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) recovery_height: Option<u32>,
+    #[serde(skip, default = "default_metrics_sink")]
+    metrics: Arc<dyn MetricsSink + Send + Sync>,
+    /// what the withdraw and cancel keys sign over in CTV completes and cancels, never written to the vault file
+    #[serde(skip, default = "default_sighash_type")]
+    sighash_type: TapSighashType,
+    /// the lowest fee rate the CAT builders accept, never written to the vault file
+    #[serde(skip, default = "default_min_fee_rate")]
+    min_fee_rate: FeeRate,
+    /// where the CAT builders' grinds start and how far they search, never written to the vault file
+    #[serde(skip)]
+    grind_bounds: GrindBounds,
+    /// the confirmations the chain queries wait for before reporting a state as final, never written to the vault file
+    #[serde(skip, default = "default_min_confirmations")]
+    min_confirmations: u32,
+    /// the cosigner keys this process can sign triggers with, never written to the vault file
+    #[serde(skip)]
+    pub(crate) trigger_signers: Vec<Keypair>,
+    /// the key of the vault file's integrity check, see [Settings::file_key], never written to the vault file
+    #[serde(skip)]
+    file_key: Option<[u8; 32]>,
+    /// memoized script trees and address, never written to the vault file
+    #[serde(skip)]
+    spend_info_cache: SpendInfoCache,
+}
+
+fn default_metrics_sink() -> Arc<dyn MetricsSink + Send + Sync> {
+    Arc::new(NoopSink)
+}
+
+fn default_trigger_threshold() -> usize {
+    1
+}
+
+fn default_sighash_type() -> TapSighashType {
+    TapSighashType::All
+}
+
+fn default_min_fee_rate() -> FeeRate {
+    FeeRate::BROADCAST_MIN
+}
+
+fn default_min_confirmations() -> u32 {
+    1
 }
 
-impl Default for VaultCovenant {
-    fn default() -> Self {
-        let secp = Secp256k1::new();
-        let vault_keypair = Keypair::new(&secp, &mut rand::thread_rng());
-        let withdraw_keypair = Keypair::new(&secp, &mut rand::thread_rng());
-        let cancel_keypair = Keypair::new(&secp, &mut rand::thread_rng());
+impl VaultCovenant {
+    /// An inactive CAT vault on `network` with fresh random keys, for the constructors to fill in.
+    /// There is deliberately no Default, a vault built for the wrong network pays to addresses nobody can use.
+    fn blank(network: Network) -> Self {
+        Self::blank_with_rng(network, &mut rand::thread_rng())
+    }
+
+    /// [Self::blank] with the keys drawn from `rng`
+    fn blank_with_rng<R: RngCore + CryptoRng>(network: Network, rng: &mut R) -> Self {
+        let vault_keypair = Keypair::new(&SECP, rng);
+        let withdraw_keypair = Keypair::new(&SECP, rng);
+        let cancel_keypair = Keypair::new(&SECP, rng);
         Self {
             current_outpoint: None,
             amount: Amount::ZERO,
-            network: Network::Regtest,
-            timelock_in_blocks: 20,
+            network,
+            timelock: TimelockKind::Blocks(20),
             withdrawal_address: None,
             trigger_transaction: None,
+            completed_txid: None,
             state: VaultState::Inactive,
             vault_keypair,
             withdraw_keypair,
             cancel_keypair,
             vault_type: VaultType::CAT,
+            derivation_path: None,
+            hardened_keys: false,
+            trigger_input_chunks: default_trigger_input_chunks(),
+            trigger_markers: default_trigger_markers(),
+            marker_amount: default_marker_amount(),
+            fee_change: false,
+            marker_addresses: Vec::new(),
+            nums_offset: 0,
+            cold_internal_key: None,
+            trigger_cosigners: Vec::new(),
+            trigger_threshold: default_trigger_threshold(),
+            fee_reserve: Amount::ZERO,
+            withdrawal_amount: None,
+            recipients: Vec::new(),
+            payout: Vec::new(),
+            vault_input_index: 0,
+            trigger_metadata: None,
+            trigger_change: None,
+            trigger_fee: None,
+            recovery_address: None,
+            recovery_height: None,
+            metrics: default_metrics_sink(),
+            sighash_type: default_sighash_type(),
+            min_fee_rate: default_min_fee_rate(),
+            grind_bounds: GrindBounds::default(),
+            trigger_signers: Vec::new(),
+            min_confirmations: default_min_confirmations(),
+            file_key: None,
+            spend_info_cache: SpendInfoCache::default(),
         }
     }
 }
 
 impl VaultCovenant {
-    pub(crate) fn new(timelock_in_blocks: u16, settings: &Settings) -> Result<Self> {
-        Ok(Self {
-            network: settings.network,
-            timelock_in_blocks,
-            vault_type: VaultType::CAT,
-            ..Default::default()
-        })
+    /// A new CAT vault with keys derived from the seed supplied in `settings`, or random keys without one
+    pub(crate) fn new(timelock: TimelockKind, settings: &Settings) -> Result<Self> {
+        Self::new_from_settings(VaultType::CAT, timelock, Amount::ZERO, settings)
     }
 
+    /// A new CTV vault with keys derived from the seed supplied in `settings`, or random keys without one
     pub(crate) fn new_ctv(
-        timelock_in_blocks: u16,
+        timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+    ) -> Result<Self> {
+        Self::new_from_settings(VaultType::CTV, timelock, amount, settings)
+    }
+
+    /// A new OP_VAULT vault with keys derived from the seed supplied in `settings`, or random keys without one
+    #[cfg(feature = "op-vault")]
+    pub(crate) fn new_op_vault(
+        timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+    ) -> Result<Self> {
+        Self::new_from_settings(VaultType::OpVault, timelock, amount, settings)
+    }
+
+    /// A new CTV vault like [VaultCovenant::new_ctv] that can only ever complete to the `payout` outputs
+    pub(crate) fn new_ctv_multi(
+        timelock: TimelockKind,
+        amount: Amount,
+        payout: &[(Address, Amount)],
+        settings: &Settings,
+    ) -> Result<Self> {
+        let mut vault = Self::new_ctv(timelock, amount, settings)?;
+        vault.set_payout(payout)?;
+        Ok(vault)
+    }
+
+    /// A new vault with random keys that only ever exist in the vault file
+    pub(crate) fn new_ephemeral(
+        vault_type: VaultType,
+        timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+    ) -> Result<Self> {
+        Self::new_with_rng(
+            vault_type,
+            timelock,
+            amount,
+            settings,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// A new vault like [Self::new_ephemeral] whose keys come from `seed`, the same seed always gives the same
+    /// keys and addresses. Anyone who knows the seed has the keys, so it is refused outside regtest.
+    pub(crate) fn new_seeded(
+        vault_type: VaultType,
+        timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+        seed: u64,
+    ) -> Result<Self> {
+        if settings.network != Network::Regtest {
+            return Err(anyhow!(
+                "seeded vault keys are guessable, they are only allowed on regtest, not {}",
+                settings.network
+            ));
+        }
+        Self::new_with_rng(
+            vault_type,
+            timelock,
+            amount,
+            settings,
+            &mut StdRng::seed_from_u64(seed),
+        )
+    }
+
+    /// A new vault with keys drawn from `rng` that only ever exist in the vault file
+    pub(crate) fn new_with_rng<R: RngCore + CryptoRng>(
+        vault_type: VaultType,
+        timelock: TimelockKind,
         amount: Amount,
         settings: &Settings,
+        rng: &mut R,
     ) -> Result<Self> {
+        csv_sequence(timelock)?;
+        if settings.trigger_input_chunks == 0 {
+            return Err(anyhow!(
+                "the trigger transaction inputs need at least one chunk"
+            ));
+        }
+        if !(1..=MAX_TRIGGER_MARKERS).contains(&settings.trigger_markers) {
+            return Err(anyhow!(
+                "a trigger needs between 1 and {} marker outputs, not {}",
+                MAX_TRIGGER_MARKERS,
+                settings.trigger_markers
+            ));
+        }
         Ok(Self {
             network: settings.network,
-            timelock_in_blocks,
+            timelock,
             amount,
-            vault_type: VaultType::CTV,
-            ..Default::default()
+            trigger_input_chunks: settings.trigger_input_chunks,
+            trigger_markers: settings.trigger_markers,
+            marker_amount: settings.marker_amount,
+            fee_change: settings.fee_change,
+            vault_type,
+            file_key: settings.file_key()?,
+            ..Self::blank_with_rng(settings.network, rng)
         })
     }
 
-    pub(crate) fn from_file(filename: &Option<String>) -> Result<Self> {
-        let filename = filename
-            .clone()
-            .unwrap_or("vault_covenant.json".to_string());
+    /// A new vault with keys derived at a fresh vault index if `settings` has a seed, random keys otherwise
+    fn new_from_settings(
+        vault_type: VaultType,
+        timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+    ) -> Result<Self> {
+        let mut vault = Self::new_ephemeral(vault_type, timelock, amount, settings)?;
+        if let Some(master_key) = settings.master_key()? {
+            vault.derive_keys(&master_key, settings.new_vault_derivation_path()?, true)?;
+        }
+        Ok(vault)
+    }
+
+    /// Derive the vault, withdraw and cancel keys as the children 0, 1 and 2 of `derivation_path`. New vaults use
+    /// hardened children, as with normal ones a single leaked key plus the vault's xpub gives away the other two.
+    fn derive_keys(
+        &mut self,
+        master_key: &Xpriv,
+        derivation_path: DerivationPath,
+        hardened: bool,
+    ) -> Result<()> {
+        let derive = |index: u32| -> Result<Keypair> {
+            let child = match hardened {
+                true => ChildNumber::from_hardened_idx(index)?,
+                false => ChildNumber::from_normal_idx(index)?,
+            };
+            let path = derivation_path.child(child);
+            Ok(master_key.derive_priv(&SECP, &path)?.to_keypair(&SECP))
+        };
+        self.vault_keypair = derive(0)?;
+        self.withdraw_keypair = derive(1)?;
+        self.cancel_keypair = derive(2)?;
+        self.derivation_path = Some(derivation_path);
+        self.hardened_keys = hardened;
+        Ok(())
+    }
+
+    /// Fund a new vault with an unsigned transaction paying its deposit address from `source_utxo`,
+    /// sending anything left over after the fee to `change_address`
+    pub(crate) fn create_and_fund(
+        mut vault: Self,
+        amount: Amount,
+        source_utxo: OutPoint,
+        source_output: TxOut,
+        change_address: &Address,
+        fee_rate: FeeRate,
+    ) -> Result<(Self, Transaction)> {
+        vault.set_amount(amount);
+
+        let mut funding_tx = Transaction {
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+            input: vec![TxIn {
+                previous_output: source_utxo,
+                ..Default::default()
+            }],
+            output: vec![
+                TxOut {
+                    script_pubkey: vault.address()?.script_pubkey(),
+                    value: amount,
+                },
+                TxOut {
+                    script_pubkey: change_address.script_pubkey(),
+                    value: Amount::ZERO,
+                },
+            ],
+        };
+        let fee = key_spend_fee(&funding_tx, fee_rate)?;
+        let change = source_output
+            .value
+            .checked_sub(amount)
+            .and_then(|remaining| remaining.checked_sub(fee))
+            .ok_or(anyhow!(
+                "source output of {} can't cover a deposit of {} plus a fee of {}",
+                source_output.value,
+                amount,
+                fee
+            ))?;
+        if change < dust_limit(&funding_tx.output[1].script_pubkey) {
+            debug!("dropping change of {} below the dust limit", change);
+            funding_tx.output.pop();
+        } else {
+            funding_tx.output[1].value = change;
+        }
+
+        vault.set_current_outpoint(OutPoint {
+            txid: funding_tx.txid(),
+            vout: 0,
+        });
+        Ok((vault, funding_tx))
+    }
+
+    pub(crate) fn file_exists(filename: &Option<String>) -> bool {
+        std::path::Path::new(&covenant_filename(filename)).exists()
+    }
+
+    /// Read the covenant from a file, migrating it from an older schema version. A vault with derived keys needs the `master_key` they were derived from.
+    pub(crate) fn from_file(filename: &Option<String>, master_key: Option<&Xpriv>) -> Result<Self> {
+        let filename = covenant_filename(filename);
         info!("reading vault covenant from file: {}", filename);
-        let file = std::fs::File::open(filename)?;
-        let covenant: VaultCovenant = serde_json::from_reader(file)?;
-        Ok(covenant)
+        let contents = std::fs::read(filename)?;
+        Self::from_slice(&contents, master_key)
+    }
+
+    fn from_slice(contents: &[u8], master_key: Option<&Xpriv>) -> Result<Self> {
+        let mut stored: serde_json::Value = serde_json::from_slice(contents)?;
+        schema::migrate(&mut stored)?;
+        let derivation_path = stored.get("derivation_path").cloned();
+        if let (Some(derivation_path), Some(object)) = (derivation_path, stored.as_object_mut()) {
+            let derivation_path: DerivationPath = serde_json::from_value(derivation_path)?;
+            let master_key = master_key.ok_or(anyhow!(
+                "the vault keys are derived at {}, supply the seed to load it",
+                derivation_path
+            ))?;
+            let network = serde_json::from_value(
+                object
+                    .get("network")
+                    .cloned()
+                    .ok_or(anyhow!("the vault file has no network"))?,
+            )?;
+            let hardened = object
+                .get("hardened_keys")
+                .and_then(|hardened| hardened.as_bool())
+                .unwrap_or(false);
+            let mut keys = Self::blank(network);
+            keys.derive_keys(master_key, derivation_path, hardened)?;
+            object.insert(
+                "vault_keypair".to_string(),
+                serde_json::to_value(keys.vault_keypair)?,
+            );
+            object.insert(
+                "withdraw_keypair".to_string(),
+                serde_json::to_value(keys.withdraw_keypair)?,
+            );
+            object.insert(
+                "cancel_keypair".to_string(),
+                serde_json::to_value(keys.cancel_keypair)?,
+            );
+        }
+        Ok(serde_json::from_value(stored)?)
     }
 
+    /// Write the covenant to a file tagged with its schema version, along with an HMAC of its contents keyed with
+    /// the file key in a `.hmac` file next to it. Without a file key there is no HMAC and any stale one is removed.
+    /// Derived keys are left out, they're re-derived from the seed on load.
+    /// Both files are replaced atomically, the HMAC first, as the covenant file may hold the only copy of the vault keys.
     pub(crate) fn to_file(&self, filename: &Option<String>) -> Result<()> {
-        let filename = filename
-            .clone()
-            .unwrap_or("vault_covenant.json".to_string());
+        let filename = covenant_filename(filename);
         info!("writing vault covenant to file: {}", filename);
-        let file = std::fs::File::create(filename)?;
-        serde_json::to_writer(file, self)?;
+        let mut stored = serde_json::to_value(self)?;
+        if let (Some(_), Some(object)) = (&self.derivation_path, stored.as_object_mut()) {
+            object.remove("vault_keypair");
+            object.remove("withdraw_keypair");
+            object.remove("cancel_keypair");
+        }
+        schema::tag(&mut stored)?;
+        let contents = serde_json::to_vec(&stored)?;
+        self.write_file_hmac(&filename, &contents)?;
+        write_atomically(&filename, &contents)
+    }
+
+    /// Write the HMAC of the `contents` about to replace `filename`, followed by the HMAC of the file it replaces
+    /// if that one checks out. Written before the covenant file, an interrupted write leaves a file that still
+    /// matches one of them.
+    fn write_file_hmac(&self, filename: &str, contents: &[u8]) -> Result<()> {
+        let hmac_filename = format!("{}.hmac", filename);
+        let Some(file_key) = &self.file_key else {
+            warn!(
+                "No seed or integrity secret supplied, {} is written without an integrity check",
+                filename
+            );
+            if Path::new(&hmac_filename).exists() {
+                std::fs::remove_file(&hmac_filename)?;
+            }
+            return Ok(());
+        };
+        let mut hmacs = file_hmac(file_key, contents);
+        if let (Ok(previous), Ok(stored_hmacs)) = (
+            std::fs::read(filename),
+            std::fs::read_to_string(&hmac_filename),
+        ) {
+            let previous_hmac = file_hmac(file_key, &previous);
+            if stored_hmacs.lines().any(|hmac| hmac == previous_hmac) {
+                hmacs = format!("{}\n{}", hmacs, previous_hmac);
+            }
+        }
+        write_atomically(&hmac_filename, hmacs.as_bytes())
+    }
+
+    /// Check the covenant file hasn't been modified since it was last written by recomputing its HMAC with
+    /// `file_key`. The key comes from the seed or the integrity secret, never from the file, so this catches
+    /// corruption and edits by anyone without them. It can't catch edits by someone holding the seed or secret,
+    /// a rollback to an older file and HMAC written with the same key or to the file the last write replaced,
+    /// which the HMAC file still vouches for in case that write was interrupted, or the HMAC file being deleted: vaults
+    /// written before the check existed have none, so a missing HMAC or file key is only warned about.
+    pub(crate) fn verify_file_integrity(
+        filename: &Option<String>,
+        master_key: Option<&Xpriv>,
+        file_key: Option<&[u8; 32]>,
+    ) -> Result<()> {
+        let filename = covenant_filename(filename);
+        let contents = std::fs::read(&filename)?;
+        Self::from_slice(&contents, master_key)
+            .map_err(|e| anyhow!("{} is corrupted: {}", filename, e))?;
+        let Some(file_key) = file_key else {
+            warn!(
+                "No seed or integrity secret supplied, can't check the integrity of {}",
+                filename
+            );
+            return Ok(());
+        };
+        let hmac_filename = format!("{}.hmac", filename);
+        if !Path::new(&hmac_filename).exists() {
+            warn!(
+                "No integrity check stored for {}, it is added the next time the vault is written",
+                filename
+            );
+            return Ok(());
+        }
+        let stored_hmacs = std::fs::read_to_string(&hmac_filename)?;
+        let hmac = file_hmac(file_key, &contents);
+        if !stored_hmacs.lines().any(|stored_hmac| stored_hmac == hmac) {
+            return Err(anyhow!(
+                "{} has been modified since it was last written",
+                filename
+            ));
+        }
         Ok(())
     }
 
+    /// Integrity checks of the files this vault is written to are keyed with `file_key`
+    pub(crate) fn set_file_key(&mut self, file_key: Option<[u8; 32]>) {
+        self.file_key = file_key;
+    }
+
     pub(crate) fn set_current_outpoint(&mut self, outpoint: OutPoint) {
         self.current_outpoint = Some(outpoint);
     }
 
+    /// Sign CTV completes and cancels with `sighash_type`, e.g. ALL|ANYONECANPAY so more fee inputs can be
+    /// attached after signing. Only the ALL variants are accepted, every output has to be signed.
+    /// The CAT leaves fix the shape of their transactions, so they always sign with ALL.
+    pub(crate) fn set_sighash_type(&mut self, sighash_type: TapSighashType) -> Result<()> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!(
+                "only CTV vaults can sign with another sighash type, the CAT leaves fix their transactions"
+            ));
+        }
+        if matches!(
+            sighash_type,
+            TapSighashType::None | TapSighashType::NonePlusAnyoneCanPay
+        ) {
+            return Err(anyhow!(
+                "{} leaves the outputs unsigned, anyone could redirect the funds",
+                sighash_type
+            ));
+        }
+        // a SINGLE signature only covers the output at the vault input's index, leaving the residual vault,
+        // recipient, payout and consolidation outputs of a multi-output transaction unsigned
+        if matches!(
+            sighash_type,
+            TapSighashType::Single | TapSighashType::SinglePlusAnyoneCanPay
+        ) {
+            return Err(anyhow!(
+                "{} signs only one of the outputs, the others could be redirected",
+                sighash_type
+            ));
+        }
+        self.sighash_type = sighash_type;
+        Ok(())
+    }
+
+    pub(crate) fn sighash_type(&self) -> TapSighashType {
+        self.sighash_type
+    }
+
+    /// Refuse to build CAT triggers, completes and cancels whose fee inputs can't pay `min_fee_rate`
+    pub(crate) fn set_min_fee_rate(&mut self, min_fee_rate: FeeRate) {
+        self.min_fee_rate = min_fee_rate;
+    }
+
+    pub(crate) fn min_fee_rate(&self) -> FeeRate {
+        self.min_fee_rate
+    }
+
+    /// Start the CAT builders' grinds at `bounds.start` and give up after `bounds.max_iterations` tries
+    pub(crate) fn set_grind_bounds(&mut self, bounds: GrindBounds) {
+        self.grind_bounds = bounds;
+    }
+
+    pub(crate) fn grind_bounds(&self) -> GrindBounds {
+        self.grind_bounds
+    }
+
+    /// Only report a state as final once the transaction deciding it has `min_confirmations`
+    pub(crate) fn set_min_confirmations(&mut self, min_confirmations: u32) {
+        self.min_confirmations = min_confirmations;
+    }
+
+    pub(crate) fn min_confirmations(&self) -> u32 {
+        self.min_confirmations
+    }
+
     pub(crate) fn get_current_outpoint(&self) -> Result<OutPoint> {
-        self.current_outpoint.ok_or(anyhow!("no current outpoint"))
+        Ok(self
+            .current_outpoint
+            .ok_or_else(|| self.missing(VaultError::NoCurrentOutpoint))?)
+    }
+
+    /// Whether the vault was ever funded, a completed vault no longer has a current outpoint
+    pub(crate) fn is_funded(&self) -> bool {
+        self.current_outpoint.is_some() || self.completed_txid.is_some()
+    }
+
+    /// The transaction that completed the vault, if it is completed and that was recorded
+    pub(crate) fn completed_txid(&self) -> Option<Txid> {
+        self.completed_txid
+    }
+
+    /// `error` for a field that is unset, or why it is gone once the vault is completed
+    fn missing(&self, error: VaultError) -> VaultError {
+        if self.state == VaultState::Completed {
+            VaultError::Completed(self.completed_txid)
+        } else {
+            error
+        }
+    }
+
+    /// Set the current outpoint from its `txid:vout` form, as a node or block explorer shows it
+    pub(crate) fn set_current_outpoint_from_str(&mut self, outpoint: &str) -> Result<()> {
+        let outpoint = OutPoint::from_str(outpoint.trim())
+            .map_err(|e| anyhow!("{:?} is not a txid:vout outpoint: {}", outpoint, e))?;
+        self.set_current_outpoint(outpoint);
+        Ok(())
+    }
+
+    /// The current outpoint in its `txid:vout` form
+    pub(crate) fn current_outpoint_string(&self) -> Result<String> {
+        Ok(self.get_current_outpoint()?.to_string())
     }
 
     pub(crate) fn set_amount(&mut self, amount: Amount) {
         self.amount = amount
     }
-    pub(crate) fn set_withdrawal_address(&mut self, address: Option<Address>) {
+
+    pub(crate) fn get_amount(&self) -> Amount {
+        self.amount
+    }
+
+    /// The output currently holding the vault's funds, as tracked by the vault state
+    pub(crate) fn current_txout(&self) -> Result<TxOut> {
+        #[cfg(feature = "op-vault")]
+        if self.vault_type == VaultType::OpVault && self.state == VaultState::Triggered {
+            return self.op_vault_triggered_output(&self.get_withdrawal_address()?);
+        }
+        let address = if self.vault_type == VaultType::CTV && self.state == VaultState::Triggered {
+            self.ctv_trigger_address()?
+        } else {
+            self.address()?
+        };
+        Ok(TxOut {
+            script_pubkey: address.script_pubkey(),
+            value: self.amount,
+        })
+    }
+
+    pub(crate) fn set_withdrawal_address(&mut self, address: Option<Address>) -> Result<()> {
+        if let Some(address) = &address {
+            self.require_network(address)?;
+        }
         self.withdrawal_address = address.map(|a| a.to_string());
+        Ok(())
+    }
+
+    /// Reject an address of another network when it is handed to the vault, rather than when it is read back
+    pub(crate) fn require_network(&self, address: &Address) -> Result<()> {
+        if !address.as_unchecked().is_valid_for_network(self.network) {
+            return Err(VaultError::WrongNetworkAddress {
+                address: address.to_string(),
+                network: self.network,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Reject a marker destination the marker amount would be dust to
+    fn require_marker_above_dust(&self, address: &Address) -> Result<()> {
+        let limit = dust_limit(&address.script_pubkey());
+        if self.marker_amount < limit {
+            return Err(anyhow!(
+                "a marker of {} to {} would be below its dust limit of {}",
+                self.marker_amount,
+                address,
+                limit
+            ));
+        }
+        Ok(())
     }
 
     pub(crate) fn get_withdrawal_address(&self) -> Result<Address> {
         Ok(Address::from_str(
             self.withdrawal_address
                 .as_ref()
-                .ok_or(anyhow!("no withdrawal address"))?,
+                .ok_or_else(|| self.missing(VaultError::NoWithdrawalAddress))?,
         )?
         .require_network(self.network)?)
     }
 
+    /// Re-validate the stored withdrawal address against the vault's network,
+    /// clearing it with a warning if it doesn't parse or belongs to another network
+    pub(crate) fn normalize_withdrawal_address(&mut self) -> Result<()> {
+        let Some(stored) = self.withdrawal_address.as_ref() else {
+            return Ok(());
+        };
+        let validated = Address::from_str(stored)
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|address| Ok(address.require_network(self.network)?));
+        if let Err(e) = validated {
+            warn!(
+                "clearing withdrawal address {} that isn't valid on {}: {}",
+                stored, self.network, e
+            );
+            self.withdrawal_address = None;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn trigger_markers(&self) -> usize {
+        self.trigger_markers
+    }
+
+    pub(crate) fn marker_amount(&self) -> Amount {
+        self.marker_amount
+    }
+
+    pub(crate) fn fee_change(&self) -> bool {
+        self.fee_change
+    }
+
+    /// Set where the trigger's marker outputs after the first pay, one per marker
+    pub(crate) fn set_marker_addresses(&mut self, addresses: &[Address]) -> Result<()> {
+        if addresses.len() + 1 != self.trigger_markers {
+            return Err(anyhow!(
+                "the trigger pays {} markers after the first, not {}",
+                self.trigger_markers - 1,
+                addresses.len()
+            ));
+        }
+        for address in addresses {
+            self.require_network(address)?;
+            self.require_marker_above_dust(address)?;
+        }
+        self.marker_addresses = addresses.iter().map(|a| a.to_string()).collect();
+        Ok(())
+    }
+
+    pub(crate) fn marker_addresses(&self) -> Result<Vec<Address>> {
+        self.marker_addresses
+            .iter()
+            .map(|address| Ok(Address::from_str(address)?.require_network(self.network)?))
+            .collect()
+    }
+
     pub(crate) fn set_trigger_transaction(&mut self, txn: Option<Transaction>) {
         self.trigger_transaction = txn;
     }
 
     pub(crate) fn get_trigger_transaction(&self) -> Result<Transaction> {
-        self.trigger_transaction
+        Ok(self
+            .trigger_transaction
             .clone()
-            .ok_or(anyhow!("no trigger transaction"))
+            .ok_or_else(|| self.missing(VaultError::NoTriggerTransaction))?)
+    }
+
+    /// Check that the current outpoint is the vault output of the stored trigger transaction,
+    /// so the complete spends the output the trigger actually created, and for a CTV vault that
+    /// the trigger is the one its deposit leaf commits to
+    pub(crate) fn verify_trigger_consistency(&self) -> Result<()> {
+        let trigger_tx = self.get_trigger_transaction()?;
+        let outpoint = self.get_current_outpoint()?;
+        if outpoint.txid != trigger_tx.txid() {
+            return Err(anyhow!(
+                "current outpoint {} is not an output of the trigger transaction {}",
+                outpoint,
+                trigger_tx.txid()
+            ));
+        }
+        let output = trigger_tx
+            .output
+            .get(outpoint.vout as usize)
+            .ok_or(anyhow!(
+                "trigger transaction {} has no output {}",
+                trigger_tx.txid(),
+                outpoint.vout
+            ))?;
+        let expected = self.triggered_output()?;
+        if output.script_pubkey != expected.script_pubkey || output.value != expected.value {
+            return Err(anyhow!(
+                "current outpoint {} pays {} to {} instead of {} to the triggered vault",
+                outpoint,
+                output.value,
+                output.script_pubkey,
+                expected.value
+            ));
+        }
+        if self.vault_type == VaultType::CTV {
+            self.verify_ctv_commitment(&trigger_tx)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_metrics_sink(&mut self, metrics: Arc<dyn MetricsSink + Send + Sync>) {
+        self.metrics = metrics;
     }
 
+    pub(crate) fn record_event(&self, event: VaultEvent) {
+        self.metrics.record_event(event);
+    }
+
+    /// Move the vault to Inactive or Triggered. Completing takes the txid that spent the vault, see
+    /// [Self::complete_with].
     pub(crate) fn set_state(&mut self, state: VaultState) {
+        debug_assert!(
+            state != VaultState::Completed,
+            "a vault completes through complete_with"
+        );
+        self.transition(state);
+    }
+
+    /// Record that `txid` moved the funds out of the vault for good
+    pub(crate) fn complete_with(&mut self, txid: Txid) {
+        self.transition(VaultState::Completed);
+        // the output the completing transaction created isn't the vault's, only the txid is kept
+        self.current_outpoint = None;
+        self.completed_txid = Some(txid);
+    }
+
+    fn transition(&mut self, state: VaultState) {
+        let event = match (&self.state, &state) {
+            (VaultState::Inactive, VaultState::Triggered) => Some(VaultEvent::Triggered),
+            (VaultState::Triggered, VaultState::Completed) => Some(VaultEvent::Completed),
+            (VaultState::Triggered, VaultState::Inactive) => Some(VaultEvent::Cancelled),
+            _ => None,
+        };
+        if let Some(event) = event {
+            self.record_event(event);
+        }
         if state == VaultState::Completed {
             self.set_trigger_transaction(None);
-            self.set_withdrawal_address(None);
+            self.withdrawal_address = None;
+        }
+        if state != VaultState::Triggered {
+            self.withdrawal_amount = None;
+            self.recipients.clear();
         }
         self.state = state;
     }
@@ -197,208 +1116,658 @@ impl VaultCovenant {
         self.vault_type.clone()
     }
 
+    pub(crate) fn get_network(&self) -> Network {
+        self.network
+    }
+
     pub(crate) fn address(&self) -> Result<Address> {
-        let spend_info = if self.vault_type == VaultType::CAT {
-            self.taproot_spend_info()?
-        } else {
-            self.ctv_deposit_spend_info()?
-        };
-        Ok(Address::p2tr_tweaked(spend_info.output_key(), self.network))
+        self.spend_info_cache
+            .get_or_build_address(self.tree_params(), || {
+                let spend_info = self.deposit_spend_info()?;
+                Ok(Address::p2tr_tweaked(spend_info.output_key(), self.network))
+            })
     }
 
-    fn ctv_trigger_address(&self) -> Result<Address> {
-        let spend_info = self.ctv_trigger_spend_info()?;
-        Ok(Address::p2tr_tweaked(spend_info.output_key(), self.network))
+    /// The parameters the cached spend infos and address are keyed on
+    fn tree_params(&self) -> TreeParams {
+        TreeParams {
+            vault_type: self.vault_type.clone(),
+            network: self.network,
+            timelock: self.timelock,
+            amount: self.amount,
+            keys: [
+                self.vault_x_only_public_key(),
+                self.withdraw_x_only_public_key(),
+                self.cancel_x_only_public_key(),
+            ],
+            trigger_input_chunks: self.trigger_input_chunks,
+            trigger_markers: self.trigger_markers,
+            marker_amount: self.marker_amount,
+            fee_change: self.fee_change,
+            nums_offset: self.nums_offset,
+            cold_internal_key: self.cold_internal_key,
+            trigger_cosigners: self.trigger_cosigners.clone(),
+            trigger_threshold: self.trigger_threshold,
+            recovery_address: self.recovery_address.clone(),
+            recovery_height: self.recovery_height,
+            payout: self.payout.clone(),
+            vault_input_index: self.vault_input_index,
+            trigger_metadata: self.trigger_metadata.clone(),
+            trigger_change: self.trigger_change.clone(),
+            trigger_fee: self.trigger_fee,
+        }
     }
 
-    fn taproot_spend_info(&self) -> Result<TaprootSpendInfo> {
-        // hash G into a NUMS point
-        let hash = sha256::Hash::hash(G.to_bytes_uncompressed().as_slice());
-        let point: Point<EvenY, Public, NonZero> = Point::from_xonly_bytes(hash.into_32())
-            .ok_or(anyhow!("G_X hash should be a valid x-only point"))?;
-        let nums_key = XOnlyPublicKey::from_slice(point.to_xonly_bytes().as_slice())?;
-        let secp = Secp256k1::new();
-        Ok(TaprootBuilder::new()
-            .add_leaf(1, vault_trigger_withdrawal(self.vault_x_only_public_key()))?
-            .add_leaf(
-                2,
-                vault_complete_withdrawal(
-                    self.withdraw_x_only_public_key(),
-                    self.timelock_in_blocks,
-                ),
-            )?
-            .add_leaf(2, vault_cancel_withdrawal(self.cancel_x_only_public_key()))?
-            .finalize(&secp, nums_key)
-            .expect("finalizing taproot spend info with a NUMS point should always work"))
+    /// A stable identifier for indexing vaults: the first 16 bytes of a hash of the parameters fixed at creation,
+    /// the three keys, vault type, timelock and network, plus the amount and payout a CTV vault's templates commit to.
+    /// It doesn't change as the vault is triggered, completed or cancelled.
+    pub(crate) fn vault_id(&self) -> String {
+        let mut engine = sha256::Hash::engine();
+        for key in [
+            self.vault_x_only_public_key(),
+            self.withdraw_x_only_public_key(),
+            self.cancel_x_only_public_key(),
+        ] {
+            engine.input(&key.serialize());
+        }
+        engine.input(&[match self.vault_type {
+            VaultType::CAT => 0,
+            VaultType::CTV => 1,
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => 2,
+        }]);
+        engine.input(&self.timelock.to_sequence().to_consensus_u32().to_le_bytes());
+        engine.input(&self.network.magic().to_bytes());
+        if self.vault_type == VaultType::CTV {
+            engine.input(&self.amount.to_sat().to_le_bytes());
+        }
+        for (address, amount) in &self.payout {
+            engine.input(address.as_bytes());
+            engine.input(&amount.to_sat().to_le_bytes());
+        }
+        if self.vault_input_index != 0 {
+            engine.input(&(self.vault_input_index as u32).to_le_bytes());
+        }
+        if let Some(data) = &self.trigger_metadata {
+            engine.input(data);
+        }
+        if let Some((address, amount)) = &self.trigger_change {
+            engine.input(address.as_bytes());
+            engine.input(&amount.to_sat().to_le_bytes());
+        }
+        if let Some(fee) = self.trigger_fee {
+            engine.input(&fee.to_sat().to_le_bytes());
+        }
+        sha256::Hash::from_engine(engine).to_byte_array()[..16].to_hex_string(Case::Lower)
     }
 
-    fn ctv_deposit_spend_info(&self) -> Result<TaprootSpendInfo> {
-        // hash G into a NUMS point
-        let hash = sha256::Hash::hash(G.to_bytes_uncompressed().as_slice());
-        let point: Point<EvenY, Public, NonZero> = Point::from_xonly_bytes(hash.into_32())
-            .ok_or(anyhow!("G_X hash should be a valid x-only point"))?;
-        let nums_key = XOnlyPublicKey::from_slice(point.to_xonly_bytes().as_slice())?;
-        let secp = Secp256k1::new();
+    /// The taproot merkle root of the vault's deposit script tree as a hex string
+    pub(crate) fn merkle_root_hex(&self) -> Result<String> {
+        let merkle_root = self
+            .deposit_spend_info()?
+            .merkle_root()
+            .ok_or(anyhow!("vault script tree has no merkle root"))?;
+        Ok(merkle_root.to_string())
+    }
 
-        Ok(TaprootBuilder::new()
-            .add_leaf(0, ctv_vault_deposit(self.ctv_hash()))?
-            .finalize(&secp, nums_key)
-            .expect("finalizing taproot spend info with a new keypair should always work"))
+    /// Check that the vault's taproot merkle root matches one computed elsewhere
+    pub(crate) fn verify_merkle_root(&self, expected: &str) -> Result<()> {
+        let merkle_root = self.merkle_root_hex()?;
+        if merkle_root != expected.trim().to_lowercase() {
+            return Err(anyhow!(
+                "merkle root mismatch: vault has {}, expected {}",
+                merkle_root,
+                expected
+            ));
+        }
+        Ok(())
     }
 
-    fn ctv_trigger_spend_info(&self) -> Result<TaprootSpendInfo> {
+    /// The number of tapleaves in the vault's deposit script tree
+    pub(crate) fn deposit_leaf_count(&self) -> Result<usize> {
+        Ok(self.deposit_spend_info()?.script_map().len())
+    }
+
+    /// A fresh vault with the same keys, timelock, amount and network as this one, but of another type
+    pub(crate) fn counterpart(&self, vault_type: VaultType) -> Self {
+        Self {
+            amount: self.amount,
+            network: self.network,
+            timelock: self.timelock,
+            vault_keypair: self.vault_keypair,
+            withdraw_keypair: self.withdraw_keypair,
+            cancel_keypair: self.cancel_keypair,
+            vault_type,
+            derivation_path: self.derivation_path.clone(),
+            hardened_keys: self.hardened_keys,
+            trigger_input_chunks: self.trigger_input_chunks,
+            trigger_markers: self.trigger_markers,
+            marker_amount: self.marker_amount,
+            fee_change: self.fee_change,
+            nums_offset: self.nums_offset,
+            cold_internal_key: self.cold_internal_key,
+            trigger_cosigners: self.trigger_cosigners.clone(),
+            trigger_threshold: self.trigger_threshold,
+            recovery_address: self.recovery_address.clone(),
+            recovery_height: self.recovery_height,
+            payout: self.payout.clone(),
+            vault_input_index: self.vault_input_index,
+            trigger_metadata: self.trigger_metadata.clone(),
+            trigger_change: self.trigger_change.clone(),
+            trigger_fee: self.trigger_fee,
+            ..Self::blank(self.network)
+        }
+    }
+
+    /// The internal key shared by the vault's script trees. Unless a cold key is set this is a NUMS point:
+    /// the x-only lift of sha256(G uncompressed), offset by `nums_offset` times G.
+    /// Nobody knows its discrete log either way, so the key path stays disabled.
+    pub(crate) fn internal_key(&self) -> Result<XOnlyPublicKey> {
+        if let Some(cold_internal_key) = self.cold_internal_key {
+            return Ok(cold_internal_key);
+        }
         // hash G into a NUMS point
         let hash = sha256::Hash::hash(G.to_bytes_uncompressed().as_slice());
         let point: Point<EvenY, Public, NonZero> = Point::from_xonly_bytes(hash.into_32())
             .ok_or(anyhow!("G_X hash should be a valid x-only point"))?;
         let nums_key = XOnlyPublicKey::from_slice(point.to_xonly_bytes().as_slice())?;
-        let secp = Secp256k1::new();
+        if self.nums_offset == 0 {
+            return Ok(nums_key);
+        }
+        let mut offset = [0u8; 32];
+        offset[28..].copy_from_slice(&self.nums_offset.to_be_bytes());
+        let (offset_key, _) = nums_key.add_tweak(&SECP, &Scalar::from_be_bytes(offset)?)?;
+        Ok(offset_key)
+    }
 
-        Ok(TaprootBuilder::new()
-            .add_leaf(
-                1,
-                ctv_vault_complete_withdrawal(
-                    self.withdraw_x_only_public_key(),
-                    self.timelock_in_blocks,
+    /// The parity of the deposit address's taproot output key
+    pub(crate) fn output_key_parity(&self) -> Result<Parity> {
+        Ok(self.deposit_spend_info()?.output_key_parity())
+    }
+
+    /// Pick the smallest NUMS offset that gives the deposit address an output key with `parity`.
+    /// This changes the vault's addresses, so it has to happen before the vault is funded.
+    pub(crate) fn set_output_key_parity(&mut self, parity: Parity) -> Result<()> {
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't change the output key of a vault that is already funded"
+            ));
+        }
+        if self.cold_internal_key.is_some() {
+            return Err(anyhow!(
+                "the cold internal key fixes the output key, there is no NUMS offset to pick"
+            ));
+        }
+        for nums_offset in 0..=MAX_NUMS_OFFSET {
+            self.nums_offset = nums_offset;
+            if self.output_key_parity()? == parity {
+                return Ok(());
+            }
+        }
+        self.nums_offset = 0;
+        Err(anyhow!(
+            "no NUMS offset up to {} gives a {:?} output key",
+            MAX_NUMS_OFFSET,
+            parity
+        ))
+    }
+
+    pub(crate) fn nums_offset(&self) -> u32 {
+        self.nums_offset
+    }
+
+    /// Replace the NUMS internal key with `key`, enabling a key path sweep by whoever holds its secret.
+    /// This changes the vault's addresses, so it has to happen before the vault is funded.
+    pub(crate) fn set_cold_internal_key(&mut self, key: XOnlyPublicKey) -> Result<()> {
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't change the internal key of a vault that is already funded"
+            ));
+        }
+        self.cold_internal_key = Some(key);
+        self.nums_offset = 0;
+        Ok(())
+    }
+
+    pub(crate) fn cold_internal_key(&self) -> Option<XOnlyPublicKey> {
+        self.cold_internal_key
+    }
+
+    /// The script tree of the output the vault's funds currently sit in
+    pub(crate) fn current_spend_info(&self) -> Result<TaprootSpendInfo> {
+        #[cfg(feature = "op-vault")]
+        if self.vault_type == VaultType::OpVault && self.state == VaultState::Triggered {
+            return self.op_vault_triggered_spend_info(&self.get_withdrawal_address()?);
+        }
+        if self.vault_type == VaultType::CTV && self.state == VaultState::Triggered {
+            self.ctv_trigger_spend_info()
+        } else {
+            self.deposit_spend_info()
+        }
+    }
+
+    pub(crate) fn deposit_spend_info(&self) -> Result<TaprootSpendInfo> {
+        match self.vault_type {
+            VaultType::CAT => self.taproot_spend_info(),
+            VaultType::CTV => self.ctv_deposit_spend_info(),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => self.op_vault_deposit_spend_info(),
+        }
+    }
+
+    fn ctv_trigger_address(&self) -> Result<Address> {
+        let spend_info = self.ctv_trigger_spend_info()?;
+        Ok(Address::p2tr_tweaked(spend_info.output_key(), self.network))
+    }
+
+    /// The keys each leaf of the deposit tree checks signatures against, one entry per leaf that checks any
+    pub(crate) fn expected_deposit_leaf_keys(&self) -> Vec<Vec<XOnlyPublicKey>> {
+        match self.vault_type {
+            VaultType::CAT => vec![
+                self.trigger_keys(),
+                vec![self.withdraw_x_only_public_key()],
+                vec![self.cancel_x_only_public_key()],
+            ],
+            // the deposit leaf only checks the template
+            VaultType::CTV => Vec::new(),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => vec![
+                vec![self.vault_x_only_public_key()],
+                vec![self.cancel_x_only_public_key()],
+            ],
+        }
+    }
+
+    /// The keys each leaf of the tree a trigger creates checks signatures against
+    pub(crate) fn expected_triggered_leaf_keys(&self) -> Vec<Vec<XOnlyPublicKey>> {
+        match self.vault_type {
+            VaultType::CAT => self.expected_deposit_leaf_keys(),
+            VaultType::CTV => vec![
+                vec![self.withdraw_x_only_public_key()],
+                vec![self.cancel_x_only_public_key()],
+            ],
+            // the withdrawal leaf only checks the template
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => vec![vec![self.cancel_x_only_public_key()]],
+        }
+    }
+
+    /// The opcodes each leaf depends on that aren't active on mainnet, leaves that list none
+    /// (and the key path, when there is a cold internal key) can be spent there today
+    pub(crate) fn leaf_opcode_requirements(&self) -> Result<Vec<(String, Vec<&'static str>)>> {
+        Ok(self
+            .named_leaves()?
+            .into_iter()
+            .map(|(name, script)| (name.to_string(), inactive_opcodes(&script)))
+            .collect())
+    }
+
+    /// Every tapleaf script of the vault, across the deposit and CTV triggered trees
+    pub(crate) fn named_leaves(&self) -> Result<Vec<(&'static str, ScriptBuf)>> {
+        let mut leaves = match self.vault_type {
+            VaultType::CAT => vec![
+                ("trigger", self.trigger_script()),
+                ("complete", self.cat_complete_script()),
+                ("cancel", self.cat_cancel_script()),
+            ],
+            VaultType::CTV => vec![
+                ("deposit", ctv_vault_deposit(self.ctv_template_hash()?)),
+                ("complete", self.ctv_complete_script()?),
+                (
+                    "cancel",
+                    ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()),
                 ),
-            )?
-            .add_leaf(
-                1,
-                ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()),
-            )?
-            .finalize(&secp, nums_key)
-            .expect("finalizing taproot spend info with a new keypair should always work"))
-    }
-
-    fn ctv_hash(&self) -> [u8; 32] {
-        let txn = self.ctv_trigger_tx_template();
-
-        let tx_commitment_spec = TxCommitmentSpec {
-            epoch: false,
-            control: false,
-            prevouts: false,
-            prev_amounts: false,
-            prev_sciptpubkeys: false,
-            spend_type: false,
-            annex: false,
-            single_output: false,
-            scriptpath: false,
-            ..Default::default()
+            ],
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => {
+                let mut leaves = vec![
+                    ("trigger", self.op_vault_trigger_script()),
+                    ("recover", self.op_vault_recover_script()),
+                ];
+                // the withdrawal leaf only exists once a trigger picked the destination
+                if let Ok(destination) = self.get_withdrawal_address() {
+                    leaves.push(("withdrawal", self.op_vault_withdrawal_script(&destination)?));
+                }
+                leaves
+            }
         };
+        if let Some(recovery_leaf) = self.recovery_leaf()? {
+            leaves.push(("recovery", recovery_leaf));
+        }
+        Ok(leaves)
+    }
 
-        let components = get_sigmsg_components(
-            &tx_commitment_spec,
-            &txn,
-            0,
-            &[],
-            None,
-            TapLeafHash::all_zeros(),
-            TapSighashType::Default,
-        )
-        .unwrap();
+    fn taproot_spend_info(&self) -> Result<TaprootSpendInfo> {
+        self.spend_info_cache
+            .get_or_build(SpendTree::Deposit, self.tree_params(), || {
+                self.build_taproot_spend_info()
+            })
+    }
+
+    fn build_taproot_spend_info(&self) -> Result<TaprootSpendInfo> {
+        let nums_key = self.internal_key()?;
+        let recovery_leaf = self.recovery_leaf()?;
+        // the recovery leaf sits next to the trigger leaf, so the tree stays balanced
+        let depth = if recovery_leaf.is_some() { 2 } else { 1 };
+        let mut builder = TaprootBuilder::new().add_leaf(depth, self.trigger_script())?;
+        if let Some(recovery_leaf) = recovery_leaf {
+            builder = builder.add_leaf(2, recovery_leaf)?;
+        }
+        let spend_info = builder
+            .add_leaf(2, self.cat_complete_script())?
+            .add_leaf(2, self.cat_cancel_script())?
+            .finalize(&SECP, nums_key)
+            .map_err(|_| VaultError::TaprootFinalize)?;
+        verify_leaf_keys_consistent(&spend_info, &self.expected_deposit_leaf_keys())?;
+        Ok(spend_info)
+    }
+
+    /// The script tree of the output a trigger transaction creates
+    pub(crate) fn triggered_spend_info(&self) -> Result<TaprootSpendInfo> {
+        match self.vault_type {
+            VaultType::CAT => self.taproot_spend_info(),
+            VaultType::CTV => self.ctv_trigger_spend_info(),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => {
+                self.op_vault_triggered_spend_info(&self.get_withdrawal_address()?)
+            }
+        }
+    }
+
+    fn ctv_deposit_spend_info(&self) -> Result<TaprootSpendInfo> {
+        self.spend_info_cache
+            .get_or_build(SpendTree::CtvDeposit, self.tree_params(), || {
+                self.build_ctv_deposit_spend_info()
+            })
+    }
+
+    fn build_ctv_deposit_spend_info(&self) -> Result<TaprootSpendInfo> {
+        let nums_key = self.internal_key()?;
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, ctv_vault_deposit(self.ctv_template_hash()?))?
+            .finalize(&SECP, nums_key)
+            .map_err(|_| VaultError::TaprootFinalize)?;
+        verify_leaf_keys_consistent(&spend_info, &self.expected_deposit_leaf_keys())?;
+        Ok(spend_info)
+    }
+
+    fn ctv_trigger_spend_info(&self) -> Result<TaprootSpendInfo> {
+        self.spend_info_cache
+            .get_or_build(SpendTree::CtvTrigger, self.tree_params(), || {
+                self.build_ctv_trigger_spend_info()
+            })
+    }
+
+    fn build_ctv_trigger_spend_info(&self) -> Result<TaprootSpendInfo> {
+        let nums_key = self.internal_key()?;
+        let complete_leaf = self.ctv_complete_script()?;
+        let cancel_leaf = ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key());
+        let mut leaves = vec![
+            ("complete", complete_leaf.clone()),
+            ("cancel", cancel_leaf.clone()),
+        ];
+
+        let spend_info = match self.recovery_leaf()? {
+            // the cancel leaf keeps the shortest control block
+            Some(recovery_leaf) => {
+                leaves.push(("recovery", recovery_leaf.clone()));
+                TaprootBuilder::new()
+                    .add_leaf(1, cancel_leaf)?
+                    .add_leaf(2, complete_leaf)?
+                    .add_leaf(2, recovery_leaf)?
+            }
+            None => TaprootBuilder::new()
+                .add_leaf(1, complete_leaf)?
+                .add_leaf(1, cancel_leaf)?,
+        }
+        .finalize(&SECP, nums_key)
+        .map_err(|_| VaultError::TaprootFinalize)?;
+        verify_leaves_reachable(&spend_info, &leaves)?;
+        verify_leaf_keys_consistent(&spend_info, &self.expected_triggered_leaf_keys())?;
+        Ok(spend_info)
+    }
 
-        let mut buffer = Vec::new();
-        buffer.extend(components[0].clone()); // version
-        buffer.extend(components[1].clone()); // locktime
-        buffer.extend((txn.input.len() as u32).to_le_bytes()); // inputs len
-        buffer.extend(components[2].clone()); // sequences
-        buffer.extend((txn.output.len() as u32).to_le_bytes()); // outputs len
-        buffer.extend(components[3].clone()); // outputs hash
-        buffer.extend(components[4].clone()); // input index
+    /// The template hash a CTV vault's deposit leaf commits to, what any trigger has to hash to
+    pub(crate) fn ctv_template_hash(&self) -> Result<[u8; 32]> {
+        template_hash(&self.ctv_trigger_tx_template()?, self.vault_input_index)
+    }
 
-        let hash = sha256::Hash::hash(&buffer);
+    /// Check that `trigger_tx` is the transaction a CTV vault's deposit leaf commits to: its version, locktime,
+    /// input count, sequences and outputs hash to the template hash in the leaf of the vault's deposit tree
+    pub(crate) fn verify_ctv_commitment(&self, trigger_tx: &Transaction) -> Result<()> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!(
+                "only a CTV vault commits to its trigger transaction"
+            ));
+        }
+        let committed = self.ctv_template_hash()?;
+        verify_leaves_reachable(
+            &self.ctv_deposit_spend_info()?,
+            &[("deposit", ctv_vault_deposit(committed))],
+        )?;
+        let actual = template_hash(trigger_tx, self.vault_input_index)?;
+        if actual != committed {
+            return Err(anyhow!(
+                "trigger transaction {} hashes to {} but the deposit leaf commits to {}",
+                trigger_tx.txid(),
+                actual.to_hex_string(Case::Lower),
+                committed.to_hex_string(Case::Lower)
+            ));
+        }
+        Ok(())
+    }
 
-        hash.to_byte_array()
+    /// Check a candidate trigger before it's signed or broadcast: it has to hash to the committed template and,
+    /// once the vault is funded, spend the vault output at the vault's input index
+    pub(crate) fn verify_ctv_spend(&self, tx: &Transaction) -> Result<()> {
+        self.verify_ctv_commitment(tx)?;
+        if let Some(outpoint) = self.current_outpoint {
+            let spent = tx
+                .input
+                .get(self.vault_input_index)
+                .map(|input| input.previous_output);
+            if spent != Some(outpoint) {
+                return Err(anyhow!(
+                    "{} doesn't spend the vault output {} as input {}",
+                    tx.txid(),
+                    outpoint,
+                    self.vault_input_index
+                ));
+            }
+        }
+        Ok(())
     }
 
     fn vault_x_only_public_key(&self) -> XOnlyPublicKey {
-        return self.vault_keypair.x_only_public_key().0;
+        self.vault_keypair.x_only_public_key().0
     }
     fn withdraw_x_only_public_key(&self) -> XOnlyPublicKey {
-        return self.withdraw_keypair.x_only_public_key().0;
+        self.withdraw_keypair.x_only_public_key().0
     }
     fn cancel_x_only_public_key(&self) -> XOnlyPublicKey {
-        return self.cancel_keypair.x_only_public_key().0;
+        self.cancel_keypair.x_only_public_key().0
     }
 
-    fn sign_transaction(
-        &self,
-        txn: &Transaction,
-        prevouts: &[TxOut],
-        leaf_hash: TapLeafHash,
-        keypair: Keypair,
-    ) -> Vec<u8> {
-        let secp = Secp256k1::new();
-        let mut sighashcache = SighashCache::new(txn);
-        let sighash = sighashcache
-            .taproot_script_spend_signature_hash(
-                0,
-                &Prevouts::All(prevouts),
-                leaf_hash,
-                TapSighashType::All,
-            )
-            .unwrap();
-        let message = Message::from_digest_slice(sighash.as_byte_array()).unwrap();
-        let signature = secp.sign_schnorr(&message, &keypair);
-        let final_sig = Signature {
-            sig: signature,
-            hash_ty: TapSighashType::All,
-        };
-        return final_sig.to_vec();
+    pub(crate) fn cat_complete_script(&self) -> ScriptBuf {
+        vault_complete_withdrawal(
+            self.withdraw_x_only_public_key(),
+            self.timelock,
+            self.trigger_input_chunks,
+            self.trigger_markers,
+            self.marker_amount,
+            self.fee_change,
+        )
     }
 
-    pub(crate) fn create_trigger_tx(
+    pub(crate) fn cat_cancel_script(&self) -> ScriptBuf {
+        vault_cancel_withdrawal(self.cancel_x_only_public_key(), self.fee_change)
+    }
+
+    /// Build the transaction for `path` with the given fee input, which is left for the fee wallet to sign.
+    /// A trigger needs the `destination` to withdraw to, a complete uses the stored withdrawal address and trigger.
+    pub(crate) fn create_path_tx(
         &self,
+        path: SpendPath,
         fee_paying_utxo: &OutPoint,
         fee_paying_output: TxOut,
-        target_address: &Address,
+        destination: Option<&Address>,
     ) -> Result<Transaction> {
+        match (path, &self.vault_type) {
+            (SpendPath::Trigger, VaultType::CAT) => {
+                let destination =
+                    destination.ok_or(anyhow!("a trigger needs a withdrawal address"))?;
+                let targets = std::iter::once(destination.clone())
+                    .chain(self.marker_addresses()?)
+                    .collect::<Vec<_>>();
+                Ok(self
+                    .create_trigger_tx(&[(*fee_paying_utxo, fee_paying_output)], &targets)?
+                    .into_transaction())
+            }
+            (SpendPath::Trigger, VaultType::CTV) => {
+                self.create_ctv_trigger_tx(Some(fee_paying_utxo))
+            }
+            (SpendPath::Complete, VaultType::CAT) => {
+                self.verify_trigger_consistency()?;
+                Ok(self
+                    .create_complete_tx(
+                        fee_paying_utxo,
+                        fee_paying_output,
+                        &self.get_withdrawal_address()?,
+                        &self.get_trigger_transaction()?,
+                    )?
+                    .into_transaction())
+            }
+            (SpendPath::Complete, VaultType::CTV) => {
+                self.create_ctv_complete_tx(fee_paying_utxo, fee_paying_output)
+            }
+            (SpendPath::Cancel, VaultType::CAT) => Ok(self
+                .create_cancel_tx(fee_paying_utxo, fee_paying_output)?
+                .into_transaction()),
+            (SpendPath::Cancel, VaultType::CTV) => {
+                self.create_ctv_cancel_tx(fee_paying_utxo, fee_paying_output)
+            }
+            #[cfg(feature = "op-vault")]
+            (SpendPath::Trigger, VaultType::OpVault) => self.create_op_vault_trigger_tx(
+                fee_paying_utxo,
+                fee_paying_output,
+                destination.ok_or(anyhow!("a trigger needs a withdrawal address"))?,
+            ),
+            #[cfg(feature = "op-vault")]
+            (SpendPath::Complete, VaultType::OpVault) => {
+                self.create_op_vault_withdraw_tx(fee_paying_utxo)
+            }
+            #[cfg(feature = "op-vault")]
+            (SpendPath::Cancel, VaultType::OpVault) => {
+                self.create_op_vault_recover_tx(fee_paying_utxo, fee_paying_output)
+            }
+        }
+    }
+
+    /// Build a trigger transaction paying its fee from one or more `fee_inputs`.
+    /// The trigger leaf only sees the fee inputs' amounts and scriptpubkeys as one concatenated witness element each,
+    /// so any number of them (taproot, segwit or legacy) are committed to the same way as a single one.
+    pub(crate) fn create_trigger_tx(
+        &self,
+        fee_inputs: &[(OutPoint, TxOut)],
+        target_addresses: &[Address],
+    ) -> Result<BuiltTx> {
+        self.build_trigger_tx(fee_inputs, target_addresses, None)
+    }
+
+    /// [Self::create_trigger_tx] paying `change` last, which a vault built with fee change allows
+    pub(crate) fn build_trigger_tx(
+        &self,
+        fee_inputs: &[(OutPoint, TxOut)],
+        target_addresses: &[Address],
+        change: Option<TxOut>,
+    ) -> Result<BuiltTx> {
+        let _span = self.build_span(SpendPath::Trigger);
+        if fee_inputs.is_empty() {
+            return Err(anyhow!("a trigger needs at least one fee input"));
+        }
+        if change.is_some() && !self.fee_change {
+            return Err(anyhow!("the vault's trigger leaf doesn't allow change"));
+        }
+        if target_addresses.len() != self.trigger_markers {
+            return Err(anyhow!(
+                "got {} marker destinations but the trigger script was built for {}",
+                target_addresses.len(),
+                self.trigger_markers
+            ));
+        }
+        // signal replaceability so a stuck trigger can be fee bumped, the ground lock time is
+        // a height long past so it doesn't matter that this enables it
         let mut vault_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
+            previous_output: self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
             ..Default::default()
         };
-        let fee_txin = TxIn {
+        let fee_txins = fee_inputs.iter().map(|(fee_paying_utxo, _)| TxIn {
             previous_output: *fee_paying_utxo,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
             ..Default::default()
-        };
+        });
         let vault_output = TxOut {
             script_pubkey: self.address()?.script_pubkey(),
             value: self.amount,
         };
-        let target_output = TxOut {
-            script_pubkey: target_address.script_pubkey(),
-            value: Amount::from_sat(546),
-        };
+        let marker_outputs = target_addresses
+            .iter()
+            .map(|target_address| {
+                self.require_marker_above_dust(target_address)?;
+                Ok(TxOut {
+                    script_pubkey: target_address.script_pubkey(),
+                    value: self.marker_amount,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let txn = Transaction {
             lock_time: LockTime::ZERO,
             version: Version::TWO,
-            input: vec![vault_txin.clone(), fee_txin],
-            output: vec![vault_output.clone(), target_output.clone()],
+            input: std::iter::once(vault_txin.clone())
+                .chain(fee_txins)
+                .collect(),
+            output: std::iter::once(vault_output.clone())
+                .chain(marker_outputs)
+                .chain(change.clone())
+                .collect(),
         };
 
-        let tx_commitment_spec = TxCommitmentSpec {
-            prev_sciptpubkeys: false,
-            prev_amounts: false,
-            input_index: false,
-            outputs: false,
-            ..Default::default()
-        };
+        // the complete leaf was built for a fixed number of chunks of this transaction's inputs
+        let mut input_buffer = Vec::new();
+        txn.input.consensus_encode(&mut input_buffer)?;
+        let chunk_count = witness_chunk_count(input_buffer.len());
+        if chunk_count != self.trigger_input_chunks {
+            return Err(anyhow!(
+                "{} fee inputs make the trigger inputs take {} chunks but the complete script was built for {}",
+                fee_inputs.len(),
+                chunk_count,
+                self.trigger_input_chunks
+            ));
+        }
 
-        let leaf_hash = TapLeafHash::from_script(
-            &vault_trigger_withdrawal(self.vault_x_only_public_key()),
-            LeafVersion::TapScript,
-        );
+        let tx_commitment_spec = TxCommitmentSpec::for_trigger();
+
+        let leaf_hash = TapLeafHash::from_script(&self.trigger_script(), LeafVersion::TapScript);
         let vault_txout = TxOut {
             script_pubkey: self.address()?.script_pubkey().clone(),
             value: self.amount,
         };
+        let prevouts: Vec<TxOut> = std::iter::once(vault_txout.clone())
+            .chain(
+                fee_inputs
+                    .iter()
+                    .map(|(_, fee_paying_output)| fee_paying_output.clone()),
+            )
+            .collect();
+        check_fee_floor(&txn, &prevouts, self.min_fee_rate)?;
         let contract_components = signature_building::grind_transaction(
             txn,
-            signature_building::GrindField::LockTime,
-            &[vault_txout.clone(), fee_paying_output.clone()],
+            grind_field_for_path(SpendPath::Trigger),
+            self.grind_bounds,
+            0,
+            &prevouts,
             leaf_hash,
         )?;
 
@@ -407,27 +1776,32 @@ impl VaultCovenant {
             &tx_commitment_spec,
             &txn,
             0,
-            &[vault_txout.clone(), fee_paying_output.clone()],
+            &prevouts,
             None,
             leaf_hash,
             TapSighashType::Default,
         )?;
 
-        for component in witness_components.iter() {
-            debug!(
-                "pushing component <0x{}> into the witness",
-                component.to_hex_string(Case::Lower)
-            );
+        for (index, component) in witness_components.iter().enumerate() {
+            witness_component(index, component);
             vault_txin.witness.push(component.as_slice());
         }
 
-        let mut target_scriptpubkey_buffer = Vec::new();
-        target_output
-            .script_pubkey
-            .consensus_encode(&mut target_scriptpubkey_buffer)?;
-        vault_txin
-            .witness
-            .push(target_scriptpubkey_buffer.as_slice());
+        if self.fee_change {
+            vault_txin
+                .witness
+                .push(change_witness_element(change.as_ref()));
+        }
+        // the script takes the marker scriptpubkeys off the stack first marker first
+        for marker_output in txn.output[1..=self.trigger_markers].iter().rev() {
+            let mut target_scriptpubkey_buffer = Vec::new();
+            marker_output
+                .script_pubkey
+                .consensus_encode(&mut target_scriptpubkey_buffer)?;
+            vault_txin
+                .witness
+                .push(target_scriptpubkey_buffer.as_slice());
+        }
 
         let mut amount_buffer = Vec::new();
         self.amount.consensus_encode(&mut amount_buffer)?;
@@ -439,14 +1813,21 @@ impl VaultCovenant {
         vault_txin.witness.push(scriptpubkey_buffer.as_slice());
 
         let mut fee_amount_buffer = Vec::new();
-        fee_paying_output
-            .value
-            .consensus_encode(&mut fee_amount_buffer)?;
-        vault_txin.witness.push(fee_amount_buffer.as_slice());
         let mut fee_scriptpubkey_buffer = Vec::new();
-        fee_paying_output
-            .script_pubkey
-            .consensus_encode(&mut fee_scriptpubkey_buffer)?;
+        for (_, fee_paying_output) in fee_inputs {
+            fee_paying_output
+                .value
+                .consensus_encode(&mut fee_amount_buffer)?;
+            fee_paying_output
+                .script_pubkey
+                .consensus_encode(&mut fee_scriptpubkey_buffer)?;
+        }
+        if scriptpubkey_buffer.len() + fee_scriptpubkey_buffer.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(anyhow!(
+                "fee input scriptpubkeys are too large to concatenate on the stack"
+            ));
+        }
+        vault_txin.witness.push(fee_amount_buffer.as_slice());
         vault_txin.witness.push(fee_scriptpubkey_buffer.as_slice());
 
         let computed_signature = signature_building::compute_signature_from_components(
@@ -457,29 +1838,50 @@ impl VaultCovenant {
         vault_txin.witness.push([computed_signature[63]]); // push the last byte of the signature
         vault_txin.witness.push([computed_signature[63] + 1]); // push the last byte of the signature
 
-        let sig = self.sign_transaction(
-            &txn,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            leaf_hash,
-            self.vault_keypair,
-        );
-        vault_txin.witness.push(sig);
+        for sig in self.trigger_key_signatures(&txn, &prevouts, leaf_hash)? {
+            vault_txin.witness.push(sig);
+        }
 
-        vault_txin
-            .witness
-            .push(vault_trigger_withdrawal(self.vault_x_only_public_key()).to_bytes());
+        vault_txin.witness.push(self.trigger_script().to_bytes());
         vault_txin.witness.push(
             self.taproot_spend_info()?
-                .control_block(&(
-                    vault_trigger_withdrawal(self.vault_x_only_public_key()).clone(),
-                    LeafVersion::TapScript,
-                ))
+                .control_block(&(self.trigger_script(), LeafVersion::TapScript))
                 .expect("control block should work")
                 .serialize(),
         );
         txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&txn, 0, &prevouts)?;
+
+        let fee_paid = fee_paid(&txn, &prevouts)?;
+        let min_fee = signed_fee(&txn, self.min_fee_rate)?;
+        if fee_paid < min_fee {
+            return Err(anyhow!(
+                "fee inputs only contribute {} in fees, the trigger needs at least {}",
+                fee_paid,
+                min_fee
+            ));
+        }
 
-        Ok(txn)
+        let built = BuiltTx::new(txn, &prevouts)?;
+        debug!(
+            "the complete will spend the trigger's vault output {}",
+            built.trigger_outpoint()
+        );
+        Ok(built)
+    }
+
+    /// The byte ranges of the serialized trigger transaction (as hashed for its txid) carried by each of the
+    /// complete witness's input chunks, in the order they're pushed
+    pub(crate) fn complete_witness_chunk_map(
+        &self,
+        trigger_tx: &Transaction,
+    ) -> Vec<(usize, usize)> {
+        let inputs_start = serialize(&trigger_tx.version).len();
+        witness_chunk_ranges(serialize(&trigger_tx.input).len())
+            .into_iter()
+            .map(|(start, end)| (inputs_start + start, inputs_start + end))
+            .collect()
     }
 
     pub(crate) fn create_complete_tx(
@@ -488,12 +1890,19 @@ impl VaultCovenant {
         fee_paying_output: TxOut,
         target_address: &Address,
         trigger_tx: &Transaction,
-    ) -> Result<Transaction> {
+    ) -> Result<BuiltTx> {
+        let _span = self.build_span(SpendPath::Complete);
+        let committed_destination = Self::trigger_committed_destination(trigger_tx)?;
+        if committed_destination != target_address.script_pubkey() {
+            return Err(anyhow!(
+                "withdrawal destination {} does not match the destination committed at trigger time",
+                target_address
+            ));
+        }
+
         let mut vault_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
-            sequence: Sequence::from_height(self.timelock_in_blocks),
+            previous_output: self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            sequence: csv_sequence(self.timelock)?,
             ..Default::default()
         };
         let fee_txin = TxIn {
@@ -513,23 +1922,24 @@ impl VaultCovenant {
             output: vec![target_output.clone()],
         };
 
-        let tx_commitment_spec = TxCommitmentSpec {
-            prevouts: false,
-            outputs: false,
-            ..Default::default()
-        };
+        let tx_commitment_spec = TxCommitmentSpec::for_complete();
 
-        let leaf_hash = TapLeafHash::from_script(
-            &vault_complete_withdrawal(self.withdraw_x_only_public_key(), self.timelock_in_blocks),
-            LeafVersion::TapScript,
-        );
+        let leaf_hash =
+            TapLeafHash::from_script(&self.cat_complete_script(), LeafVersion::TapScript);
         let vault_txout = TxOut {
             script_pubkey: self.address()?.script_pubkey().clone(),
             value: self.amount,
         };
+        check_fee_floor(
+            &txn,
+            &[vault_txout.clone(), fee_paying_output.clone()],
+            self.min_fee_rate,
+        )?;
         let contract_components = signature_building::grind_transaction(
             txn,
-            signature_building::GrindField::Sequence,
+            grind_field_for_path(SpendPath::Complete),
+            self.grind_bounds,
+            0,
             &[vault_txout.clone(), fee_paying_output.clone()],
             leaf_hash,
         )?;
@@ -545,11 +1955,8 @@ impl VaultCovenant {
             TapSighashType::Default,
         )?;
 
-        for component in witness_components.iter() {
-            debug!(
-                "pushing component <0x{}> into the witness",
-                component.to_hex_string(Case::Lower)
-            );
+        for (index, component) in witness_components.iter().enumerate() {
+            witness_component(index, component);
             vault_txin.witness.push(component.as_slice());
         }
 
@@ -561,14 +1968,19 @@ impl VaultCovenant {
         vault_txin.witness.push(version_buffer.as_slice());
 
         // push the trigger_tx input in chunks no larger than 80 bytes
+        // we have to break this up into 80 byte chunks because there's a policy limit on the size of a single push
         let mut input_buffer = Vec::new();
         trigger_tx.input.consensus_encode(&mut input_buffer)?;
-        //vault_txin.witness.push(input_buffer.as_slice());
-        // TODO: handle the case where we have more than 2 chunks
-        // we have to break this up into 80 byte chunks because there's a policy limit on the size of a single push
-        let chunk_size = 80;
-        for chunk in input_buffer.chunks(chunk_size) {
-            vault_txin.witness.push(chunk);
+        let chunk_count = witness_chunk_count(input_buffer.len());
+        if chunk_count != self.trigger_input_chunks {
+            return Err(anyhow!(
+                "trigger transaction inputs take {} chunks but the complete script was built for {}",
+                chunk_count,
+                self.trigger_input_chunks
+            ));
+        }
+        for (start, end) in witness_chunk_ranges(input_buffer.len()) {
+            vault_txin.witness.push(&input_buffer[start..end]);
         }
 
         let mut locktime_buffer = Vec::new();
@@ -577,6 +1989,22 @@ impl VaultCovenant {
             .consensus_encode(&mut locktime_buffer)?;
         vault_txin.witness.push(locktime_buffer.as_slice());
 
+        // the markers after the first go in whole, the script appends them first marker first
+        let change = self.trigger_paid_change(trigger_tx);
+        if trigger_tx.output.len() != self.trigger_markers + 1 + change.is_some() as usize {
+            return Err(anyhow!(
+                "trigger transaction has {} outputs but the complete script was built for {} markers",
+                trigger_tx.output.len(),
+                self.trigger_markers
+            ));
+        }
+        if self.fee_change {
+            vault_txin.witness.push(change_witness_element(change));
+        }
+        for marker_output in trigger_tx.output[2..=self.trigger_markers].iter().rev() {
+            vault_txin.witness.push(serialize(marker_output).as_slice());
+        }
+
         let mut vault_scriptpubkey_buffer = Vec::new();
         self.address()?
             .script_pubkey()
@@ -611,338 +2039,1771 @@ impl VaultCovenant {
         vault_txin.witness.push([computed_signature[63]]); // push the last byte of the signature
         vault_txin.witness.push([computed_signature[63] + 1]); // push the last byte of the signature
 
-        let sig = self.sign_transaction(
+        let sig = sign_transaction(
             &txn,
             &[vault_txout.clone(), fee_paying_output.clone()],
             leaf_hash,
             self.withdraw_keypair,
-        );
+            TapSighashType::All,
+        )?;
         vault_txin.witness.push(sig);
 
-        vault_txin.witness.push(
-            vault_complete_withdrawal(self.withdraw_x_only_public_key(), self.timelock_in_blocks)
-                .to_bytes(),
-        );
+        vault_txin
+            .witness
+            .push(self.cat_complete_script().to_bytes());
         vault_txin.witness.push(
             self.taproot_spend_info()?
-                .control_block(&(
-                    vault_complete_withdrawal(
-                        self.withdraw_x_only_public_key(),
-                        self.timelock_in_blocks,
-                    )
-                    .clone(),
-                    LeafVersion::TapScript,
-                ))
+                .control_block(&(self.cat_complete_script().clone(), LeafVersion::TapScript))
                 .expect("control block should work")
                 .serialize(),
         );
 
         txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        require_csv_satisfied(&txn, 0, self.timelock)?;
+        let prevouts = [vault_txout, fee_paying_output];
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&txn, 0, &prevouts)?;
 
-        Ok(txn)
+        BuiltTx::new(txn, &prevouts)
+    }
+
+    /// The CAT trigger commits to the final withdrawal destination through the scriptpubkey of its marker output,
+    /// the complete script rebuilds the trigger txid using the complete's own target scriptpubkey,
+    /// so the complete can only pay out to this destination
+    pub(crate) fn trigger_committed_destination(trigger_tx: &Transaction) -> Result<ScriptBuf> {
+        let marker = trigger_tx
+            .output
+            .get(1)
+            .ok_or(anyhow!("trigger transaction has no marker output"))?;
+        Ok(marker.script_pubkey.clone())
+    }
+
+    /// Build an unsigned transaction reclaiming the dust marker output left behind by a CAT trigger.
+    /// The marker pays the withdrawal destination, so its owner (and the owner of the fee input) must sign it.
+    pub(crate) fn sweep_marker(
+        &self,
+        marker_outpoint: OutPoint,
+        destination: &Address,
+        fee_input: (OutPoint, TxOut),
+        fee: Amount,
+    ) -> Result<Transaction> {
+        if self.vault_type != VaultType::CAT {
+            return Err(anyhow!("only CAT vault triggers create a marker output"));
+        }
+        let (fee_paying_utxo, fee_paying_output) = fee_input;
+        let value = (self.marker_amount + fee_paying_output.value)
+            .checked_sub(fee)
+            .ok_or(anyhow!(
+                "fee of {} is more than the marker and fee input are worth",
+                fee
+            ))?;
+
+        Ok(Transaction {
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+            input: vec![
+                TxIn {
+                    previous_output: marker_outpoint,
+                    ..Default::default()
+                },
+                TxIn {
+                    previous_output: fee_paying_utxo,
+                    ..Default::default()
+                },
+            ],
+            output: vec![TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value,
+            }],
+        })
     }
 
     pub(crate) fn create_cancel_tx(
         &self,
         fee_paying_utxo: &OutPoint,
         fee_paying_output: TxOut,
+    ) -> Result<BuiltTx> {
+        let _span = self.build_span(SpendPath::Cancel);
+        let txn = self.cancel_signer()?.create_cancel_tx(
+            self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            fee_paying_utxo,
+            fee_paying_output.clone(),
+        )?;
+        BuiltTx::new(txn, &[self.triggered_output()?, fee_paying_output])
+    }
+
+    pub(crate) fn create_ctv_complete_tx(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
     ) -> Result<Transaction> {
+        self.build_ctv_complete_tx(Some((fee_paying_utxo, fee_paying_output)), Amount::ZERO)
+    }
+
+    /// Build and sign a CTV complete paying the recipients and the withdrawal address the vault amount minus `fee`,
+    /// with an external fee input if there is one. A partial withdrawal pays the rest into a residual vault.
+    pub(crate) fn build_ctv_complete_tx(
+        &self,
+        fee_input: Option<(&OutPoint, TxOut)>,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Complete);
         let mut vault_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
+            previous_output: self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            sequence: csv_sequence(self.timelock)?,
             ..Default::default()
         };
-        let fee_txin = TxIn {
-            previous_output: fee_paying_utxo.clone(),
-            ..Default::default()
-        };
-        let output = TxOut {
-            script_pubkey: self.address()?.script_pubkey(),
-            value: self.amount,
-        };
-
-        let txn = Transaction {
-            lock_time: LockTime::ZERO,
+        let mut output = self.withdrawal_outputs(fee)?;
+        if self.withdrawal_amount.is_some() {
+            output.push(self.residual_vault()?.current_txout()?);
+        }
+        if !self.payout.is_empty() && fee_input.is_none() {
+            return Err(anyhow!("the payout template pays its fee from a fee input"));
+        }
+        let (fee_txin, fee_prevout) = fee_input
+            .map(|(fee_paying_utxo, fee_paying_output)| {
+                let fee_txin = TxIn {
+                    previous_output: *fee_paying_utxo,
+                    ..Default::default()
+                };
+                (fee_txin, fee_paying_output)
+            })
+            .unzip();
+        let (input, vault_index) =
+            arrange_inputs(vault_txin.clone(), fee_txin, self.vault_input_index);
+        let (prevouts, _) = arrange_inputs(
+            self.ctv_trigger_output()?,
+            fee_prevout,
+            self.vault_input_index,
+        );
+        let mut txn = Transaction {
             version: Version::TWO,
-            input: vec![vault_txin.clone(), fee_txin],
-            output: vec![output.clone()],
-        };
-
-        let tx_commitment_spec = TxCommitmentSpec {
-            prev_sciptpubkeys: false,
-            prev_amounts: false,
-            input_index: false,
-            outputs: false,
-            ..Default::default()
+            lock_time: LockTime::ZERO,
+            input,
+            output,
         };
+        let complete_script = self.ctv_complete_script()?;
+        let leafhash = TapLeafHash::from_script(&complete_script, LeafVersion::TapScript);
+        let sig = sign_transaction_input(
+            &txn,
+            vault_index,
+            &prevouts,
+            leafhash,
+            self.withdraw_keypair,
+            self.sighash_type,
+        )?;
+        vault_txin.witness.push(sig);
 
-        let leaf_hash = TapLeafHash::from_script(
-            &vault_cancel_withdrawal(self.cancel_x_only_public_key()),
-            LeafVersion::TapScript,
+        vault_txin.witness.push(complete_script.to_bytes());
+        vault_txin.witness.push(
+            self.ctv_trigger_spend_info()?
+                .control_block(&(complete_script, LeafVersion::TapScript))
+                .expect("control block should work")
+                .serialize(),
         );
-        let vault_txout = TxOut {
-            script_pubkey: self.address()?.script_pubkey().clone(),
-            value: self.amount,
-        };
-        let contract_components = signature_building::grind_transaction(
-            txn,
-            signature_building::GrindField::LockTime,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            leaf_hash,
-        )?;
+        txn.input[vault_index].witness = vault_txin.witness.clone();
+        require_csv_satisfied(&txn, vault_index, self.timelock)?;
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&txn, vault_index, &prevouts)?;
 
-        let mut txn = contract_components.transaction;
-        let witness_components = get_sigmsg_components(
-            &tx_commitment_spec,
-            &txn,
-            0,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            None,
-            leaf_hash,
-            TapSighashType::Default,
-        )?;
+        Ok(txn)
+    }
 
-        for component in witness_components.iter() {
-            debug!(
-                "pushing component <0x{}> into the witness",
-                component.to_hex_string(Case::Lower)
-            );
-            vault_txin.witness.push(component.as_slice());
+    pub(crate) fn create_ctv_cancel_tx(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Cancel);
+        self.create_ctv_cancel_tx_to(
+            fee_paying_utxo,
+            fee_paying_output,
+            self.address()?.script_pubkey(),
+        )
+    }
+
+    /// Cancel a triggered withdrawal and move the funds straight into a brand new vault with fresh keys,
+    /// returning the new vault and the cancel transaction paying its deposit address.
+    /// Only CTV vaults can do this, the CAT cancel leaf requires the funds to go back to the same vault.
+    pub(crate) fn cancel_and_rotate(
+        &self,
+        new_timelock: TimelockKind,
+        fee_input: (OutPoint, TxOut),
+        settings: &Settings,
+    ) -> Result<(VaultCovenant, Transaction)> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!(
+                "the CAT cancel leaf can only send the funds back to the same vault"
+            ));
         }
-        let computed_signature = signature_building::compute_signature_from_components(
-            &contract_components.signature_components,
+        let (fee_paying_utxo, fee_paying_output) = fee_input;
+        let mut new_vault =
+            self.rotated_vault(VaultType::CTV, new_timelock, self.amount, settings)?;
+        let txn = self.create_ctv_cancel_tx_to(
+            &fee_paying_utxo,
+            fee_paying_output,
+            new_vault.address()?.script_pubkey(),
         )?;
+        new_vault.set_current_outpoint(OutPoint {
+            txid: txn.txid(),
+            vout: 0,
+        });
+        Ok((new_vault, txn))
+    }
 
-        let mut amount_buffer = Vec::new();
-        self.amount.consensus_encode(&mut amount_buffer)?;
-        vault_txin.witness.push(amount_buffer.as_slice());
-        let mut scriptpubkey_buffer = Vec::new();
-        output
-            .script_pubkey
-            .consensus_encode(&mut scriptpubkey_buffer)?;
-        vault_txin.witness.push(scriptpubkey_buffer.as_slice());
+    /// Cancel a triggered withdrawal into a cold vault: a new vault like [Self::cancel_and_rotate] makes,
+    /// but with a longer timelock than this one so funds pulled out of a hot vault get more time to be watched
+    pub(crate) fn cancel_to_cold_vault(
+        &self,
+        cold_timelock: TimelockKind,
+        fee_input: (OutPoint, TxOut),
+        settings: &Settings,
+    ) -> Result<(VaultCovenant, Transaction)> {
+        self.require_colder_timelock(cold_timelock)?;
+        self.cancel_and_rotate(cold_timelock, fee_input, settings)
+    }
 
-        let mut fee_amount_buffer = Vec::new();
-        fee_paying_output
-            .value
-            .consensus_encode(&mut fee_amount_buffer)?;
-        vault_txin.witness.push(fee_amount_buffer.as_slice());
-        let mut fee_scriptpubkey_buffer = Vec::new();
-        fee_paying_output
-            .script_pubkey
-            .consensus_encode(&mut fee_scriptpubkey_buffer)?;
-        vault_txin.witness.push(fee_scriptpubkey_buffer.as_slice());
+    /// Reject a cold vault timelock that doesn't outlast this vault's, time-based timelocks are compared
+    /// at 600 seconds a block
+    pub(crate) fn require_colder_timelock(&self, cold_timelock: TimelockKind) -> Result<()> {
+        if cold_timelock.approx_blocks() <= self.timelock.approx_blocks() {
+            return Err(anyhow!(
+                "a cold vault needs a longer timelock than the {} of this vault, not {}",
+                self.timelock,
+                cold_timelock
+            ));
+        }
+        Ok(())
+    }
 
-        let mangled_signature: [u8; 63] = computed_signature[0..63].try_into().unwrap(); // chop off the last byte, so we can provide the 0x00 and 0x01 bytes on the stack
-        vault_txin.witness.push(mangled_signature);
-        vault_txin.witness.push([computed_signature[63]]); // push the last byte of the signature
-        vault_txin.witness.push([computed_signature[63] + 1]); // push the last byte of the signature
+    /// A new `vault_type` vault holding `amount` with keys derived at a fresh vault index, or random keys if this
+    /// vault's aren't derived
+    pub(crate) fn rotated_vault(
+        &self,
+        vault_type: VaultType,
+        new_timelock: TimelockKind,
+        amount: Amount,
+        settings: &Settings,
+    ) -> Result<VaultCovenant> {
+        let mut new_vault = match &self.derivation_path {
+            Some(_) => {
+                if settings.master_key()?.is_none() {
+                    return Err(anyhow!(
+                        "no seed supplied to derive the new vault keys from"
+                    ));
+                }
+                VaultCovenant::new_from_settings(vault_type, new_timelock, amount, settings)?
+            }
+            None => VaultCovenant::new_ephemeral(vault_type, new_timelock, amount, settings)?,
+        };
+        new_vault.recovery_address = self.recovery_address.clone();
+        new_vault.recovery_height = self.recovery_height;
+        Ok(new_vault)
+    }
 
-        let sig = self.sign_transaction(
-            &txn,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            leaf_hash,
-            self.cancel_keypair,
-        );
-        vault_txin.witness.push(sig);
+    fn create_ctv_cancel_tx_to(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        destination: ScriptBuf,
+    ) -> Result<Transaction> {
+        self.cancel_signer()?.create_cancel_tx_to(
+            self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            fee_paying_utxo,
+            fee_paying_output,
+            destination,
+        )
+    }
 
-        vault_txin
+    /// The output a trigger transaction creates, which the complete and cancel transactions spend
+    pub(crate) fn triggered_output(&self) -> Result<TxOut> {
+        match self.vault_type {
+            VaultType::CAT => Ok(TxOut {
+                script_pubkey: self.address()?.script_pubkey(),
+                value: self.amount,
+            }),
+            VaultType::CTV => self.ctv_trigger_output(),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => self.op_vault_triggered_output(&self.get_withdrawal_address()?),
+        }
+    }
+
+    /// The cancel leaf script and the control block proving it is in the tree of the triggered output
+    pub(crate) fn cancel_leaf(&self) -> Result<(ScriptBuf, ControlBlock)> {
+        let (script, spend_info) = match self.vault_type {
+            VaultType::CAT => (self.cat_cancel_script(), self.taproot_spend_info()?),
+            VaultType::CTV => (
+                ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()),
+                self.ctv_trigger_spend_info()?,
+            ),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => (self.op_vault_recover_script(), self.triggered_spend_info()?),
+        };
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("cancel leaf is missing from the script tree"))?;
+        Ok((script, control_block))
+    }
+
+    pub(crate) fn vault_keypair(&self) -> Keypair {
+        self.vault_keypair
+    }
+
+    /// Where the keys were derived from the seed and whether as hardened children, None for random keys
+    pub(crate) fn derivation(&self) -> Option<(&DerivationPath, bool)> {
+        self.derivation_path
+            .as_ref()
+            .map(|path| (path, self.hardened_keys))
+    }
+
+    pub(crate) fn cancel_keypair(&self) -> Keypair {
+        self.cancel_keypair
+    }
+
+    /// The CTV complete leaf script and the control block proving it is in the tree of the triggered output
+    pub(crate) fn ctv_complete_leaf(&self) -> Result<(ScriptBuf, ControlBlock)> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!("only CTV vaults have a CTV complete leaf"));
+        }
+        let script = self.ctv_complete_script()?;
+        let control_block = self
+            .ctv_trigger_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("complete leaf is missing from the script tree"))?;
+        Ok((script, control_block))
+    }
+
+    pub(crate) fn withdraw_keypair(&self) -> Keypair {
+        self.withdraw_keypair
+    }
+
+    /// The output the CTV trigger template commits to, which the complete and cancel transactions spend
+    fn ctv_trigger_output(&self) -> Result<TxOut> {
+        self.ctv_trigger_tx_template()?
+            .output
+            .first()
+            .cloned()
+            .ok_or(anyhow!("CTV trigger template has no outputs"))
+    }
+
+    /// The trigger the CTV deposit leaf commits to: the vault input and a fee input, paying the triggered vault
+    /// output followed by any metadata and change outputs. With a trigger fee the vault input is the only one.
+    pub(crate) fn ctv_trigger_tx_template(&self) -> Result<Transaction> {
+        let output = TxOut {
+            script_pubkey: self.ctv_trigger_address()?.script_pubkey(),
+            value: self.triggered_amount(),
+        };
+        let input = TxIn {
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        };
+        let input_count = if self.trigger_fee.is_some() { 1 } else { 2 };
+
+        Ok(Transaction {
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+            input: vec![input; input_count],
+            output: std::iter::once(output)
+                .chain(self.trigger_extra_outputs()?)
+                .collect(),
+        })
+    }
+
+    /// Build the CTV trigger spending the vault and `fee_paying_utxo`, which has to be None exactly when the
+    /// template pays a trigger fee out of the vault amount
+    pub(crate) fn create_ctv_trigger_tx(
+        &self,
+        fee_paying_utxo: Option<&OutPoint>,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Trigger);
+        let mut txn = self.ctv_trigger_tx_template()?;
+        match (fee_paying_utxo, self.trigger_fee) {
+            (Some(_), Some(fee)) => {
+                return Err(anyhow!(
+                    "the trigger template pays its {} fee out of the vault, it has no slot for a fee input",
+                    fee
+                ))
+            }
+            (None, None) => {
+                return Err(anyhow!(
+                    "the trigger template needs a fee input, the vault doesn't pay a trigger fee"
+                ))
+            }
+            _ => {}
+        }
+        let fee_txin = fee_paying_utxo.map(|fee_paying_utxo| TxIn {
+            previous_output: *fee_paying_utxo,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        });
+        let mut trigger_txin = TxIn {
+            previous_output: self.current_outpoint.ok_or(VaultError::NoCurrentOutpoint)?,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        };
+        let (input, vault_index) =
+            arrange_inputs(trigger_txin.clone(), fee_txin, self.vault_input_index);
+        txn.input = input;
+
+        trigger_txin
             .witness
-            .push(vault_cancel_withdrawal(self.cancel_x_only_public_key()).to_bytes());
-        vault_txin.witness.push(
-            self.taproot_spend_info()?
+            .push(ctv_vault_deposit(self.ctv_template_hash()?).to_bytes());
+        trigger_txin.witness.push(
+            self.ctv_deposit_spend_info()?
                 .control_block(&(
-                    vault_cancel_withdrawal(self.cancel_x_only_public_key()).clone(),
+                    ctv_vault_deposit(self.ctv_template_hash()?).clone(),
                     LeafVersion::TapScript,
                 ))
                 .expect("control block should work")
                 .serialize(),
         );
-        txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        txn.input[vault_index].witness = trigger_txin.witness.clone();
+        // the deposit leaf checks no signature, so the fee input's output doesn't matter here
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(
+            &txn,
+            vault_index,
+            &vec![
+                TxOut {
+                    script_pubkey: self.address()?.script_pubkey(),
+                    value: self.amount,
+                };
+                txn.input.len()
+            ],
+        )?;
 
         Ok(txn)
     }
+}
 
-    pub(crate) fn create_ctv_complete_tx(
-        &self,
-        fee_paying_utxo: &OutPoint,
-        fee_paying_output: TxOut,
-    ) -> Result<Transaction> {
-        let mut vault_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
-            sequence: Sequence::from_height(self.timelock_in_blocks),
-            ..Default::default()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::fees::{input_weight_breakdown, signed_weight, PathFeeRates};
+
+    /// A regtest taproot address of a key only the tests know
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    /// An unspent output of 50k sats a fee input can spend, distinguished by `vout`
+    fn test_fee_input(vout: u32) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    /// A funded seeded CAT vault and the trigger withdrawing it to `destination`, recorded as triggered
+    fn triggered_cat(destination: &Address) -> (VaultCovenant, Transaction) {
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault
+            .create_trigger_tx(&[test_fee_input(0)], std::slice::from_ref(destination))
+            .unwrap()
+            .transaction;
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_trigger_transaction(Some(trigger_tx.clone()));
+        vault.set_state(VaultState::Triggered);
+        (vault, trigger_tx)
+    }
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn vault_trees_pass_the_leaf_key_check() {
+        let cat = seeded_vault(VaultType::CAT);
+        cat.address().unwrap();
+        let ctv = seeded_vault(VaultType::CTV);
+        ctv.address().unwrap();
+        ctv.triggered_spend_info().unwrap();
+    }
+
+    #[test]
+    fn leaf_built_with_the_wrong_key_is_caught() {
+        let vault = seeded_vault(VaultType::CAT);
+        // the cancel leaf signed by the withdraw key instead of the cancel key
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, vault.trigger_script())
+            .unwrap()
+            .add_leaf(2, vault.cat_complete_script())
+            .unwrap()
+            .add_leaf(
+                2,
+                vault_cancel_withdrawal(vault.withdraw_x_only_public_key(), false),
+            )
+            .unwrap()
+            .finalize(&SECP, vault.internal_key().unwrap())
+            .unwrap();
+        assert!(
+            verify_leaf_keys_consistent(&spend_info, &vault.expected_deposit_leaf_keys()).is_err()
+        );
+    }
+
+    #[test]
+    fn missing_leaf_is_caught() {
+        let vault = seeded_vault(VaultType::CTV);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(
+                0,
+                ctv_vault_cancel_withdrawal(vault.cancel_x_only_public_key()),
+            )
+            .unwrap()
+            .finalize(&SECP, vault.internal_key().unwrap())
+            .unwrap();
+        assert!(
+            verify_leaf_keys_consistent(&spend_info, &vault.expected_triggered_leaf_keys())
+                .is_err()
+        );
+    }
+
+    fn temp_vault_file(name: &str) -> Option<String> {
+        let dir = std::env::temp_dir().join(format!("scv-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        Some(dir.join("vault.json").to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn modified_vault_file_fails_the_integrity_check() {
+        let file = temp_vault_file("modified");
+        let key = [3u8; 32];
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_file_key(Some(key));
+        vault.to_file(&file).unwrap();
+        VaultCovenant::verify_file_integrity(&file, None, Some(&key)).unwrap();
+        assert!(VaultCovenant::verify_file_integrity(&file, None, Some(&[4u8; 32])).is_err());
+
+        let path = file.clone().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("100000", "100001", 1)).unwrap();
+        assert!(VaultCovenant::verify_file_integrity(&file, None, Some(&key)).is_err());
+    }
+
+    #[test]
+    fn vault_file_without_hmac_only_warns() {
+        let file = temp_vault_file("unchecked");
+        let vault = seeded_vault(VaultType::CAT);
+        vault.to_file(&file).unwrap();
+        assert!(!Path::new(&format!("{}.hmac", file.clone().unwrap())).exists());
+        VaultCovenant::verify_file_integrity(&file, None, Some(&[3u8; 32])).unwrap();
+        VaultCovenant::verify_file_integrity(&file, None, None).unwrap();
+    }
+
+    #[test]
+    fn interrupted_write_leaves_a_checked_vault_file() {
+        let file = temp_vault_file("interrupted");
+        let path = file.clone().unwrap();
+        let key = [3u8; 32];
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_file_key(Some(key));
+        vault.to_file(&file).unwrap();
+
+        // interrupted after the HMAC and the temporary covenant file are written, before the rename
+        vault.set_amount(Amount::from_sat(50_000));
+        vault.write_file_hmac(&path, b"{}").unwrap();
+        let temp_path = Path::new(&path).with_file_name(".vault.json.tmp");
+        std::fs::write(&temp_path, b"{}").unwrap();
+        VaultCovenant::verify_file_integrity(&file, None, Some(&key)).unwrap();
+        let reloaded = VaultCovenant::from_file(&file, None).unwrap();
+        assert_eq!(reloaded.get_amount(), Amount::from_sat(100_000));
+
+        // the next complete write replaces both
+        vault.to_file(&file).unwrap();
+        assert!(!temp_path.exists());
+        VaultCovenant::verify_file_integrity(&file, None, Some(&key)).unwrap();
+        let reloaded = VaultCovenant::from_file(&file, None).unwrap();
+        assert_eq!(reloaded.get_amount(), Amount::from_sat(50_000));
+    }
+
+    fn seed_settings(name: &str) -> Settings {
+        let dir = std::env::temp_dir().join(format!("scv-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.toml");
+        Settings::default().to_toml_file(&path).unwrap();
+        Settings {
+            path: Some(path),
+            seed: Some("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn each_derived_vault_gets_its_own_hardened_keys() {
+        let settings = seed_settings("derived");
+        let first = VaultCovenant::new(TimelockKind::Blocks(10), &settings).unwrap();
+        let second = VaultCovenant::new(TimelockKind::Blocks(10), &settings).unwrap();
+        assert_eq!(first.derivation().unwrap().0.to_string(), "m/86'/1'/0'/0'");
+        assert_eq!(second.derivation().unwrap().0.to_string(), "m/86'/1'/0'/1'");
+        assert!(first.derivation().unwrap().1);
+        assert_ne!(first.vault_keypair, second.vault_keypair);
+        assert_ne!(first.cancel_keypair, second.cancel_keypair);
+
+        // the hardened cancel key can't be reached from the vault's xpub
+        let master_key = settings.master_key().unwrap().unwrap();
+        let mut normal = VaultCovenant::blank(Network::Regtest);
+        normal
+            .derive_keys(&master_key, first.derivation().unwrap().0.clone(), false)
+            .unwrap();
+        assert_ne!(normal.cancel_keypair, first.cancel_keypair);
+    }
+
+    #[test]
+    fn derived_vault_reloads_with_the_same_keys() {
+        let settings = seed_settings("reload");
+        let file = temp_vault_file("reload");
+        let master_key = settings.master_key().unwrap().unwrap();
+        for hardened in [true, false] {
+            let mut vault = seeded_vault(VaultType::CTV);
+            vault
+                .derive_keys(&master_key, settings.account_path().unwrap(), hardened)
+                .unwrap();
+            vault.to_file(&file).unwrap();
+            assert!(!std::fs::read_to_string(file.as_ref().unwrap())
+                .unwrap()
+                .contains("cancel_keypair"));
+            let reloaded = VaultCovenant::from_file(&file, Some(&master_key)).unwrap();
+            assert_eq!(reloaded.cancel_keypair, vault.cancel_keypair);
+            assert_eq!(reloaded.address().unwrap(), vault.address().unwrap());
+        }
+    }
+
+    #[test]
+    fn seedless_new_vault_has_random_keys() {
+        let vault = VaultCovenant::new_ctv(
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+        )
+        .unwrap();
+        assert!(vault.derivation().is_none());
+    }
+
+    #[test]
+    fn sighash_types_that_leave_outputs_unsigned_are_refused() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        for sighash_type in [
+            TapSighashType::None,
+            TapSighashType::NonePlusAnyoneCanPay,
+            TapSighashType::Single,
+            TapSighashType::SinglePlusAnyoneCanPay,
+        ] {
+            assert!(vault.set_sighash_type(sighash_type).is_err());
+        }
+        vault
+            .set_sighash_type(TapSighashType::AllPlusAnyoneCanPay)
+            .unwrap();
+        assert_eq!(vault.sighash_type(), TapSighashType::AllPlusAnyoneCanPay);
+        assert!(seeded_vault(VaultType::CAT)
+            .set_sighash_type(TapSighashType::AllPlusAnyoneCanPay)
+            .is_err());
+    }
+
+    #[test]
+    fn signing_refuses_mismatched_prevouts() {
+        let vault = seeded_vault(VaultType::CTV);
+        let txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: vault.address().unwrap().script_pubkey(),
+                value: Amount::from_sat(100_000),
+            }],
         };
-        let fee_txin = TxIn {
-            previous_output: fee_paying_utxo.clone(),
-            ..Default::default()
+        let prevout = TxOut {
+            script_pubkey: vault.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(60_000),
         };
-        let output = TxOut {
-            script_pubkey: self.get_withdrawal_address()?.script_pubkey(),
-            value: self.amount,
+        let leaf_hash = TapLeafHash::all_zeros();
+        let sign = |prevouts: &[TxOut]| {
+            sign_transaction(
+                &txn,
+                prevouts,
+                leaf_hash,
+                vault.cancel_keypair(),
+                TapSighashType::All,
+            )
         };
-        let mut txn = Transaction {
-            version: Version::TWO,
-            lock_time: LockTime::ZERO,
-            input: vec![vault_txin.clone(), fee_txin],
-            output: vec![output],
+        // one prevout short
+        assert!(sign(std::slice::from_ref(&prevout)).is_err());
+        // one too many
+        assert!(sign(&[prevout.clone(), prevout.clone(), prevout.clone()]).is_err());
+        // the outputs spend more than the prevouts hold
+        let short = TxOut {
+            value: Amount::from_sat(30_000),
+            ..prevout.clone()
         };
-        let leafhash = TapLeafHash::from_script(
-            &ctv_vault_complete_withdrawal(
-                self.withdraw_x_only_public_key(),
-                self.timelock_in_blocks,
-            ),
-            LeafVersion::TapScript,
+        assert!(sign(&[prevout.clone(), short]).is_err());
+        sign(&[prevout.clone(), prevout]).unwrap();
+    }
+
+    #[test]
+    fn completing_keeps_only_the_txid() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        let trigger = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        vault.set_current_outpoint(trigger);
+        vault
+            .set_withdrawal_address(Some(vault.address().unwrap()))
+            .unwrap();
+        vault.set_state(VaultState::Triggered);
+        let complete = Txid::from_byte_array([2; 32]);
+        vault.complete_with(complete);
+
+        assert_eq!(vault.get_state(), VaultState::Completed);
+        assert_eq!(vault.completed_txid(), Some(complete));
+        assert!(vault.get_current_outpoint().is_err());
+        assert!(vault.get_withdrawal_address().is_err());
+        assert!(vault.get_trigger_transaction().is_err());
+        assert!(vault.recipients.is_empty());
+        assert!(vault.withdrawal_amount.is_none());
+    }
+
+    #[test]
+    fn addresses_use_the_network_prefix() {
+        for (network, prefix) in [
+            (Network::Bitcoin, "bc1p"),
+            (Network::Testnet, "tb1p"),
+            (Network::Signet, "tb1p"),
+            (Network::Regtest, "bcrt1p"),
+        ] {
+            let settings = Settings {
+                network,
+                ..Settings::default()
+            };
+            let mut vault = VaultCovenant::new_ephemeral(
+                VaultType::CAT,
+                TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &settings,
+            )
+            .unwrap();
+            let address = vault.address().unwrap();
+            assert!(address.to_string().starts_with(prefix), "{}", address);
+            vault.set_withdrawal_address(Some(address)).unwrap();
+        }
+        let mut regtest = seeded_vault(VaultType::CAT);
+        let mainnet_address = Address::p2tr(
+            &SECP,
+            regtest.cancel_x_only_public_key(),
+            None,
+            Network::Bitcoin,
         );
-        let vault_txout = TxOut {
-            script_pubkey: self.ctv_trigger_address()?.script_pubkey().clone(),
-            value: self.amount,
-        };
-        let sig = self.sign_transaction(
-            &txn,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            leafhash,
-            self.withdraw_keypair,
+        assert_eq!(
+            regtest
+                .set_withdrawal_address(Some(mainnet_address))
+                .unwrap_err()
+                .downcast_ref::<VaultError>()
+                .map(|e| matches!(e, VaultError::WrongNetworkAddress { .. })),
+            Some(true)
         );
-        vault_txin.witness.push(sig);
+    }
 
-        vault_txin.witness.push(
-            ctv_vault_complete_withdrawal(
-                self.withdraw_x_only_public_key(),
-                self.timelock_in_blocks,
+    #[test]
+    fn seeded_keys_are_regtest_only() {
+        for network in [Network::Bitcoin, Network::Testnet, Network::Signet] {
+            let settings = Settings {
+                network,
+                ..Settings::default()
+            };
+            assert!(VaultCovenant::new_seeded(
+                VaultType::CTV,
+                TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &settings,
+                7
             )
-            .to_bytes(),
+            .is_err());
+        }
+    }
+
+    /// The merkle roots of the seeded test vaults, pinned so another implementation can check it builds the same trees
+    const CAT_MERKLE_ROOT: &str =
+        "b536b21ca6cf1c5dc15274d3de3a6c96a6534be0bf6551f9f6e16cbfbcea9e0d";
+    const CTV_MERKLE_ROOT: &str =
+        "7a11bb27138179b786ae2de020412987cbcf54a21fa1000dd7279c7014ff69d7";
+
+    #[test]
+    fn merkle_root_matches_the_fixture() {
+        let cat = seeded_vault(VaultType::CAT);
+        assert_eq!(cat.merkle_root_hex().unwrap(), CAT_MERKLE_ROOT);
+        assert_eq!(
+            cat.merkle_root_hex().unwrap(),
+            cat.merkle_root_hex().unwrap()
         );
-        vault_txin.witness.push(
-            self.ctv_trigger_spend_info()?
-                .control_block(&(
-                    ctv_vault_complete_withdrawal(
-                        self.withdraw_x_only_public_key(),
-                        self.timelock_in_blocks,
-                    )
-                    .clone(),
-                    LeafVersion::TapScript,
-                ))
-                .expect("control block should work")
-                .serialize(),
+        cat.verify_merkle_root(&CAT_MERKLE_ROOT.to_uppercase())
+            .unwrap();
+        assert!(cat.verify_merkle_root(CTV_MERKLE_ROOT).is_err());
+        seeded_vault(VaultType::CTV)
+            .verify_merkle_root(CTV_MERKLE_ROOT)
+            .unwrap();
+    }
+
+    #[test]
+    fn complete_must_pay_the_destination_committed_at_trigger_time() {
+        let destination = test_address(2);
+        let (vault, trigger_tx) = triggered_cat(&destination);
+        assert_eq!(
+            VaultCovenant::trigger_committed_destination(&trigger_tx).unwrap(),
+            destination.script_pubkey()
         );
-        txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        let (fee_utxo, fee_output) = test_fee_input(1);
+        let error = vault
+            .create_complete_tx(&fee_utxo, fee_output.clone(), &test_address(3), &trigger_tx)
+            .unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{}", error);
+        let complete = vault
+            .create_complete_tx(&fee_utxo, fee_output, &destination, &trigger_tx)
+            .unwrap();
+        assert_eq!(
+            complete.transaction.output[0].script_pubkey,
+            destination.script_pubkey()
+        );
+    }
 
-        Ok(txn)
+    #[test]
+    fn sweep_spends_the_marker_and_pays_the_destination() {
+        let destination = test_address(2);
+        let (vault, trigger_tx) = triggered_cat(&destination);
+        let marker_outpoint = OutPoint::new(trigger_tx.txid(), 1);
+        let (fee_utxo, fee_output) = test_fee_input(1);
+        let fee = Amount::from_sat(1_000);
+        let sweep = vault
+            .sweep_marker(
+                marker_outpoint,
+                &test_address(4),
+                (fee_utxo, fee_output.clone()),
+                fee,
+            )
+            .unwrap();
+        assert_eq!(sweep.input[0].previous_output, marker_outpoint);
+        assert_eq!(sweep.input[1].previous_output, fee_utxo);
+        assert_eq!(sweep.output.len(), 1);
+        assert_eq!(
+            sweep.output[0].script_pubkey,
+            test_address(4).script_pubkey()
+        );
+        assert_eq!(
+            sweep.output[0].value,
+            vault.marker_amount() + fee_output.value - fee
+        );
     }
 
-    pub(crate) fn create_ctv_cancel_tx(
-        &self,
-        fee_paying_utxo: &OutPoint,
-        fee_paying_output: TxOut,
-    ) -> Result<Transaction> {
-        let mut vault_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
-            ..Default::default()
+    #[test]
+    fn funding_pays_the_deposit_address_and_returns_change() {
+        let (source_utxo, source_output) = test_fee_input(2);
+        let change_address = test_address(5);
+        let (vault, funding_tx) = VaultCovenant::create_and_fund(
+            seeded_vault(VaultType::CAT),
+            Amount::from_sat(30_000),
+            source_utxo,
+            source_output.clone(),
+            &change_address,
+            FeeRate::from_sat_per_vb_unchecked(2),
+        )
+        .unwrap();
+        assert_eq!(funding_tx.input[0].previous_output, source_utxo);
+        assert_eq!(
+            funding_tx.output[0].script_pubkey,
+            vault.address().unwrap().script_pubkey()
+        );
+        assert_eq!(funding_tx.output[0].value, Amount::from_sat(30_000));
+        assert_eq!(
+            funding_tx.output[1].script_pubkey,
+            change_address.script_pubkey()
+        );
+        let fee = source_output.value - Amount::from_sat(30_000) - funding_tx.output[1].value;
+        assert!(
+            fee > Amount::ZERO && fee < Amount::from_sat(1_000),
+            "{}",
+            fee
+        );
+        assert_eq!(
+            vault.get_current_outpoint().unwrap(),
+            OutPoint::new(funding_tx.txid(), 0)
+        );
+    }
+
+    #[test]
+    fn trigger_builds_are_byte_identical() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let build = || {
+            vault
+                .create_trigger_tx(&[test_fee_input(0)], &[test_address(2)])
+                .unwrap()
+                .transaction
         };
-        let fee_txin = TxIn {
-            previous_output: fee_paying_utxo.clone(),
-            ..Default::default()
+        let (first, second) = (build(), build());
+        assert_eq!(serialize(&first), serialize(&second));
+        // the grind picks one of these
+        assert_eq!(first.lock_time, second.lock_time);
+        assert_eq!(first.input[0].sequence, second.input[0].sequence);
+    }
+
+    #[test]
+    fn cancel_and_rotate_pays_a_fresh_vault() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let (new_vault, cancel_tx) = vault
+            .cancel_and_rotate(
+                TimelockKind::Blocks(20),
+                test_fee_input(0),
+                &Settings::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            cancel_tx.output[0].script_pubkey,
+            new_vault.address().unwrap().script_pubkey()
+        );
+        assert_ne!(new_vault.address().unwrap(), vault.address().unwrap());
+        assert_ne!(
+            new_vault.vault_keypair().public_key(),
+            vault.vault_keypair().public_key()
+        );
+        assert_ne!(
+            new_vault.cancel_keypair().public_key(),
+            vault.cancel_keypair().public_key()
+        );
+        assert_eq!(new_vault.get_type(), VaultType::CTV);
+        assert_eq!(new_vault.timelock, TimelockKind::Blocks(20));
+        assert_eq!(new_vault.get_amount(), vault.get_amount());
+        assert_eq!(
+            new_vault.get_current_outpoint().unwrap(),
+            OutPoint::new(cancel_tx.txid(), 0)
+        );
+        assert!(seeded_vault(VaultType::CAT)
+            .cancel_and_rotate(
+                TimelockKind::Blocks(20),
+                test_fee_input(0),
+                &Settings::default()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn complete_carries_a_trigger_spanning_three_chunks() {
+        let settings = Settings {
+            trigger_input_chunks: 3,
+            ..Settings::default()
         };
-        let output = TxOut {
-            script_pubkey: self.address()?.script_pubkey(),
-            value: self.amount,
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination = test_address(2);
+        // the vault input and three fee inputs serialize to more than two 80 byte chunks
+        let fee_inputs = [test_fee_input(0), test_fee_input(1), test_fee_input(2)];
+        let trigger_tx = vault
+            .create_trigger_tx(&fee_inputs, std::slice::from_ref(&destination))
+            .unwrap()
+            .transaction;
+        let chunks = vault.complete_witness_chunk_map(&trigger_tx);
+        assert_eq!(chunks.len(), 3);
+        // the map indexes into the serialization hashed for the txid, without witnesses
+        let mut stripped_tx = trigger_tx.clone();
+        stripped_tx
+            .input
+            .iter_mut()
+            .for_each(|input| input.witness.clear());
+        let serialized = serialize(&stripped_tx);
+        let inputs: Vec<u8> = chunks
+            .iter()
+            .flat_map(|(start, end)| serialized[*start..*end].to_vec())
+            .collect();
+        assert_eq!(inputs, serialize(&trigger_tx.input));
+
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_trigger_transaction(Some(trigger_tx.clone()));
+        vault.set_state(VaultState::Triggered);
+        let (fee_utxo, fee_output) = test_fee_input(3);
+        // under the verify feature the builder also runs the complete through the interpreter
+        vault
+            .create_complete_tx(&fee_utxo, fee_output, &destination, &trigger_tx)
+            .unwrap();
+    }
+
+    #[test]
+    fn cancel_is_funded_at_a_higher_rate_than_the_complete() {
+        let destination = test_address(2);
+        let (vault, _) = triggered_cat(&destination);
+        let rates = PathFeeRates::default();
+        let fee_script = test_address(9).script_pubkey();
+        let rate_paid = |path: SpendPath| {
+            let value = vault
+                .fee_input_amount(path, rates.for_path(path), fee_script.clone(), None)
+                .unwrap();
+            let (fee_utxo, mut fee_output) = test_fee_input(1);
+            fee_output.value = value;
+            let txn = vault
+                .create_path_tx(path, &fee_utxo, fee_output.clone(), None)
+                .unwrap();
+            let fee = fee_paid(&txn, &[vault.triggered_output().unwrap(), fee_output]).unwrap();
+            fee.to_sat() as f64 / (signed_weight(&txn).to_wu() as f64 / 4.0)
         };
-        let mut txn = Transaction {
-            version: Version::TWO,
-            lock_time: LockTime::ZERO,
-            input: vec![vault_txin.clone(), fee_txin],
-            output: vec![output],
+        let (complete, cancel) = (rate_paid(SpendPath::Complete), rate_paid(SpendPath::Cancel));
+        assert!(cancel > complete, "cancel {} complete {}", cancel, complete);
+        assert!(complete >= rates.complete.to_sat_per_vb_floor() as f64);
+        assert!(cancel >= rates.cancel.to_sat_per_vb_floor() as f64);
+    }
+
+    #[test]
+    fn trigger_is_funded_by_two_fee_inputs() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let taproot_fee = test_fee_input(0);
+        // a segwit v0 fee input, its scriptpubkey is committed the same way
+        let (segwit_utxo, mut segwit_output) = test_fee_input(1);
+        let key = bitcoin::PublicKey::new(
+            Keypair::from_seckey_slice(&SECP, &[8; 32])
+                .unwrap()
+                .public_key(),
+        );
+        segwit_output.script_pubkey = Address::p2wpkh(&key, Network::Regtest)
+            .unwrap()
+            .script_pubkey();
+        let fee_inputs = [taproot_fee.clone(), (segwit_utxo, segwit_output.clone())];
+        let built = vault
+            .create_trigger_tx(&fee_inputs, &[test_address(2)])
+            .unwrap();
+        let inputs: Vec<OutPoint> = built
+            .transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                vault.get_current_outpoint().unwrap(),
+                taproot_fee.0,
+                segwit_utxo
+            ]
+        );
+        let paid_in = vault.get_amount() + taproot_fee.1.value + segwit_output.value;
+        let paid_out: Amount = built
+            .transaction
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum();
+        assert_eq!(built.fee, paid_in - paid_out);
+        assert!(vault.create_trigger_tx(&[], &[test_address(2)]).is_err());
+    }
+
+    #[test]
+    fn withdrawal_address_from_another_network_is_cleared() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        let keypair = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        let mainnet = Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Bitcoin);
+        vault.withdrawal_address = Some(mainnet.to_string());
+        assert!(vault.get_withdrawal_address().is_err());
+        vault.normalize_withdrawal_address().unwrap();
+        assert_eq!(vault.withdrawal_address, None);
+
+        vault.withdrawal_address = Some(test_address(2).to_string());
+        vault.normalize_withdrawal_address().unwrap();
+        assert_eq!(vault.get_withdrawal_address().unwrap(), test_address(2));
+    }
+
+    #[test]
+    fn both_ctv_trigger_leaves_are_reachable() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        let spend_info = vault.triggered_spend_info().unwrap();
+        let output_key = spend_info.output_key().to_inner();
+        for (script, control_block) in [
+            vault.ctv_complete_leaf().unwrap(),
+            vault.cancel_leaf().unwrap(),
+        ] {
+            assert!(control_block.verify_taproot_commitment(&SECP, output_key, &script));
+        }
+        let stray = ("stray", ScriptBuf::new_op_return([1]));
+        assert!(verify_leaves_reachable(&spend_info, &[stray]).is_err());
+
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let (fee_utxo, fee_output) = test_fee_input(1);
+        let cancel_tx = vault.create_ctv_cancel_tx(&fee_utxo, fee_output).unwrap();
+        let (cancel_script, _) = vault.cancel_leaf().unwrap();
+        assert!(cancel_tx.input[0]
+            .witness
+            .iter()
+            .any(|element| element == cancel_script.as_bytes()));
+    }
+
+    #[test]
+    fn output_key_parity_of_a_known_vault_is_stable() {
+        let mut cat = seeded_vault(VaultType::CAT);
+        assert_eq!(cat.output_key_parity().unwrap(), Parity::Odd);
+        assert_eq!(
+            seeded_vault(VaultType::CAT).output_key_parity().unwrap(),
+            Parity::Odd
+        );
+        assert_eq!(
+            seeded_vault(VaultType::CTV).output_key_parity().unwrap(),
+            Parity::Even
+        );
+
+        let address = cat.address().unwrap();
+        cat.set_output_key_parity(Parity::Even).unwrap();
+        assert_eq!(cat.output_key_parity().unwrap(), Parity::Even);
+        assert_ne!(cat.address().unwrap(), address);
+    }
+
+    #[test]
+    fn trigger_with_three_markers_is_classified_as_triggered() {
+        let settings = Settings {
+            trigger_markers: 3,
+            ..Settings::default()
         };
-        let leafhash = TapLeafHash::from_script(
-            &ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()),
-            LeafVersion::TapScript,
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        vault
+            .set_marker_addresses(&[test_address(3), test_address(4)])
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let targets = [test_address(2), test_address(3), test_address(4)];
+        let trigger_tx = vault
+            .create_trigger_tx(&[test_fee_input(0)], &targets)
+            .unwrap()
+            .transaction;
+        assert_eq!(trigger_tx.output.len(), 4);
+        for (marker, target) in trigger_tx.output[1..].iter().zip(&targets) {
+            assert_eq!(marker.script_pubkey, target.script_pubkey());
+            assert_eq!(marker.value, vault.marker_amount());
+        }
+        assert_eq!(
+            vault.outpoint_spender_state(&trigger_tx).unwrap(),
+            VaultState::Triggered
         );
+    }
 
-        let vault_txout = TxOut {
-            script_pubkey: self.ctv_trigger_address()?.script_pubkey().clone(),
-            value: self.amount,
+    #[test]
+    fn mismatched_outpoint_fails_the_trigger_consistency_check() {
+        let (mut vault, trigger_tx) = triggered_cat(&test_address(2));
+        vault.verify_trigger_consistency().unwrap();
+
+        // the marker output of the right trigger
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 1));
+        assert!(vault.verify_trigger_consistency().is_err());
+        // the vault output of some other transaction
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        assert!(vault.verify_trigger_consistency().is_err());
+    }
+
+    #[test]
+    fn leaves_report_the_inactive_opcodes_they_need() {
+        let requirements = |vault_type| {
+            seeded_vault(vault_type)
+                .leaf_opcode_requirements()
+                .unwrap()
+                .into_iter()
+                .map(|(leaf, opcodes)| (leaf, opcodes.join(" ")))
+                .collect::<Vec<_>>()
         };
-        let sig = self.sign_transaction(
-            &txn,
-            &[vault_txout.clone(), fee_paying_output.clone()],
-            leafhash,
-            self.cancel_keypair,
+        let owned = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(leaf, opcodes)| (leaf.to_string(), opcodes.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            requirements(VaultType::CAT),
+            owned(&[
+                ("trigger", "OP_CAT"),
+                ("complete", "OP_CAT"),
+                ("cancel", "OP_CAT")
+            ])
         );
-        vault_txin.witness.push(sig);
+        assert_eq!(
+            requirements(VaultType::CTV),
+            owned(&[
+                ("deposit", "OP_CHECKTEMPLATEVERIFY"),
+                ("complete", ""),
+                ("cancel", "")
+            ])
+        );
+    }
 
-        vault_txin
-            .witness
-            .push(ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()).to_bytes());
-        vault_txin.witness.push(
-            self.ctv_trigger_spend_info()?
-                .control_block(&(
-                    ctv_vault_cancel_withdrawal(self.cancel_x_only_public_key()).clone(),
-                    LeafVersion::TapScript,
-                ))
-                .expect("control block should work")
-                .serialize(),
+    #[test]
+    fn complete_vault_input_outweighs_the_fee_input() {
+        let destination = test_address(2);
+        let (vault, trigger_tx) = triggered_cat(&destination);
+        let (fee_utxo, fee_output) = test_fee_input(1);
+        let complete = vault
+            .create_complete_tx(&fee_utxo, fee_output.clone(), &destination, &trigger_tx)
+            .unwrap();
+        let prevouts = [vault.triggered_output().unwrap(), fee_output];
+        let breakdown = input_weight_breakdown(&complete.transaction, &prevouts).unwrap();
+        assert_eq!(breakdown.len(), 2);
+        let (vault_input, fee_input) = (breakdown[0], breakdown[1]);
+        assert_eq!((vault_input.0, fee_input.0), (0, 1));
+        assert!(vault_input.1 > fee_input.1, "{:?}", breakdown);
+    }
+
+    #[test]
+    fn vault_id_is_stable_across_states_and_unique_per_vault() {
+        let destination = test_address(2);
+        let inactive = seeded_vault(VaultType::CAT);
+        let (mut triggered, _) = triggered_cat(&destination);
+        let id = inactive.vault_id();
+        assert_eq!(triggered.vault_id(), id);
+        triggered.complete_with(Txid::from_byte_array([5; 32]));
+        assert_eq!(triggered.vault_id(), id);
+
+        assert_ne!(seeded_vault(VaultType::CTV).vault_id(), id);
+        let other = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            8,
+        )
+        .unwrap();
+        assert_ne!(other.vault_id(), id);
+    }
+
+    #[test]
+    fn repeated_address_calls_use_the_cache() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        let address = vault.address().unwrap();
+        assert_eq!(vault.address().unwrap(), address);
+        let cached = vault
+            .spend_info_cache
+            .get_or_build_address(vault.tree_params(), || {
+                panic!("the address was built again")
+            })
+            .unwrap();
+        assert_eq!(cached, address);
+        vault
+            .spend_info_cache
+            .get_or_build(SpendTree::Deposit, vault.tree_params(), || {
+                panic!("the spend info was built again")
+            })
+            .unwrap();
+
+        // changing a parameter the tree is built from invalidates it
+        vault.timelock = TimelockKind::Blocks(11);
+        assert_ne!(vault.address().unwrap(), address);
+    }
+
+    #[test]
+    fn tampered_ctv_trigger_fails_the_commitment_check() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault
+            .create_ctv_trigger_tx(Some(&test_fee_input(0).0))
+            .unwrap();
+        vault.verify_ctv_commitment(&trigger_tx).unwrap();
+
+        let mut tampered = trigger_tx.clone();
+        tampered.output[0].value -= Amount::from_sat(1);
+        assert!(vault.verify_ctv_commitment(&tampered).is_err());
+        assert!(seeded_vault(VaultType::CAT)
+            .verify_ctv_commitment(&trigger_tx)
+            .is_err());
+    }
+
+    #[test]
+    fn testnet_withdrawal_address_is_refused_on_regtest() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        let keypair = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        let testnet = Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Testnet);
+        let error = vault.set_withdrawal_address(Some(testnet)).unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<VaultError>(),
+                Some(VaultError::WrongNetworkAddress { .. })
+            ),
+            "{}",
+            error
         );
-        txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        assert_eq!(vault.withdrawal_address, None);
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+    }
 
-        Ok(txn)
+    #[test]
+    fn outpoint_parses_from_txid_and_vout() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        vault
+            .set_current_outpoint_from_str(&format!(" {}:7\n", txid))
+            .unwrap();
+        assert_eq!(
+            vault.get_current_outpoint().unwrap(),
+            OutPoint::new(Txid::from_str(txid).unwrap(), 7)
+        );
+        assert_eq!(
+            vault.current_outpoint_string().unwrap(),
+            format!("{}:7", txid)
+        );
     }
 
-    fn ctv_trigger_tx_template(&self) -> Transaction {
-        let output = TxOut {
-            script_pubkey: self.ctv_trigger_address().unwrap().script_pubkey(),
-            value: self.amount,
+    #[test]
+    fn outpoint_with_an_out_of_range_vout_is_refused() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        for vout in ["4294967296", "-1", ""] {
+            assert!(vault
+                .set_current_outpoint_from_str(&format!("{}:{}", txid, vout))
+                .is_err());
+        }
+        assert!(vault.get_current_outpoint().is_err());
+    }
+
+    #[test]
+    fn outpoint_with_a_malformed_txid_is_refused() {
+        let mut vault = seeded_vault(VaultType::CAT);
+        for outpoint in [
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda3:0",
+            "zz5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b:0",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ] {
+            let error = vault.set_current_outpoint_from_str(outpoint).unwrap_err();
+            assert!(
+                error.to_string().contains("is not a txid:vout outpoint"),
+                "{}",
+                error
+            );
+        }
+        assert!(vault.get_current_outpoint().is_err());
+    }
+
+    #[test]
+    fn anyone_can_pay_signature_survives_an_extra_input() {
+        let vault = seeded_vault(VaultType::CTV);
+        let prevout = TxOut {
+            script_pubkey: vault.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(100_000),
         };
-        let input = TxIn {
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            ..Default::default()
+        let mut txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: test_address(2).script_pubkey(),
+                value: Amount::from_sat(99_000),
+            }],
         };
+        let leaf_hash = TapLeafHash::all_zeros();
+        let keypair = vault.cancel_keypair();
+        let signature = sign_transaction(
+            &txn,
+            std::slice::from_ref(&prevout),
+            leaf_hash,
+            keypair,
+            TapSighashType::AllPlusAnyoneCanPay,
+        )
+        .unwrap();
+        let signature = Signature::from_slice(&signature).unwrap();
+        assert_eq!(signature.hash_ty, TapSighashType::AllPlusAnyoneCanPay);
 
-        let txn = Transaction {
-            lock_time: LockTime::ZERO,
-            version: Version::TWO,
-            input: vec![input.clone(), input],
-            output: vec![output],
+        // a fee input attached after signing
+        let (fee_utxo, _) = test_fee_input(1);
+        txn.input.push(TxIn {
+            previous_output: fee_utxo,
+            ..Default::default()
+        });
+        let sighash = SighashCache::new(&txn)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::One(0, &prevout),
+                leaf_hash,
+                TapSighashType::AllPlusAnyoneCanPay,
+            )
+            .unwrap();
+        let message = Message::from_digest_slice(sighash.as_byte_array()).unwrap();
+        let key = keypair.x_only_public_key().0;
+        SECP.verify_schnorr(&signature.sig, &message, &key).unwrap();
+    }
+
+    #[test]
+    fn version_1_vault_file_loads_as_a_cat_vault() {
+        let vault = VaultCovenant::from_slice(include_bytes!("schema_v1.json"), None).unwrap();
+        assert_eq!(vault.vault_type, VaultType::CAT);
+        assert_eq!(vault.timelock, TimelockKind::Blocks(10));
+        assert_eq!(vault.amount, Amount::from_sat(100_000));
+        assert_eq!(vault.network, Network::Regtest);
+        assert!(!vault.hardened_keys);
+        let seeded = seeded_vault(VaultType::CAT);
+        assert_eq!(vault.address().unwrap(), seeded.address().unwrap());
+    }
+
+    #[test]
+    fn version_2_vault_file_keeps_its_vault_type() {
+        let vault = VaultCovenant::from_slice(include_bytes!("schema_v2.json"), None).unwrap();
+        assert_eq!(vault.vault_type, VaultType::CTV);
+        assert_eq!(vault.timelock, TimelockKind::Blocks(10));
+        assert!(!vault.hardened_keys);
+        let seeded = seeded_vault(VaultType::CTV);
+        assert_eq!(vault.address().unwrap(), seeded.address().unwrap());
+    }
+
+    #[test]
+    fn vault_file_from_a_newer_schema_is_refused() {
+        let mut stored: serde_json::Value =
+            serde_json::from_slice(include_bytes!("schema_v2.json")).unwrap();
+        stored["schema_version"] = serde_json::Value::from(schema::SCHEMA_VERSION + 1);
+        let contents = serde_json::to_vec(&stored).unwrap();
+        assert!(VaultCovenant::from_slice(&contents, None).is_err());
+    }
+
+    #[test]
+    fn reported_fees_match_the_inputs_less_the_outputs() {
+        let deposit = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let destination = test_address(2);
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(deposit);
+        let trigger = vault
+            .create_trigger_tx(&[test_fee_input(0)], std::slice::from_ref(&destination))
+            .unwrap();
+        let (vault, trigger_tx) = triggered_cat(&destination);
+        let complete = vault
+            .create_complete_tx(
+                &test_fee_input(1).0,
+                test_fee_input(1).1,
+                &destination,
+                &trigger_tx,
+            )
+            .unwrap();
+        let cancel = vault
+            .create_cancel_tx(&test_fee_input(2).0, test_fee_input(2).1)
+            .unwrap();
+
+        let value_of = |outpoint: &OutPoint| -> u64 {
+            if *outpoint == deposit {
+                100_000
+            } else if outpoint.txid == trigger_tx.txid() {
+                trigger_tx.output[outpoint.vout as usize].value.to_sat()
+            } else {
+                assert_eq!(outpoint.txid, Txid::from_byte_array([9; 32]));
+                50_000
+            }
         };
+        for built in [&trigger, &complete, &cancel] {
+            let inputs: u64 = built
+                .transaction
+                .input
+                .iter()
+                .map(|input| value_of(&input.previous_output))
+                .sum();
+            let outputs: u64 = built
+                .transaction
+                .output
+                .iter()
+                .map(|output| output.value.to_sat())
+                .sum();
+            assert_eq!(built.fee, Amount::from_sat(inputs - outputs));
+            assert_eq!(
+                built.fee_rate,
+                built.fee.to_sat() as f64 / built.vsize as f64
+            );
+        }
+    }
+
+    #[test]
+    fn cold_vault_withdraws_after_its_longer_timelock() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        assert!(vault
+            .cancel_to_cold_vault(
+                TimelockKind::Blocks(10),
+                test_fee_input(0),
+                &Settings::default()
+            )
+            .is_err());
+        let (mut cold, cancel_tx) = vault
+            .cancel_to_cold_vault(
+                TimelockKind::Blocks(144),
+                test_fee_input(0),
+                &Settings::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            cancel_tx.output[0].script_pubkey,
+            cold.address().unwrap().script_pubkey()
+        );
 
-        txn
+        cold.set_withdrawal_address(Some(test_address(2))).unwrap();
+        let trigger_tx = cold
+            .create_ctv_trigger_tx(Some(&test_fee_input(1).0))
+            .unwrap();
+        assert_eq!(
+            trigger_tx.input[0].previous_output,
+            OutPoint::new(cancel_tx.txid(), 0)
+        );
+        cold.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        let complete_tx = cold
+            .create_ctv_complete_tx(&test_fee_input(2).0, test_fee_input(2).1)
+            .unwrap();
+        assert_eq!(complete_tx.input[0].sequence, Sequence::from_height(144));
+        assert_eq!(
+            complete_tx.output[0].script_pubkey,
+            test_address(2).script_pubkey()
+        );
     }
 
-    pub(crate) fn create_ctv_trigger_tx(&self, fee_paying_utxo: &OutPoint) -> Result<Transaction> {
-        let mut txn = self.ctv_trigger_tx_template();
-        let fee_txin = TxIn {
-            previous_output: *fee_paying_utxo,
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            ..Default::default()
+    #[test]
+    fn undersized_fee_inputs_fail_before_grinding() {
+        let small_fee_input = |vout: u32, sats: u64| {
+            let (outpoint, mut output) = test_fee_input(vout);
+            output.value = Amount::from_sat(sats);
+            (outpoint, output)
         };
-        let mut trigger_txin = TxIn {
-            previous_output: self
-                .current_outpoint
-                .ok_or(anyhow!("no current outpoint"))?,
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            ..Default::default()
+        let is_insufficient = |error: anyhow::Error| {
+            matches!(
+                error.downcast_ref::<VaultError>(),
+                Some(VaultError::InsufficientFee { .. })
+            )
         };
-        txn.input = vec![trigger_txin.clone(), fee_txin];
+        let destination = test_address(2);
+
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let error = vault
+            .create_trigger_tx(
+                &[small_fee_input(0, 700)],
+                std::slice::from_ref(&destination),
+            )
+            .unwrap_err();
+        assert!(is_insufficient(error));
+
+        let (mut vault, trigger_tx) = triggered_cat(&destination);
+        let (outpoint, output) = small_fee_input(1, 100);
+        let error = vault
+            .create_complete_tx(&outpoint, output, &destination, &trigger_tx)
+            .unwrap_err();
+        assert!(is_insufficient(error));
+        let (outpoint, output) = small_fee_input(2, 100);
+        assert!(is_insufficient(
+            vault.create_cancel_tx(&outpoint, output).unwrap_err()
+        ));
+
+        let (outpoint, output) = test_fee_input(3);
+        vault.set_min_fee_rate(FeeRate::from_sat_per_vb(1_000).unwrap());
+        assert!(is_insufficient(
+            vault.create_cancel_tx(&outpoint, output).unwrap_err()
+        ));
+    }
 
-        trigger_txin
-            .witness
-            .push(ctv_vault_deposit(self.ctv_hash()).to_bytes());
-        trigger_txin.witness.push(
-            self.ctv_deposit_spend_info()?
-                .control_block(&(
-                    ctv_vault_deposit(self.ctv_hash()).clone(),
-                    LeafVersion::TapScript,
-                ))
-                .expect("control block should work")
-                .serialize(),
+    #[test]
+    fn ctv_spend_matches_only_the_canonical_trigger() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+        let template = vault.ctv_trigger_tx_template().unwrap();
+        vault.verify_ctv_spend(&template).unwrap();
+
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault
+            .create_ctv_trigger_tx(Some(&test_fee_input(0).0))
+            .unwrap();
+        vault.verify_ctv_spend(&trigger_tx).unwrap();
+        assert_eq!(
+            template_hash(&trigger_tx, 0).unwrap(),
+            vault.ctv_template_hash().unwrap()
         );
-        txn.input.first_mut().unwrap().witness = trigger_txin.witness.clone();
 
-        Ok(txn)
+        let mut tampered = trigger_tx.clone();
+        tampered.output[0].script_pubkey = test_address(3).script_pubkey();
+        let error = vault.verify_ctv_spend(&tampered).unwrap_err();
+        assert!(error.to_string().contains("the deposit leaf commits to"));
+
+        let mut elsewhere = trigger_tx;
+        elsewhere.input[0].previous_output = OutPoint::new(Txid::from_byte_array([2; 32]), 0);
+        assert!(vault.verify_ctv_spend(&elsewhere).is_err());
+    }
+
+    #[test]
+    fn trigger_reports_its_final_txid() {
+        let destination = test_address(2);
+        let mut vault = seeded_vault(VaultType::CAT);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let built = vault
+            .create_trigger_tx(&[test_fee_input(0)], std::slice::from_ref(&destination))
+            .unwrap();
+        assert!(!built.transaction.input[0].witness.is_empty());
+        assert_eq!(built.txid, built.transaction.txid());
+        assert_eq!(built.trigger_outpoint(), OutPoint::new(built.txid, 0));
+
+        let (vault, trigger_tx) = triggered_cat(&destination);
+        assert_eq!(trigger_tx.txid(), built.txid);
+        let complete = vault
+            .create_complete_tx(
+                &test_fee_input(1).0,
+                test_fee_input(1).1,
+                &destination,
+                &trigger_tx,
+            )
+            .unwrap();
+        assert_eq!(
+            complete.transaction.input[0].previous_output,
+            built.trigger_outpoint()
+        );
+    }
+
+    #[test]
+    fn trigger_with_a_10k_sat_marker_is_classified_as_triggered() {
+        let settings = Settings {
+            marker_amount: Amount::from_sat(10_000),
+            ..Settings::default()
+        };
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        assert_eq!(vault.marker_amount(), Amount::from_sat(10_000));
+        let destination = test_address(2);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault
+            .create_trigger_tx(&[test_fee_input(0)], std::slice::from_ref(&destination))
+            .unwrap()
+            .transaction;
+        assert_eq!(trigger_tx.output[0].value, Amount::from_sat(100_000));
+        assert_eq!(
+            trigger_tx.output[1],
+            TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value: Amount::from_sat(10_000),
+            }
+        );
+
+        // the marker pays the withdrawal address, but the trigger leaf it spends through decides the state
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        assert_eq!(
+            vault.outpoint_spender_state(&trigger_tx).unwrap(),
+            VaultState::Triggered
+        );
+    }
+
+    #[test]
+    fn shared_context_gives_the_same_results_as_a_fresh_one() {
+        let fresh = Secp256k1::new();
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let vault = seeded_vault(vault_type);
+            let spend_info = vault.deposit_spend_info().unwrap();
+            let rebuilt = Address::p2tr(
+                &fresh,
+                spend_info.internal_key(),
+                spend_info.merkle_root(),
+                Network::Regtest,
+            );
+            assert_eq!(rebuilt, vault.address().unwrap());
+        }
+
+        let vault = seeded_vault(VaultType::CTV);
+        let prevout = TxOut {
+            script_pubkey: vault.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(100_000),
+        };
+        let txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: test_address(2).script_pubkey(),
+                value: Amount::from_sat(99_000),
+            }],
+        };
+        let leaf_hash = TapLeafHash::all_zeros();
+        let signature = sign_transaction(
+            &txn,
+            std::slice::from_ref(&prevout),
+            leaf_hash,
+            vault.cancel_keypair(),
+            TapSighashType::Default,
+        )
+        .unwrap();
+        let sighash = SighashCache::new(&txn)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout]),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let message = Message::from_digest_slice(sighash.as_byte_array()).unwrap();
+        let expected = fresh.sign_schnorr_no_aux_rand(&message, &vault.cancel_keypair());
+        assert_eq!(signature, expected.as_ref().to_vec());
+    }
+
+    #[test]
+    fn vault_addresses_use_the_prefix_of_their_network() {
+        for (network, prefix) in [
+            (Network::Bitcoin, "bc1p"),
+            (Network::Testnet, "tb1p"),
+            (Network::Signet, "tb1p"),
+            (Network::Regtest, "bcrt1p"),
+        ] {
+            let settings = Settings {
+                network,
+                ..Settings::default()
+            };
+            for vault_type in [VaultType::CAT, VaultType::CTV] {
+                let vault = VaultCovenant::new_ephemeral(
+                    vault_type,
+                    TimelockKind::Blocks(10),
+                    Amount::from_sat(100_000),
+                    &settings,
+                )
+                .unwrap();
+                assert_eq!(vault.get_network(), network);
+                let address = vault.address().unwrap();
+                assert!(address.to_string().starts_with(prefix), "{}", address);
+            }
+        }
+    }
+
+    #[test]
+    fn reloaded_vault_keeps_its_network() {
+        let settings = Settings {
+            network: Network::Signet,
+            ..Settings::default()
+        };
+        let vault = VaultCovenant::new_ephemeral(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+        )
+        .unwrap();
+        let file = temp_vault_file("signet");
+        vault.to_file(&file).unwrap();
+        let reloaded = VaultCovenant::from_file(&file, None).unwrap();
+        assert_eq!(reloaded.get_network(), Network::Signet);
+        assert_eq!(reloaded.address().unwrap(), vault.address().unwrap());
     }
 }
@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, FeeRate, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+    WitnessProgram, WitnessVersion,
+};
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::fees::{dust_limit, fee_paid, signed_fee};
+
+/// The keyless pay-to-anchor output (`OP_1 0x4e73`) from the ephemeral anchors proposal, anyone can spend it
+/// with an empty witness
+pub(crate) fn ephemeral_anchor(network: Network) -> Result<Address> {
+    let program = WitnessProgram::new(WitnessVersion::V1, [0x4e, 0x73])?;
+    Ok(Address::from_script(
+        &ScriptBuf::new_witness_program(&program),
+        network,
+    )?)
+}
+
+/// The output of `parent` paying the keyless anchor, if it has one
+pub(crate) fn ephemeral_anchor_vout(parent: &Transaction) -> Option<u32> {
+    let anchor = ephemeral_anchor(Network::Bitcoin).ok()?.script_pubkey();
+    parent
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == anchor)
+        .map(|vout| vout as u32)
+}
+
+impl VaultCovenant {
+    /// Send the trigger's last marker to `anchor` so a child can spend it to fee bump the trigger.
+    /// The first marker commits to the withdrawal destination, so this needs a vault built with more than one marker
+    /// and its marker addresses set.
+    pub(crate) fn set_trigger_anchor(&mut self, anchor: &Address) -> Result<()> {
+        if self.get_type() != VaultType::CAT {
+            return Err(anyhow!("only CAT vault triggers have marker outputs"));
+        }
+        if self.trigger_markers() < 2 {
+            return Err(anyhow!(
+                "the only marker commits to the withdrawal destination, build the vault with more markers"
+            ));
+        }
+        let mut addresses = self.marker_addresses()?;
+        if addresses.len() + 1 != self.trigger_markers() {
+            return Err(anyhow!("set the marker addresses before the anchor"));
+        }
+        if let Some(last) = addresses.last_mut() {
+            *last = anchor.clone();
+        }
+        self.set_marker_addresses(&addresses)
+    }
+}
+
+/// Build an unsigned child spending the `anchor_vout` output of `parent` and a fee input, so the two together pay
+/// `package_fee_rate`. `parent_prevouts` are the outputs the parent spends, everything left over goes to `change`.
+pub(crate) fn create_cpfp_child(
+    parent: &Transaction,
+    parent_prevouts: &[TxOut],
+    anchor_vout: u32,
+    fee_input: (OutPoint, TxOut),
+    change: &Address,
+    package_fee_rate: FeeRate,
+) -> Result<Transaction> {
+    let (fee_paying_utxo, fee_paying_output) = fee_input;
+    let anchor_output = parent.output.get(anchor_vout as usize).ok_or(anyhow!(
+        "{} has no output {}",
+        parent.txid(),
+        anchor_vout
+    ))?;
+    let mut child = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![
+            TxIn {
+                previous_output: OutPoint {
+                    txid: parent.txid(),
+                    vout: anchor_vout,
+                },
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: fee_paying_utxo,
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut {
+            script_pubkey: change.script_pubkey(),
+            value: Amount::ZERO,
+        }],
+    };
+    let fee = child_fee(parent, parent_prevouts, &child, package_fee_rate)?;
+    let change_value = (anchor_output.value + fee_paying_output.value)
+        .checked_sub(fee)
+        .ok_or(anyhow!(
+            "the anchor and fee input hold {} but the child has to pay {}",
+            anchor_output.value + fee_paying_output.value,
+            fee
+        ))?;
+    if change_value < dust_limit(&change.script_pubkey()) {
+        return Err(anyhow!(
+            "the child's change of {} would be dust",
+            change_value
+        ));
+    }
+    child.output[0].value = change_value;
+    Ok(child)
+}
+
+/// The smallest fee input [create_cpfp_child] can bump `parent` with while leaving `change` above dust,
+/// sized from a dry run with a placeholder fee input
+pub(crate) fn cpfp_fee_input_amount(
+    parent: &Transaction,
+    parent_prevouts: &[TxOut],
+    anchor_vout: u32,
+    change: &Address,
+    package_fee_rate: FeeRate,
+) -> Result<Amount> {
+    let placeholder = TxOut {
+        script_pubkey: change.script_pubkey(),
+        value: Amount::MAX_MONEY,
+    };
+    let dry_run = create_cpfp_child(
+        parent,
+        parent_prevouts,
+        anchor_vout,
+        (OutPoint::null(), placeholder),
+        change,
+        package_fee_rate,
+    )?;
+    let fee = child_fee(parent, parent_prevouts, &dry_run, package_fee_rate)?;
+    let anchor_value = parent.output[anchor_vout as usize].value;
+    Ok((fee + dust_limit(&change.script_pubkey()))
+        .checked_sub(anchor_value)
+        .unwrap_or(Amount::ZERO))
+}
+
+/// What the child has to pay so `parent` and `child` together pay `package_fee_rate`, and at least its own share.
+/// Both child inputs are counted as single-key spends, which overestimates a keyless anchor a little.
+fn child_fee(
+    parent: &Transaction,
+    parent_prevouts: &[TxOut],
+    child: &Transaction,
+    package_fee_rate: FeeRate,
+) -> Result<Amount> {
+    let parent_fee = fee_paid(parent, parent_prevouts)?;
+    let own_fee = signed_fee(child, package_fee_rate)?;
+    let package_fee = package_fee_rate
+        .fee_wu(parent.weight())
+        .ok_or(anyhow!("fee for {} overflows", parent.weight()))?
+        + own_fee;
+    Ok(package_fee
+        .checked_sub(parent_fee)
+        .unwrap_or(Amount::ZERO)
+        .max(own_fee))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::fees::signed_weight;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::Txid;
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn utxo(byte: u8, sats: u64) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([byte; 32]), 0),
+            TxOut {
+                script_pubkey: test_address(byte).script_pubkey(),
+                value: Amount::from_sat(sats),
+            },
+        )
+    }
+
+    fn two_marker_vault() -> VaultCovenant {
+        let settings = Settings {
+            trigger_markers: 2,
+            ..Settings::default()
+        };
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault.set_marker_addresses(&[test_address(3)]).unwrap();
+        vault
+    }
+
+    #[test]
+    fn anchored_trigger_and_child_pay_the_package_fee_rate() {
+        let mut vault = two_marker_vault();
+        let anchor = ephemeral_anchor(Network::Regtest).unwrap();
+        vault.set_trigger_anchor(&anchor).unwrap();
+        let vault_output = TxOut {
+            script_pubkey: vault.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(100_000),
+        };
+        // a small fee input leaves the trigger below the package rate, so the child makes up the difference
+        let trigger_fee_input = utxo(9, 2_000);
+        let parent = vault
+            .create_trigger_tx(
+                std::slice::from_ref(&trigger_fee_input),
+                &[test_address(2), anchor.clone()],
+            )
+            .unwrap()
+            .transaction;
+        let anchor_vout = ephemeral_anchor_vout(&parent).unwrap();
+        assert_eq!(anchor_vout as usize, parent.output.len() - 1);
+        let parent_prevouts = [vault_output, trigger_fee_input.1];
+
+        let rate = FeeRate::from_sat_per_vb(50).unwrap();
+        let change = test_address(4);
+        let needed =
+            cpfp_fee_input_amount(&parent, &parent_prevouts, anchor_vout, &change, rate).unwrap();
+        let child = create_cpfp_child(
+            &parent,
+            &parent_prevouts,
+            anchor_vout,
+            utxo(5, needed.to_sat()),
+            &change,
+            rate,
+        )
+        .unwrap();
+        assert_eq!(
+            child.input[0].previous_output,
+            OutPoint::new(parent.txid(), anchor_vout)
+        );
+        assert_eq!(child.output[0].value, dust_limit(&change.script_pubkey()));
+
+        let child_prevouts = [
+            parent.output[anchor_vout as usize].clone(),
+            utxo(5, needed.to_sat()).1,
+        ];
+        let package_fee = fee_paid(&parent, &parent_prevouts).unwrap()
+            + fee_paid(&child, &child_prevouts).unwrap();
+        let package_weight = parent.weight() + signed_weight(&child);
+        assert_eq!(package_fee, rate.fee_wu(package_weight).unwrap());
+
+        // one sat less and the change drops below dust
+        assert!(create_cpfp_child(
+            &parent,
+            &parent_prevouts,
+            anchor_vout,
+            utxo(5, needed.to_sat() - 1),
+            &change,
+            rate,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn anchor_needs_a_second_cat_marker() {
+        let anchor = ephemeral_anchor(Network::Regtest).unwrap();
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let mut vault = VaultCovenant::new_seeded(
+                vault_type,
+                TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &Settings::default(),
+                7,
+            )
+            .unwrap();
+            assert!(vault.set_trigger_anchor(&anchor).is_err());
+        }
+        let settings = Settings {
+            trigger_markers: 2,
+            ..Settings::default()
+        };
+        let mut unset = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        assert!(unset.set_trigger_anchor(&anchor).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::Encodable;
+use bitcoin::key::Keypair;
+use bitcoin::taproot::{ControlBlock, LeafVersion};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, FeeRate, Network, OutPoint, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxIn,
+    TxOut,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::vault::contract::{
+    sign_transaction, sign_transaction_input, SpendPath, VaultCovenant, VaultType,
+};
+use crate::vault::fee_change::change_witness_element;
+use crate::vault::fees::check_fee_floor;
+use crate::vault::inputs::arrange_inputs;
+use crate::vault::signature_building;
+use crate::vault::signature_building::{
+    get_sigmsg_components, grind_field_for_path, GrindBounds, TxCommitmentSpec,
+};
+use crate::vault::trace::witness_component;
+
+/// Everything needed to sign a cancel of a triggered withdrawal, including the cancel key.
+/// Only a CAT package is handed to a watchtower, see [VaultCovenant::cancel_delegation_package].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CancelDelegation {
+    vault_type: VaultType,
+    network: Network,
+    amount: Amount,
+    /// the triggered output the cancel transaction spends
+    triggered_output: TxOut,
+    /// where the cancel transaction sends the funds
+    destination: ScriptBuf,
+    cancel_script: ScriptBuf,
+    control_block: Vec<u8>,
+    cancel_keypair: Keypair,
+    /// where the cancel transaction puts the vault input
+    #[serde(default)]
+    vault_input_index: usize,
+    /// whether the CAT cancel leaf takes a change output
+    #[serde(default)]
+    fee_change: bool,
+    /// what the cancel key signs over in a CTV cancel, never written to the package file
+    #[serde(skip, default = "default_sighash_type")]
+    sighash_type: TapSighashType,
+    /// the lowest fee rate a CAT cancel is built at, never written to the package file
+    #[serde(skip, default = "default_min_fee_rate")]
+    min_fee_rate: FeeRate,
+    /// where a CAT cancel's grind starts and how far it searches, never written to the package file
+    #[serde(skip)]
+    grind_bounds: GrindBounds,
+}
+
+fn default_sighash_type() -> TapSighashType {
+    TapSighashType::All
+}
+
+fn default_min_fee_rate() -> FeeRate {
+    FeeRate::BROADCAST_MIN
+}
+
+impl VaultCovenant {
+    /// A package a watchtower can cancel triggered withdrawals of this vault with. Only CAT vaults can delegate:
+    /// their cancel leaf only pays the vault's own address, while the CTV cancel leaf takes any transaction the
+    /// cancel key signs, so its key would let the watchtower sweep the funds to itself.
+    pub(crate) fn cancel_delegation_package(&self) -> Result<CancelDelegation> {
+        if self.get_type() != VaultType::CAT {
+            return Err(anyhow!(
+                "only CAT vaults can delegate their cancel, the {:?} cancel key can send the funds anywhere",
+                self.get_type()
+            ));
+        }
+        self.cancel_signer()
+    }
+
+    /// The cancel key and leaf this process signs its own cancels with, never to be handed to anyone else
+    pub(crate) fn cancel_signer(&self) -> Result<CancelDelegation> {
+        let (cancel_script, control_block) = self.cancel_leaf()?;
+        Ok(CancelDelegation {
+            vault_type: self.get_type(),
+            network: self.get_network(),
+            amount: self.get_amount(),
+            triggered_output: self.triggered_output()?,
+            destination: self.address()?.script_pubkey(),
+            cancel_script,
+            control_block: control_block.serialize(),
+            cancel_keypair: self.cancel_keypair(),
+            vault_input_index: self.vault_input_index,
+            fee_change: self.fee_change(),
+            sighash_type: self.sighash_type(),
+            min_fee_rate: self.min_fee_rate(),
+            grind_bounds: self.grind_bounds(),
+        })
+    }
+}
+
+impl CancelDelegation {
+    /// Build and sign a transaction cancelling the withdrawal triggered at `triggered_outpoint`
+    pub(crate) fn create_cancel_tx(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+    ) -> Result<Transaction> {
+        self.create_cancel_tx_to(
+            triggered_outpoint,
+            fee_paying_utxo,
+            fee_paying_output,
+            self.destination.clone(),
+        )
+    }
+
+    /// Build and sign a cancel transaction paying `destination`.
+    /// The CAT cancel leaf only accepts the vault's own address as the destination.
+    pub(crate) fn create_cancel_tx_to(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        destination: ScriptBuf,
+    ) -> Result<Transaction> {
+        match self.vault_type {
+            VaultType::CAT => {
+                if destination != self.destination {
+                    return Err(anyhow!(
+                        "the CAT cancel leaf can only send the funds back to the same vault"
+                    ));
+                }
+                self.create_cat_cancel_tx(
+                    triggered_outpoint,
+                    fee_paying_utxo,
+                    fee_paying_output,
+                    None,
+                )
+            }
+            VaultType::CTV => self.create_ctv_cancel_tx(
+                triggered_outpoint,
+                Some((fee_paying_utxo, fee_paying_output)),
+                &[],
+                destination,
+                Amount::ZERO,
+            ),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => Err(anyhow!(
+                "an OP_VAULT recovery needs the cancel key, it can't be delegated"
+            )),
+        }
+    }
+
+    /// Build a CTV cancel paying `destination` that also spends `extra_inputs` and folds their value into its one
+    /// output. The cancel key signs over every input, the caller signs the extra inputs and the fee input afterwards.
+    pub(crate) fn create_consolidating_cancel_tx_to(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_input: (&OutPoint, TxOut),
+        extra_inputs: &[(OutPoint, TxOut)],
+        destination: ScriptBuf,
+    ) -> Result<Transaction> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!("only a CTV cancel can spend extra inputs"));
+        }
+        self.create_ctv_cancel_tx(
+            triggered_outpoint,
+            Some(fee_input),
+            extra_inputs,
+            destination,
+            Amount::ZERO,
+        )
+    }
+
+    /// Build and sign a CTV cancel transaction without a fee input, paying `fee` out of the cancelled funds
+    pub(crate) fn create_fee_deducting_cancel_tx_to(
+        &self,
+        triggered_outpoint: OutPoint,
+        destination: ScriptBuf,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        if self.vault_type != VaultType::CTV {
+            return Err(anyhow!(
+                "the CAT cancel leaf requires the cancel to send the full vault amount"
+            ));
+        }
+        self.create_ctv_cancel_tx(triggered_outpoint, None, &[], destination, fee)
+    }
+
+    /// Build and sign a CAT cancel that pays `change` after the vault output, which a vault built with fee change allows
+    pub(crate) fn create_cancel_tx_paying_change(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        change: TxOut,
+    ) -> Result<Transaction> {
+        if self.vault_type != VaultType::CAT || !self.fee_change {
+            return Err(anyhow!("the vault's cancel leaf doesn't allow change"));
+        }
+        self.create_cat_cancel_tx(
+            triggered_outpoint,
+            fee_paying_utxo,
+            fee_paying_output,
+            Some(change),
+        )
+    }
+
+    fn create_cat_cancel_tx(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        change: Option<TxOut>,
+    ) -> Result<Transaction> {
+        let mut vault_txin = TxIn {
+            previous_output: triggered_outpoint,
+            ..Default::default()
+        };
+        let fee_txin = TxIn {
+            previous_output: *fee_paying_utxo,
+            ..Default::default()
+        };
+        let output = TxOut {
+            script_pubkey: self.destination.clone(),
+            value: self.amount,
+        };
+
+        let txn = Transaction {
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+            input: vec![vault_txin.clone(), fee_txin],
+            output: std::iter::once(output.clone())
+                .chain(change.clone())
+                .collect(),
+        };
+
+        let tx_commitment_spec = TxCommitmentSpec::for_cancel();
+
+        let leaf_hash = TapLeafHash::from_script(&self.cancel_script, LeafVersion::TapScript);
+        let vault_txout = self.triggered_output.clone();
+        check_fee_floor(
+            &txn,
+            &[vault_txout.clone(), fee_paying_output.clone()],
+            self.min_fee_rate,
+        )?;
+        let contract_components = signature_building::grind_transaction(
+            txn,
+            grind_field_for_path(SpendPath::Cancel),
+            self.grind_bounds,
+            0,
+            &[vault_txout.clone(), fee_paying_output.clone()],
+            leaf_hash,
+        )?;
+
+        let mut txn = contract_components.transaction;
+        let witness_components = get_sigmsg_components(
+            &tx_commitment_spec,
+            &txn,
+            0,
+            &[vault_txout.clone(), fee_paying_output.clone()],
+            None,
+            leaf_hash,
+            TapSighashType::Default,
+        )?;
+
+        for (index, component) in witness_components.iter().enumerate() {
+            witness_component(index, component);
+            vault_txin.witness.push(component.as_slice());
+        }
+        let computed_signature = signature_building::compute_signature_from_components(
+            &contract_components.signature_components,
+        )?;
+
+        if self.fee_change {
+            vault_txin
+                .witness
+                .push(change_witness_element(change.as_ref()));
+        }
+        let mut amount_buffer = Vec::new();
+        self.amount.consensus_encode(&mut amount_buffer)?;
+        vault_txin.witness.push(amount_buffer.as_slice());
+        let mut scriptpubkey_buffer = Vec::new();
+        output
+            .script_pubkey
+            .consensus_encode(&mut scriptpubkey_buffer)?;
+        vault_txin.witness.push(scriptpubkey_buffer.as_slice());
+
+        let mut fee_amount_buffer = Vec::new();
+        fee_paying_output
+            .value
+            .consensus_encode(&mut fee_amount_buffer)?;
+        vault_txin.witness.push(fee_amount_buffer.as_slice());
+        let mut fee_scriptpubkey_buffer = Vec::new();
+        fee_paying_output
+            .script_pubkey
+            .consensus_encode(&mut fee_scriptpubkey_buffer)?;
+        vault_txin.witness.push(fee_scriptpubkey_buffer.as_slice());
+
+        let mangled_signature: [u8; 63] = computed_signature[0..63].try_into().unwrap(); // chop off the last byte, so we can provide the 0x00 and 0x01 bytes on the stack
+        vault_txin.witness.push(mangled_signature);
+        vault_txin.witness.push([computed_signature[63]]); // push the last byte of the signature
+        vault_txin.witness.push([computed_signature[63] + 1]); // push the last byte of the signature
+
+        let sig = sign_transaction(
+            &txn,
+            &[vault_txout.clone(), fee_paying_output.clone()],
+            leaf_hash,
+            self.cancel_keypair,
+            TapSighashType::All,
+        )?;
+        vault_txin.witness.push(sig);
+
+        vault_txin.witness.push(self.cancel_script.to_bytes());
+        vault_txin.witness.push(self.control_block()?.serialize());
+        txn.input.first_mut().unwrap().witness = vault_txin.witness.clone();
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&txn, 0, &[vault_txout, fee_paying_output])?;
+
+        Ok(txn)
+    }
+
+    fn create_ctv_cancel_tx(
+        &self,
+        triggered_outpoint: OutPoint,
+        fee_input: Option<(&OutPoint, TxOut)>,
+        extra_inputs: &[(OutPoint, TxOut)],
+        destination: ScriptBuf,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let mut vault_txin = TxIn {
+            previous_output: triggered_outpoint,
+            ..Default::default()
+        };
+        let extra_value = extra_inputs
+            .iter()
+            .map(|(_, output)| output.value)
+            .sum::<Amount>();
+        let output = TxOut {
+            script_pubkey: destination,
+            value: (self.amount + extra_value)
+                .checked_sub(fee)
+                .ok_or(anyhow!("a fee of {} is more than the vault holds", fee))?,
+        };
+        let (fee_txin, fee_prevout) = fee_input
+            .map(|(fee_paying_utxo, fee_paying_output)| {
+                let fee_txin = TxIn {
+                    previous_output: *fee_paying_utxo,
+                    ..Default::default()
+                };
+                (fee_txin, fee_paying_output)
+            })
+            .unzip();
+        let (mut input, vault_index) =
+            arrange_inputs(vault_txin.clone(), fee_txin, self.vault_input_index);
+        let (mut prevouts, _) = arrange_inputs(
+            self.triggered_output.clone(),
+            fee_prevout,
+            self.vault_input_index,
+        );
+        for (outpoint, output) in extra_inputs {
+            input.push(TxIn {
+                previous_output: *outpoint,
+                ..Default::default()
+            });
+            prevouts.push(output.clone());
+        }
+        let mut txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output: vec![output],
+        };
+        let leafhash = TapLeafHash::from_script(&self.cancel_script, LeafVersion::TapScript);
+
+        let sig = sign_transaction_input(
+            &txn,
+            vault_index,
+            &prevouts,
+            leafhash,
+            self.cancel_keypair,
+            self.sighash_type,
+        )?;
+        vault_txin.witness.push(sig);
+
+        vault_txin.witness.push(self.cancel_script.to_bytes());
+        vault_txin.witness.push(self.control_block()?.serialize());
+        txn.input[vault_index].witness = vault_txin.witness.clone();
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(&txn, vault_index, &prevouts)?;
+
+        Ok(txn)
+    }
+
+    fn control_block(&self) -> Result<ControlBlock> {
+        ControlBlock::decode(&self.control_block)
+            .map_err(|e| anyhow!("invalid cancel control block: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::timelock::TimelockKind;
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_cat_vaults_delegate_their_cancel() {
+        let cat = seeded_vault(VaultType::CAT);
+        let delegation = cat.cancel_delegation_package().unwrap();
+        assert_eq!(
+            delegation.destination,
+            cat.address().unwrap().script_pubkey()
+        );
+        assert!(seeded_vault(VaultType::CTV)
+            .cancel_delegation_package()
+            .is_err());
+    }
+}
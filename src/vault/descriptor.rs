@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+
+use crate::vault::contract::VaultCovenant;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (bit, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if c0 >> bit & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// The BIP 380 checksum Bitcoin Core expects after the `#` of an imported descriptor
+pub(crate) fn descriptor_checksum(descriptor: &str) -> Result<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET
+            .find(ch)
+            .ok_or(anyhow!("{:?} can't appear in a descriptor", ch))? as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[(c >> (5 * (7 - j)) & 31) as usize] as char)
+        .collect())
+}
+
+impl VaultCovenant {
+    /// A watch-only descriptor for the deposit address, with its checksum, for importing into Bitcoin Core or Sparrow.
+    /// Descriptors can't express the OP_CAT and OP_CTV leaves, so this is `rawtr()` of the tweaked output key:
+    /// a wallet importing it sees the deposits and balance but can't sign for any leaf.
+    pub(crate) fn descriptor(&self) -> Result<String> {
+        let descriptor = format!("rawtr({})", self.deposit_spend_info()?.output_key());
+        let checksum = descriptor_checksum(&descriptor)?;
+        Ok(format!("{}#{}", descriptor, checksum))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::VaultType;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::key::TweakedPublicKey;
+    use bitcoin::{Address, Amount, XOnlyPublicKey};
+    use std::str::FromStr;
+
+    #[test]
+    fn checksum_matches_bitcoin_core() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+    }
+
+    #[test]
+    fn descriptor_derives_the_vault_address() {
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let vault = VaultCovenant::new_seeded(
+                vault_type,
+                TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &Settings::default(),
+                7,
+            )
+            .unwrap();
+            let descriptor = vault.descriptor().unwrap();
+            let (body, checksum) = descriptor.split_once('#').unwrap();
+            assert_eq!(checksum, descriptor_checksum(body).unwrap());
+            let key = body
+                .strip_prefix("rawtr(")
+                .and_then(|key| key.strip_suffix(')'))
+                .unwrap();
+            let output_key =
+                TweakedPublicKey::dangerous_assume_tweaked(XOnlyPublicKey::from_str(key).unwrap());
+            assert_eq!(
+                Address::p2tr_tweaked(output_key, vault.get_network()),
+                vault.address().unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use bitcoin::bip32::Xpriv;
+use bitcoin::Amount;
+use serde::Serialize;
+
+use crate::vault::contract::{VaultCovenant, VaultState, VaultType};
+use crate::vault::timelock::TimelockKind;
+
+/// The covenants read from a directory, next to the files that failed to load
+pub(crate) struct LoadedVaults {
+    pub(crate) vaults: Vec<(PathBuf, VaultCovenant)>,
+    pub(crate) failures: Vec<(PathBuf, anyhow::Error)>,
+}
+
+/// One vault's line in an overview of several
+#[derive(Serialize, Debug)]
+pub(crate) struct VaultSummary {
+    pub(crate) file: String,
+    pub(crate) vault_id: String,
+    pub(crate) vault_type: VaultType,
+    /// where the vault's funds are now, the triggered output's address for a triggered CTV vault
+    pub(crate) address: String,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub(crate) amount: Amount,
+    pub(crate) state: VaultState,
+    pub(crate) timelock: TimelockKind,
+}
+
+impl VaultCovenant {
+    /// Read every `*.json` covenant in `dir`, in file name order. A file that doesn't load is recorded with
+    /// its error and the rest are still read, only an unreadable directory fails the whole load.
+    pub(crate) fn load_all(dir: &Path, master_key: Option<&Xpriv>) -> Result<LoadedVaults> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+        let mut loaded = LoadedVaults {
+            vaults: Vec::new(),
+            failures: Vec::new(),
+        };
+        for path in paths {
+            match Self::from_file(&Some(path.to_string_lossy().to_string()), master_key) {
+                Ok(vault) => loaded.vaults.push((path, vault)),
+                Err(e) => loaded.failures.push((path, e)),
+            }
+        }
+        Ok(loaded)
+    }
+
+    pub(crate) fn summary(&self, file: &Path) -> Result<VaultSummary> {
+        Ok(VaultSummary {
+            file: file.display().to_string(),
+            vault_id: self.vault_id(),
+            vault_type: self.get_type(),
+            address: self.address()?.to_string(),
+            amount: self.get_amount(),
+            state: self.get_state(),
+            timelock: self.timelock,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn malformed_file_is_reported_and_the_rest_load() {
+        let dir = std::env::temp_dir().join(format!("scv-test-{}-load-all", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| Some(dir.join(name).to_string_lossy().to_string());
+        seeded_vault(VaultType::CAT)
+            .to_file(&file("a.json"))
+            .unwrap();
+        seeded_vault(VaultType::CTV)
+            .to_file(&file("b.json"))
+            .unwrap();
+        std::fs::write(dir.join("c.json"), "{ not a vault").unwrap();
+
+        let loaded = VaultCovenant::load_all(&dir, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.vaults.len(), 2);
+        assert_eq!(loaded.failures.len(), 1);
+        assert_eq!(loaded.failures[0].0, dir.join("c.json"));
+
+        let summaries = loaded
+            .vaults
+            .iter()
+            .map(|(path, vault)| vault.summary(path).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(summaries[0].vault_type, VaultType::CAT);
+        assert_eq!(summaries[1].vault_type, VaultType::CTV);
+        for summary in summaries {
+            assert_eq!(summary.amount, Amount::from_sat(100_000));
+            assert_eq!(summary.state, VaultState::Inactive);
+            assert_eq!(summary.timelock, TimelockKind::Blocks(10));
+        }
+    }
+}
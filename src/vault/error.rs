@@ -0,0 +1,80 @@
+use bitcoin::{Amount, Network, Txid};
+use thiserror::Error;
+
+/// Failures a caller may want to handle rather than just report. They travel inside an [anyhow::Error],
+/// `downcast_ref::<VaultError>()` gets them back out.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub(crate) enum VaultError {
+    /// the vault isn't funded yet, or its output hasn't been set
+    #[error("no current outpoint")]
+    NoCurrentOutpoint,
+    /// the funds have left the vault, in the recorded transaction if it is known
+    #[error("the vault is completed{}", .0.map(|txid| format!(", {} spent it", txid)).unwrap_or_default())]
+    Completed(Option<Txid>),
+    /// no withdrawal has been triggered to an address yet
+    #[error("no withdrawal address")]
+    NoWithdrawalAddress,
+    /// the trigger transaction wasn't recorded or recovered
+    #[error("no trigger transaction")]
+    NoTriggerTransaction,
+    /// an address for a different network than the vault's
+    #[error("{address} is not a {network} address")]
+    WrongNetworkAddress { address: String, network: Network },
+    /// a vault file loaded with settings for another network
+    #[error("the vault is on {vault} but the settings are for {settings}")]
+    NetworkMismatch { vault: Network, settings: Network },
+    /// the fee inputs leave less than the minimum fee rate needs
+    #[error("fee UTXO provides {provided}, need at least {required}")]
+    InsufficientFee { provided: Amount, required: Amount },
+    /// the outputs spend more than the inputs hold
+    #[error("the outputs pay {outputs} but the inputs only hold {inputs}")]
+    NegativeFee { inputs: Amount, outputs: Amount },
+    /// the taproot tree of a vault output couldn't be finalized
+    #[error("the taproot tree could not be finalized")]
+    TaprootFinalize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn messages_match_the_variants() {
+        assert_eq!(
+            VaultError::Completed(None).to_string(),
+            "the vault is completed"
+        );
+        assert_eq!(
+            VaultError::Completed(Some(Txid::all_zeros())).to_string(),
+            format!("the vault is completed, {} spent it", Txid::all_zeros())
+        );
+        assert_eq!(
+            VaultError::NetworkMismatch {
+                vault: Network::Bitcoin,
+                settings: Network::Regtest
+            }
+            .to_string(),
+            "the vault is on bitcoin but the settings are for regtest"
+        );
+    }
+
+    #[test]
+    fn variants_survive_a_trip_through_anyhow() {
+        let error: anyhow::Error = VaultError::InsufficientFee {
+            provided: Amount::from_sat(1),
+            required: Amount::from_sat(2),
+        }
+        .into();
+        assert_eq!(
+            error.downcast_ref::<VaultError>(),
+            Some(&VaultError::InsufficientFee {
+                provided: Amount::from_sat(1),
+                required: Amount::from_sat(2),
+            })
+        );
+        assert!(anyhow::Error::from(VaultError::NoCurrentOutpoint)
+            .downcast_ref::<VaultError>()
+            .is_some_and(|e| matches!(e, VaultError::NoCurrentOutpoint)));
+    }
+}
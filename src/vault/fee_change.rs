@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use bitcoin::consensus::serialize;
+use bitcoin::{Address, Amount, FeeRate, OutPoint, Transaction, TxOut};
+use log::debug;
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultType};
+use crate::vault::fees::{dust_limit, signed_fee, BuiltTx};
+
+/// The witness element a fee change leaf appends to the outputs it hashes: the encoded change output,
+/// or nothing when the transaction pays no change
+pub(crate) fn change_witness_element(change: Option<&TxOut>) -> Vec<u8> {
+    change.map(serialize).unwrap_or_default()
+}
+
+impl VaultCovenant {
+    /// The change output a trigger of this vault paid after its markers, if it paid one
+    pub(crate) fn trigger_paid_change<'a>(&self, trigger_tx: &'a Transaction) -> Option<&'a TxOut> {
+        if !self.fee_change() {
+            return None;
+        }
+        trigger_tx.output.get(self.trigger_markers() + 1)
+    }
+
+    /// Build a CAT trigger like [VaultCovenant::create_trigger_tx] that sends what the fee inputs hold beyond the
+    /// markers and `fee_rate` back to `change_address`. The whole excess goes to fees when it is below the dust limit.
+    pub(crate) fn create_trigger_tx_with_change(
+        &self,
+        fee_inputs: &[(OutPoint, TxOut)],
+        target_addresses: &[Address],
+        change_address: &Address,
+        fee_rate: FeeRate,
+    ) -> Result<BuiltTx> {
+        self.require_fee_change(SpendPath::Trigger)?;
+        let dry_run = self.build_trigger_tx(
+            fee_inputs,
+            target_addresses,
+            Some(empty_change(change_address)),
+        )?;
+        let change = change_output(&dry_run, change_address, fee_rate)?;
+        self.build_trigger_tx(fee_inputs, target_addresses, change)
+    }
+
+    /// Build a CAT cancel like [VaultCovenant::create_cancel_tx] that sends what the fee input holds beyond
+    /// `fee_rate` back to `change_address`. The whole excess goes to fees when it is below the dust limit.
+    pub(crate) fn create_cancel_tx_with_change(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        change_address: &Address,
+        fee_rate: FeeRate,
+    ) -> Result<BuiltTx> {
+        self.require_fee_change(SpendPath::Cancel)?;
+        let _span = self.build_span(SpendPath::Cancel);
+        let triggered_outpoint = self.get_current_outpoint()?;
+        let prevouts = [self.triggered_output()?, fee_paying_output.clone()];
+        let delegation = self.cancel_signer()?;
+        let dry_run = BuiltTx::new(
+            delegation.create_cancel_tx_paying_change(
+                triggered_outpoint,
+                fee_paying_utxo,
+                fee_paying_output.clone(),
+                empty_change(change_address),
+            )?,
+            &prevouts,
+        )?;
+        let txn = match change_output(&dry_run, change_address, fee_rate)? {
+            Some(change) => delegation.create_cancel_tx_paying_change(
+                triggered_outpoint,
+                fee_paying_utxo,
+                fee_paying_output,
+                change,
+            )?,
+            None => delegation.create_cancel_tx(
+                triggered_outpoint,
+                fee_paying_utxo,
+                fee_paying_output,
+            )?,
+        };
+        BuiltTx::new(txn, &prevouts)
+    }
+
+    /// Refuse to pay change from the `path` fee input unless this vault's leaves commit to it
+    pub(crate) fn require_fee_change(&self, path: SpendPath) -> Result<()> {
+        if self.get_type() != VaultType::CAT || !self.fee_change() {
+            return Err(anyhow!(
+                "only a CAT vault built with fee change can pay change from its {:?} fee input",
+                path
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A change output with no value, it sizes a dry run the same as the real change
+fn empty_change(change_address: &Address) -> TxOut {
+    TxOut {
+        script_pubkey: change_address.script_pubkey(),
+        value: Amount::ZERO,
+    }
+}
+
+/// The change that leaves `dry_run`, built with an empty change output, paying `fee_rate`. None when the fee inputs
+/// don't hold enough for change above the dust limit.
+fn change_output(
+    dry_run: &BuiltTx,
+    change_address: &Address,
+    fee_rate: FeeRate,
+) -> Result<Option<TxOut>> {
+    let script_pubkey = change_address.script_pubkey();
+    let change = dry_run
+        .fee
+        .checked_sub(signed_fee(&dry_run.transaction, fee_rate)?)
+        .unwrap_or(Amount::ZERO);
+    if change < dust_limit(&script_pubkey) {
+        debug!("dropping change of {} below the dust limit", change);
+        return Ok(None);
+    }
+    Ok(Some(TxOut {
+        script_pubkey,
+        value: change,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultState, SECP};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn fee_input(vout: u32, sats: u64) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(sats),
+            },
+        )
+    }
+
+    fn fee_change_vault() -> VaultCovenant {
+        let settings = Settings {
+            fee_change: true,
+            ..Settings::default()
+        };
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &settings,
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    #[test]
+    fn large_fee_input_pays_change_at_the_fee_rate() {
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+        let change_address = test_address(4);
+        let mut vault = fee_change_vault();
+        let trigger = vault
+            .create_trigger_tx_with_change(
+                &[fee_input(0, 200_000)],
+                &[test_address(2)],
+                &change_address,
+                fee_rate,
+            )
+            .unwrap();
+        let change = vault.trigger_paid_change(&trigger.transaction).unwrap();
+        assert_eq!(change.script_pubkey, change_address.script_pubkey());
+        assert_eq!(
+            trigger.fee,
+            signed_fee(&trigger.transaction, fee_rate).unwrap()
+        );
+        assert_eq!(
+            change.value,
+            Amount::from_sat(200_000) - vault.marker_amount() - trigger.fee
+        );
+
+        vault.set_current_outpoint(trigger.trigger_outpoint());
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+        vault.set_trigger_transaction(Some(trigger.transaction));
+        vault.set_state(VaultState::Triggered);
+        let (outpoint, output) = fee_input(1, 200_000);
+        let cancel = vault
+            .create_cancel_tx_with_change(&outpoint, output, &change_address, fee_rate)
+            .unwrap();
+        let change = cancel.transaction.output.last().unwrap();
+        assert_eq!(change.script_pubkey, change_address.script_pubkey());
+        assert_eq!(
+            cancel.fee,
+            signed_fee(&cancel.transaction, fee_rate).unwrap()
+        );
+        assert_eq!(change.value, Amount::from_sat(200_000) - cancel.fee);
+    }
+
+    #[test]
+    fn change_below_the_dust_limit_goes_to_fees() {
+        let vault = fee_change_vault();
+        let exact = vault
+            .create_trigger_tx_with_change(
+                &[fee_input(0, 200_000)],
+                &[test_address(2)],
+                &test_address(4),
+                FeeRate::from_sat_per_vb(10).unwrap(),
+            )
+            .unwrap();
+        let barely_over = exact.fee + vault.marker_amount() + Amount::from_sat(100);
+        let trigger = vault
+            .create_trigger_tx_with_change(
+                &[fee_input(0, barely_over.to_sat())],
+                &[test_address(2)],
+                &test_address(4),
+                FeeRate::from_sat_per_vb(10).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(vault.trigger_paid_change(&trigger.transaction), None);
+    }
+
+    #[test]
+    fn vault_without_fee_change_refuses_it() {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        assert!(vault
+            .create_trigger_tx_with_change(
+                &[fee_input(0, 200_000)],
+                &[test_address(2)],
+                &test_address(4),
+                FeeRate::from_sat_per_vb(10).unwrap(),
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Result};
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::secp256k1::Message;
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache};
+use bitcoin::{
+    ecdsa, taproot, Address, Network, PublicKey, TapSighashType, Transaction, TxOut, Witness,
+};
+
+use crate::vault::contract::SECP;
+
+/// Where to fund a fee input `fee_keypair` can sign for: its taproot key path, or P2WPKH when `segwit_v0` is set
+pub(crate) fn fee_key_address(
+    fee_keypair: &Keypair,
+    network: Network,
+    segwit_v0: bool,
+) -> Result<Address> {
+    Ok(if segwit_v0 {
+        Address::p2wpkh(&PublicKey::new(fee_keypair.public_key()), network)?
+    } else {
+        Address::p2tr(&SECP, fee_keypair.x_only_public_key().0, None, network)
+    })
+}
+
+/// Sign the inputs of `txn` spending outputs `fee_keypair` holds, through the taproot key path or as P2WPKH,
+/// so a covenant transaction can be broadcast without an external wallet. `prevouts` are the outputs every input
+/// spends in input order, the vault input's covenant witness is left alone.
+pub(crate) fn sign_fee_input(
+    txn: &Transaction,
+    prevouts: &[TxOut],
+    fee_keypair: &Keypair,
+) -> Result<Transaction> {
+    if prevouts.len() != txn.input.len() {
+        return Err(anyhow!(
+            "got {} prevouts for a transaction with {} inputs",
+            prevouts.len(),
+            txn.input.len()
+        ));
+    }
+    let p2tr = fee_key_address(fee_keypair, Network::Bitcoin, false)?.script_pubkey();
+    let p2wpkh = fee_key_address(fee_keypair, Network::Bitcoin, true)?.script_pubkey();
+    let mut signed = txn.clone();
+    let mut cache = SighashCache::new(txn);
+    let mut signed_any = false;
+    for (index, prevout) in prevouts.iter().enumerate() {
+        let witness = if prevout.script_pubkey == p2tr {
+            let sighash = cache.taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(prevouts),
+                TapSighashType::Default,
+            )?;
+            let tweaked_keypair = fee_keypair.tap_tweak(&SECP, None);
+            let signature = SECP.sign_schnorr(
+                &Message::from_digest_slice(sighash.as_ref())?,
+                &tweaked_keypair.to_inner(),
+            );
+            Witness::from_slice(&[taproot::Signature {
+                sig: signature,
+                hash_ty: TapSighashType::Default,
+            }
+            .to_vec()])
+        } else if prevout.script_pubkey == p2wpkh {
+            let sighash = cache.p2wpkh_signature_hash(
+                index,
+                &prevout.script_pubkey,
+                prevout.value,
+                EcdsaSighashType::All,
+            )?;
+            let signature = SECP.sign_ecdsa(
+                &Message::from_digest_slice(sighash.as_ref())?,
+                &fee_keypair.secret_key(),
+            );
+            Witness::p2wpkh(
+                &ecdsa::Signature {
+                    sig: signature,
+                    hash_ty: EcdsaSighashType::All,
+                },
+                &fee_keypair.public_key(),
+            )
+        } else {
+            continue;
+        };
+        signed.input[index].witness = witness;
+        signed_any = true;
+    }
+    if !signed_any {
+        return Err(anyhow!(
+            "{} spends nothing the fee key can sign for",
+            txn.txid()
+        ));
+    }
+    Ok(signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultCovenant, VaultType};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1;
+    use bitcoin::{Amount, OutPoint, Txid};
+
+    fn fee_utxo(vout: u32, address: &Address) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: Amount::from_sat(25_000),
+            },
+        )
+    }
+
+    #[test]
+    fn trigger_fee_inputs_are_signed_for_both_output_types() {
+        let fee_keypair = Keypair::from_seckey_slice(&SECP, &[5; 32]).unwrap();
+        let p2tr = fee_utxo(
+            0,
+            &fee_key_address(&fee_keypair, Network::Regtest, false).unwrap(),
+        );
+        let p2wpkh = fee_utxo(
+            1,
+            &fee_key_address(&fee_keypair, Network::Regtest, true).unwrap(),
+        );
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination = fee_key_address(&fee_keypair, Network::Regtest, false).unwrap();
+        let trigger = vault
+            .create_trigger_tx(&[p2tr.clone(), p2wpkh.clone()], &[destination])
+            .unwrap()
+            .transaction;
+        let prevouts = [vault.current_txout().unwrap(), p2tr.1, p2wpkh.1];
+        let signed = sign_fee_input(&trigger, &prevouts, &fee_keypair).unwrap();
+        assert_eq!(signed.input[0].witness, trigger.input[0].witness);
+
+        let mut cache = SighashCache::new(&trigger);
+        let sighash = cache
+            .taproot_key_spend_signature_hash(1, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .unwrap();
+        let signature =
+            taproot::Signature::from_slice(signed.input[1].witness.nth(0).unwrap()).unwrap();
+        let output_key = fee_keypair.x_only_public_key().0.tap_tweak(&SECP, None).0;
+        SECP.verify_schnorr(
+            &signature.sig,
+            &Message::from_digest_slice(sighash.as_ref()).unwrap(),
+            &output_key.to_inner(),
+        )
+        .unwrap();
+
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                2,
+                &prevouts[2].script_pubkey,
+                prevouts[2].value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let witness = &signed.input[2].witness;
+        let signature = ecdsa::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        assert_eq!(
+            secp256k1::PublicKey::from_slice(witness.nth(1).unwrap()).unwrap(),
+            fee_keypair.public_key()
+        );
+        SECP.verify_ecdsa(
+            &Message::from_digest_slice(sighash.as_ref()).unwrap(),
+            &signature.sig,
+            &fee_keypair.public_key(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn inputs_the_fee_key_doesnt_hold_are_left_unsigned() {
+        let fee_keypair = Keypair::from_seckey_slice(&SECP, &[5; 32]).unwrap();
+        let other = Keypair::from_seckey_slice(&SECP, &[6; 32]).unwrap();
+        let foreign = fee_utxo(
+            0,
+            &fee_key_address(&other, Network::Regtest, false).unwrap(),
+        );
+        let own = fee_utxo(
+            1,
+            &fee_key_address(&fee_keypair, Network::Regtest, true).unwrap(),
+        );
+        let txn = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: [&foreign, &own]
+                .iter()
+                .map(|(outpoint, _)| bitcoin::TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                script_pubkey: foreign.1.script_pubkey.clone(),
+                value: Amount::from_sat(40_000),
+            }],
+        };
+        let signed = sign_fee_input(&txn, &[foreign.1.clone(), own.1], &fee_keypair).unwrap();
+        assert!(signed.input[0].witness.is_empty());
+        assert_eq!(signed.input[1].witness.len(), 2);
+
+        assert!(
+            sign_fee_input(&txn, &[foreign.1.clone(), foreign.1.clone()], &fee_keypair).is_err()
+        );
+        assert!(sign_fee_input(&txn, &[foreign.1], &fee_keypair).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Address, Amount, FeeRate, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid, Weight,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultType};
+use crate::vault::error::VaultError;
+
+/// The fee rate to build each spend path's transaction at.
+/// Cancel defaults highest since it has to win the race against a thief's complete.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub(crate) struct PathFeeRates {
+    pub(crate) trigger: FeeRate,
+    pub(crate) complete: FeeRate,
+    pub(crate) cancel: FeeRate,
+}
+
+impl Default for PathFeeRates {
+    fn default() -> Self {
+        Self {
+            trigger: FeeRate::from_sat_per_vb_unchecked(20),
+            complete: FeeRate::from_sat_per_vb_unchecked(10),
+            cancel: FeeRate::from_sat_per_vb_unchecked(40),
+        }
+    }
+}
+
+impl PathFeeRates {
+    pub(crate) fn for_path(&self, path: SpendPath) -> FeeRate {
+        match path {
+            SpendPath::Trigger => self.trigger,
+            SpendPath::Complete => self.complete,
+            SpendPath::Cancel => self.cancel,
+        }
+    }
+}
+
+/// The smallest value Bitcoin Core relays an output paying to `script_pubkey` with, at the default
+/// dust relay fee of 3 sat/vB: 330 sats for P2TR, 294 for P2WPKH and 546 for P2PKH
+pub(crate) fn dust_limit(script_pubkey: &Script) -> Amount {
+    script_pubkey.dust_value()
+}
+
+/// The absolute fee paid by a transaction, i.e. the value of its inputs minus the value of its outputs.
+/// `prevouts` are the outputs being spent, in the same order as the transaction inputs.
+pub(crate) fn fee_paid(txn: &Transaction, prevouts: &[TxOut]) -> Result<Amount> {
+    if prevouts.len() != txn.input.len() {
+        return Err(anyhow!(
+            "got {} prevouts for a transaction with {} inputs",
+            prevouts.len(),
+            txn.input.len()
+        ));
+    }
+    let input_value = prevouts
+        .iter()
+        .try_fold(Amount::ZERO, |total, prevout| {
+            total.checked_add(prevout.value)
+        })
+        .ok_or(anyhow!("input value overflows"))?;
+    let output_value = txn
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |total, output| {
+            total.checked_add(output.value)
+        })
+        .ok_or(anyhow!("output value overflows"))?;
+    input_value.checked_sub(output_value).ok_or(
+        VaultError::NegativeFee {
+            inputs: input_value,
+            outputs: output_value,
+        }
+        .into(),
+    )
+}
+
+/// The fee rate a transaction pays, computed from its full weight including the covenant witness
+pub(crate) fn effective_fee_rate(txn: &Transaction, prevouts: &[TxOut]) -> Result<FeeRate> {
+    let fee = fee_paid(txn, prevouts)?;
+    let weight = txn.weight().to_wu();
+    if weight == 0 {
+        return Err(anyhow!("transaction has no weight"));
+    }
+    Ok(FeeRate::from_sat_per_kwu(fee.to_sat() * 1000 / weight))
+}
+
+/// Worst case weight a single-key input adds once signed: a P2WPKH witness (item count, DER signature with
+/// sighash byte and compressed pubkey, each length prefixed) plus the segwit marker and flag.
+/// This also covers a taproot key path spend.
+pub(crate) const KEY_SPEND_WITNESS_WEIGHT: Weight = Weight::from_wu(1 + 1 + 73 + 1 + 33 + 2);
+
+/// The fee needed for an unsigned transaction whose inputs are all single-key spends to pay `fee_rate` once signed
+pub(crate) fn key_spend_fee(txn: &Transaction, fee_rate: FeeRate) -> Result<Amount> {
+    let weight = txn.weight() + KEY_SPEND_WITNESS_WEIGHT * txn.input.len() as u64;
+    fee_rate
+        .fee_wu(weight)
+        .ok_or(anyhow!("fee for weight {} overflows", weight))
+}
+
+/// The weight of a transaction once every input without a witness gets a single-key signature
+pub(crate) fn signed_weight(txn: &Transaction) -> Weight {
+    let unsigned_inputs = txn.input.iter().filter(|i| i.witness.is_empty()).count();
+    txn.weight() + KEY_SPEND_WITNESS_WEIGHT * unsigned_inputs as u64
+}
+
+/// The fee needed for a transaction to pay `fee_rate` once every input without a witness gets a single-key signature
+pub(crate) fn signed_fee(txn: &Transaction, fee_rate: FeeRate) -> Result<Amount> {
+    let weight = signed_weight(txn);
+    fee_rate
+        .fee_wu(weight)
+        .ok_or(anyhow!("fee for weight {} overflows", weight))
+}
+
+/// Fail early if the fee inputs of an unfinished `txn` leave too little over its outputs to pay `min_fee_rate`,
+/// before any time goes into grinding it. The vault input is counted as a key spend since its witness isn't
+/// built yet, so this is a lower bound and the finished transaction still has to be checked.
+pub(crate) fn check_fee_floor(
+    txn: &Transaction,
+    prevouts: &[TxOut],
+    min_fee_rate: FeeRate,
+) -> Result<()> {
+    let available = fee_paid(txn, prevouts)?;
+    let needed = signed_fee(txn, min_fee_rate)?;
+    if available < needed {
+        return Err(VaultError::InsufficientFee {
+            provided: available,
+            required: needed,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// A built vault transaction with what it pays, sized as [signed_fee] does so the fee inputs count as signed
+#[derive(Clone, Debug)]
+pub(crate) struct BuiltTx {
+    pub(crate) transaction: Transaction,
+    /// final once built, grinding is done and the witness doesn't count towards it
+    pub(crate) txid: Txid,
+    pub(crate) fee: Amount,
+    pub(crate) vsize: u64,
+    /// sat/vB
+    pub(crate) fee_rate: f64,
+}
+
+impl BuiltTx {
+    /// `prevouts` are the outputs `transaction` spends, in input order
+    pub(crate) fn new(transaction: Transaction, prevouts: &[TxOut]) -> Result<Self> {
+        let fee = fee_paid(&transaction, prevouts)?;
+        let vsize = signed_weight(&transaction).to_vbytes_ceil();
+        Ok(Self {
+            txid: transaction.txid(),
+            transaction,
+            fee,
+            vsize,
+            fee_rate: fee.to_sat() as f64 / vsize as f64,
+        })
+    }
+
+    /// The vault output a trigger creates, the first, which its complete or cancel spends.
+    /// Co-signers can pre-sign the complete against it before the trigger is broadcast.
+    pub(crate) fn trigger_outpoint(&self) -> OutPoint {
+        OutPoint {
+            txid: self.txid,
+            vout: 0,
+        }
+    }
+
+    /// Log what the transaction pays and hand it over
+    pub(crate) fn into_transaction(self) -> Transaction {
+        info!(
+            "{} pays {} over {} vB ({:.2} sat/vB)",
+            self.txid, self.fee, self.vsize, self.fee_rate
+        );
+        self.transaction
+    }
+}
+
+/// Each input's share of the transaction weight, its outpoint, script sig and sequence at four units a byte
+/// plus its witness. An input without a witness is counted as a single-key spend, as [signed_fee] does,
+/// leaving out the segwit marker and flag which belong to the transaction.
+pub(crate) fn input_weight_breakdown(
+    txn: &Transaction,
+    prevouts: &[TxOut],
+) -> Result<Vec<(usize, Weight)>> {
+    if prevouts.len() != txn.input.len() {
+        return Err(anyhow!(
+            "got {} prevouts for a transaction with {} inputs",
+            prevouts.len(),
+            txn.input.len()
+        ));
+    }
+    Ok(txn
+        .input
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let weight = if input.witness.is_empty() {
+                input.segwit_weight() + KEY_SPEND_WITNESS_WEIGHT - Weight::from_wu(2)
+            } else {
+                input.segwit_weight()
+            };
+            (index, weight)
+        })
+        .collect())
+}
+
+/// The node's estimate of the fee rate needed to confirm within `conf_target` blocks
+pub(crate) fn estimate_fee_rate(client: &Client, conf_target: u16) -> Result<FeeRate> {
+    let estimate = client.estimate_smart_fee(conf_target, None)?;
+    let btc_per_kvb = estimate.fee_rate.ok_or(anyhow!(
+        "node has no fee estimate for {} blocks: {}",
+        conf_target,
+        estimate.errors.unwrap_or_default().join(", ")
+    ))?;
+    // 1 kvB is 4 kwu
+    Ok(FeeRate::from_sat_per_kwu(btc_per_kvb.to_sat() / 4))
+}
+
+impl VaultCovenant {
+    /// The fee the `path` transaction needs to confirm within `conf_target` blocks according to the node,
+    /// i.e. the amount its fee input has to hold
+    pub(crate) fn estimate_path_fee(
+        &self,
+        client: &Client,
+        path: SpendPath,
+        conf_target: u16,
+        fee_script_pubkey: ScriptBuf,
+        destination: Option<&Address>,
+    ) -> Result<Amount> {
+        let fee_rate = estimate_fee_rate(client, conf_target)?;
+        self.fee_input_amount(path, fee_rate, fee_script_pubkey, destination)
+    }
+
+    /// How much a fee input paying to `fee_script_pubkey` has to hold for the `path` transaction to pay `fee_rate`.
+    /// The vault transactions have no change output besides one a CTV trigger template commits to,
+    /// so the rest of the fee input goes to fees.
+    /// Sized from a dry run of the transaction with a placeholder fee input, the witness size doesn't depend on its value.
+    pub(crate) fn fee_input_amount(
+        &self,
+        path: SpendPath,
+        fee_rate: FeeRate,
+        fee_script_pubkey: ScriptBuf,
+        destination: Option<&Address>,
+    ) -> Result<Amount> {
+        let placeholder_utxo = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+        // big enough that the dry run never trips a minimum fee check
+        let placeholder_output = TxOut {
+            script_pubkey: fee_script_pubkey,
+            value: Amount::MAX_MONEY,
+        };
+        let txn = self.create_path_tx(path, &placeholder_utxo, placeholder_output, destination)?;
+        // change a CTV trigger template commits to comes out of the fee input too, as do the CAT trigger's
+        // markers and refilling what a trigger fee took out of the vault when cancelling
+        let committed_change = match (path, self.get_type()) {
+            (SpendPath::Trigger, VaultType::CAT) => {
+                self.marker_amount() * self.trigger_markers() as u64
+            }
+            (SpendPath::Trigger, VaultType::CTV) => self
+                .trigger_extra_outputs()?
+                .iter()
+                .map(|output| output.value)
+                .sum(),
+            (SpendPath::Cancel, VaultType::CTV) => self.get_amount() - self.triggered_amount(),
+            _ => Amount::ZERO,
+        };
+        Ok(signed_fee(&txn, fee_rate)? + committed_change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::key::{Keypair, Secp256k1};
+    use bitcoin::transaction::Version;
+    use bitcoin::{Network, TxIn};
+
+    fn p2tr_script() -> ScriptBuf {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[9; 32]).unwrap();
+        Address::p2tr(&secp, keypair.x_only_public_key().0, None, Network::Regtest).script_pubkey()
+    }
+
+    /// A transaction spending one output without a witness and paying `outputs` to P2TR
+    fn spend(outputs: &[Amount]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: outputs
+                .iter()
+                .map(|value| TxOut {
+                    script_pubkey: p2tr_script(),
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    fn prevout(value: Amount) -> TxOut {
+        TxOut {
+            script_pubkey: p2tr_script(),
+            value,
+        }
+    }
+
+    #[test]
+    fn dust_limits_match_bitcoin_core() {
+        let secp = Secp256k1::new();
+        let key = bitcoin::PublicKey::new(
+            Keypair::from_seckey_slice(&secp, &[9; 32])
+                .unwrap()
+                .public_key(),
+        );
+        assert_eq!(dust_limit(&p2tr_script()), Amount::from_sat(330));
+        let p2wpkh = Address::p2wpkh(&key, Network::Regtest).unwrap();
+        assert_eq!(dust_limit(&p2wpkh.script_pubkey()), Amount::from_sat(294));
+        let p2pkh = Address::p2pkh(&key, Network::Regtest);
+        assert_eq!(dust_limit(&p2pkh.script_pubkey()), Amount::from_sat(546));
+    }
+
+    #[test]
+    fn known_fee_and_vsize_give_the_expected_rate() {
+        let txn = spend(&[Amount::from_sat(90_600)]);
+        assert_eq!(txn.vsize(), 94);
+        let rate = effective_fee_rate(&txn, &[prevout(Amount::from_sat(100_000))]).unwrap();
+        assert_eq!(rate, FeeRate::from_sat_per_vb_unchecked(100));
+    }
+
+    #[test]
+    fn output_value_overflow_is_an_error() {
+        let txn = spend(&[Amount::MAX, Amount::MAX]);
+        let error = fee_paid(&txn, &[prevout(Amount::MAX)]).unwrap_err();
+        assert_eq!(error.to_string(), "output value overflows");
+
+        let mut txn = spend(&[Amount::from_sat(1)]);
+        txn.input.push(TxIn::default());
+        let error = fee_paid(&txn, &[prevout(Amount::MAX), prevout(Amount::MAX)]).unwrap_err();
+        assert_eq!(error.to_string(), "input value overflows");
+    }
+
+    #[test]
+    fn outputs_worth_more_than_the_inputs_are_a_negative_fee() {
+        let txn = spend(&[Amount::from_sat(60_000), Amount::from_sat(40_001)]);
+        let error = fee_paid(&txn, &[prevout(Amount::from_sat(100_000))]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<VaultError>(),
+            Some(&VaultError::NegativeFee {
+                inputs: Amount::from_sat(100_000),
+                outputs: Amount::from_sat(100_001),
+            })
+        );
+        assert_eq!(
+            fee_paid(&txn, &[prevout(Amount::from_sat(100_001))]).unwrap(),
+            Amount::ZERO
+        );
+    }
+}
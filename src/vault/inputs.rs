@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+
+/// Put the vault's entry at `vault_input_index` and the fee input's in the other slot, returning them with the
+/// index the vault's entry ended up at. Without a fee input the vault input is the only one, whatever the index.
+pub(crate) fn arrange_inputs<T>(
+    vault: T,
+    fee: Option<T>,
+    vault_input_index: usize,
+) -> (Vec<T>, usize) {
+    let mut inputs: Vec<T> = fee.into_iter().collect();
+    let index = vault_input_index.min(inputs.len());
+    inputs.insert(index, vault);
+    (inputs, index)
+}
+
+impl VaultCovenant {
+    /// Put the vault input of the vault's transactions at `index`, ahead of or after the fee input.
+    /// The CTV templates commit to it, so it changes the vault address and has to be set before the vault is funded.
+    pub(crate) fn set_vault_input_index(&mut self, index: usize) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "the CAT scripts rebuild the signature message with the vault input at index 0"
+            ));
+        }
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't move the vault input of a vault that is already funded"
+            ));
+        }
+        if index != 0 && self.trigger_fee.is_some() {
+            return Err(anyhow!(
+                "a trigger paying its fee out of the vault only has the vault input, at index 0"
+            ));
+        }
+        if index > 1 {
+            return Err(anyhow!(
+                "vault transactions have at most one fee input, the vault input goes at 0 or 1, not {}",
+                index
+            ));
+        }
+        self.vault_input_index = index;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::template_hash;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, Txid};
+
+    fn seeded_ctv_vault() -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn trigger_with_the_vault_input_second_matches_the_template() {
+        let deposit = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let fee_input = OutPoint::new(Txid::from_byte_array([9; 32]), 0);
+        let mut vault = seeded_ctv_vault();
+        vault.set_vault_input_index(1).unwrap();
+        vault.set_current_outpoint(deposit);
+        assert_ne!(
+            vault.address().unwrap(),
+            seeded_ctv_vault().address().unwrap()
+        );
+
+        let trigger_tx = vault.create_ctv_trigger_tx(Some(&fee_input)).unwrap();
+        assert_eq!(trigger_tx.input[0].previous_output, fee_input);
+        assert_eq!(trigger_tx.input[1].previous_output, deposit);
+        vault.verify_ctv_commitment(&trigger_tx).unwrap();
+        assert_ne!(
+            template_hash(&trigger_tx, 0).unwrap(),
+            template_hash(&trigger_tx, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn vault_input_index_is_refused_once_funded_or_for_cat() {
+        let mut vault = seeded_ctv_vault();
+        assert!(vault.set_vault_input_index(2).is_err());
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        assert!(vault.set_vault_input_index(1).is_err());
+        let mut cat = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        assert!(cat.set_vault_input_index(1).is_err());
+    }
+}
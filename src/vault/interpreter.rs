@@ -0,0 +1,489 @@
+use anyhow::{anyhow, Result};
+use bitcoin::blockdata::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::opcodes::all::{
+    OP_0NOTEQUAL, OP_2DUP, OP_ADD, OP_CAT, OP_CHECKSIG, OP_CHECKSIGADD, OP_CHECKSIGVERIFY, OP_CLTV,
+    OP_CSV, OP_DROP, OP_DUP, OP_EQUALVERIFY, OP_FROMALTSTACK, OP_HASH256, OP_NOP4,
+    OP_NUMEQUALVERIFY, OP_PUSHNUM_1, OP_PUSHNUM_16, OP_PUSHNUM_NEG1, OP_ROT, OP_SHA256, OP_SIZE,
+    OP_SWAP, OP_TOALTSTACK,
+};
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{schnorr, Message};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{Opcode, Script, TapLeafHash, TapSighashType, Transaction, TxOut, XOnlyPublicKey};
+
+use crate::vault::contract::SECP;
+
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000ffff;
+const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+
+/// Run a tapscript path spend of `input_index` through a local interpreter.
+/// Only the opcodes the vault leaves use are implemented, OP_NOP4 is treated as OP_CHECKTEMPLATEVERIFY.
+/// `prevouts` only has to hold every input's output when the leaf checks a signature.
+pub(crate) fn verify_input(
+    txn: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+) -> Result<()> {
+    verify_script_path(txn, input_index, prevouts)
+        .map_err(|e| anyhow!("input {} fails script verification: {}", input_index, e))
+}
+
+fn verify_script_path(txn: &Transaction, input_index: usize, prevouts: &[TxOut]) -> Result<()> {
+    let input = txn
+        .input
+        .get(input_index)
+        .ok_or(anyhow!("transaction has no such input"))?;
+    let prevout = prevouts
+        .get(input_index)
+        .ok_or(anyhow!("missing the output being spent"))?;
+    if !prevout.script_pubkey.is_p2tr() {
+        return Err(anyhow!("the output being spent is not taproot"));
+    }
+    let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..34])?;
+
+    let mut witness: Vec<Vec<u8>> = input.witness.to_vec();
+    if witness.len() >= 2
+        && witness.last().and_then(|annex| annex.first()) == Some(&TAPROOT_ANNEX_PREFIX)
+    {
+        witness.pop();
+    }
+    if witness.len() < 2 {
+        return Err(anyhow!("witness is not a script path spend"));
+    }
+    let control_block = ControlBlock::decode(&witness.pop().unwrap())
+        .map_err(|e| anyhow!("invalid control block: {}", e))?;
+    let script = bitcoin::ScriptBuf::from_bytes(witness.pop().unwrap());
+    if control_block.leaf_version != LeafVersion::TapScript {
+        return Err(anyhow!("unexpected leaf version"));
+    }
+    if !control_block.verify_taproot_commitment(&SECP, output_key, &script) {
+        return Err(anyhow!("control block does not commit to the leaf script"));
+    }
+
+    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+    let mut interpreter = Interpreter {
+        txn,
+        input_index,
+        prevouts,
+        leaf_hash,
+        stack: witness,
+        altstack: Vec::new(),
+    };
+    interpreter.execute(&script)?;
+
+    match interpreter.stack.as_slice() {
+        [top] if cast_to_bool(top) => Ok(()),
+        [_] => Err(anyhow!("script finished with a false value on the stack")),
+        stack => Err(anyhow!(
+            "script finished with {} stack elements, expected 1",
+            stack.len()
+        )),
+    }
+}
+
+struct Interpreter<'a> {
+    txn: &'a Transaction,
+    input_index: usize,
+    prevouts: &'a [TxOut],
+    leaf_hash: TapLeafHash,
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn execute(&mut self, script: &Script) -> Result<()> {
+        for (position, instruction) in script.instructions().enumerate() {
+            match instruction? {
+                Instruction::PushBytes(bytes) => self.stack.push(bytes.as_bytes().to_vec()),
+                Instruction::Op(opcode) => self
+                    .step(opcode)
+                    .map_err(|e| anyhow!("{:?} (opcode #{}): {}", opcode, position, e))?,
+            }
+            if self
+                .stack
+                .iter()
+                .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+            {
+                return Err(anyhow!(
+                    "stack element exceeds {} bytes after opcode #{}",
+                    MAX_SCRIPT_ELEMENT_SIZE,
+                    position
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, opcode: Opcode) -> Result<()> {
+        let code = opcode.to_u8();
+        if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&code) {
+            let value = (code - OP_PUSHNUM_1.to_u8() + 1) as i64;
+            self.stack.push(encode_num(value));
+            return Ok(());
+        }
+        match opcode {
+            OP_PUSHNUM_NEG1 => self.stack.push(encode_num(-1)),
+            OP_DUP => {
+                let top = self.peek(0)?.clone();
+                self.stack.push(top);
+            }
+            OP_2DUP => {
+                let second = self.peek(1)?.clone();
+                let top = self.peek(0)?.clone();
+                self.stack.push(second);
+                self.stack.push(top);
+            }
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_SWAP => {
+                let top = self.pop()?;
+                let second = self.pop()?;
+                self.stack.push(top);
+                self.stack.push(second);
+            }
+            OP_ROT => {
+                let third = self
+                    .stack
+                    .len()
+                    .checked_sub(3)
+                    .ok_or(anyhow!("stack underflow"))?;
+                let item = self.stack.remove(third);
+                self.stack.push(item);
+            }
+            OP_TOALTSTACK => {
+                let top = self.pop()?;
+                self.altstack.push(top);
+            }
+            OP_FROMALTSTACK => {
+                let top = self.altstack.pop().ok_or(anyhow!("altstack underflow"))?;
+                self.stack.push(top);
+            }
+            OP_SIZE => {
+                let size = self.peek(0)?.len();
+                self.stack.push(encode_num(size as i64));
+            }
+            OP_0NOTEQUAL => {
+                let top = decode_num(&self.pop()?)?;
+                self.stack.push(encode_num((top != 0) as i64));
+            }
+            OP_ADD => {
+                let top = decode_num(&self.pop()?)?;
+                let second = decode_num(&self.pop()?)?;
+                self.stack.push(encode_num(second + top));
+            }
+            OP_CAT => {
+                let top = self.pop()?;
+                let mut second = self.pop()?;
+                second.extend(top);
+                self.stack.push(second);
+            }
+            OP_SHA256 => {
+                let top = self.pop()?;
+                self.stack
+                    .push(sha256::Hash::hash(&top).to_byte_array().to_vec());
+            }
+            OP_HASH256 => {
+                let top = self.pop()?;
+                self.stack
+                    .push(sha256d::Hash::hash(&top).to_byte_array().to_vec());
+            }
+            OP_EQUALVERIFY => {
+                let top = self.pop()?;
+                let second = self.pop()?;
+                if top != second {
+                    return Err(anyhow!("items are not equal"));
+                }
+            }
+            OP_CHECKSIG => {
+                let valid = self.check_sig()?;
+                self.stack.push(if valid { vec![1] } else { vec![] });
+            }
+            OP_CHECKSIGVERIFY => {
+                if !self.check_sig()? {
+                    return Err(anyhow!("empty signature"));
+                }
+            }
+            OP_CHECKSIGADD => {
+                let pubkey = self.pop()?;
+                let count = decode_num(&self.pop()?)?;
+                self.stack.push(pubkey);
+                let valid = self.check_sig()?;
+                self.stack.push(encode_num(count + valid as i64));
+            }
+            OP_NUMEQUALVERIFY => {
+                let top = decode_num(&self.pop()?)?;
+                let second = decode_num(&self.pop()?)?;
+                if top != second {
+                    return Err(anyhow!("{} is not {}", second, top));
+                }
+            }
+            OP_CLTV => self.check_locktime()?,
+            OP_CSV => self.check_sequence()?,
+            OP_NOP4 => self.check_template()?,
+            _ => return Err(anyhow!("opcode is not supported by the local interpreter")),
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>> {
+        self.stack.pop().ok_or(anyhow!("stack underflow"))
+    }
+
+    fn peek(&self, depth: usize) -> Result<&Vec<u8>> {
+        self.stack
+            .len()
+            .checked_sub(depth + 1)
+            .map(|index| &self.stack[index])
+            .ok_or(anyhow!("stack underflow"))
+    }
+
+    /// BIP342 signature check, an empty signature is a valid "false"
+    fn check_sig(&mut self) -> Result<bool> {
+        let pubkey = self.pop()?;
+        let sig = self.pop()?;
+        if sig.is_empty() {
+            return Ok(false);
+        }
+        if pubkey.len() != 32 {
+            return Err(anyhow!("unknown public key type"));
+        }
+        let (sig, sighash_type) = match sig.len() {
+            64 => (sig.as_slice(), TapSighashType::Default),
+            65 if sig[64] != 0 => (
+                &sig[..64],
+                TapSighashType::from_consensus_u8(sig[64])
+                    .map_err(|e| anyhow!("invalid sighash type: {}", e))?,
+            ),
+            len => return Err(anyhow!("invalid signature length {}", len)),
+        };
+        let sighash = SighashCache::new(self.txn)
+            .taproot_script_spend_signature_hash(
+                self.input_index,
+                &Prevouts::All(self.prevouts),
+                self.leaf_hash,
+                sighash_type,
+            )
+            .map_err(|e| anyhow!("cannot compute sighash: {}", e))?;
+        SECP.verify_schnorr(
+            &schnorr::Signature::from_slice(sig)?,
+            &Message::from_digest(sighash.to_byte_array()),
+            &XOnlyPublicKey::from_slice(&pubkey)?,
+        )
+        .map_err(|_| anyhow!("signature does not verify"))?;
+        Ok(true)
+    }
+
+    /// BIP65, the absolute timelock is left on the stack
+    fn check_locktime(&self) -> Result<()> {
+        let required = decode_num(self.peek(0)?)?;
+        if required < 0 {
+            return Err(anyhow!("negative absolute timelock"));
+        }
+        let lock_time = self.txn.lock_time.to_consensus_u32() as i64;
+        if (required < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return Err(anyhow!("timelock and lock time use different units"));
+        }
+        if lock_time < required {
+            return Err(anyhow!(
+                "lock time {} is below the required {}",
+                lock_time,
+                required
+            ));
+        }
+        if self.txn.input[self.input_index].sequence.is_final() {
+            return Err(anyhow!("a final input sequence disables the lock time"));
+        }
+        Ok(())
+    }
+
+    /// BIP112, the relative timelock is left on the stack
+    fn check_sequence(&self) -> Result<()> {
+        let required = decode_num(self.peek(0)?)?;
+        if required < 0 {
+            return Err(anyhow!("negative relative timelock"));
+        }
+        if required & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Ok(());
+        }
+        if self.txn.version.0 < 2 {
+            return Err(anyhow!("transaction version is below 2"));
+        }
+        let sequence = self.txn.input[self.input_index].sequence.0 as i64;
+        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Err(anyhow!("input sequence disables relative timelocks"));
+        }
+        let type_mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let (required, sequence) = (required & type_mask, sequence & type_mask);
+        if (required < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return Err(anyhow!("timelock and sequence use different units"));
+        }
+        if sequence < required {
+            return Err(anyhow!(
+                "input sequence {} is below the required {}",
+                sequence,
+                required
+            ));
+        }
+        Ok(())
+    }
+
+    /// BIP119, a 32 byte argument must match the spending transaction's template hash
+    fn check_template(&self) -> Result<()> {
+        let expected = self.peek(0)?;
+        if expected.len() != 32 {
+            return Ok(());
+        }
+        if standard_template_hash(self.txn, self.input_index)?.as_slice() != expected.as_slice() {
+            return Err(anyhow!("transaction does not match the committed template"));
+        }
+        Ok(())
+    }
+}
+
+fn standard_template_hash(txn: &Transaction, input_index: usize) -> Result<[u8; 32]> {
+    let mut buffer = Vec::new();
+    txn.version.consensus_encode(&mut buffer)?;
+    txn.lock_time.consensus_encode(&mut buffer)?;
+    if txn.input.iter().any(|input| !input.script_sig.is_empty()) {
+        let mut script_sigs = Vec::new();
+        for input in txn.input.iter() {
+            input.script_sig.consensus_encode(&mut script_sigs)?;
+        }
+        buffer.extend(sha256::Hash::hash(&script_sigs).to_byte_array());
+    }
+    buffer.extend((txn.input.len() as u32).to_le_bytes());
+    let mut sequences = Vec::new();
+    for input in txn.input.iter() {
+        input.sequence.consensus_encode(&mut sequences)?;
+    }
+    buffer.extend(sha256::Hash::hash(&sequences).to_byte_array());
+    buffer.extend((txn.output.len() as u32).to_le_bytes());
+    let mut outputs = Vec::new();
+    for output in txn.output.iter() {
+        output.consensus_encode(&mut outputs)?;
+    }
+    buffer.extend(sha256::Hash::hash(&outputs).to_byte_array());
+    buffer.extend((input_index as u32).to_le_bytes());
+    Ok(sha256::Hash::hash(&buffer).to_byte_array())
+}
+
+fn cast_to_bool(item: &[u8]) -> bool {
+    match item.split_last() {
+        None => false,
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last & 0x7f) != 0,
+    }
+}
+
+fn encode_num(value: i64) -> Vec<u8> {
+    let mut result = Vec::new();
+    let negative = value < 0;
+    let mut absolute = value.unsigned_abs();
+    while absolute > 0 {
+        result.push((absolute & 0xff) as u8);
+        absolute >>= 8;
+    }
+    if let Some(last) = result.last_mut() {
+        if *last & 0x80 != 0 {
+            result.push(if negative { 0x80 } else { 0x00 });
+        } else if negative {
+            *last |= 0x80;
+        }
+    }
+    result
+}
+
+/// Script numbers used by CSV may be up to 5 bytes long
+fn decode_num(item: &[u8]) -> Result<i64> {
+    if item.len() > 5 {
+        return Err(anyhow!("script number is longer than 5 bytes"));
+    }
+    let Some((last, _)) = item.split_last() else {
+        return Ok(0);
+    };
+    let mut value: i64 = 0;
+    for (i, byte) in item.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        value &= !(0x80_i64 << (8 * (item.len() - 1)));
+        value = -value;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultCovenant, VaultType};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, Txid};
+
+    /// A signed cancel of a seeded vault's trigger, with the outputs it spends
+    fn signed_cancel(vault_type: VaultType) -> (Transaction, Vec<TxOut>) {
+        let vault = VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        let fee_output = TxOut {
+            script_pubkey: vault.address().unwrap().script_pubkey(),
+            value: Amount::from_sat(10_000),
+        };
+        let cancel_tx = vault
+            .cancel_signer()
+            .unwrap()
+            .create_cancel_tx(
+                OutPoint::new(Txid::all_zeros(), 0),
+                &OutPoint::new(Txid::all_zeros(), 1),
+                fee_output.clone(),
+            )
+            .unwrap();
+        (
+            cancel_tx,
+            vec![vault.triggered_output().unwrap(), fee_output],
+        )
+    }
+
+    #[test]
+    fn corrupted_signature_fails_verification() {
+        let (mut cancel_tx, prevouts) = signed_cancel(VaultType::CTV);
+        verify_input(&cancel_tx, 0, &prevouts).unwrap();
+        let mut witness = cancel_tx.input[0].witness.to_vec();
+        witness[0][10] ^= 1;
+        cancel_tx.input[0].witness = witness.into();
+        assert!(verify_input(&cancel_tx, 0, &prevouts).is_err());
+    }
+
+    #[test]
+    fn corrupted_cat_witness_fails_verification() {
+        let (cancel_tx, prevouts) = signed_cancel(VaultType::CAT);
+        verify_input(&cancel_tx, 0, &prevouts).unwrap();
+        // flip one byte of each element the script checks in turn, leaving the leaf and control block alone
+        let witness = cancel_tx.input[0].witness.to_vec();
+        for element in 0..witness.len() - 2 {
+            if witness[element].is_empty() {
+                continue;
+            }
+            let mut corrupted = witness.clone();
+            corrupted[element][0] ^= 1;
+            let mut corrupted_tx = cancel_tx.clone();
+            corrupted_tx.input[0].witness = corrupted.into();
+            assert!(
+                verify_input(&corrupted_tx, 0, &prevouts).is_err(),
+                "corrupting witness element {} went unnoticed",
+                element
+            );
+        }
+    }
+}
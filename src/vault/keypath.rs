@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::secp256k1::Message;
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::taproot::Signature;
+use bitcoin::transaction::Version;
+use bitcoin::{Address, Amount, FeeRate, TapSighashType, Transaction, TxIn, TxOut};
+
+use crate::vault::contract::{VaultCovenant, SECP};
+use crate::vault::fees::signed_fee;
+
+impl VaultCovenant {
+    /// Sweep the vault's current output to `destination` through the taproot key path,
+    /// signed with the secret of the cold internal key. No leaf is revealed and no fee input is needed.
+    pub(crate) fn create_keypath_sweep_tx(
+        &self,
+        cold_keypair: &Keypair,
+        destination: &Address,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let cold_internal_key = self.cold_internal_key().ok_or(anyhow!(
+            "the vault has a NUMS internal key, its key path is disabled"
+        ))?;
+        if cold_keypair.x_only_public_key().0 != cold_internal_key {
+            return Err(anyhow!("the keypair is not the vault's cold internal key"));
+        }
+        let mut txn = self.keypath_sweep_template(destination, fee)?;
+        let prevouts = [self.current_txout()?];
+
+        let tweaked_keypair =
+            cold_keypair.tap_tweak(&SECP, self.current_spend_info()?.merkle_root());
+        let sighash = SighashCache::new(&txn).taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&prevouts),
+            TapSighashType::Default,
+        )?;
+        let signature = SECP.sign_schnorr(
+            &Message::from_digest_slice(sighash.as_ref())?,
+            &tweaked_keypair.to_inner(),
+        );
+        txn.input[0].witness.push(
+            Signature {
+                sig: signature,
+                hash_ty: TapSighashType::Default,
+            }
+            .to_vec(),
+        );
+
+        Ok(txn)
+    }
+
+    /// The fee a key path sweep to `destination` needs to pay `fee_rate`
+    pub(crate) fn keypath_sweep_fee(
+        &self,
+        destination: &Address,
+        fee_rate: FeeRate,
+    ) -> Result<Amount> {
+        signed_fee(
+            &self.keypath_sweep_template(destination, Amount::ZERO)?,
+            fee_rate,
+        )
+    }
+
+    fn keypath_sweep_template(&self, destination: &Address, fee: Amount) -> Result<Transaction> {
+        let value = self
+            .get_amount()
+            .checked_sub(fee)
+            .ok_or(anyhow!("a fee of {} is more than the vault holds", fee))?;
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: self.get_current_outpoint()?,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::VaultType;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    #[test]
+    fn keypath_sweep_pays_the_destination_directly() {
+        let cold_keypair = Keypair::from_seckey_slice(&SECP, &[5; 32]).unwrap();
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault
+            .set_cold_internal_key(cold_keypair.x_only_public_key().0)
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination_key = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        let destination = Address::p2tr(
+            &SECP,
+            destination_key.x_only_public_key().0,
+            None,
+            Network::Regtest,
+        );
+        let fee = Amount::from_sat(500);
+
+        let txn = vault
+            .create_keypath_sweep_tx(&cold_keypair, &destination, fee)
+            .unwrap();
+        assert_eq!(txn.input.len(), 1);
+        assert_eq!(
+            txn.input[0].previous_output,
+            vault.get_current_outpoint().unwrap()
+        );
+        assert_eq!(txn.output[0].script_pubkey, destination.script_pubkey());
+        assert_eq!(txn.output[0].value, vault.get_amount() - fee);
+
+        // a key path witness is just the signature, checked against the vault's output key
+        assert_eq!(txn.input[0].witness.len(), 1);
+        let signature = Signature::from_slice(&txn.input[0].witness[0]).unwrap();
+        let sighash = SighashCache::new(&txn)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[vault.current_txout().unwrap()]),
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let output_key = vault.current_spend_info().unwrap().output_key().to_inner();
+        SECP.verify_schnorr(
+            &signature.sig,
+            &Message::from_digest_slice(sighash.as_ref()).unwrap(),
+            &output_key,
+        )
+        .unwrap();
+
+        let other_keypair = Keypair::from_seckey_slice(&SECP, &[6; 32]).unwrap();
+        assert!(vault
+            .create_keypath_sweep_tx(&other_keypair, &destination, fee)
+            .is_err());
+    }
+}
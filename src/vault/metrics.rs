@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use log::info;
+
+/// A vault lifecycle event worth reporting to an operator's metrics pipeline
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum VaultEvent {
+    Created,
+    Triggered,
+    Completed,
+    Cancelled,
+}
+
+/// Somewhere to send vault lifecycle events, e.g. a Prometheus exporter or a log pipeline
+pub(crate) trait MetricsSink {
+    fn record_event(&self, event: VaultEvent);
+}
+
+/// Drops every event, used when no sink is configured
+pub(crate) struct NoopSink;
+
+impl MetricsSink for NoopSink {
+    fn record_event(&self, _event: VaultEvent) {}
+}
+
+/// Writes every event to the log
+pub(crate) struct LogSink;
+
+impl MetricsSink for LogSink {
+    fn record_event(&self, event: VaultEvent) {
+        info!("vault event: {:?}", event);
+    }
+}
+
+/// Counts every event before passing it on to `inner`, for reporting totals at the end of a run
+pub(crate) struct CountingSink<S> {
+    inner: S,
+    counts: Mutex<HashMap<VaultEvent, u64>>,
+}
+
+impl<S: MetricsSink> CountingSink<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How many of each event have been recorded so far
+    pub(crate) fn counts(&self) -> HashMap<VaultEvent, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+impl<S: MetricsSink> MetricsSink for CountingSink<S> {
+    fn record_event(&self, event: VaultEvent) {
+        *self.counts.lock().unwrap().entry(event).or_default() += 1;
+        self.inner.record_event(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn counting_sink_counts_across_threads() {
+        let sink = Arc::new(CountingSink::new(NoopSink));
+        let threads = (0..4)
+            .map(|_| {
+                let sink = sink.clone();
+                std::thread::spawn(move || {
+                    sink.record_event(VaultEvent::Triggered);
+                    sink.record_event(VaultEvent::Cancelled);
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        sink.record_event(VaultEvent::Triggered);
+        let counts = sink.counts();
+        assert_eq!(counts.get(&VaultEvent::Triggered), Some(&5));
+        assert_eq!(counts.get(&VaultEvent::Cancelled), Some(&4));
+        assert_eq!(counts.get(&VaultEvent::Completed), None);
+    }
+}
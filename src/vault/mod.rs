@@ -1,3 +1,46 @@
+pub(crate) mod actions;
+pub(crate) mod audit;
+pub(crate) mod batch;
+pub(crate) mod broadcast;
+pub(crate) mod builder;
+pub(crate) mod bundle;
+pub(crate) mod cache;
+pub(crate) mod consolidate;
 pub(crate) mod contract;
+pub(crate) mod cpfp;
+pub(crate) mod delegation;
+pub(crate) mod descriptor;
+pub(crate) mod directory;
+pub(crate) mod error;
+pub(crate) mod fee_change;
+pub(crate) mod fee_key;
+pub(crate) mod fees;
+pub(crate) mod inputs;
+#[cfg(feature = "verify")]
+pub(crate) mod interpreter;
+pub(crate) mod keypath;
+pub(crate) mod metrics;
+pub(crate) mod multisig;
+#[cfg(feature = "op-vault")]
+pub(crate) mod op_vault;
+pub(crate) mod partial;
+pub(crate) mod payout;
+pub(crate) mod psbt;
+pub(crate) mod rbf;
+pub(crate) mod recipients;
+pub(crate) mod recovery;
+pub(crate) mod reserve;
+pub(crate) mod rotate;
+pub(crate) mod schema;
 pub(crate) mod script;
+pub(crate) mod session;
+#[cfg(test)]
+mod sigmsg_check;
 pub(crate) mod signature_building;
+pub(crate) mod simulation;
+pub(crate) mod sync;
+pub(crate) mod timelock;
+pub(crate) mod trace;
+pub(crate) mod trigger_outputs;
+pub(crate) mod vsize;
+pub(crate) mod watchtower;
@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Result};
+use bitcoin::key::Keypair;
+use bitcoin::{ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxOut, XOnlyPublicKey};
+
+use crate::vault::contract::{sign_transaction, VaultCovenant, VaultType};
+use crate::vault::script::vault_trigger_withdrawal;
+
+impl VaultCovenant {
+    /// The keys the CAT trigger leaf checks, the vault key first and then the cosigners
+    pub(crate) fn trigger_keys(&self) -> Vec<XOnlyPublicKey> {
+        std::iter::once(self.vault_keypair().x_only_public_key().0)
+            .chain(self.trigger_cosigners.iter().copied())
+            .collect()
+    }
+
+    pub(crate) fn trigger_script(&self) -> ScriptBuf {
+        vault_trigger_withdrawal(
+            &self.trigger_keys(),
+            self.trigger_threshold,
+            self.trigger_markers(),
+            self.marker_amount(),
+            self.fee_change(),
+        )
+    }
+
+    /// Require `threshold` of the vault key and `cosigners` to sign a CAT trigger. It changes the vault address,
+    /// so it has to be set before the vault is funded.
+    pub(crate) fn set_trigger_cosigners(
+        &mut self,
+        cosigners: Vec<XOnlyPublicKey>,
+        threshold: usize,
+    ) -> Result<()> {
+        if self.get_type() != VaultType::CAT {
+            return Err(anyhow!(
+                "only the CAT trigger leaf checks signatures, a CTV trigger is only bound by its template"
+            ));
+        }
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't change the trigger keys of a vault that is already funded"
+            ));
+        }
+        let mut keys = vec![self.vault_keypair().x_only_public_key().0];
+        for cosigner in &cosigners {
+            if keys.contains(cosigner) {
+                return Err(anyhow!("{} is a trigger key more than once", cosigner));
+            }
+            keys.push(*cosigner);
+        }
+        if threshold == 0 || threshold > keys.len() {
+            return Err(anyhow!(
+                "a threshold of {} doesn't fit {} trigger keys",
+                threshold,
+                keys.len()
+            ));
+        }
+        self.trigger_cosigners = cosigners;
+        self.trigger_threshold = threshold;
+        Ok(())
+    }
+
+    /// Let this process sign triggers with the cosigner key of `keypair`
+    pub(crate) fn add_trigger_signer(&mut self, keypair: Keypair) -> Result<()> {
+        let x_only_pubkey = keypair.x_only_public_key().0;
+        if !self.trigger_cosigners.contains(&x_only_pubkey) {
+            return Err(anyhow!("{} is not a trigger cosigner", x_only_pubkey));
+        }
+        self.trigger_signers.push(keypair);
+        Ok(())
+    }
+
+    /// The trigger leaf's signature witness elements for `txn`, last key first so the vault key's ends up on top.
+    /// The vault key and the first available cosigners sign until the threshold is met, the other keys get an
+    /// empty signature, as CHECKSIGADD needs exactly `threshold` valid ones.
+    pub(crate) fn trigger_key_signatures(
+        &self,
+        txn: &Transaction,
+        prevouts: &[TxOut],
+        leaf_hash: TapLeafHash,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut remaining = self.trigger_threshold;
+        let mut signatures = Vec::new();
+        for key in self.trigger_keys() {
+            let keypair = if key == self.vault_keypair().x_only_public_key().0 {
+                Some(self.vault_keypair())
+            } else {
+                self.trigger_signers
+                    .iter()
+                    .find(|signer| signer.x_only_public_key().0 == key)
+                    .copied()
+            };
+            match keypair {
+                Some(keypair) if remaining > 0 => {
+                    signatures.push(sign_transaction(
+                        txn,
+                        prevouts,
+                        leaf_hash,
+                        keypair,
+                        TapSighashType::All,
+                    )?);
+                    remaining -= 1;
+                }
+                _ => signatures.push(Vec::new()),
+            }
+        }
+        if remaining > 0 {
+            return Err(anyhow!(
+                "the trigger needs {} signatures but only {} of its keys can sign here",
+                self.trigger_threshold,
+                self.trigger_threshold - remaining
+            ));
+        }
+        signatures.reverse();
+        Ok(signatures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Address, Amount, Network, OutPoint, Txid};
+
+    fn test_keypair(byte: u8) -> Keypair {
+        Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap()
+    }
+
+    fn test_address(byte: u8) -> Address {
+        Address::p2tr(
+            &SECP,
+            test_keypair(byte).x_only_public_key().0,
+            None,
+            Network::Regtest,
+        )
+    }
+
+    fn fee_input() -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    /// A funded CAT vault whose trigger needs the vault key and the cosigner key of byte 5
+    fn two_of_two_vault() -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault
+            .set_trigger_cosigners(vec![test_keypair(5).x_only_public_key().0], 2)
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    #[test]
+    fn two_of_two_trigger_needs_both_signatures() {
+        let mut vault = two_of_two_vault();
+        let error = vault
+            .create_trigger_tx(&[fee_input()], &[test_address(2)])
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("needs 2 signatures but only 1"),
+            "{}",
+            error
+        );
+
+        assert!(vault.add_trigger_signer(test_keypair(6)).is_err());
+        vault.add_trigger_signer(test_keypair(5)).unwrap();
+        vault
+            .create_trigger_tx(&[fee_input()], &[test_address(2)])
+            .unwrap();
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn trigger_missing_the_cosigner_signature_fails_the_script() {
+        use crate::vault::interpreter::verify_input;
+
+        let mut vault = two_of_two_vault();
+        vault.add_trigger_signer(test_keypair(5)).unwrap();
+        let trigger_tx = vault
+            .create_trigger_tx(&[fee_input()], &[test_address(2)])
+            .unwrap()
+            .transaction;
+        let prevouts = [vault.current_txout().unwrap(), fee_input().1];
+        verify_input(&trigger_tx, 0, &prevouts).unwrap();
+
+        // the cosigner's signature sits under the vault key's, the leaf script and the control block
+        let mut one_signature = trigger_tx;
+        let mut elements = one_signature.input[0].witness.to_vec();
+        let cosigner_signature = elements.len() - 4;
+        elements[cosigner_signature] = Vec::new();
+        one_signature.input[0].witness = elements.into();
+        assert!(verify_input(&one_signature, 0, &prevouts).is_err());
+    }
+
+    #[test]
+    fn threshold_has_to_fit_the_keys() {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        let cosigner = test_keypair(5).x_only_public_key().0;
+        assert!(vault.set_trigger_cosigners(vec![cosigner], 3).is_err());
+        assert!(vault.set_trigger_cosigners(vec![cosigner], 0).is_err());
+        assert!(vault
+            .set_trigger_cosigners(vec![cosigner, cosigner], 2)
+            .is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, OutPoint, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxIn, TxOut, Witness,
+};
+
+use crate::vault::contract::{
+    sign_transaction_input, template_hash, verify_leaf_keys_consistent, SpendPath, VaultCovenant,
+    VaultState, SECP,
+};
+use crate::vault::error::VaultError;
+use crate::vault::script::{op_vault_recover, op_vault_trigger, op_vault_withdrawal};
+use crate::vault::timelock::csv_sequence;
+
+/// OP_VAULT's revault output index when a trigger withdraws the whole vault, -1 as a minimal script number
+const NO_REVAULT: [u8; 1] = [0x81];
+
+impl VaultCovenant {
+    /// Where the recover leaf sends the funds: the cancel key's key path, which the vault can't stop
+    pub(crate) fn op_vault_recovery_address(&self) -> Address {
+        Address::p2tr(
+            &SECP,
+            self.cancel_keypair().x_only_public_key().0,
+            None,
+            self.get_network(),
+        )
+    }
+
+    pub(crate) fn op_vault_trigger_script(&self) -> ScriptBuf {
+        op_vault_trigger(self.vault_keypair().x_only_public_key().0, self.timelock)
+    }
+
+    pub(crate) fn op_vault_recover_script(&self) -> ScriptBuf {
+        let recovery_spk = self.op_vault_recovery_address().script_pubkey();
+        op_vault_recover(
+            self.cancel_keypair().x_only_public_key().0,
+            sha256::Hash::hash(recovery_spk.as_bytes()).to_byte_array(),
+        )
+    }
+
+    /// The withdrawal a trigger to `destination` commits to: the triggered vault input after the spend delay
+    /// and one fee input, paying the whole vault amount to `destination`
+    fn op_vault_withdrawal_template(&self, destination: &Address) -> Result<Transaction> {
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    sequence: csv_sequence(self.timelock)?,
+                    ..Default::default()
+                },
+                TxIn::default(),
+            ],
+            output: vec![TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value: self.get_amount(),
+            }],
+        })
+    }
+
+    /// The leaf OP_VAULT puts in place of the trigger leaf when triggering a withdrawal to `destination`
+    pub(crate) fn op_vault_withdrawal_script(&self, destination: &Address) -> Result<ScriptBuf> {
+        Ok(op_vault_withdrawal(
+            template_hash(&self.op_vault_withdrawal_template(destination)?, 0)?,
+            self.timelock,
+        ))
+    }
+
+    /// The deposit tree: the trigger and recover leaves side by side
+    pub(crate) fn op_vault_deposit_spend_info(&self) -> Result<TaprootSpendInfo> {
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, self.op_vault_trigger_script())?
+            .add_leaf(1, self.op_vault_recover_script())?
+            .finalize(&SECP, self.internal_key()?)
+            .map_err(|_| VaultError::TaprootFinalize)?;
+        verify_leaf_keys_consistent(&spend_info, &self.expected_deposit_leaf_keys())?;
+        Ok(spend_info)
+    }
+
+    /// The tree of the output a trigger to `destination` creates. OP_VAULT only swaps the trigger leaf for the
+    /// withdrawal leaf, so the recover leaf stays at the same place.
+    pub(crate) fn op_vault_triggered_spend_info(
+        &self,
+        destination: &Address,
+    ) -> Result<TaprootSpendInfo> {
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, self.op_vault_withdrawal_script(destination)?)?
+            .add_leaf(1, self.op_vault_recover_script())?
+            .finalize(&SECP, self.internal_key()?)
+            .map_err(|_| VaultError::TaprootFinalize)?;
+        verify_leaf_keys_consistent(&spend_info, &self.expected_triggered_leaf_keys())?;
+        Ok(spend_info)
+    }
+
+    pub(crate) fn op_vault_triggered_output(&self, destination: &Address) -> Result<TxOut> {
+        let spend_info = self.op_vault_triggered_spend_info(destination)?;
+        Ok(TxOut {
+            script_pubkey: Address::p2tr_tweaked(spend_info.output_key(), self.get_network())
+                .script_pubkey(),
+            value: self.get_amount(),
+        })
+    }
+
+    /// Build and sign a trigger moving the whole vault into the triggered output committing to `destination`,
+    /// the fee input is left for the fee wallet to sign and all of it goes to fees
+    pub(crate) fn create_op_vault_trigger_tx(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        destination: &Address,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Trigger);
+        let mut txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: self.get_current_outpoint()?,
+                    ..Default::default()
+                },
+                TxIn {
+                    previous_output: *fee_paying_utxo,
+                    ..Default::default()
+                },
+            ],
+            output: vec![self.op_vault_triggered_output(destination)?],
+        };
+        let script = self.op_vault_trigger_script();
+        let sig = sign_transaction_input(
+            &txn,
+            0,
+            &[self.current_txout()?, fee_paying_output],
+            TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            self.vault_keypair(),
+            TapSighashType::Default,
+        )?;
+        let control_block = self
+            .op_vault_deposit_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("trigger leaf is missing from the script tree"))?;
+        let target_hash = template_hash(&self.op_vault_withdrawal_template(destination)?, 0)?;
+        // revault index, trigger output index, then the leaf-update data and the signature the leaf checks
+        txn.input[0].witness = Witness::from_slice(&[
+            NO_REVAULT.to_vec(),
+            vec![],
+            target_hash.to_vec(),
+            sig,
+            script.to_bytes(),
+            control_block.serialize(),
+        ]);
+        Ok(txn)
+    }
+
+    /// Build the withdrawal the triggered output commits to, nothing in it needs a vault signature
+    pub(crate) fn create_op_vault_withdraw_tx(
+        &self,
+        fee_paying_utxo: &OutPoint,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Complete);
+        let destination = self.get_withdrawal_address()?;
+        let mut txn = self.op_vault_withdrawal_template(&destination)?;
+        txn.input[0].previous_output = self.get_current_outpoint()?;
+        txn.input[1].previous_output = *fee_paying_utxo;
+        let script = self.op_vault_withdrawal_script(&destination)?;
+        let control_block = self
+            .op_vault_triggered_spend_info(&destination)?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("withdrawal leaf is missing from the script tree"))?;
+        txn.input[0].witness = Witness::from_slice(&[script.to_bytes(), control_block.serialize()]);
+        Ok(txn)
+    }
+
+    /// Build and sign a recovery sending the vault, triggered or not, to [Self::op_vault_recovery_address]
+    pub(crate) fn create_op_vault_recover_tx(
+        &self,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+    ) -> Result<Transaction> {
+        let _span = self.build_span(SpendPath::Cancel);
+        let spend_info = if self.get_state() == VaultState::Triggered {
+            self.op_vault_triggered_spend_info(&self.get_withdrawal_address()?)?
+        } else {
+            self.op_vault_deposit_spend_info()?
+        };
+        let mut txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: self.get_current_outpoint()?,
+                    ..Default::default()
+                },
+                TxIn {
+                    previous_output: *fee_paying_utxo,
+                    ..Default::default()
+                },
+            ],
+            output: vec![TxOut {
+                script_pubkey: self.op_vault_recovery_address().script_pubkey(),
+                value: self.get_amount(),
+            }],
+        };
+        let script = self.op_vault_recover_script();
+        let sig = sign_transaction_input(
+            &txn,
+            0,
+            &[self.current_txout()?, fee_paying_output],
+            TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            self.cancel_keypair(),
+            TapSighashType::Default,
+        )?;
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("recover leaf is missing from the script tree"))?;
+        // the recovery output index, then the signature the leaf checks
+        txn.input[0].witness =
+            Witness::from_slice(&[vec![], sig, script.to_bytes(), control_block.serialize()]);
+        Ok(txn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::VaultType;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::key::Keypair;
+    use bitcoin::taproot::ControlBlock;
+    use bitcoin::{Amount, Network, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn fee_input(vout: u32) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    fn funded_vault() -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::OpVault,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    /// Check the control block revealed by input 0 of `txn` proves its leaf against the output key of `spend_info`
+    fn assert_leaf_commits(txn: &Transaction, spend_info: &TaprootSpendInfo) {
+        let witness = &txn.input[0].witness;
+        let script = ScriptBuf::from_bytes(witness.nth(witness.len() - 2).unwrap().to_vec());
+        let control_block = ControlBlock::decode(witness.last().unwrap()).unwrap();
+        assert!(control_block.verify_taproot_commitment(
+            &SECP,
+            spend_info.output_key().to_inner(),
+            &script
+        ));
+    }
+
+    #[test]
+    fn trigger_commits_to_the_withdrawal_it_allows() {
+        let mut vault = funded_vault();
+        let destination = test_address(2);
+        let (fee_paying_utxo, fee_paying_output) = fee_input(0);
+        let trigger_tx = vault
+            .create_op_vault_trigger_tx(&fee_paying_utxo, fee_paying_output, &destination)
+            .unwrap();
+        assert_leaf_commits(&trigger_tx, &vault.op_vault_deposit_spend_info().unwrap());
+        assert_eq!(
+            trigger_tx.output,
+            vec![vault.op_vault_triggered_output(&destination).unwrap()]
+        );
+
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault.set_state(VaultState::Triggered);
+        let withdraw_tx = vault.create_op_vault_withdraw_tx(&fee_input(1).0).unwrap();
+        assert_leaf_commits(
+            &withdraw_tx,
+            &vault.op_vault_triggered_spend_info(&destination).unwrap(),
+        );
+        // the target hash pushed by the trigger is the template hash of the withdrawal
+        assert_eq!(
+            trigger_tx.input[0].witness.nth(2).unwrap(),
+            template_hash(&withdraw_tx, 0).unwrap().as_slice()
+        );
+        assert_eq!(
+            withdraw_tx.output[0].script_pubkey,
+            destination.script_pubkey()
+        );
+        assert_eq!(
+            withdraw_tx.input[0].sequence,
+            csv_sequence(vault.timelock).unwrap()
+        );
+    }
+
+    #[test]
+    fn recover_pays_the_recovery_address_before_and_after_a_trigger() {
+        let mut vault = funded_vault();
+        let destination = test_address(2);
+        let (fee_paying_utxo, fee_paying_output) = fee_input(0);
+        let recover_tx = vault
+            .create_op_vault_recover_tx(&fee_paying_utxo, fee_paying_output.clone())
+            .unwrap();
+        assert_leaf_commits(&recover_tx, &vault.op_vault_deposit_spend_info().unwrap());
+        let recovery = vault.op_vault_recovery_address().script_pubkey();
+        assert_eq!(recover_tx.output[0].script_pubkey, recovery);
+
+        let trigger_tx = vault
+            .create_op_vault_trigger_tx(&fee_paying_utxo, fee_paying_output.clone(), &destination)
+            .unwrap();
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault.set_state(VaultState::Triggered);
+        let recover_tx = vault
+            .create_op_vault_recover_tx(&fee_input(1).0, fee_input(1).1)
+            .unwrap();
+        assert_leaf_commits(
+            &recover_tx,
+            &vault.op_vault_triggered_spend_info(&destination).unwrap(),
+        );
+        assert_eq!(recover_tx.output[0].script_pubkey, recovery);
+        assert_eq!(recover_tx.output[0].value, vault.get_amount());
+    }
+}
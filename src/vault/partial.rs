@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use bitcoin::Amount;
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+
+/// Smallest output a partial withdrawal will create on either side
+const MIN_PARTIAL_OUTPUT: Amount = Amount::from_sat(546);
+
+impl VaultCovenant {
+    /// Withdraw only `amount` of a CTV vault, the complete pays the rest into a residual vault.
+    /// The CAT complete leaf pins its single output to the whole vault amount, so only CTV vaults can.
+    pub(crate) fn set_withdrawal_amount(&mut self, amount: Amount) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only CTV vaults can withdraw part of their amount, the CAT complete pays out all of it"
+            ));
+        }
+        if !self.payout.is_empty() {
+            return Err(anyhow!(
+                "a vault with a committed payout can only withdraw all of its amount"
+            ));
+        }
+        if self.trigger_fee.is_some() {
+            return Err(anyhow!(
+                "a vault paying its trigger fee out of its amount can only withdraw all of what is left"
+            ));
+        }
+        if self.fee_reserve > Amount::ZERO {
+            return Err(anyhow!(
+                "a vault paying its fees from a reserve can only withdraw all of its amount"
+            ));
+        }
+        let residual = self
+            .get_amount()
+            .checked_sub(amount)
+            .unwrap_or(Amount::ZERO);
+        if amount < MIN_PARTIAL_OUTPUT || residual < MIN_PARTIAL_OUTPUT {
+            return Err(anyhow!(
+                "withdrawing {} of {} leaves an output below {}",
+                amount,
+                self.get_amount(),
+                MIN_PARTIAL_OUTPUT
+            ));
+        }
+        self.withdrawal_amount = Some(amount);
+        Ok(())
+    }
+
+    /// What a partial withdrawal leaves in the vault
+    pub(crate) fn residual_amount(&self) -> Option<Amount> {
+        self.withdrawal_amount
+            .map(|withdrawal_amount| self.get_amount() - withdrawal_amount)
+    }
+
+    /// The vault a partial withdrawal's complete pays the residual amount into:
+    /// the same keys and parameters, its CTV template committing to the smaller amount
+    pub(crate) fn residual_vault(&self) -> Result<VaultCovenant> {
+        let residual = self
+            .residual_amount()
+            .ok_or(anyhow!("the withdrawal is not partial"))?;
+        let mut residual_vault = self.counterpart(VaultType::CTV);
+        residual_vault.set_amount(residual);
+        Ok(residual_vault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Address, Network, OutPoint, TxOut, Txid};
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn withdrawing_half_revaults_the_rest() {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        let destination =
+            Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest);
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault
+            .set_withdrawal_amount(Amount::from_sat(50_000))
+            .unwrap();
+        assert_eq!(vault.residual_amount(), Some(Amount::from_sat(50_000)));
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+
+        let fee_output = TxOut {
+            script_pubkey: destination.script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let complete_tx = vault
+            .create_ctv_complete_tx(
+                &OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+                fee_output,
+            )
+            .unwrap();
+        let residual_vault = vault.residual_vault().unwrap();
+        assert_eq!(residual_vault.get_amount(), Amount::from_sat(50_000));
+        assert_eq!(
+            complete_tx.output[0],
+            TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value: Amount::from_sat(50_000),
+            }
+        );
+        assert_eq!(
+            complete_tx.output[1],
+            TxOut {
+                script_pubkey: residual_vault.address().unwrap().script_pubkey(),
+                value: Amount::from_sat(50_000),
+            }
+        );
+
+        assert!(seeded_vault(VaultType::CAT)
+            .set_withdrawal_amount(Amount::from_sat(50_000))
+            .is_err());
+    }
+}
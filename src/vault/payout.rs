@@ -0,0 +1,163 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::transaction::Version;
+use bitcoin::{Address, Amount, ScriptBuf, Transaction, TxIn, TxOut};
+
+use crate::vault::contract::{template_hash, VaultCovenant, VaultType};
+use crate::vault::fees::dust_limit;
+use crate::vault::inputs::arrange_inputs;
+use crate::vault::script::{ctv_vault_complete_payout, ctv_vault_complete_withdrawal};
+use crate::vault::timelock::csv_sequence;
+
+impl VaultCovenant {
+    /// Commit the complete leaf of a CTV vault to paying exactly `payout`, anything the outputs leave of the
+    /// vault amount goes to fees. It changes the vault address, so it has to be set before the vault is funded.
+    pub(crate) fn set_payout(&mut self, payout: &[(Address, Amount)]) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only CTV vaults can commit to a payout, the CAT complete pays one destination picked at trigger"
+            ));
+        }
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't commit a vault that is already funded to a payout"
+            ));
+        }
+        if payout.is_empty() {
+            return Err(anyhow!("a payout needs at least one output"));
+        }
+        for (address, amount) in payout {
+            self.require_network(address)?;
+            let limit = dust_limit(&address.script_pubkey());
+            if *amount < limit {
+                return Err(anyhow!(
+                    "paying {} to {} is below its dust limit of {}",
+                    amount,
+                    address,
+                    limit
+                ));
+            }
+        }
+        let total = payout.iter().map(|(_, amount)| *amount).sum::<Amount>();
+        if total > self.triggered_amount() {
+            return Err(anyhow!(
+                "the payout totals {} but the vault only holds {}",
+                total,
+                self.triggered_amount()
+            ));
+        }
+        self.payout = payout
+            .iter()
+            .map(|(address, amount)| (address.to_string(), *amount))
+            .collect();
+        Ok(())
+    }
+
+    pub(crate) fn payout_outputs(&self) -> Result<Vec<TxOut>> {
+        self.payout
+            .iter()
+            .map(|(address, amount)| {
+                Ok(TxOut {
+                    script_pubkey: Address::from_str(address)?
+                        .require_network(self.get_network())?
+                        .script_pubkey(),
+                    value: *amount,
+                })
+            })
+            .collect()
+    }
+
+    /// The complete the payout leaf commits to: the triggered vault output after the timelock and one fee input,
+    /// paying the payout outputs
+    fn ctv_payout_template(&self) -> Result<Transaction> {
+        Ok(Transaction {
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+            input: arrange_inputs(
+                TxIn {
+                    sequence: csv_sequence(self.timelock)?,
+                    ..Default::default()
+                },
+                Some(TxIn::default()),
+                self.vault_input_index,
+            )
+            .0,
+            output: self.payout_outputs()?,
+        })
+    }
+
+    /// The CTV complete leaf, committing to the payout template if the vault has one
+    pub(crate) fn ctv_complete_script(&self) -> Result<ScriptBuf> {
+        let withdraw_key = self.withdraw_keypair().x_only_public_key().0;
+        if self.payout.is_empty() {
+            return Ok(ctv_vault_complete_withdrawal(withdraw_key, self.timelock));
+        }
+        Ok(ctv_vault_complete_payout(
+            withdraw_key,
+            self.timelock,
+            template_hash(&self.ctv_payout_template()?, self.vault_input_index)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    #[test]
+    fn complete_pays_exactly_the_committed_payout() {
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        let payout = [
+            (test_address(3), Amount::from_sat(60_000)),
+            (test_address(4), Amount::from_sat(39_000)),
+        ];
+        vault.set_payout(&payout).unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let fee_output = TxOut {
+            script_pubkey: test_address(9).script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let complete_tx = vault
+            .create_ctv_complete_tx(
+                &OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+                fee_output,
+            )
+            .unwrap();
+        let committed = template_hash(&vault.ctv_payout_template().unwrap(), 0).unwrap();
+        assert_eq!(template_hash(&complete_tx, 0).unwrap(), committed);
+        assert_eq!(complete_tx.output, vault.payout_outputs().unwrap());
+
+        // any other output set hashes to something the complete leaf doesn't commit to
+        let mut redirected = complete_tx.clone();
+        redirected.output[1].script_pubkey = test_address(5).script_pubkey();
+        let mut resized = complete_tx.clone();
+        resized.output[0].value -= Amount::from_sat(1);
+        let mut extra = complete_tx.clone();
+        extra.output.push(redirected.output[1].clone());
+        for deviation in [redirected, resized, extra] {
+            assert_ne!(template_hash(&deviation, 0).unwrap(), committed);
+        }
+        assert!(vault.withdrawal_outputs(Amount::from_sat(1)).is_err());
+        assert!(vault.set_recipients(&payout).is_err());
+    }
+}
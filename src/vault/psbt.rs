@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+use bitcoin::psbt::Psbt;
+use bitcoin::{Transaction, TxOut, Witness};
+
+use crate::vault::contract::VaultCovenant;
+
+impl VaultCovenant {
+    /// Wrap a covenant transaction spending the vault's current output in a PSBT, so the fee inputs can be
+    /// signed by an external wallet. `fee_outputs` are the outputs the fee inputs spend, in input order.
+    pub(crate) fn to_psbt(&self, txn: &Transaction, fee_outputs: &[TxOut]) -> Result<Psbt> {
+        let prevouts: Vec<TxOut> = std::iter::once(self.current_txout()?)
+            .chain(fee_outputs.iter().cloned())
+            .collect();
+        covenant_tx_to_psbt(txn, &prevouts)
+    }
+}
+
+/// Wrap a covenant transaction in a PSBT. The vault input (input 0) already carries its complete covenant
+/// witness so it goes in finalized, the other inputs are left unsigned with their `witness_utxo` filled in.
+pub(crate) fn covenant_tx_to_psbt(txn: &Transaction, prevouts: &[TxOut]) -> Result<Psbt> {
+    if prevouts.len() != txn.input.len() {
+        return Err(anyhow!(
+            "got {} prevouts for a transaction with {} inputs",
+            prevouts.len(),
+            txn.input.len()
+        ));
+    }
+    let vault_witness = txn
+        .input
+        .first()
+        .map(|input| input.witness.clone())
+        .filter(|witness| !witness.is_empty())
+        .ok_or(anyhow!("the vault input has no covenant witness"))?;
+
+    let mut unsigned_tx = txn.clone();
+    for input in unsigned_tx.input.iter_mut() {
+        input.witness.clear();
+        input.script_sig = Default::default();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+    for (input, prevout) in psbt.inputs.iter_mut().zip(prevouts) {
+        input.witness_utxo = Some(prevout.clone());
+    }
+    psbt.inputs[0].final_script_witness = Some(vault_witness);
+    Ok(psbt)
+}
+
+/// Finalize the externally signed fee inputs of a covenant PSBT and extract the broadcastable transaction.
+/// Inputs the signer already finalized are kept as is, otherwise taproot key path and P2WPKH signatures
+/// are turned into final witnesses.
+pub(crate) fn finalize_psbt(mut psbt: Psbt) -> Result<Transaction> {
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+            continue;
+        }
+        let script_pubkey = input
+            .witness_utxo
+            .as_ref()
+            .map(|utxo| utxo.script_pubkey.clone())
+            .ok_or(anyhow!("input {} has no witness utxo", index))?;
+        let final_witness = if let Some(signature) = input.tap_key_sig {
+            Witness::from_slice(&[signature.to_vec()])
+        } else if script_pubkey.is_p2wpkh() && input.partial_sigs.len() == 1 {
+            let (public_key, signature) = input.partial_sigs.iter().next().unwrap();
+            Witness::from_slice(&[signature.to_vec(), public_key.to_bytes()])
+        } else {
+            return Err(anyhow!("input {} isn't signed", index));
+        };
+        input.final_script_witness = Some(final_witness);
+        input.partial_sigs.clear();
+        input.tap_key_sig = None;
+    }
+    psbt.extract_tx()
+        .map_err(|e| anyhow!("couldn't extract the signed transaction: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::VaultType;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::{Keypair, Secp256k1};
+    use bitcoin::secp256k1::Message;
+    use bitcoin::{taproot, Address, Amount, Network, OutPoint, TapSighashType, Txid};
+
+    #[test]
+    fn psbt_round_trips_and_finalizes() {
+        let secp = Secp256k1::new();
+        let fee_key = Keypair::from_seckey_slice(&secp, &[9; 32]).unwrap();
+        let fee_output = TxOut {
+            script_pubkey: Address::p2tr(
+                &secp,
+                fee_key.x_only_public_key().0,
+                None,
+                Network::Regtest,
+            )
+            .script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination =
+            Address::p2tr(&secp, fee_key.x_only_public_key().0, None, Network::Regtest);
+        let trigger_tx = vault
+            .create_trigger_tx(
+                &[(
+                    OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+                    fee_output.clone(),
+                )],
+                &[destination],
+            )
+            .unwrap()
+            .transaction;
+
+        let psbt = vault.to_psbt(&trigger_tx, &[fee_output]).unwrap();
+        let mut round_tripped = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(round_tripped, psbt);
+        assert!(round_tripped.inputs[1].final_script_witness.is_none());
+
+        // stands in for the external wallet's signature, finalizing doesn't check it
+        let signature = taproot::Signature {
+            sig: secp.sign_schnorr_no_aux_rand(&Message::from_digest([3; 32]), &fee_key),
+            hash_ty: TapSighashType::Default,
+        };
+        round_tripped.inputs[1].tap_key_sig = Some(signature);
+        let signed = finalize_psbt(round_tripped).unwrap();
+        assert_eq!(signed.txid(), trigger_tx.txid());
+        assert_eq!(signed.input[0].witness, trigger_tx.input[0].witness);
+        assert_eq!(signed.input[1].witness.to_vec(), vec![signature.to_vec()]);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Amount, FeeRate, OutPoint, ScriptBuf, Transaction, TxOut};
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultType};
+use crate::vault::fees::{fee_paid, signed_fee};
+
+/// The fee rate a replacement has to pay on top of the fee of the transaction it replaces (BIP125 rule 4)
+const INCREMENTAL_RELAY_FEE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1);
+
+impl VaultCovenant {
+    /// Build a replacement for the vault's broadcast trigger `original` paying `fee_rate` from a new fee input.
+    /// The trigger is rebuilt from scratch since the CAT grind depends on the whole transaction, keeping
+    /// the original's marker destinations. `original_fee_inputs` are the outputs its fee inputs spent.
+    /// BIP125 needs the replacement to pay more absolute fee than the original plus its own relay fee.
+    pub(crate) fn bump_trigger_fee(
+        &self,
+        original: &Transaction,
+        original_fee_inputs: &[TxOut],
+        fee_rate: FeeRate,
+        fee_input: (OutPoint, TxOut),
+    ) -> Result<Transaction> {
+        let (fee_paying_utxo, fee_paying_output) = fee_input;
+        let replacement =
+            self.trigger_replacement(original, &fee_paying_utxo, fee_paying_output.clone())?;
+        let vault_output = self.pre_trigger_vault(original)?.current_txout()?;
+        let fee = fee_paid(&replacement, &[vault_output, fee_paying_output])?;
+        let required =
+            self.replacement_fee(original, original_fee_inputs, &replacement, fee_rate)?;
+        if fee < required {
+            return Err(anyhow!(
+                "the replacement pays {} but has to pay at least {} to replace {} at {} sat/vB",
+                fee,
+                required,
+                original.txid(),
+                fee_rate.to_sat_per_vb_ceil()
+            ));
+        }
+        Ok(replacement)
+    }
+
+    /// How much a fee input paying to `fee_script_pubkey` has to hold for [VaultCovenant::bump_trigger_fee],
+    /// sized from a dry run with a placeholder fee input
+    pub(crate) fn bump_trigger_fee_input(
+        &self,
+        original: &Transaction,
+        original_fee_inputs: &[TxOut],
+        fee_rate: FeeRate,
+        fee_script_pubkey: ScriptBuf,
+    ) -> Result<Amount> {
+        let placeholder = TxOut {
+            script_pubkey: fee_script_pubkey,
+            value: Amount::MAX_MONEY,
+        };
+        let dry_run = self.trigger_replacement(original, &OutPoint::null(), placeholder)?;
+        // the CAT markers are paid from the fee input too
+        let markers = dry_run.output[1..]
+            .iter()
+            .map(|marker| marker.value)
+            .sum::<Amount>();
+        Ok(self.replacement_fee(original, original_fee_inputs, &dry_run, fee_rate)? + markers)
+    }
+
+    /// The vault as it was before `original` triggered it, at the deposit output the trigger spends
+    fn pre_trigger_vault(&self, original: &Transaction) -> Result<VaultCovenant> {
+        if self.get_trigger_transaction()?.txid() != original.txid() {
+            return Err(anyhow!(
+                "{} is not the vault's trigger transaction",
+                original.txid()
+            ));
+        }
+        let mut vault = self.counterpart(self.get_type());
+        vault.set_current_outpoint(
+            original
+                .input
+                .get(self.vault_input_index)
+                .ok_or(anyhow!("trigger transaction has no vault input"))?
+                .previous_output,
+        );
+        Ok(vault)
+    }
+
+    fn trigger_replacement(
+        &self,
+        original: &Transaction,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+    ) -> Result<Transaction> {
+        let vault = self.pre_trigger_vault(original)?;
+        match self.get_type() {
+            VaultType::CAT => {
+                let targets = original
+                    .output
+                    .iter()
+                    .skip(1)
+                    .take(self.trigger_markers())
+                    .map(|marker| {
+                        Ok(Address::from_script(
+                            &marker.script_pubkey,
+                            self.get_network(),
+                        )?)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(vault
+                    .create_trigger_tx(&[(*fee_paying_utxo, fee_paying_output)], &targets)?
+                    .transaction)
+            }
+            VaultType::CTV => {
+                vault.create_path_tx(SpendPath::Trigger, fee_paying_utxo, fee_paying_output, None)
+            }
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => vault.create_path_tx(
+                SpendPath::Trigger,
+                fee_paying_utxo,
+                fee_paying_output,
+                Some(&self.get_withdrawal_address()?),
+            ),
+        }
+    }
+
+    /// The fee `replacement` has to pay: the original's fee plus relay fee for its own size, or `fee_rate`, whichever is more
+    fn replacement_fee(
+        &self,
+        original: &Transaction,
+        original_fee_inputs: &[TxOut],
+        replacement: &Transaction,
+        fee_rate: FeeRate,
+    ) -> Result<Amount> {
+        let original_prevouts: Vec<TxOut> =
+            std::iter::once(self.pre_trigger_vault(original)?.current_txout()?)
+                .chain(original_fee_inputs.iter().cloned())
+                .collect();
+        let original_fee = fee_paid(original, &original_prevouts)?;
+        Ok(
+            (original_fee + signed_fee(replacement, INCREMENTAL_RELAY_FEE)?)
+                .max(signed_fee(replacement, fee_rate)?),
+        )
+    }
+}
@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Amount, TxOut};
+use log::debug;
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::fees::dust_limit;
+
+impl VaultCovenant {
+    /// Split the withdrawal of a CTV vault across `recipients`, whatever they don't take goes to the withdrawal address.
+    /// The CAT complete leaf rebuilds its single output from the trigger's marker, so only CTV vaults can.
+    pub(crate) fn set_recipients(&mut self, recipients: &[(Address, Amount)]) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only CTV vaults can pay several recipients, the CAT complete pays one committed destination"
+            ));
+        }
+        if !self.payout.is_empty() {
+            return Err(anyhow!("the vault can only pay out its committed payout"));
+        }
+        for (address, amount) in recipients {
+            let limit = dust_limit(&address.script_pubkey());
+            if *amount < limit {
+                return Err(anyhow!(
+                    "paying {} to {} is below its dust limit of {}",
+                    amount,
+                    address,
+                    limit
+                ));
+            }
+        }
+        let total = recipients.iter().map(|(_, amount)| *amount).sum::<Amount>();
+        if total > self.withdrawal_total() {
+            return Err(anyhow!(
+                "recipients take {} but the withdrawal is only {}",
+                total,
+                self.withdrawal_total()
+            ));
+        }
+        self.recipients = recipients
+            .iter()
+            .map(|(address, amount)| (address.to_string(), *amount))
+            .collect();
+        Ok(())
+    }
+
+    /// What the complete pays out before fees, all the trigger left unless the withdrawal is partial
+    fn withdrawal_total(&self) -> Amount {
+        self.withdrawal_amount.unwrap_or(self.triggered_amount())
+    }
+
+    /// The outputs a CTV complete pays the withdrawal through once `fee` comes out of it: one per recipient,
+    /// then the rest to the withdrawal address, dropped to fees if it would be dust
+    pub(crate) fn withdrawal_outputs(&self, fee: Amount) -> Result<Vec<TxOut>> {
+        if !self.payout.is_empty() {
+            if fee > Amount::ZERO {
+                return Err(anyhow!(
+                    "the payout template can't take a fee out of the vault amount"
+                ));
+            }
+            return self.payout_outputs();
+        }
+        let available = self
+            .withdrawal_total()
+            .checked_sub(fee)
+            .ok_or(anyhow!("a fee of {} is more than the withdrawal", fee))?;
+        let mut outputs = self
+            .recipients
+            .iter()
+            .map(|(address, amount)| {
+                Ok(TxOut {
+                    script_pubkey: Address::from_str(address)?
+                        .require_network(self.get_network())?
+                        .script_pubkey(),
+                    value: *amount,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let paid = outputs.iter().map(|output| output.value).sum::<Amount>();
+        let rest = available.checked_sub(paid).ok_or(anyhow!(
+            "recipients take {} but only {} is left after a fee of {}",
+            paid,
+            available,
+            fee
+        ))?;
+        let withdrawal_script_pubkey = self.get_withdrawal_address()?.script_pubkey();
+        if outputs.is_empty() || rest >= dust_limit(&withdrawal_script_pubkey) {
+            outputs.push(TxOut {
+                script_pubkey: withdrawal_script_pubkey,
+                value: rest,
+            });
+        } else {
+            debug!("leaving the {} the recipients don't take to fees", rest);
+        }
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn complete_pays_three_recipients() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let recipients = [
+            (test_address(3), Amount::from_sat(30_000)),
+            (test_address(4), Amount::from_sat(30_000)),
+            (test_address(5), Amount::from_sat(40_000)),
+        ];
+        vault.set_recipients(&recipients).unwrap();
+
+        let fee_output = TxOut {
+            script_pubkey: test_address(9).script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let complete_tx = vault
+            .create_ctv_complete_tx(
+                &OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+                fee_output,
+            )
+            .unwrap();
+        let expected: Vec<TxOut> = recipients
+            .iter()
+            .map(|(address, amount)| TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: *amount,
+            })
+            .collect();
+        assert_eq!(complete_tx.output, expected);
+
+        assert!(vault
+            .set_recipients(&[(test_address(3), Amount::from_sat(100_001))])
+            .is_err());
+        assert!(seeded_vault(VaultType::CAT)
+            .set_recipients(&recipients)
+            .is_err());
+    }
+}
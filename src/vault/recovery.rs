@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::taproot::LeafVersion;
+use bitcoin::transaction::Version;
+use bitcoin::{Address, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut};
+
+use crate::vault::contract::{template_hash, VaultCovenant, VaultState, VaultType};
+use crate::vault::fees::signed_fee;
+use crate::vault::script::vault_recovery;
+
+impl VaultCovenant {
+    /// Add a leaf letting anyone move the funds to `address` once the chain reaches `height`.
+    /// It changes the vault address, so it has to be set before the vault is funded.
+    pub(crate) fn set_recovery(&mut self, address: Address, height: u32) -> Result<()> {
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't add a recovery leaf to a vault that is already funded"
+            ));
+        }
+        LockTime::from_height(height)
+            .map_err(|e| anyhow!("invalid recovery height {}: {}", height, e))?;
+        self.require_network(&address)?;
+        self.recovery_address = Some(address.to_string());
+        self.recovery_height = Some(height);
+        Ok(())
+    }
+
+    pub(crate) fn get_recovery_address(&self) -> Result<Address> {
+        Ok(Address::from_str(
+            self.recovery_address
+                .as_ref()
+                .ok_or(anyhow!("no recovery address"))?,
+        )?
+        .require_network(self.get_network())?)
+    }
+
+    /// The CLTV recovery leaf, if the vault has one
+    pub(crate) fn recovery_leaf(&self) -> Result<Option<ScriptBuf>> {
+        let Some(recovery_height) = self.recovery_height else {
+            return Ok(None);
+        };
+        Ok(Some(vault_recovery(
+            recovery_height,
+            template_hash(&self.recovery_tx_template()?, self.vault_input_index)?,
+        )))
+    }
+
+    /// The recovery transaction the leaf commits to: the vault input and a fee input,
+    /// locked until the recovery height, paying the whole vault amount to the recovery address
+    fn recovery_tx_template(&self) -> Result<Transaction> {
+        let recovery_height = self.recovery_height.ok_or(anyhow!("no recovery height"))?;
+        let input = TxIn {
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            ..Default::default()
+        };
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_height(recovery_height)?,
+            input: vec![input.clone(), input],
+            output: vec![TxOut {
+                script_pubkey: self.get_recovery_address()?.script_pubkey(),
+                value: self.get_amount(),
+            }],
+        })
+    }
+
+    /// Build the recovery transaction, with the fee input left for the fee wallet to sign.
+    /// A CAT vault can recover from its deposit or triggered output, a CTV vault only once triggered.
+    pub(crate) fn create_recovery_tx(&self, fee_paying_utxo: &OutPoint) -> Result<Transaction> {
+        if self.get_type() == VaultType::CTV && self.get_state() != VaultState::Triggered {
+            return Err(anyhow!(
+                "a CTV vault's recovery leaf is only in the tree of its triggered output"
+            ));
+        }
+        let script = self
+            .recovery_leaf()?
+            .ok_or(anyhow!("the vault has no recovery leaf"))?;
+        let control_block = self
+            .triggered_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("recovery leaf is missing from the script tree"))?;
+
+        let mut txn = self.recovery_tx_template()?;
+        let (vault_index, fee_index) = (self.vault_input_index, 1 - self.vault_input_index);
+        txn.input[vault_index].previous_output = self.get_current_outpoint()?;
+        txn.input[vault_index].witness.push(script.to_bytes());
+        txn.input[vault_index]
+            .witness
+            .push(control_block.serialize());
+        txn.input[fee_index].previous_output = *fee_paying_utxo;
+        // the recovery leaf checks no signature, so the fee input's output doesn't matter here
+        #[cfg(feature = "verify")]
+        crate::vault::interpreter::verify_input(
+            &txn,
+            vault_index,
+            &vec![self.triggered_output()?; txn.input.len()],
+        )?;
+
+        Ok(txn)
+    }
+
+    /// The fee input value the recovery transaction needs to pay `fee_rate`,
+    /// the recovery output takes the whole vault amount so the fee input pays all of it
+    pub(crate) fn recovery_fee(&self, fee_rate: FeeRate) -> Result<Amount> {
+        signed_fee(&self.create_recovery_tx(&OutPoint::null())?, fee_rate)
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Amount, FeeRate, OutPoint, Transaction};
+
+use crate::settings::Settings;
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::fees::{fee_paid, signed_fee};
+use crate::vault::timelock::TimelockKind;
+
+impl VaultCovenant {
+    /// Set aside `reserve` of the vault amount to pay the complete and cancel fees, so they need no fee input.
+    /// Only CTV vaults can, the CAT complete and cancel leaves require their output to hold the whole vault amount.
+    pub(crate) fn set_fee_reserve(&mut self, reserve: Amount) -> Result<()> {
+        if reserve > Amount::ZERO && self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only CTV vaults can pay their fees out of a reserve"
+            ));
+        }
+        if reserve > Amount::ZERO && !self.payout.is_empty() {
+            return Err(anyhow!(
+                "the payout template pays its fee from a fee input, not a reserve"
+            ));
+        }
+        if reserve >= self.get_amount() {
+            return Err(anyhow!(
+                "a fee reserve of {} leaves nothing of the {} vault",
+                reserve,
+                self.get_amount()
+            ));
+        }
+        self.fee_reserve = reserve;
+        Ok(())
+    }
+
+    /// Take a fee paid out of the vault amount off the reserve
+    pub(crate) fn draw_fee_reserve(&mut self, fee: Amount) -> Result<()> {
+        self.fee_reserve = self.fee_reserve.checked_sub(fee).ok_or(anyhow!(
+            "a fee of {} is more than the {} left in the reserve",
+            fee,
+            self.fee_reserve
+        ))?;
+        Ok(())
+    }
+
+    /// The fee a complete or cancel without a fee input takes out of the triggered output
+    pub(crate) fn reserve_fee_paid(&self, txn: &Transaction) -> Result<Amount> {
+        fee_paid(txn, &[self.triggered_output()?])
+    }
+
+    /// Build and sign a complete paying its fee out of the reserve instead of a fee input
+    pub(crate) fn create_reserve_complete_tx(&self, fee_rate: FeeRate) -> Result<Transaction> {
+        let fee = self.fee_from_reserve(fee_rate, |fee| self.build_ctv_complete_tx(None, fee))?;
+        self.build_ctv_complete_tx(None, fee)
+    }
+
+    /// Cancel a triggered withdrawal into a new vault like `cancel_and_rotate`, paying the fee out of the reserve.
+    /// The new vault holds, and keeps in reserve, whatever is left after the fee.
+    pub(crate) fn cancel_and_rotate_from_reserve(
+        &self,
+        new_timelock: TimelockKind,
+        fee_rate: FeeRate,
+        settings: &Settings,
+    ) -> Result<(VaultCovenant, Transaction)> {
+        let delegation = self.cancel_signer()?;
+        let triggered_outpoint = self.get_current_outpoint()?;
+        let fee = self.fee_from_reserve(fee_rate, |fee| {
+            delegation.create_fee_deducting_cancel_tx_to(
+                triggered_outpoint,
+                self.address()?.script_pubkey(),
+                fee,
+            )
+        })?;
+        let mut new_vault = self.rotated_vault(
+            VaultType::CTV,
+            new_timelock,
+            self.get_amount() - fee,
+            settings,
+        )?;
+        new_vault.fee_reserve = self.fee_reserve - fee;
+        let txn = delegation.create_fee_deducting_cancel_tx_to(
+            triggered_outpoint,
+            new_vault.address()?.script_pubkey(),
+            fee,
+        )?;
+        new_vault.set_current_outpoint(OutPoint {
+            txid: txn.txid(),
+            vout: 0,
+        });
+        Ok((new_vault, txn))
+    }
+
+    /// The fee the transaction `build` makes needs to pay `fee_rate`, as long as the reserve covers it.
+    /// Sized from a dry run at zero fee, the witness size doesn't depend on the output value.
+    fn fee_from_reserve(
+        &self,
+        fee_rate: FeeRate,
+        build: impl Fn(Amount) -> Result<Transaction>,
+    ) -> Result<Amount> {
+        let fee = signed_fee(&build(Amount::ZERO)?, fee_rate)?;
+        if fee > self.fee_reserve {
+            return Err(anyhow!(
+                "the {} fee is more than the {} left in the reserve",
+                fee,
+                self.fee_reserve
+            ));
+        }
+        Ok(fee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::{Keypair, Secp256k1};
+    use bitcoin::{Address, Network, Txid};
+
+    #[test]
+    fn reserve_complete_pays_the_fee_out_of_the_reserve() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[2; 32]).unwrap();
+        let destination =
+            Address::p2tr(&secp, keypair.x_only_public_key().0, None, Network::Regtest);
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_fee_reserve(Amount::from_sat(5_000)).unwrap();
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+
+        let complete_tx = vault
+            .create_reserve_complete_tx(FeeRate::from_sat_per_vb_unchecked(2))
+            .unwrap();
+        let fee = vault.reserve_fee_paid(&complete_tx).unwrap();
+        assert!(
+            fee > Amount::ZERO && fee < Amount::from_sat(5_000),
+            "{}",
+            fee
+        );
+        assert_eq!(complete_tx.input.len(), 1);
+        assert_eq!(
+            complete_tx.output[0].script_pubkey,
+            destination.script_pubkey()
+        );
+        assert_eq!(
+            complete_tx.output[0].value,
+            vault.triggered_output().unwrap().value - fee
+        );
+
+        vault.draw_fee_reserve(fee).unwrap();
+        assert_eq!(vault.fee_reserve, Amount::from_sat(5_000) - fee);
+        assert!(vault.draw_fee_reserve(Amount::from_sat(5_000)).is_err());
+    }
+}
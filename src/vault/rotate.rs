@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, OutPoint, Transaction, TxOut};
+
+use crate::settings::Settings;
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultState, VaultType};
+
+/// What moving a vault to fresh keys takes: the new vault and the two transactions that fund it
+pub(crate) struct KeyRotation {
+    pub(crate) new_vault: VaultCovenant,
+    pub(crate) trigger_tx: Transaction,
+    /// the CAT complete or the CTV cancel paying the new vault, it spends the trigger's vault output
+    pub(crate) transfer_tx: Transaction,
+}
+
+impl VaultCovenant {
+    /// Move the funds of this untriggered vault into a new vault of the same type, timelock and amount with fresh
+    /// keys, for when its keys may have leaked before anyone triggered it. A CAT vault triggers to the new vault and
+    /// completes once the timelock is up, a CTV vault triggers and cancels straight into the new vault since its
+    /// withdrawal destination is fixed. `fund_fee_input` is asked for each fee input as the vault would be when
+    /// spending it, with the withdrawal destination of a trigger. This vault ends up Triggered, to be persisted along
+    /// with the new vault before anything is broadcast and completed with the transfer's txid once that is sent.
+    /// The fee inputs of both transactions are left for the caller to sign.
+    pub(crate) fn rotate_key(
+        &mut self,
+        settings: &Settings,
+        mut fund_fee_input: impl FnMut(
+            &VaultCovenant,
+            SpendPath,
+            Option<&Address>,
+        ) -> Result<(OutPoint, TxOut)>,
+    ) -> Result<KeyRotation> {
+        if self.get_state() != VaultState::Inactive {
+            return Err(anyhow!(
+                "only an untriggered vault can rotate its keys, this one is {:?}",
+                self.get_state()
+            ));
+        }
+        let (transfer_path, new_vault) = match self.get_type() {
+            VaultType::CAT => (
+                SpendPath::Complete,
+                Some(self.rotated_vault(
+                    VaultType::CAT,
+                    self.timelock,
+                    self.get_amount(),
+                    settings,
+                )?),
+            ),
+            // cancel_and_rotate builds the new vault
+            VaultType::CTV => (SpendPath::Cancel, None),
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => {
+                return Err(anyhow!("an OP_VAULT vault can't rotate its keys"));
+            }
+        };
+        let destination = new_vault
+            .as_ref()
+            .map(|new_vault| new_vault.address())
+            .transpose()?;
+
+        let trigger_tx = if self.trigger_fee.is_some() {
+            self.create_ctv_trigger_tx(None)?
+        } else {
+            let (fee_paying_utxo, fee_paying_output) =
+                fund_fee_input(self, SpendPath::Trigger, destination.as_ref())?;
+            self.create_path_tx(
+                SpendPath::Trigger,
+                &fee_paying_utxo,
+                fee_paying_output,
+                destination.as_ref(),
+            )?
+        };
+        self.set_current_outpoint(OutPoint {
+            txid: trigger_tx.txid(),
+            vout: 0,
+        });
+        self.set_withdrawal_address(destination)?;
+        self.set_trigger_transaction(Some(trigger_tx.clone()));
+        self.set_state(VaultState::Triggered);
+
+        let fee_input = fund_fee_input(self, transfer_path, None)?;
+        let (mut new_vault, transfer_tx) = match new_vault {
+            Some(new_vault) => {
+                let (fee_paying_utxo, fee_paying_output) = fee_input;
+                let transfer_tx = self.create_path_tx(
+                    SpendPath::Complete,
+                    &fee_paying_utxo,
+                    fee_paying_output,
+                    None,
+                )?;
+                (new_vault, transfer_tx)
+            }
+            None => self.cancel_and_rotate(self.timelock, fee_input, settings)?,
+        };
+        new_vault.set_current_outpoint(OutPoint {
+            txid: transfer_tx.txid(),
+            vout: 0,
+        });
+        Ok(KeyRotation {
+            new_vault,
+            trigger_tx,
+            transfer_tx,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, Txid};
+
+    #[test]
+    fn rotation_leaves_the_old_vault_triggered() {
+        let settings = Settings::default();
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let mut vault = VaultCovenant::new_seeded(
+                vault_type,
+                TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &settings,
+                7,
+            )
+            .unwrap();
+            vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+            let fee_script = vault.address().unwrap().script_pubkey();
+            let mut fee_inputs = 0;
+            let rotation = vault
+                .rotate_key(&settings, |_, _, _| {
+                    fee_inputs += 1;
+                    Ok((
+                        OutPoint::new(Txid::from_byte_array([2; 32]), fee_inputs),
+                        TxOut {
+                            script_pubkey: fee_script.clone(),
+                            value: Amount::from_sat(50_000),
+                        },
+                    ))
+                })
+                .unwrap();
+
+            assert_eq!(vault.get_state(), VaultState::Triggered);
+            assert_eq!(
+                vault.get_current_outpoint().unwrap().txid,
+                rotation.trigger_tx.txid()
+            );
+            assert_eq!(
+                rotation.transfer_tx.input[0].previous_output.txid,
+                rotation.trigger_tx.txid()
+            );
+            assert_eq!(
+                rotation.new_vault.get_current_outpoint().unwrap().txid,
+                rotation.transfer_tx.txid()
+            );
+            assert_ne!(
+                rotation.new_vault.address().unwrap(),
+                vault.address().unwrap()
+            );
+            vault.complete_with(rotation.transfer_tx.txid());
+            assert_eq!(vault.completed_txid(), Some(rotation.transfer_tx.txid()));
+        }
+    }
+}
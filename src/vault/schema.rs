@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Result};
+use bitcoincore_rpc::jsonrpc::serde_json::{self, Value};
+
+/// The layout of the vault file `to_file` writes. Files without a `schema_version` are version 1.
+pub(crate) const SCHEMA_VERSION: u64 = 3;
+
+const VERSION_FIELD: &str = "schema_version";
+
+/// Stamp a serialized vault with the current schema version
+pub(crate) fn tag(stored: &mut Value) -> Result<()> {
+    stored
+        .as_object_mut()
+        .ok_or(anyhow!("a vault file is a JSON object"))?
+        .insert(VERSION_FIELD.to_string(), Value::from(SCHEMA_VERSION));
+    Ok(())
+}
+
+/// Bring a stored vault up to the current schema one version at a time and strip the version tag.
+/// Files from a newer version are refused instead of being read partially.
+pub(crate) fn migrate(stored: &mut Value) -> Result<()> {
+    let object = stored
+        .as_object_mut()
+        .ok_or(anyhow!("a vault file is a JSON object"))?;
+    let version = match object.remove(VERSION_FIELD) {
+        Some(version) => serde_json::from_value::<u64>(version)
+            .map_err(|e| anyhow!("invalid {}: {}", VERSION_FIELD, e))?,
+        None => 1,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "the vault file is schema version {} but this build only reads up to {}, upgrade to load it",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    if version < 2 {
+        // version 1 vaults were all CAT vaults, written before the vault type was stored
+        object
+            .entry("vault_type")
+            .or_insert_with(|| Value::from("CAT"));
+    }
+    if version < 3 {
+        // version 2 vaults derived their keys as normal children of the derivation path
+        object
+            .entry("hardened_keys")
+            .or_insert_with(|| Value::from(false));
+    }
+    Ok(())
+}
@@ -1,24 +1,80 @@
-use crate::vault::signature_building::{BIP0340_CHALLENGE_TAG, DUST_AMOUNT, G_X, TAPSIGHASH_TAG};
+use crate::vault::signature_building::{BIP0340_CHALLENGE_TAG, G_X, TAPSIGHASH_TAG};
+use crate::vault::timelock::TimelockKind;
 use bitcoin::opcodes::all::{
-    OP_2DUP, OP_CAT, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CSV, OP_DROP, OP_DUP, OP_EQUALVERIFY,
-    OP_FROMALTSTACK, OP_HASH256, OP_NOP4, OP_ROT, OP_SHA256, OP_SWAP, OP_TOALTSTACK,
+    OP_0NOTEQUAL, OP_2DUP, OP_ADD, OP_CAT, OP_CHECKSIG, OP_CHECKSIGADD, OP_CHECKSIGVERIFY, OP_CLTV,
+    OP_CSV, OP_DROP, OP_DUP, OP_EQUALVERIFY, OP_FROMALTSTACK, OP_HASH256, OP_NOP4,
+    OP_NUMEQUALVERIFY, OP_ROT, OP_SHA256, OP_SIZE, OP_SWAP, OP_TOALTSTACK,
 };
-use bitcoin::script::Builder;
-use bitcoin::{Opcode, Script, ScriptBuf, Sequence, XOnlyPublicKey};
+use bitcoin::script::{Builder, Instruction};
+use bitcoin::{Amount, Opcode, Script, ScriptBuf, XOnlyPublicKey};
 
 const OP_CTV: Opcode = OP_NOP4;
+/// BIP 345 OP_VAULT, OP_SUCCESS187 where the soft fork isn't deployed
+#[cfg(feature = "op-vault")]
+const OP_VAULT: Opcode = bitcoin::opcodes::all::OP_RETURN_187;
+/// BIP 345 OP_VAULT_RECOVER, OP_SUCCESS188 where the soft fork isn't deployed
+#[cfg(feature = "op-vault")]
+const OP_VAULT_RECOVER: Opcode = bitcoin::opcodes::all::OP_RETURN_188;
 
-pub(crate) fn vault_trigger_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptBuf {
+/// Most marker outputs a CAT trigger can carry, their concatenation has to fit in one stack element
+pub(crate) const MAX_TRIGGER_MARKERS: usize = 8;
+
+/// Largest single witness element standardness policy allows, pushes bigger than this have to be split up
+pub(crate) const MAX_WITNESS_PUSH_SIZE: usize = 80;
+
+/// How many witness pushes it takes to carry `len` bytes
+pub(crate) fn witness_chunk_count(len: usize) -> usize {
+    len.div_ceil(MAX_WITNESS_PUSH_SIZE)
+}
+
+/// The `(start, end)` byte ranges of each witness push carrying `len` bytes
+pub(crate) fn witness_chunk_ranges(len: usize) -> Vec<(usize, usize)> {
+    (0..witness_chunk_count(len))
+        .map(|chunk| {
+            let start = chunk * MAX_WITNESS_PUSH_SIZE;
+            (start, (start + MAX_WITNESS_PUSH_SIZE).min(len))
+        })
+        .collect()
+}
+
+/// `trigger_markers` is the number of marker outputs of `marker_amount` the trigger transaction pays after the
+/// vault output. One trigger key is checked with CHECKSIGVERIFY, several need exactly `threshold` signatures
+/// through CHECKSIGADD. With `fee_change` the witness also carries an encoded change output, or an empty element
+/// for none, which the trigger pays last.
+pub(crate) fn vault_trigger_withdrawal(
+    trigger_keys: &[XOnlyPublicKey],
+    threshold: usize,
+    trigger_markers: usize,
+    marker_amount: Amount,
+    fee_change: bool,
+) -> ScriptBuf {
     let mut builder = Script::builder();
     // The witness program needs to have the signature components except the outputs and the pre_scriptpubkeys and pre_amounts,
-    // followed by the target scriptpubkey (the amount for that output will be fixed)
+    // followed by the encoded change output if the leaf takes one,
+    // followed by the marker scriptpubkeys, last marker first (the amount for those outputs will be fixed)
     // followed by the vault output amount, then the vault scriptpubkey,
     // followed by the fee amount, then the fee-paying scriptpubkey
     // followed by the mangled signature
-    // and finally the a normal signature that signs with vault pubkey
+    // and finally the a normal signature that signs with vault pubkey, one per trigger key with the first on top
+    if let [x_only_pubkey] = trigger_keys {
+        builder = builder
+            .push_x_only_key(x_only_pubkey) // push vault pubkey
+            .push_opcode(OP_CHECKSIGVERIFY); // checksig for pubkey
+    } else {
+        for (index, x_only_pubkey) in trigger_keys.iter().enumerate() {
+            builder = builder
+                .push_x_only_key(x_only_pubkey)
+                .push_opcode(if index == 0 {
+                    OP_CHECKSIG // the first signature starts the count
+                } else {
+                    OP_CHECKSIGADD // every later valid signature adds one
+                });
+        }
+        builder = builder
+            .push_int(threshold as i64)
+            .push_opcode(OP_NUMEQUALVERIFY); // exactly threshold of them signed
+    }
     builder = builder
-        .push_x_only_key(&x_only_pubkey) // push vault pubkey
-        .push_opcode(OP_CHECKSIGVERIFY) // checksig for pubkey
         .push_opcode(OP_TOALTSTACK) // move pre-computed signature minus last byte to alt stack
         .push_opcode(OP_TOALTSTACK) // move last byte to alt stack
         .push_opcode(OP_TOALTSTACK) // move last byte to alt stack
@@ -28,23 +84,47 @@ pub(crate) fn vault_trigger_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptB
         .push_opcode(OP_TOALTSTACK) // push the first copy of the vault scriptpubkey to the alt stack
         .push_opcode(OP_TOALTSTACK) // push the first copy of the vault amount to the alt stack
         .push_opcode(OP_TOALTSTACK) // push the second copy of the vault scriptpubkey to the alt stack
-        .push_opcode(OP_TOALTSTACK) // push the second copy of the vault amount to the alt stack
-        .push_opcode(OP_TOALTSTACK) // move the target scriptpubkey to the alt stack
+        .push_opcode(OP_TOALTSTACK); // push the second copy of the vault amount to the alt stack
+    for _ in 0..trigger_markers {
+        builder = builder.push_opcode(OP_TOALTSTACK); // move the marker scriptpubkeys to the alt stack
+    }
+    if fee_change {
+        builder = builder.push_opcode(OP_TOALTSTACK); // move the change output to the alt stack
+    }
+    builder = builder
         // start with encoded leaf hash
         .push_opcode(OP_CAT) // encoded leaf hash
         .push_opcode(OP_CAT) // encoded leaf hash
         .push_slice([0x00u8, 0x00u8, 0x00u8, 0x00u8]) // add input index of 0
         .push_opcode(OP_SWAP) // bring working sigmsg back to top of stack
         .push_opcode(OP_CAT) // input index
-        .push_opcode(OP_CAT) // spend type
-        .push_slice(*DUST_AMOUNT) // push the dust amount for the target output
-        .push_opcode(OP_FROMALTSTACK) // get the target scriptpubkey
-        .push_opcode(OP_CAT) // cat the dust amount and the target scriptpubkey
+        .push_opcode(OP_CAT); // spend type
+    if fee_change {
+        builder = builder.push_opcode(OP_FROMALTSTACK); // get the change output, it goes after the markers
+    }
+    builder = builder
+        .push_slice(marker_amount.to_sat().to_le_bytes()) // push the amount of the last marker output
+        .push_opcode(OP_FROMALTSTACK) // get the last marker scriptpubkey
+        .push_opcode(OP_CAT); // cat the dust amount and the marker scriptpubkey
+    if fee_change {
+        builder = builder
+            .push_opcode(OP_SWAP) // move the change output to the top of the stack
+            .push_opcode(OP_CAT); // cat the change output after the last marker output
+    }
+    for _ in 1..trigger_markers {
+        builder = builder
+            .push_slice(marker_amount.to_sat().to_le_bytes()) // push the amount of the previous marker output
+            .push_opcode(OP_FROMALTSTACK) // get the previous marker scriptpubkey
+            .push_opcode(OP_CAT) // cat the dust amount and the marker scriptpubkey
+            .push_opcode(OP_SWAP) // put the marker outputs in order
+            .push_opcode(OP_CAT); // cat the previous marker output in front of the later ones
+    }
+    builder = builder
         .push_opcode(OP_FROMALTSTACK) // get the output amount
         .push_opcode(OP_FROMALTSTACK) // get the second copy of the scriptpubkey
         .push_opcode(OP_CAT) // cat the output amount and the second copy of the scriptpubkey
-        .push_opcode(OP_SWAP) // put the outputs in the right order (vault then target)
-        .push_opcode(OP_CAT) // cat the vault output and target output together
+        .push_opcode(OP_SWAP) // put the outputs in the right order (vault then markers)
+        .push_opcode(OP_CAT) // cat the vault output and marker outputs together
         .push_opcode(OP_SHA256) // hash the output
         .push_opcode(OP_SWAP) // move the hashed encoded outputs below our working sigmsg
         .push_opcode(OP_CAT) // outputs
@@ -77,13 +157,22 @@ pub(crate) fn vault_trigger_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptB
     builder.into_script()
 }
 
+/// `trigger_input_chunks` is the number of witness pushes the serialized inputs of the trigger transaction are split into,
+/// `trigger_markers` the number of marker outputs of `marker_amount` it pays. With `fee_change` the trigger may pay
+/// a change output after the markers, which the witness carries encoded or as an empty element.
 pub(crate) fn vault_complete_withdrawal(
     x_only_pubkey: XOnlyPublicKey,
-    timelock_in_blocks: u16,
+    timelock: TimelockKind,
+    trigger_input_chunks: usize,
+    trigger_markers: usize,
+    marker_amount: Amount,
+    fee_change: bool,
 ) -> ScriptBuf {
     let mut builder = Script::builder();
     // The witness program needs to have the signature components except the outputs, prevouts,
     // followed by the previous transaction version, inputs, and locktime
+    // followed by the previous transaction's encoded change output if the leaf takes one
+    // followed by the encoded marker outputs after the first, last marker first
     // followed by vault SPK, the vault amount, and the target SPK
     // followed by the fee-paying txout
     // followed by the mangled signature
@@ -91,7 +180,7 @@ pub(crate) fn vault_complete_withdrawal(
     builder = builder
         .push_x_only_key(&x_only_pubkey) // push vault pubkey
         .push_opcode(OP_CHECKSIGVERIFY) // checksig for pubkey
-        .push_sequence(Sequence::from_height(timelock_in_blocks))
+        .push_sequence(timelock.to_sequence())
         .push_opcode(OP_CSV) // check relative timelock on withdrawal
         .push_opcode(OP_DROP) // drop the result
         .push_opcode(OP_TOALTSTACK) // move pre-computed signature minus last byte to alt stack
@@ -100,7 +189,7 @@ pub(crate) fn vault_complete_withdrawal(
         .push_opcode(OP_TOALTSTACK) // move the fee-paying txout to the alt stack
         .push_opcode(OP_DUP) // make a second copy of the target scriptpubkey so we can use it later
         .push_opcode(OP_TOALTSTACK) // push the target scriptpubkey to the alt stack
-        .push_slice(*DUST_AMOUNT) // push the dust amount for the previous tx second output
+        .push_slice(marker_amount.to_sat().to_le_bytes()) // push the amount of the previous tx second output
         .push_opcode(OP_SWAP) // swap the dust amount to the top of the stack
         .push_opcode(OP_CAT) // consensus-encode the second output for the previous TX
         .push_opcode(OP_SWAP) // get the vault amount to the top of the stack
@@ -113,13 +202,36 @@ pub(crate) fn vault_complete_withdrawal(
         .push_opcode(OP_ROT) // move the vault address to the top of the stack
         .push_opcode(OP_SWAP) // move the second output from the previous TX to the top of the stack
         .push_opcode(OP_CAT) // cat the vault amount and the second output from the previous TX
-        .push_opcode(OP_CAT) // cat the vault address, now have all the outputs from the previous TX
-        .push_int(2) // add the number of outputs from the previous TX
-        .push_opcode(OP_SWAP)
-        .push_opcode(OP_CAT) // cat the outputs with their count from the previous TX
+        .push_opcode(OP_CAT); // cat the vault address, now have the vault and first marker outputs
+    for _ in 1..trigger_markers {
+        builder = builder
+            .push_opcode(OP_SWAP) // bring the next encoded marker output to the top of the stack
+            .push_opcode(OP_CAT); // append it to the outputs from the previous TX
+    }
+    if fee_change {
+        builder = builder
+            .push_opcode(OP_SWAP) // bring the change output to the top of the stack
+            .push_opcode(OP_SIZE) // an empty change output means the previous TX paid no change
+            .push_opcode(OP_0NOTEQUAL)
+            .push_int(trigger_markers as i64 + 1)
+            .push_opcode(OP_ADD) // add the number of outputs from the previous TX
+            .push_opcode(OP_ROT) // bring the outputs to the top of the stack
+            .push_opcode(OP_CAT) // cat the outputs with their count from the previous TX
+            .push_opcode(OP_SWAP) // bring the change output back to the top of the stack
+            .push_opcode(OP_CAT); // cat the change output after the other outputs
+    } else {
+        builder = builder
+            .push_int(trigger_markers as i64 + 1) // add the number of outputs from the previous TX
+            .push_opcode(OP_SWAP)
+            .push_opcode(OP_CAT); // cat the outputs with their count from the previous TX
+    }
+    builder = builder
         .push_opcode(OP_SWAP) // move the outputs down, and the previous TX locktime to the top of the stack
-        .push_opcode(OP_CAT) // cat the previous TX locktime with the outputs
-        .push_opcode(OP_CAT) // we had to split the input into two chunks
+        .push_opcode(OP_CAT); // cat the previous TX locktime with the outputs
+    for _ in 1..trigger_input_chunks {
+        builder = builder.push_opcode(OP_CAT); // we had to split the input into chunks
+    }
+    builder = builder
         .push_opcode(OP_CAT) // add the inputs
         .push_opcode(OP_CAT) // add the previous TX version
         .push_opcode(OP_HASH256) // hash the whole thing twice to get the TXID
@@ -154,9 +266,15 @@ pub(crate) fn vault_complete_withdrawal(
     builder.into_script()
 }
 
-pub(crate) fn vault_cancel_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptBuf {
+/// With `fee_change` the witness also carries an encoded change output, or an empty element for none,
+/// which the cancel pays after the vault output
+pub(crate) fn vault_cancel_withdrawal(
+    x_only_pubkey: XOnlyPublicKey,
+    fee_change: bool,
+) -> ScriptBuf {
     let mut builder = Script::builder();
     // The witness program needs to have the signature components except the outputs and the pre_scriptpubkeys and pre_amounts,
+    // followed by the encoded change output if the leaf takes one,
     // followed by the output amount, then the script pubkey,
     // followed by the fee amount, then the fee-paying scriptpubkey
     // followed by the mangled signature
@@ -173,17 +291,31 @@ pub(crate) fn vault_cancel_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptBu
         .push_opcode(OP_TOALTSTACK) // push the first copy of the vault scriptpubkey to the alt stack
         .push_opcode(OP_TOALTSTACK) // push the first copy of the vault amount to the alt stack
         .push_opcode(OP_TOALTSTACK) // push the second copy of the vault scriptpubkey to the alt stack
-        .push_opcode(OP_TOALTSTACK) // push the second copy of the vault amount to the alt stack
+        .push_opcode(OP_TOALTSTACK); // push the second copy of the vault amount to the alt stack
+    if fee_change {
+        builder = builder.push_opcode(OP_TOALTSTACK); // move the change output to the alt stack
+    }
+    builder = builder
         // start with encoded leaf hash
         .push_opcode(OP_CAT) // encoded leaf hash
         .push_opcode(OP_CAT) // encoded leaf hash
         .push_slice([0x00u8, 0x00u8, 0x00u8, 0x00u8]) // add input index of 0
         .push_opcode(OP_SWAP) // bring working sigmsg back to top of stack
         .push_opcode(OP_CAT) // input index
-        .push_opcode(OP_CAT) // spend type
+        .push_opcode(OP_CAT); // spend type
+    if fee_change {
+        builder = builder.push_opcode(OP_FROMALTSTACK); // get the change output, it goes after the vault output
+    }
+    builder = builder
         .push_opcode(OP_FROMALTSTACK) // get the output amount
         .push_opcode(OP_FROMALTSTACK) // get the second copy of the scriptpubkey
-        .push_opcode(OP_CAT) // cat the output amount and the second copy of the scriptpubkey
+        .push_opcode(OP_CAT); // cat the output amount and the second copy of the scriptpubkey
+    if fee_change {
+        builder = builder
+            .push_opcode(OP_SWAP) // move the change output to the top of the stack
+            .push_opcode(OP_CAT); // cat the change output after the vault output
+    }
+    builder = builder
         .push_opcode(OP_SHA256) // hash the output
         .push_opcode(OP_SWAP) // move the hashed encoded outputs below our working sigmsg
         .push_opcode(OP_CAT) // outputs
@@ -270,10 +402,10 @@ pub(crate) fn ctv_vault_deposit(ctv_hash: [u8; 32]) -> ScriptBuf {
 
 pub(crate) fn ctv_vault_complete_withdrawal(
     x_only_pubkey: XOnlyPublicKey,
-    timelock_in_blocks: u16,
+    timelock: TimelockKind,
 ) -> ScriptBuf {
     Builder::new()
-        .push_sequence(Sequence::from_height(timelock_in_blocks))
+        .push_sequence(timelock.to_sequence())
         .push_opcode(OP_CSV) // check relative timelock on withdrawal
         .push_opcode(OP_DROP) // drop the result
         .push_x_only_key(&x_only_pubkey) // push vault pubkey
@@ -281,9 +413,147 @@ pub(crate) fn ctv_vault_complete_withdrawal(
         .into_script()
 }
 
+/// The complete leaf of a vault with a fixed payout: after the timelock the withdraw key can only sign
+/// the transaction `payout_hash` commits to
+pub(crate) fn ctv_vault_complete_payout(
+    x_only_pubkey: XOnlyPublicKey,
+    timelock: TimelockKind,
+    payout_hash: [u8; 32],
+) -> ScriptBuf {
+    Builder::new()
+        .push_sequence(timelock.to_sequence())
+        .push_opcode(OP_CSV) // check relative timelock on withdrawal
+        .push_opcode(OP_DROP) // drop the result
+        .push_slice(payout_hash)
+        .push_opcode(OP_CTV) // check the transaction against the payout template
+        .push_opcode(OP_DROP) // drop the template hash
+        .push_x_only_key(&x_only_pubkey) // push withdraw pubkey
+        .push_opcode(OP_CHECKSIG) // checksig for pubkey
+        .into_script()
+}
+
 pub(crate) fn ctv_vault_cancel_withdrawal(x_only_pubkey: XOnlyPublicKey) -> ScriptBuf {
     Builder::new()
         .push_x_only_key(&x_only_pubkey) // push vault pubkey
         .push_opcode(OP_CHECKSIG) // checksig for pubkey
         .into_script()
 }
+
+/// Starts an OP_VAULT withdrawal once the vault key signs. OP_VAULT replaces this leaf in the output it creates
+/// with `<target hash> <spend delay> OP_CSV OP_DROP OP_CTV`, taking the target template hash from the witness.
+#[cfg(feature = "op-vault")]
+pub(crate) fn op_vault_trigger(x_only_pubkey: XOnlyPublicKey, timelock: TimelockKind) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(&x_only_pubkey) // push vault pubkey
+        .push_opcode(OP_CHECKSIGVERIFY) // checksig for pubkey
+        .push_sequence(timelock.to_sequence()) // the spend delay, the last leaf-update data item
+        .push_int(2) // the target hash under it on the stack is the first
+        .push_slice([OP_CSV.to_u8(), OP_DROP.to_u8(), OP_CTV.to_u8()]) // the leaf-update script body
+        .push_opcode(OP_VAULT)
+        .into_script()
+}
+
+/// The leaf an OP_VAULT trigger leaves in place of [op_vault_trigger]: pays out exactly as `target_hash`
+/// commits to once the spend delay has passed
+#[cfg(feature = "op-vault")]
+pub(crate) fn op_vault_withdrawal(target_hash: [u8; 32], timelock: TimelockKind) -> ScriptBuf {
+    Builder::new()
+        .push_slice(target_hash)
+        .push_sequence(timelock.to_sequence())
+        .push_opcode(OP_CSV) // check relative timelock on withdrawal
+        .push_opcode(OP_DROP) // drop the result
+        .push_opcode(OP_CTV) // check the transaction against the target template
+        .into_script()
+}
+
+/// Sends the funds, triggered or not, to the output whose scriptPubKey hashes to `recovery_spk_hash`.
+/// The recovery key has to sign so nobody else can force the vault into recovery.
+#[cfg(feature = "op-vault")]
+pub(crate) fn op_vault_recover(
+    x_only_pubkey: XOnlyPublicKey,
+    recovery_spk_hash: [u8; 32],
+) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(&x_only_pubkey) // push recovery pubkey
+        .push_opcode(OP_CHECKSIGVERIFY) // checksig for pubkey
+        .push_slice(recovery_spk_hash)
+        .push_opcode(OP_VAULT_RECOVER)
+        .into_script()
+}
+
+/// Sends the funds to the recovery template once the chain reaches `recovery_height`, no signature needed
+pub(crate) fn vault_recovery(recovery_height: u32, ctv_hash: [u8; 32]) -> ScriptBuf {
+    Builder::new()
+        .push_int(recovery_height as i64)
+        .push_opcode(OP_CLTV) // check absolute timelock on recovery
+        .push_opcode(OP_DROP) // drop the result
+        .push_slice(ctv_hash)
+        .push_opcode(OP_CTV)
+        .into_script()
+}
+
+/// The x-only keys a script checks signatures against, i.e. every 32 byte push directly followed by
+/// OP_CHECKSIG, OP_CHECKSIGVERIFY or OP_CHECKSIGADD
+pub(crate) fn checksig_keys(script: &Script) -> Vec<XOnlyPublicKey> {
+    let instructions: Vec<Instruction> = script.instructions().filter_map(|i| i.ok()).collect();
+    instructions
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Instruction::PushBytes(key), Instruction::Op(op)]
+                if *op == OP_CHECKSIG || *op == OP_CHECKSIGVERIFY || *op == OP_CHECKSIGADD =>
+            {
+                XOnlyPublicKey::from_slice(key.as_bytes()).ok()
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The opcodes `script` uses that aren't active on mainnet: OP_CAT is OP_SUCCESS126 there,
+/// which makes the leaf spendable by anyone, and OP_NOP4 does nothing until CTV activates
+pub(crate) fn inactive_opcodes(script: &Script) -> Vec<&'static str> {
+    let mut opcodes = Vec::new();
+    for instruction in script.instructions().filter_map(|i| i.ok()) {
+        let name = match instruction {
+            Instruction::Op(op) if op == OP_CAT => "OP_CAT",
+            Instruction::Op(op) if op == OP_CTV => "OP_CHECKTEMPLATEVERIFY",
+            #[cfg(feature = "op-vault")]
+            Instruction::Op(op) if op == OP_VAULT => "OP_VAULT",
+            #[cfg(feature = "op-vault")]
+            Instruction::Op(op) if op == OP_VAULT_RECOVER => "OP_VAULT_RECOVER",
+            _ => continue,
+        };
+        if !opcodes.contains(&name) {
+            opcodes.push(name);
+        }
+    }
+    opcodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_ranges_cover_the_bytes_in_order() {
+        assert!(witness_chunk_ranges(0).is_empty());
+        assert_eq!(witness_chunk_ranges(1), vec![(0, 1)]);
+        assert_eq!(witness_chunk_ranges(80), vec![(0, 80)]);
+        assert_eq!(witness_chunk_ranges(81), vec![(0, 80), (80, 81)]);
+        assert_eq!(
+            witness_chunk_ranges(200),
+            vec![(0, 80), (80, 160), (160, 200)]
+        );
+        for len in 0..400 {
+            let ranges = witness_chunk_ranges(len);
+            assert_eq!(ranges.len(), witness_chunk_count(len));
+            assert_eq!(ranges.last().map_or(0, |(_, end)| *end), len);
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].1, pair[1].0);
+            }
+            assert!(ranges
+                .iter()
+                .all(|(start, end)| start < end && end - start <= MAX_WITNESS_PUSH_SIZE));
+        }
+    }
+}
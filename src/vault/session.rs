@@ -0,0 +1,125 @@
+use anyhow::Result;
+use bitcoin::{Address, Amount, FeeRate, OutPoint, ScriptBuf, Transaction, TxOut};
+
+use crate::vault::contract::{SpendPath, VaultCovenant};
+
+/// A sequence of builds against one vault. The vault is borrowed for the whole session, so every
+/// build sees the same parameters and reuses the spend infos the first one cached.
+pub(crate) struct VaultBuildSession<'a> {
+    vault: &'a VaultCovenant,
+}
+
+impl<'a> VaultBuildSession<'a> {
+    pub(crate) fn new(vault: &'a VaultCovenant) -> Self {
+        Self { vault }
+    }
+
+    pub(crate) fn vault(&self) -> &'a VaultCovenant {
+        self.vault
+    }
+
+    pub(crate) fn create_path_tx(
+        &self,
+        path: SpendPath,
+        fee_paying_utxo: &OutPoint,
+        fee_paying_output: TxOut,
+        destination: Option<&Address>,
+    ) -> Result<Transaction> {
+        self.vault
+            .create_path_tx(path, fee_paying_utxo, fee_paying_output, destination)
+    }
+
+    pub(crate) fn fee_input_amount(
+        &self,
+        path: SpendPath,
+        fee_rate: FeeRate,
+        fee_script_pubkey: ScriptBuf,
+        destination: Option<&Address>,
+    ) -> Result<Amount> {
+        self.vault
+            .fee_input_amount(path, fee_rate, fee_script_pubkey, destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultState, VaultType, SECP};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, Txid};
+
+    #[test]
+    fn session_builds_match_standalone_builds() {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        let destination =
+            Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest);
+        let fee_output = TxOut {
+            script_pubkey: destination.script_pubkey(),
+            value: Amount::from_sat(50_000),
+        };
+        let fee_utxo = OutPoint::new(Txid::from_byte_array([9; 32]), 0);
+        let mut vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+
+        let trigger_tx = {
+            let session = VaultBuildSession::new(&vault);
+            let built = session
+                .create_path_tx(
+                    SpendPath::Trigger,
+                    &fee_utxo,
+                    fee_output.clone(),
+                    Some(&destination),
+                )
+                .unwrap();
+            let standalone = vault
+                .create_path_tx(
+                    SpendPath::Trigger,
+                    &fee_utxo,
+                    fee_output.clone(),
+                    Some(&destination),
+                )
+                .unwrap();
+            assert_eq!(built, standalone);
+            built
+        };
+
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+        vault.set_trigger_transaction(Some(trigger_tx));
+        vault.set_state(VaultState::Triggered);
+        let session = VaultBuildSession::new(&vault);
+        for path in [SpendPath::Complete, SpendPath::Cancel] {
+            assert_eq!(
+                session
+                    .create_path_tx(path, &fee_utxo, fee_output.clone(), None)
+                    .unwrap(),
+                vault
+                    .create_path_tx(path, &fee_utxo, fee_output.clone(), None)
+                    .unwrap(),
+                "{:?}",
+                path
+            );
+            let fee_rate = FeeRate::from_sat_per_vb_unchecked(10);
+            assert_eq!(
+                session
+                    .fee_input_amount(path, fee_rate, destination.script_pubkey(), None)
+                    .unwrap(),
+                vault
+                    .fee_input_amount(path, fee_rate, destination.script_pubkey(), None)
+                    .unwrap()
+            );
+        }
+    }
+}
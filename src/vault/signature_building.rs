@@ -1,5 +1,7 @@
-use anyhow::Result;
-use bitcoin::absolute::LockTime;
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::{LockTime, LOCK_TIME_THRESHOLD};
 use bitcoin::consensus::Encodable;
 use bitcoin::hashes::{sha256, Hash, HashEngine};
 use bitcoin::hex::{Case, DisplayHex};
@@ -9,6 +11,14 @@ use bitcoin::{Amount, Sequence, TapLeafHash, TapSighash, TapSighashType, Transac
 use lazy_static::lazy_static;
 use log::debug;
 use secp256kfun::G;
+use serde::{Deserialize, Serialize};
+
+use crate::vault::contract::SpendPath;
+
+/// What each CAT trigger marker output pays unless the settings ask for more. The leaves commit to it, so it
+/// can't follow the dust limit of the marker's own script, 546 is the dust limit of P2PKH and covers every
+/// standard output type.
+pub(crate) const MARKER_AMOUNT: Amount = Amount::from_sat(546);
 
 lazy_static! {
     pub(crate) static ref G_X: [u8; 32] = G.into_point_with_even_y().0.to_xonly_bytes();
@@ -24,17 +34,9 @@ lazy_static! {
         tag.copy_from_slice(val);
         tag
     };
-    pub(crate) static ref DUST_AMOUNT: [u8; 8] = {
-        let mut dust = [0u8; 8];
-        let mut buffer = Vec::new();
-        let amount = Amount::from_sat(546);
-        amount.consensus_encode(&mut buffer).unwrap();
-        dust.copy_from_slice(&buffer);
-        dust
-    };
 }
 
-#[derive()]
+#[derive(Debug)]
 pub(crate) struct TxCommitmentSpec {
     pub(crate) epoch: bool,
     pub(crate) control: bool,
@@ -73,6 +75,51 @@ impl Default for TxCommitmentSpec {
     }
 }
 
+impl TxCommitmentSpec {
+    /// The trigger script fills in the outputs, the prevout amounts and scripts and the input index (always 0)
+    /// itself, so that it can check the triggered output and markers
+    pub(crate) fn for_trigger() -> Self {
+        Self {
+            prev_sciptpubkeys: false,
+            prev_amounts: false,
+            input_index: false,
+            outputs: false,
+            ..Default::default()
+        }
+    }
+
+    /// The complete script fills in the outputs and the prevouts itself, so that it can check it spends the
+    /// trigger's outputs and pays the committed destination
+    pub(crate) fn for_complete() -> Self {
+        Self {
+            prevouts: false,
+            outputs: false,
+            ..Default::default()
+        }
+    }
+
+    /// The cancel script fills in the same fields as the trigger script, to check the output it pays back to the vault
+    pub(crate) fn for_cancel() -> Self {
+        Self::for_trigger()
+    }
+
+    /// The fields of a BIP119 standard template hash, which commits to no prevouts or sighash metadata
+    pub(crate) fn for_ctv_template() -> Self {
+        Self {
+            epoch: false,
+            control: false,
+            prevouts: false,
+            prev_amounts: false,
+            prev_sciptpubkeys: false,
+            spend_type: false,
+            annex: false,
+            single_output: false,
+            scriptpath: false,
+            ..Default::default()
+        }
+    }
+}
+
 pub(crate) fn get_sigmsg_components<S: Into<TapLeafHash>>(
     spec: &TxCommitmentSpec,
     tx: &Transaction,
@@ -416,65 +463,394 @@ pub(crate) struct ContractComponents {
     pub(crate) signature_components: Vec<Vec<u8>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) enum GrindField {
     LockTime,
     Sequence,
 }
 
+impl GrindField {
+    /// Every counter value the field can take: lock time heights stay below the timestamp
+    /// threshold, and the sequence counter leaves bit 31 free for the BIP68 disable flag
+    pub(crate) fn counter_range(&self) -> Range<u32> {
+        match self {
+            GrindField::LockTime => 0..LOCK_TIME_THRESHOLD,
+            GrindField::Sequence => 0..1 << 31,
+        }
+    }
+
+    /// The counter values a grind within `bounds` tries
+    pub(crate) fn bounded_range(&self, bounds: GrindBounds) -> Result<Range<u32>> {
+        let range = self.counter_range();
+        if !range.contains(&bounds.start) {
+            return Err(anyhow!(
+                "a {:?} grind can't start at {}, the counter runs {}..{}",
+                self,
+                bounds.start,
+                range.start,
+                range.end
+            ));
+        }
+        let end = match bounds.max_iterations {
+            Some(iterations) => bounds.start.saturating_add(iterations).min(range.end),
+            None => range.end,
+        };
+        Ok(bounds.start..end)
+    }
+}
+
+/// Split `range` into `parts` contiguous ranges, in increasing order
+#[cfg(feature = "parallel-grind")]
+fn partition(range: Range<u32>, parts: usize) -> Vec<Range<u32>> {
+    let parts = parts.clamp(1, range.len().max(1)) as u32;
+    let size = (range.end - range.start).div_ceil(parts);
+    (0..parts)
+        .map(|part| {
+            let start = range.start + part * size;
+            start..(start + size).min(range.end)
+        })
+        .collect()
+}
+
+/// Where a grind starts counting and how many counter values it tries before giving up, so a grind can be
+/// reproduced, resumed or cut short
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub(crate) struct GrindBounds {
+    #[serde(default)]
+    pub(crate) start: u32,
+    /// None tries every value from `start` to the end of the field's range
+    #[serde(default)]
+    pub(crate) max_iterations: Option<u32>,
+}
+
+/// The single source of truth for which field each spend path grinds, the builders and scripts rely on it.
+/// Trigger and cancel grind the lock time. Complete grinds the sequence of its last (fee-paying) input,
+/// its vault input's sequence carries the CSV timelock and can't be ground.
+pub(crate) fn grind_field_for_path(path: SpendPath) -> GrindField {
+    match path {
+        SpendPath::Trigger | SpendPath::Cancel => GrindField::LockTime,
+        SpendPath::Complete => GrindField::Sequence,
+    }
+}
+
+/// Search for a value of `grind_field` that gives the transaction a usable challenge.
+/// The search order is fixed so the same transaction and prevouts always grind to the same value:
+/// the counter starts at `bounds.start` (0 by default) and counts up by one, used directly as the lock time height for
+/// [GrindField::LockTime], or with the BIP68 disable bit (1 << 31) set as the last input's sequence for
+/// [GrindField::Sequence]. The first counter value that works is used, the search fails once `bounds.max_iterations`
+/// values have been tried.
+/// With the `parallel-grind` feature each thread searches one partition of the counter range and
+/// the lowest partition's match wins, which is the same value the serial search finds.
+/// `input_index` is the covenant input whose signature message is ground.
 pub(crate) fn grind_transaction<S>(
     initial_tx: Transaction,
     grind_field: GrindField,
+    bounds: GrindBounds,
+    input_index: usize,
     prevouts: &[TxOut],
     leaf_hash: S,
 ) -> anyhow::Result<ContractComponents>
 where
     S: Into<TapLeafHash> + Clone,
 {
-    let signature_components: Vec<Vec<u8>>;
-    let mut counter = 0;
+    let leaf_hash: TapLeafHash = leaf_hash.into();
+    let range = grind_field.bounded_range(bounds)?;
+    let try_counter = |counter| {
+        try_grind_counter(
+            &initial_tx,
+            &grind_field,
+            counter,
+            input_index,
+            prevouts,
+            leaf_hash,
+        )
+    };
 
+    #[cfg(not(feature = "parallel-grind"))]
+    let found = search_serial(range.clone(), try_counter)?;
+    #[cfg(feature = "parallel-grind")]
+    let found = search_parallel(range.clone(), rayon::current_num_threads(), try_counter)?;
+
+    found.ok_or(anyhow!(
+        "no {:?} value in {}..{} gives a usable challenge",
+        grind_field,
+        range.start,
+        range.end
+    ))
+}
+
+/// The first counter in `range` that `try_counter` accepts
+fn search_serial<T>(
+    range: Range<u32>,
+    try_counter: impl Fn(u32) -> anyhow::Result<Option<T>>,
+) -> anyhow::Result<Option<T>> {
+    for counter in range {
+        if let Some(found) = try_counter(counter)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+/// The same counter [search_serial] finds, with each of `parts` threads searching one partition of `range`
+#[cfg(feature = "parallel-grind")]
+fn search_parallel<T: Send>(
+    range: Range<u32>,
+    parts: usize,
+    try_counter: impl Fn(u32) -> anyhow::Result<Option<T>> + Sync,
+) -> anyhow::Result<Option<T>> {
+    use rayon::prelude::*;
+    let matches = partition(range, parts)
+        .into_par_iter()
+        .map(|part| search_serial(part, &try_counter))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(matches.into_iter().flatten().next())
+}
+
+/// Set `grind_field` to `counter` and keep the transaction if its challenge is usable
+fn try_grind_counter(
+    initial_tx: &Transaction,
+    grind_field: &GrindField,
+    counter: u32,
+    input_index: usize,
+    prevouts: &[TxOut],
+    leaf_hash: TapLeafHash,
+) -> anyhow::Result<Option<ContractComponents>> {
     let mut spend_tx = initial_tx.clone();
+    match grind_field {
+        GrindField::LockTime => spend_tx.lock_time = LockTime::from_height(counter)?,
+        GrindField::Sequence => {
+            // make sure the sequence has the 31st bit set, so that it's not used as a relative timelock
+            // (BIP68 tells us that bit disables the consensus meaning of sequence numbers for RTL)
+            // set the sequence number of the last input, we'll use that to pay fees if there is more than one input
+            spend_tx.input.last_mut().unwrap().sequence =
+                Sequence::from_consensus(counter | 1 << 31);
+        }
+    }
+    debug!("grinding counter {}", counter);
+
+    let components_for_signature = get_sigmsg_components(
+        &TxCommitmentSpec::default(),
+        &spend_tx,
+        input_index,
+        prevouts,
+        None,
+        leaf_hash,
+        TapSighashType::Default,
+    )?;
+    let sigmsg = compute_sigmsg_from_components(&components_for_signature)?;
+    let challenge = compute_challenge(&sigmsg);
+
+    if challenge[31] == 0x7f || challenge[31] == 0xff {
+        return Ok(None);
+    }
+    debug!("Found a challenge with a {} at the end!", challenge[31]);
+    debug!("{:?} is {}", grind_field, counter);
+    debug!(
+        "Here's the challenge: {}",
+        challenge.to_hex_string(Case::Lower)
+    );
+    Ok(Some(ContractComponents {
+        transaction: spend_tx,
+        signature_components: components_for_signature,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::TweakedPublicKey;
+    use bitcoin::transaction::Version;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, Txid, XOnlyPublicKey};
+
+    #[test]
+    fn complete_grinds_the_sequence_and_the_rest_the_lock_time() {
+        // the complete's vault input sequence holds the CSV timelock, so it grinds its fee input's sequence
+        assert_eq!(
+            grind_field_for_path(SpendPath::Complete),
+            GrindField::Sequence
+        );
+        assert_eq!(
+            grind_field_for_path(SpendPath::Trigger),
+            GrindField::LockTime
+        );
+        assert_eq!(
+            grind_field_for_path(SpendPath::Cancel),
+            GrindField::LockTime
+        );
+        assert!(GrindField::Sequence.counter_range().end <= 1 << 31);
+        assert!(GrindField::LockTime.counter_range().end <= LOCK_TIME_THRESHOLD);
+    }
 
-    loop {
-        match grind_field {
-            GrindField::LockTime => spend_tx.lock_time = LockTime::from_height(counter)?,
-            GrindField::Sequence => {
-                // make sure counter has the 31st bit set, so that it's not used as a relative timelock
-                // (BIP68 tells us that bit disables the consensus meaning of sequence numbers for RTL)
-                counter |= 1 << 31;
-                // set the sequence number of the last input to the counter, we'll use that to pay fees if there is more than one input
-                spend_tx.input.last_mut().unwrap().sequence = Sequence::from_consensus(counter);
+    #[cfg(feature = "parallel-grind")]
+    #[test]
+    fn parallel_grind_finds_the_serial_match() {
+        use bitcoin::{ScriptBuf, TxIn};
+
+        let initial_tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new_op_return([1]),
+                value: Amount::from_sat(1_000),
+            }],
+        };
+        let prevouts = vec![
+            TxOut {
+                script_pubkey: ScriptBuf::new_op_return([2]),
+                value: Amount::from_sat(2_000),
+            };
+            2
+        ];
+        for grind_field in [GrindField::LockTime, GrindField::Sequence] {
+            let try_counter = |counter| {
+                try_grind_counter(
+                    &initial_tx,
+                    &grind_field,
+                    counter,
+                    0,
+                    &prevouts,
+                    TapLeafHash::all_zeros(),
+                )
+            };
+            let serial = search_serial(0..64, try_counter).unwrap().unwrap();
+            for parts in 1..8 {
+                let parallel = search_parallel(0..64, parts, try_counter).unwrap().unwrap();
+                assert_eq!(parallel.transaction, serial.transaction);
+                assert_eq!(parallel.signature_components, serial.signature_components);
             }
         }
-        debug!("grinding counter {}", counter);
+        // a match only in the last partition is still found, and the lowest match wins
+        let last = |counter: u32| Ok((counter >= 60).then_some(counter));
+        assert_eq!(search_parallel(0..64, 4, last).unwrap(), Some(60));
+        assert_eq!(search_serial(0..64, last).unwrap(), Some(60));
+    }
 
-        let components_for_signature = get_sigmsg_components(
-            &TxCommitmentSpec::default(),
-            &spend_tx,
+    /// A one-input, one-output transaction and the output it spends, fixed so its grinds are reproducible
+    fn grind_fixture() -> (Transaction, Vec<TxOut>) {
+        let spent = TxOut {
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                XOnlyPublicKey::from_slice(&*G_X).unwrap(),
+            )),
+            value: Amount::from_sat(100_000),
+        };
+        let txn = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            }],
+            output: vec![spent.clone()],
+        };
+        (txn, vec![spent])
+    }
+
+    /// The sigmsg fields a spec leaves for the script to fill in
+    fn left_out(spec: &TxCommitmentSpec) -> Vec<&'static str> {
+        [
+            ("epoch", spec.epoch),
+            ("control", spec.control),
+            ("version", spec.version),
+            ("lock_time", spec.lock_time),
+            ("prevouts", spec.prevouts),
+            ("prev_amounts", spec.prev_amounts),
+            ("prev_sciptpubkeys", spec.prev_sciptpubkeys),
+            ("sequences", spec.sequences),
+            ("input_index", spec.input_index),
+            ("outputs", spec.outputs),
+            ("spend_type", spec.spend_type),
+            ("annex", spec.annex),
+            ("single_output", spec.single_output),
+            ("scriptpath", spec.scriptpath),
+        ]
+        .into_iter()
+        .filter(|(_, included)| !included)
+        .map(|(field, _)| field)
+        .collect()
+    }
+
+    #[test]
+    fn trigger_and_cancel_leave_out_the_outputs_prevout_details_and_input_index() {
+        let expected = [
+            "prev_amounts",
+            "prev_sciptpubkeys",
+            "input_index",
+            "outputs",
+        ];
+        assert_eq!(left_out(&TxCommitmentSpec::for_trigger()), expected);
+        assert_eq!(left_out(&TxCommitmentSpec::for_cancel()), expected);
+    }
+
+    #[test]
+    fn complete_leaves_out_the_prevouts_and_outputs() {
+        assert_eq!(
+            left_out(&TxCommitmentSpec::for_complete()),
+            ["prevouts", "outputs"]
+        );
+    }
+
+    #[test]
+    fn ctv_template_keeps_only_the_bip119_fields() {
+        assert_eq!(
+            left_out(&TxCommitmentSpec::for_ctv_template()),
+            [
+                "epoch",
+                "control",
+                "prevouts",
+                "prev_amounts",
+                "prev_sciptpubkeys",
+                "spend_type",
+                "annex",
+                "single_output",
+                "scriptpath",
+            ]
+        );
+    }
+
+    fn grind_lock_time(bounds: GrindBounds) -> anyhow::Result<ContractComponents> {
+        let (txn, prevouts) = grind_fixture();
+        grind_transaction(
+            txn,
+            GrindField::LockTime,
+            bounds,
             0,
-            prevouts,
-            None,
-            leaf_hash.clone(),
-            TapSighashType::Default,
-        )?;
-        let sigmsg = compute_sigmsg_from_components(&components_for_signature)?;
-        let challenge = compute_challenge(&sigmsg);
-
-        if challenge[31] != 0x7f && challenge[31] != 0xff {
-            debug!("Found a challenge with a {} at the end!", challenge[31]);
-            debug!("{:?} is {}", grind_field, counter);
-            debug!(
-                "Here's the challenge: {}",
-                challenge.to_hex_string(Case::Lower)
-            );
-            signature_components = components_for_signature;
-            break;
-        }
-        counter += 1;
+            &prevouts,
+            TapLeafHash::all_zeros(),
+        )
+    }
+
+    #[test]
+    fn grind_from_a_known_start_finds_a_known_lock_time() {
+        // lock time 378 gives the fixture a challenge ending in 0x7f or 0xff, 379 is the next usable one
+        let bounds = GrindBounds {
+            start: 378,
+            max_iterations: None,
+        };
+        let found = grind_lock_time(bounds).unwrap();
+        assert_eq!(
+            found.transaction.lock_time,
+            LockTime::from_height(379).unwrap()
+        );
+        assert_eq!(
+            grind_lock_time(bounds).unwrap().transaction,
+            found.transaction
+        );
+    }
+
+    #[test]
+    fn grind_fails_once_max_iterations_are_tried() {
+        let bounds = GrindBounds {
+            start: 378,
+            max_iterations: Some(1),
+        };
+        assert!(grind_lock_time(bounds).is_err());
+        let out_of_range = GrindBounds {
+            start: LOCK_TIME_THRESHOLD,
+            max_iterations: None,
+        };
+        assert!(grind_lock_time(out_of_range).is_err());
     }
-    Ok(ContractComponents {
-        transaction: spend_tx,
-        signature_components,
-    })
 }
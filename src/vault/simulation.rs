@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Address, OutPoint, Transaction, TxOut, Txid};
+use serde::Serialize;
+
+use crate::vault::contract::{VaultCovenant, VaultState, VaultType};
+
+/// A transaction of a simulated flow, hex encoded for review
+#[derive(Serialize, Debug)]
+pub(crate) struct SimulatedTx {
+    pub(crate) txid: Txid,
+    pub(crate) hex: String,
+}
+
+impl From<&Transaction> for SimulatedTx {
+    fn from(txn: &Transaction) -> Self {
+        Self {
+            txid: txn.txid(),
+            hex: serialize(txn).to_lower_hex_string(),
+        }
+    }
+}
+
+/// Every transaction a CTV vault can produce, built without a node
+#[derive(Serialize, Debug)]
+pub(crate) struct FlowSimulation {
+    pub(crate) deposit_address: String,
+    pub(crate) template_hash: String,
+    pub(crate) trigger: SimulatedTx,
+    pub(crate) complete: SimulatedTx,
+    /// the cancel of the triggered output
+    pub(crate) cancel: SimulatedTx,
+}
+
+/// A stand-in for an output that doesn't exist yet, `n` tells them apart
+fn placeholder_outpoint(n: u32) -> OutPoint {
+    OutPoint {
+        txid: Txid::all_zeros(),
+        vout: n,
+    }
+}
+
+impl VaultCovenant {
+    /// Build the trigger, complete and cancel of a CTV vault withdrawing to `withdrawal_address`, so the whole
+    /// covenant can be reviewed before it is funded. The vault's own outpoint is used once it has one, the fee
+    /// inputs are placeholders, so only the trigger matches what a broadcast with the same inputs would produce
+    /// and the complete and cancel signatures commit to fee inputs that don't exist.
+    pub(crate) fn simulate_full_flow(
+        &self,
+        withdrawal_address: &Address,
+    ) -> Result<FlowSimulation> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only a CTV vault's transactions are fixed before deposit"
+            ));
+        }
+        self.require_network(withdrawal_address)?;
+        // enough for the cancel to refill what a trigger fee took out of the vault
+        let placeholder_fee_output = TxOut {
+            script_pubkey: withdrawal_address.script_pubkey(),
+            value: self.get_amount() - self.triggered_amount(),
+        };
+        let mut vault = self.counterpart(VaultType::CTV);
+        vault.set_current_outpoint(
+            self.get_current_outpoint()
+                .unwrap_or(placeholder_outpoint(0)),
+        );
+        let fee_outpoint = placeholder_outpoint(1);
+        let trigger =
+            vault.create_ctv_trigger_tx(vault.trigger_fee.is_none().then_some(&fee_outpoint))?;
+
+        vault.set_current_outpoint(OutPoint {
+            txid: trigger.txid(),
+            vout: 0,
+        });
+        vault.set_withdrawal_address(Some(withdrawal_address.clone()))?;
+        vault.set_trigger_transaction(Some(trigger.clone()));
+        vault.set_state(VaultState::Triggered);
+        let complete = vault
+            .create_ctv_complete_tx(&placeholder_outpoint(2), placeholder_fee_output.clone())?;
+        let cancel =
+            vault.create_ctv_cancel_tx(&placeholder_outpoint(3), placeholder_fee_output)?;
+        Ok(FlowSimulation {
+            deposit_address: self.address()?.to_string(),
+            template_hash: self.ctv_template_hash()?.to_lower_hex_string(),
+            trigger: (&trigger).into(),
+            complete: (&complete).into(),
+            cancel: (&cancel).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hex::FromHex;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Amount, Network};
+
+    fn seeded_vault(vault_type: VaultType) -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    fn withdrawal_address() -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    #[test]
+    fn simulated_trigger_matches_the_broadcast_one() {
+        let mut vault = seeded_vault(VaultType::CTV);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let simulation = vault.simulate_full_flow(&withdrawal_address()).unwrap();
+
+        let trigger_tx = vault
+            .create_ctv_trigger_tx(Some(&placeholder_outpoint(1)))
+            .unwrap();
+        assert_eq!(simulation.trigger.txid, trigger_tx.txid());
+        assert_eq!(
+            simulation.deposit_address,
+            vault.address().unwrap().to_string()
+        );
+        for spend in [&simulation.complete, &simulation.cancel] {
+            let txn: Transaction = deserialize(&Vec::from_hex(&spend.hex).unwrap()).unwrap();
+            assert_eq!(txn.txid(), spend.txid);
+            assert_eq!(
+                txn.input[0].previous_output,
+                OutPoint::new(trigger_tx.txid(), 0)
+            );
+        }
+    }
+
+    #[test]
+    fn only_ctv_vaults_are_simulated() {
+        assert!(seeded_vault(VaultType::CAT)
+            .simulate_full_flow(&withdrawal_address())
+            .is_err());
+    }
+}
@@ -0,0 +1,1025 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::{Height, Time};
+use bitcoin::{Address, Amount, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::{Client, RpcApi};
+use log::{debug, info, warn};
+
+use crate::vault::contract::{VaultCovenant, VaultState, VaultType};
+use crate::vault::metrics::VaultEvent;
+
+/// The confirmed value of the vault output next to the amount the vault has recorded
+#[derive(Debug, PartialEq)]
+pub(crate) enum Balance {
+    Matches(Amount),
+    /// the covenant scripts commit to the recorded amount, so a vault in this state can't be spent
+    Differs {
+        onchain: Amount,
+        recorded: Amount,
+    },
+    /// the vault output has fewer than the vault's min_confirmations, a reorg could still undo it
+    Pending {
+        onchain: Amount,
+        confirmations: u32,
+    },
+}
+
+/// Where the unspent outputs a transaction is about to spend are looked up
+pub(crate) trait UtxoLookup {
+    /// The output if it's unspent in the chain or mempool, None if it's spent or never existed
+    fn unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>>;
+}
+
+impl UtxoLookup for Client {
+    fn unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+        self.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .map(|utxo| {
+                Ok(TxOut {
+                    value: utxo.value,
+                    script_pubkey: utxo.script_pub_key.script()?,
+                })
+            })
+            .transpose()
+    }
+}
+
+/// Where the confirmed value of the vault output is looked up
+pub(crate) trait ConfirmedOutputLookup {
+    /// The value and confirmations of the output if it's unspent in the chain, None if it's spent or unconfirmed
+    fn confirmed_output(&self, outpoint: &OutPoint) -> Result<Option<(Amount, u32)>>;
+}
+
+impl ConfirmedOutputLookup for Client {
+    fn confirmed_output(&self, outpoint: &OutPoint) -> Result<Option<(Amount, u32)>> {
+        Ok(self
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+            .map(|utxo| (utxo.value, utxo.confirmations)))
+    }
+}
+
+/// Where the confirmations of the transaction deciding a vault's state are looked up
+pub(crate) trait ConfirmationLookup {
+    /// How many blocks have confirmed `txid`, 0 while it's only in the mempool
+    fn confirmations(&self, txid: &Txid) -> Result<u32>;
+}
+
+impl ConfirmationLookup for Client {
+    fn confirmations(&self, txid: &Txid) -> Result<u32> {
+        Ok(self
+            .get_raw_transaction_info(txid, None)?
+            .confirmations
+            .unwrap_or(0))
+    }
+}
+
+/// A vault state as the node reports it
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum ChainState {
+    /// the transaction deciding it has at least the vault's min_confirmations
+    Final(VaultState),
+    /// the transaction deciding it could still be reorged out
+    Pending(VaultState),
+}
+
+/// How long to wait between polls of the node
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+impl VaultCovenant {
+    /// The vault state according to the node along with the confirmations of the transaction deciding it.
+    /// That's the transaction spending the current outpoint if it's spent, otherwise the one creating it.
+    /// Transactions are matched against the recorded trigger transaction and the withdrawal address
+    /// instead of being guessed at from the shape of their outputs. The state is Pending until that transaction
+    /// has the vault's min_confirmations.
+    pub(crate) fn verify_state(&self, client: &Client) -> Result<(ChainState, u32)> {
+        // a completed vault has no outpoint left to look at, only the transaction that completed it
+        if let Some(txid) = self.completed_txid() {
+            return self.confirmed_state(client, &txid, VaultState::Completed);
+        }
+        let outpoint = self.get_current_outpoint()?;
+        let unspent = client
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .is_some();
+        let (deciding_tx, state) = if unspent {
+            let creating_tx = client.get_raw_transaction(&outpoint.txid, None)?;
+            let state = self.outpoint_creator_state(&creating_tx, outpoint.vout)?;
+            (creating_tx, state)
+        } else {
+            let spending_tx = find_spending_transaction(client, &outpoint)?.ok_or(anyhow!(
+                "vault outpoint {} is spent but the spending transaction wasn't found",
+                outpoint
+            ))?;
+            let state = self.outpoint_spender_state(&spending_tx)?;
+            (spending_tx, state)
+        };
+        self.confirmed_state(client, &deciding_tx.txid(), state)
+    }
+
+    /// `state` as decided by `txid`, final or pending depending on the confirmations `lookup` reports for it,
+    /// along with those confirmations
+    pub(crate) fn confirmed_state(
+        &self,
+        lookup: &impl ConfirmationLookup,
+        txid: &Txid,
+        state: VaultState,
+    ) -> Result<(ChainState, u32)> {
+        let confirmations = lookup.confirmations(txid)?;
+        debug!(
+            "vault transaction {} puts the vault in {:?} with {} confirmations",
+            txid, state, confirmations
+        );
+        Ok((self.chain_state(state, confirmations), confirmations))
+    }
+
+    /// `state` as final once its deciding transaction has the vault's min_confirmations, pending before that
+    pub(crate) fn chain_state(&self, state: VaultState, confirmations: u32) -> ChainState {
+        if confirmations >= self.min_confirmations() {
+            ChainState::Final(state)
+        } else {
+            ChainState::Pending(state)
+        }
+    }
+
+    /// Wait for `txid` to get the vault's min_confirmations. Returns false if it drops out of the mempool and the
+    /// chain first, e.g. replaced or reorged out. Gives up with an error once `timeout` has elapsed or `cancel` has been set.
+    pub(crate) fn await_confirmations(
+        &self,
+        client: &Client,
+        txid: &Txid,
+        timeout: Duration,
+        cancel: &AtomicBool,
+    ) -> Result<bool> {
+        let waiting_for = format!("{} confirmations of {}", self.min_confirmations(), txid);
+        poll_until(timeout, cancel, &waiting_for, || {
+            match client.get_raw_transaction_info(txid, None) {
+                Ok(info) => Ok(
+                    (info.confirmations.unwrap_or(0) >= self.min_confirmations()).then_some(true),
+                ),
+                Err(_) => Ok(Some(false)),
+            }
+        })
+    }
+
+    /// Wait until the state the node reports is final and return it with the confirmations of its deciding transaction.
+    /// Gives up with an error once `timeout` has elapsed or `cancel` has been set.
+    pub(crate) fn sync_state(
+        &self,
+        client: &Client,
+        timeout: Duration,
+        cancel: &AtomicBool,
+    ) -> Result<(VaultState, u32)> {
+        poll_until(timeout, cancel, "vault transaction confirmation", || {
+            let (state, confirmations) = self.verify_state(client)?;
+            Ok(match state {
+                ChainState::Final(state) => Some((state, confirmations)),
+                ChainState::Pending(_) => None,
+            })
+        })
+    }
+
+    /// Fetch the trigger transaction from the node when the vault file has lost it, e.g. after a crash between
+    /// broadcasting the trigger and saving the vault. The trigger is the transaction creating the current outpoint
+    /// if that is unspent, otherwise the one spending it. It is only accepted if it spends a vault output through
+    /// the trigger leaf and creates the triggered output, and for a CAT vault the withdrawal address is restored
+    /// from the destination it commits to.
+    pub(crate) fn recover_trigger_transaction(&mut self, client: &Client) -> Result<Transaction> {
+        let outpoint = self.get_current_outpoint()?;
+        let unspent = client
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .is_some();
+        let trigger_tx = if unspent {
+            client.get_raw_transaction(&outpoint.txid, None)?
+        } else {
+            find_spending_transaction(client, &outpoint)?.ok_or(anyhow!(
+                "vault outpoint {} is spent but the spending transaction wasn't found",
+                outpoint
+            ))?
+        };
+        self.accept_recovered_trigger(trigger_tx)
+    }
+
+    /// Take `trigger_tx` as the vault's trigger if it spends through the trigger leaf and creates the triggered
+    /// output, moving the vault onto that output
+    fn accept_recovered_trigger(&mut self, trigger_tx: Transaction) -> Result<Transaction> {
+        let trigger_leaf_name = match self.get_type() {
+            VaultType::CAT => "trigger",
+            VaultType::CTV => "deposit",
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => "trigger",
+        };
+        let trigger_leaf = self
+            .named_leaves()?
+            .into_iter()
+            .find_map(|(name, script)| (name == trigger_leaf_name).then_some(script))
+            .ok_or(anyhow!("the vault has no {} leaf", trigger_leaf_name))?;
+        if !trigger_tx
+            .input
+            .iter()
+            .any(|input| input.witness.tapscript() == Some(trigger_leaf.as_script()))
+        {
+            return Err(anyhow!(
+                "{} doesn't spend the vault through its trigger leaf",
+                trigger_tx.txid()
+            ));
+        }
+        let triggered_output = self.triggered_output()?;
+        let vout = trigger_tx
+            .output
+            .iter()
+            .position(|output| *output == triggered_output)
+            .ok_or(anyhow!(
+                "{} doesn't pay {} into the triggered vault output",
+                trigger_tx.txid(),
+                triggered_output.value
+            ))?;
+        self.set_current_outpoint(OutPoint {
+            txid: trigger_tx.txid(),
+            vout: vout as u32,
+        });
+        self.set_trigger_transaction(Some(trigger_tx.clone()));
+        self.set_state(VaultState::Triggered);
+        if self.get_type() == VaultType::CAT {
+            let destination = Self::trigger_committed_destination(&trigger_tx)?;
+            self.set_withdrawal_address(Some(Address::from_script(
+                &destination,
+                self.get_network(),
+            )?))?;
+        }
+        self.verify_trigger_consistency()?;
+        info!("recovered trigger transaction {}", trigger_tx.txid());
+        Ok(trigger_tx)
+    }
+
+    /// The state of a vault whose current outpoint is the unspent output `vout` of `creating_tx`
+    fn outpoint_creator_state(&self, creating_tx: &Transaction, vout: u32) -> Result<VaultState> {
+        let output = creating_tx.output.get(vout as usize).ok_or(anyhow!(
+            "vault outpoint {}:{} doesn't exist",
+            creating_tx.txid(),
+            vout
+        ))?;
+        if self.is_recorded_trigger(creating_tx)
+            || (self.get_type() == VaultType::CTV
+                && output.script_pubkey == self.triggered_output()?.script_pubkey)
+        {
+            return Ok(VaultState::Triggered);
+        }
+        if output.script_pubkey == self.address()?.script_pubkey() {
+            return Ok(VaultState::Inactive);
+        }
+        if self.pays_withdrawal_address(creating_tx) || self.get_state() == VaultState::Completed {
+            return Ok(VaultState::Completed);
+        }
+        Err(anyhow!(
+            "vault outpoint {}:{} pays neither the vault nor the withdrawal address",
+            creating_tx.txid(),
+            vout
+        ))
+    }
+
+    /// The state a vault is in once its current outpoint has been spent by `spending_tx`
+    pub(crate) fn outpoint_spender_state(&self, spending_tx: &Transaction) -> Result<VaultState> {
+        if self.is_recorded_trigger(spending_tx) {
+            return Ok(VaultState::Triggered);
+        }
+        // the leaf it went through comes first, a CAT trigger's first marker pays the withdrawal address too,
+        // whatever the marker amount
+        if let Ok(state) = self.spent_leaf_state(spending_tx) {
+            return Ok(state);
+        }
+        if self.pays_withdrawal_address(spending_tx) {
+            return Ok(VaultState::Completed);
+        }
+        Err(anyhow!(
+            "vault outpoint was spent by {}, which this vault didn't build",
+            spending_tx.txid()
+        ))
+    }
+
+    /// The state `spending_tx` puts the vault in, from the vault leaf script one of its inputs reveals.
+    /// Unlike the shape of the outputs this doesn't depend on change, marker values or where the vault input is.
+    pub(crate) fn spent_leaf_state(&self, spending_tx: &Transaction) -> Result<VaultState> {
+        let leaves = self.named_leaves()?;
+        let name = spending_tx
+            .input
+            .iter()
+            .filter_map(|input| input.witness.tapscript())
+            .find_map(|script| {
+                leaves
+                    .iter()
+                    .find_map(|(name, leaf)| (leaf.as_script() == script).then_some(*name))
+            })
+            .ok_or(anyhow!(
+                "{} doesn't spend any of the vault's leaves",
+                spending_tx.txid()
+            ))?;
+        Ok(match name {
+            "trigger" | "deposit" => VaultState::Triggered,
+            "cancel" => VaultState::Inactive,
+            // the complete, the OP_VAULT withdrawal and both recoveries take the funds out of the vault
+            _ => VaultState::Completed,
+        })
+    }
+
+    pub(crate) fn is_recorded_trigger(&self, txn: &Transaction) -> bool {
+        self.get_trigger_transaction()
+            .is_ok_and(|trigger_tx| trigger_tx.txid() == txn.txid())
+    }
+
+    fn pays_withdrawal_address(&self, txn: &Transaction) -> bool {
+        self.get_withdrawal_address().is_ok_and(|address| {
+            txn.output
+                .iter()
+                .any(|output| output.script_pubkey == address.script_pubkey())
+        })
+    }
+
+    /// Watch the vault's current outpoint until it is spent and return the transaction that spent it.
+    /// Gives up with an error once `timeout` has elapsed or `cancel` has been set.
+    pub(crate) fn monitor(
+        &self,
+        client: &Client,
+        timeout: Duration,
+        cancel: &AtomicBool,
+    ) -> Result<Transaction> {
+        let outpoint = self.get_current_outpoint()?;
+        info!("monitoring vault outpoint {} for spends", outpoint);
+        poll_until(timeout, cancel, "a vault spend", || {
+            if client
+                .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+                .is_some()
+            {
+                return Ok(None);
+            }
+            find_spending_transaction(client, &outpoint)
+        })
+    }
+
+    /// Wait for a UTXO paying the vault's deposit address exactly the vault amount to reach `min_confs`
+    /// confirmations and make it the vault's current outpoint.
+    /// For CTV vaults this is the only deposit the trigger template can spend.
+    /// The UTXO set is scanned once for a deposit made before the call, after that only new mempool transactions
+    /// and new blocks are looked at. Gives up with an error once `timeout` has elapsed or `cancel` has been set.
+    pub(crate) fn await_deposit(
+        &mut self,
+        client: &Client,
+        min_confs: u32,
+        timeout: Duration,
+        cancel: &AtomicBool,
+    ) -> Result<OutPoint> {
+        let script_pubkey = self.address()?.script_pubkey();
+        let amount = self.get_amount();
+        let descriptors = [ScanTxOutRequest::Single(format!(
+            "addr({})",
+            self.address()?
+        ))];
+        // walks the whole UTXO set, which can take minutes on mainnet, so it only runs once
+        let mut deposit = client
+            .scan_tx_out_set_blocking(&descriptors)?
+            .unspents
+            .iter()
+            .find(|utxo| utxo.amount == amount)
+            .map(|utxo| OutPoint {
+                txid: utxo.txid,
+                vout: utxo.vout,
+            });
+        let mut scanned_height = client.get_block_count()?;
+        let mut seen_in_mempool = HashSet::new();
+        let outpoint = poll_until(timeout, cancel, "vault deposit", || {
+            if deposit.is_none() {
+                let mut new_txs = Vec::new();
+                for txid in client.get_raw_mempool()? {
+                    if seen_in_mempool.insert(txid) {
+                        new_txs.push(client.get_raw_transaction(&txid, None)?);
+                    }
+                }
+                let tip = client.get_block_count()?;
+                for height in scanned_height + 1..=tip {
+                    new_txs.extend(client.get_block(&client.get_block_hash(height)?)?.txdata);
+                }
+                scanned_height = tip;
+                deposit = find_deposit(&new_txs, &script_pubkey, amount);
+            }
+            let Some(outpoint) = deposit else {
+                return Ok(None);
+            };
+            match client.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))? {
+                Some(utxo) => Ok((utxo.confirmations >= min_confs).then_some(outpoint)),
+                None => {
+                    // replaced or spent before it was deep enough, keep looking
+                    debug!("deposit {} disappeared", outpoint);
+                    deposit = None;
+                    Ok(None)
+                }
+            }
+        })?;
+        info!("found vault deposit {}", outpoint);
+        self.set_current_outpoint(outpoint);
+        self.record_event(VaultEvent::Created);
+        Ok(outpoint)
+    }
+
+    /// Look up the current vault output in the chain and compare its value with the recorded amount.
+    /// Errors if the output is spent or not confirmed yet.
+    pub(crate) fn balance(&self, lookup: &impl ConfirmedOutputLookup) -> Result<Balance> {
+        if self.get_state() == VaultState::Completed {
+            return Err(anyhow!("a completed vault has no vault output"));
+        }
+        let outpoint = self.get_current_outpoint()?;
+        let (onchain, confirmations) = lookup.confirmed_output(&outpoint)?.ok_or(anyhow!(
+            "vault output {} is spent or not confirmed",
+            outpoint
+        ))?;
+        if confirmations < self.min_confirmations() {
+            return Ok(Balance::Pending {
+                onchain,
+                confirmations,
+            });
+        }
+        if onchain == self.get_amount() {
+            return Ok(Balance::Matches(onchain));
+        }
+        warn!(
+            "vault output {} holds {} but the vault recorded {}",
+            outpoint,
+            onchain,
+            self.get_amount()
+        );
+        Ok(Balance::Differs {
+            onchain,
+            recorded: self.get_amount(),
+        })
+    }
+
+    /// Check that a fee input is still unspent (in the chain or mempool) and pays what the caller expects
+    /// before it's committed to in a covenant transaction
+    pub(crate) fn validate_fee_input_live(
+        &self,
+        utxos: &impl UtxoLookup,
+        outpoint: &OutPoint,
+        expected: &TxOut,
+    ) -> Result<()> {
+        let utxo = utxos
+            .unspent_output(outpoint)?
+            .ok_or(anyhow!("fee input {} is spent or does not exist", outpoint))?;
+        if utxo.value != expected.value {
+            return Err(anyhow!(
+                "fee input {} is worth {}, expected {}",
+                outpoint,
+                utxo.value,
+                expected.value
+            ));
+        }
+        if utxo.script_pubkey != expected.script_pubkey {
+            return Err(anyhow!(
+                "fee input {} pays to {}, expected {}",
+                outpoint,
+                utxo.script_pubkey,
+                expected.script_pubkey
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Call `poll` every [POLL_INTERVAL] until it returns a value.
+/// Errors if `timeout` elapses or `cancel` is set first.
+pub(crate) fn poll_until<T>(
+    timeout: Duration,
+    cancel: &AtomicBool,
+    waiting_for: &str,
+    mut poll: impl FnMut() -> Result<Option<T>>,
+) -> Result<T> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(anyhow!("cancelled while waiting for {}", waiting_for));
+        }
+        if let Some(value) = poll()? {
+            return Ok(value);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(anyhow!(
+                "timed out after {:?} waiting for {}",
+                timeout,
+                waiting_for
+            ));
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// The first output of `txs` paying `amount` to `script_pubkey`
+fn find_deposit(
+    txs: &[Transaction],
+    script_pubkey: &ScriptBuf,
+    amount: Amount,
+) -> Option<OutPoint> {
+    txs.iter().find_map(|tx| {
+        tx.output
+            .iter()
+            .position(|output| output.script_pubkey == *script_pubkey && output.value == amount)
+            .map(|vout| OutPoint {
+                txid: tx.txid(),
+                vout: vout as u32,
+            })
+    })
+}
+
+/// Refuse a transaction the next block couldn't include yet because of its absolute lock time.
+/// The trigger and cancel grind their lock time as a block height, which a short chain may not have reached.
+pub(crate) fn require_final(client: &Client, txn: &Transaction) -> Result<()> {
+    let tip = client.get_block_header_info(&client.get_best_block_hash()?)?;
+    let median_time = tip
+        .median_time
+        .ok_or(anyhow!("the node didn't report a median time past"))?;
+    // final in the next block means a lock time below that block's height or below the tip's median time past
+    let height = Height::from_consensus(tip.height as u32)?;
+    let time = Time::from_consensus(median_time as u32 - 1)?;
+    if !txn.is_absolute_timelock_satisfied(height, time) {
+        return Err(anyhow!(
+            "transaction {} is locked until {}, it can't be mined in block {}",
+            txn.txid(),
+            txn.lock_time,
+            tip.height + 1
+        ));
+    }
+    Ok(())
+}
+
+/// Find the transaction spending `outpoint`, looking in the mempool first and then in every block from the one
+/// confirming `outpoint` to the tip
+pub(crate) fn find_spending_transaction(
+    client: &Client,
+    outpoint: &OutPoint,
+) -> Result<Option<Transaction>> {
+    let spends_outpoint = |tx: &Transaction| {
+        tx.input
+            .iter()
+            .any(|input| input.previous_output == *outpoint)
+    };
+
+    for txid in client.get_raw_mempool()? {
+        let tx = client.get_raw_transaction(&txid, None)?;
+        if spends_outpoint(&tx) {
+            return Ok(Some(tx));
+        }
+    }
+
+    // an outpoint created in the mempool can only be spent in the mempool
+    let Some(created_in) = client
+        .get_raw_transaction_info(&outpoint.txid, None)?
+        .blockhash
+    else {
+        return Ok(None);
+    };
+    let created_at = client.get_block_header_info(&created_in)?.height as u64;
+    let tip = client.get_block_count()?;
+    for height in created_at..=tip {
+        let block = client.get_block(&client.get_block_hash(height)?)?;
+        if let Some(tx) = block.txdata.into_iter().find(|tx| spends_outpoint(tx)) {
+            return Ok(Some(tx));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn poll_until_times_out_within_the_bound() {
+        let timeout = Duration::from_millis(300);
+        let started = Instant::now();
+        // a node that answers slowly and never has what we wait for
+        let result = poll_until(timeout, &AtomicBool::new(false), "a slow node", || {
+            thread::sleep(Duration::from_millis(50));
+            Ok(None::<()>)
+        });
+        let elapsed = started.elapsed();
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("timed out after"));
+        assert!(elapsed >= timeout);
+        assert!(elapsed < timeout + POLL_INTERVAL);
+    }
+
+    #[test]
+    fn poll_until_stops_once_cancelled() {
+        let cancel = AtomicBool::new(false);
+        let mut polls = 0;
+        let result = poll_until(Duration::from_secs(60), &cancel, "a cancelled wait", || {
+            polls += 1;
+            cancel.store(true, Ordering::Relaxed);
+            Ok(None::<()>)
+        });
+        assert!(result.unwrap_err().to_string().starts_with("cancelled"));
+        assert_eq!(polls, 1);
+    }
+
+    #[test]
+    fn deposit_is_the_output_paying_the_exact_amount() {
+        let address = ScriptBuf::new_op_return([1]);
+        let output = |script_pubkey: &ScriptBuf, sats| TxOut {
+            script_pubkey: script_pubkey.clone(),
+            value: Amount::from_sat(sats),
+        };
+        let tx = |output| Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output,
+        };
+        let other = ScriptBuf::new_op_return([2]);
+        let short = tx(vec![output(&address, 99_999)]);
+        let elsewhere = tx(vec![output(&other, 100_000)]);
+        let deposit = tx(vec![output(&other, 5), output(&address, 100_000)]);
+        let amount = Amount::from_sat(100_000);
+        assert_eq!(
+            find_deposit(&[short.clone(), elsewhere.clone()], &address, amount),
+            None
+        );
+        assert_eq!(
+            find_deposit(&[short, elsewhere, deposit.clone()], &address, amount),
+            Some(OutPoint {
+                txid: deposit.txid(),
+                vout: 1
+            })
+        );
+    }
+
+    #[test]
+    fn poll_until_returns_the_first_value() {
+        let mut polls = 0;
+        let value = poll_until(
+            Duration::from_secs(60),
+            &AtomicBool::new(false),
+            "a value",
+            || {
+                polls += 1;
+                Ok((polls == 3).then_some(polls))
+            },
+        )
+        .unwrap();
+        assert_eq!(value, 3);
+    }
+
+    /// A transaction spending a vault leaf with a placeholder signature and control block
+    fn spending(script: &ScriptBuf) -> Transaction {
+        Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                witness: bitcoin::Witness::from_slice(&[
+                    vec![1; 64],
+                    script.to_bytes(),
+                    vec![0xc0; 33],
+                ]),
+                ..Default::default()
+            }],
+            output: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn spent_leaf_gives_the_state() {
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let vault = VaultCovenant::new_seeded(
+                vault_type.clone(),
+                crate::vault::timelock::TimelockKind::Blocks(10),
+                Amount::from_sat(100_000),
+                &crate::settings::Settings::default(),
+                7,
+            )
+            .unwrap();
+            for (name, script) in vault.named_leaves().unwrap() {
+                let expected = match name {
+                    "trigger" | "deposit" => VaultState::Triggered,
+                    "cancel" => VaultState::Inactive,
+                    "complete" => VaultState::Completed,
+                    other => panic!("unexpected {:?} leaf {}", vault_type, other),
+                };
+                assert_eq!(
+                    vault.spent_leaf_state(&spending(&script)).unwrap(),
+                    expected
+                );
+            }
+            assert!(vault
+                .spent_leaf_state(&spending(&ScriptBuf::new_op_return([1])))
+                .is_err());
+        }
+    }
+
+    /// The node's unspent outputs, spending one removes it
+    struct UtxoSet(std::collections::HashMap<OutPoint, TxOut>);
+
+    impl UtxoLookup for UtxoSet {
+        fn unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+            Ok(self.0.get(outpoint).cloned())
+        }
+    }
+
+    fn fee_input() -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+            TxOut {
+                script_pubkey: ScriptBuf::new_op_return([9]),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    fn seeded_vault() -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            VaultType::CAT,
+            crate::vault::timelock::TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &crate::settings::Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn spent_fee_input_is_rejected() {
+        let vault = seeded_vault();
+        let (outpoint, output) = fee_input();
+        let mut utxos = UtxoSet([(outpoint, output.clone())].into());
+        vault
+            .validate_fee_input_live(&utxos, &outpoint, &output)
+            .unwrap();
+        utxos.0.remove(&outpoint);
+        let error = vault
+            .validate_fee_input_live(&utxos, &outpoint, &output)
+            .unwrap_err();
+        assert!(error.to_string().contains("is spent"), "{}", error);
+    }
+
+    #[test]
+    fn missing_fee_input_is_rejected() {
+        let vault = seeded_vault();
+        let (outpoint, output) = fee_input();
+        let utxos = UtxoSet([(outpoint, output.clone())].into());
+        let missing = OutPoint::new(outpoint.txid, 1);
+        let error = vault
+            .validate_fee_input_live(&utxos, &missing, &output)
+            .unwrap_err();
+        assert!(error.to_string().contains("does not exist"), "{}", error);
+    }
+
+    /// Confirmation counts the node reports, by transaction
+    struct Confirmations(std::collections::HashMap<Txid, u32>);
+
+    impl ConfirmationLookup for Confirmations {
+        fn confirmations(&self, txid: &Txid) -> Result<u32> {
+            Ok(self.0.get(txid).copied().unwrap_or(0))
+        }
+    }
+
+    #[test]
+    fn trigger_is_pending_until_it_has_min_confirmations() {
+        let mut vault = seeded_vault();
+        vault.set_min_confirmations(6);
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination = vault.address().unwrap();
+        let (outpoint, mut output) = fee_input();
+        output.script_pubkey = destination.script_pubkey();
+        let trigger_tx = vault
+            .create_trigger_tx(&[(outpoint, output)], &[destination])
+            .unwrap()
+            .transaction;
+        let state = vault.outpoint_spender_state(&trigger_tx).unwrap();
+        assert_eq!(state, VaultState::Triggered);
+
+        let txid = trigger_tx.txid();
+        for (confirmations, expected) in [
+            (0, ChainState::Pending(VaultState::Triggered)),
+            (5, ChainState::Pending(VaultState::Triggered)),
+            (6, ChainState::Final(VaultState::Triggered)),
+        ] {
+            let lookup = Confirmations([(txid, confirmations)].into());
+            assert_eq!(
+                vault
+                    .confirmed_state(&lookup, &txid, state.clone())
+                    .unwrap(),
+                (expected, confirmations)
+            );
+        }
+    }
+
+    fn regtest_address(byte: u8) -> bitcoin::Address {
+        let keypair =
+            bitcoin::key::Keypair::from_seckey_slice(&crate::vault::contract::SECP, &[byte; 32])
+                .unwrap();
+        bitcoin::Address::p2tr(
+            &crate::vault::contract::SECP,
+            keypair.x_only_public_key().0,
+            None,
+            bitcoin::Network::Regtest,
+        )
+    }
+
+    fn funded_vault(vault_type: VaultType, settings: &crate::settings::Settings) -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            vault_type,
+            crate::vault::timelock::TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            settings,
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    #[test]
+    fn real_vault_transactions_are_classified_by_their_leaf() {
+        let settings = crate::settings::Settings {
+            fee_change: true,
+            ..crate::settings::Settings::default()
+        };
+        let destination = regtest_address(2);
+        let (fee_outpoint, fee_output) = fee_input();
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            // a vault that didn't record the trigger, it only has the leaves to go by
+            let observer = funded_vault(vault_type.clone(), &settings);
+            let mut vault = funded_vault(vault_type.clone(), &settings);
+            let trigger_tx = if vault_type == VaultType::CAT {
+                // a trigger paying change, so it has more outputs than the vault and its marker
+                let trigger_tx = vault
+                    .create_trigger_tx_with_change(
+                        &[(fee_outpoint, fee_output.clone())],
+                        std::slice::from_ref(&destination),
+                        &regtest_address(4),
+                        bitcoin::FeeRate::from_sat_per_vb(1).unwrap(),
+                    )
+                    .unwrap()
+                    .transaction;
+                assert_eq!(trigger_tx.output.len(), 3);
+                trigger_tx
+            } else {
+                vault.create_ctv_trigger_tx(Some(&fee_outpoint)).unwrap()
+            };
+            assert_eq!(
+                observer.outpoint_spender_state(&trigger_tx).unwrap(),
+                VaultState::Triggered
+            );
+
+            vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+            vault
+                .set_withdrawal_address(Some(destination.clone()))
+                .unwrap();
+            vault.set_trigger_transaction(Some(trigger_tx));
+            vault.set_state(VaultState::Triggered);
+            for (path, expected) in [
+                (
+                    crate::vault::contract::SpendPath::Complete,
+                    VaultState::Completed,
+                ),
+                (
+                    crate::vault::contract::SpendPath::Cancel,
+                    VaultState::Inactive,
+                ),
+            ] {
+                let spending_tx = vault
+                    .create_path_tx(path, &fee_outpoint, fee_output.clone(), None)
+                    .unwrap();
+                assert_eq!(
+                    observer.spent_leaf_state(&spending_tx).unwrap(),
+                    expected,
+                    "{:?} {:?}",
+                    vault_type,
+                    path
+                );
+            }
+        }
+
+        // a transaction paying somewhere else through none of the leaves
+        let unrelated = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: regtest_address(5).script_pubkey(),
+                value: Amount::from_sat(100_000),
+            }],
+        };
+        assert!(funded_vault(VaultType::CAT, &settings)
+            .outpoint_spender_state(&unrelated)
+            .is_err());
+    }
+
+    /// The confirmed outputs the node knows about, with their confirmations
+    struct ConfirmedOutputs(std::collections::HashMap<OutPoint, (Amount, u32)>);
+
+    impl ConfirmedOutputLookup for ConfirmedOutputs {
+        fn confirmed_output(&self, outpoint: &OutPoint) -> Result<Option<(Amount, u32)>> {
+            Ok(self.0.get(outpoint).copied())
+        }
+    }
+
+    #[test]
+    fn balance_reports_a_wrongly_recorded_amount() {
+        let mut vault = seeded_vault();
+        let outpoint = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        vault.set_current_outpoint(outpoint);
+        let chain = ConfirmedOutputs([(outpoint, (Amount::from_sat(100_000), 3))].into());
+        assert_eq!(
+            vault.balance(&chain).unwrap(),
+            Balance::Matches(Amount::from_sat(100_000))
+        );
+
+        vault.set_amount(Amount::from_sat(90_000));
+        assert_eq!(
+            vault.balance(&chain).unwrap(),
+            Balance::Differs {
+                onchain: Amount::from_sat(100_000),
+                recorded: Amount::from_sat(90_000),
+            }
+        );
+
+        vault.set_min_confirmations(6);
+        assert_eq!(
+            vault.balance(&chain).unwrap(),
+            Balance::Pending {
+                onchain: Amount::from_sat(100_000),
+                confirmations: 3,
+            }
+        );
+
+        // spent or never confirmed
+        vault.set_current_outpoint(OutPoint::new(outpoint.txid, 1));
+        assert!(vault.balance(&chain).is_err());
+    }
+
+    #[test]
+    fn lost_trigger_is_recovered_from_the_chain() {
+        let settings = crate::settings::Settings::default();
+        let destination = regtest_address(2);
+        let (fee_outpoint, fee_output) = fee_input();
+        for vault_type in [VaultType::CAT, VaultType::CTV] {
+            let vault = funded_vault(vault_type.clone(), &settings);
+            let trigger_tx = vault
+                .create_path_tx(
+                    crate::vault::contract::SpendPath::Trigger,
+                    &fee_outpoint,
+                    fee_output.clone(),
+                    Some(&destination),
+                )
+                .unwrap();
+
+            // the vault file lost everything after the deposit
+            let mut recovered = funded_vault(vault_type.clone(), &settings);
+            assert!(recovered.get_trigger_transaction().is_err());
+            let unrelated = recovered
+                .accept_recovered_trigger(spending(&ScriptBuf::new_op_return([1])))
+                .err()
+                .unwrap();
+            assert!(
+                unrelated.to_string().contains("trigger leaf"),
+                "{}",
+                unrelated
+            );
+            recovered
+                .accept_recovered_trigger(trigger_tx.clone())
+                .unwrap();
+            assert_eq!(
+                recovered.get_trigger_transaction().unwrap().txid(),
+                trigger_tx.txid()
+            );
+            assert_eq!(
+                recovered.get_current_outpoint().unwrap(),
+                OutPoint::new(trigger_tx.txid(), 0)
+            );
+            assert_eq!(recovered.get_state(), VaultState::Triggered);
+            if vault_type == VaultType::CAT {
+                assert_eq!(recovered.get_withdrawal_address().unwrap(), destination);
+            }
+        }
+    }
+
+    #[test]
+    fn recovered_trigger_has_to_create_the_triggered_output() {
+        let mut vault = funded_vault(VaultType::CAT, &crate::settings::Settings::default());
+        let (fee_outpoint, fee_output) = fee_input();
+        let mut trigger_tx = vault
+            .create_trigger_tx(&[(fee_outpoint, fee_output)], &[regtest_address(2)])
+            .unwrap()
+            .transaction;
+        trigger_tx.output[0].value -= Amount::from_sat(1);
+        let error = vault.accept_recovered_trigger(trigger_tx).err().unwrap();
+        assert!(
+            error.to_string().contains("triggered vault output"),
+            "{}",
+            error
+        );
+        assert!(vault.get_trigger_transaction().is_err());
+    }
+}
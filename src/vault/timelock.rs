@@ -0,0 +1,294 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use bitcoin::transaction::Version;
+use bitcoin::{relative, Sequence, Transaction};
+use log::debug;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Granularity of a BIP68 time-based relative timelock, in seconds
+pub(crate) const SECONDS_PER_INTERVAL: u64 = 512;
+
+/// A BIP68 relative timelock, either in blocks or in units of 512 seconds
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) enum TimelockKind {
+    Blocks(u16),
+    Seconds(u16),
+}
+
+impl TimelockKind {
+    pub(crate) fn to_sequence(self) -> Sequence {
+        match self {
+            TimelockKind::Blocks(blocks) => Sequence::from_height(blocks),
+            TimelockKind::Seconds(intervals) => Sequence::from_512_second_intervals(intervals),
+        }
+    }
+
+    /// Roughly how many blocks the timelock lasts, counting 600 seconds a block for time-based timelocks
+    pub(crate) fn approx_blocks(self) -> u64 {
+        match self {
+            TimelockKind::Blocks(blocks) => blocks as u64,
+            TimelockKind::Seconds(intervals) => intervals as u64 * SECONDS_PER_INTERVAL / 600,
+        }
+    }
+}
+
+impl fmt::Display for TimelockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimelockKind::Blocks(blocks) => write!(f, "{} blocks", blocks),
+            TimelockKind::Seconds(intervals) => {
+                write!(f, "{} seconds", *intervals as u64 * SECONDS_PER_INTERVAL)
+            }
+        }
+    }
+}
+
+/// Vault files written before time-based timelocks were supported hold a bare block count
+pub(crate) fn deserialize_timelock<'de, D>(deserializer: D) -> Result<TimelockKind, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredTimelock {
+        Blocks(u16),
+        Kind(TimelockKind),
+    }
+    Ok(match StoredTimelock::deserialize(deserializer)? {
+        StoredTimelock::Blocks(blocks) => TimelockKind::Blocks(blocks),
+        StoredTimelock::Kind(kind) => kind,
+    })
+}
+
+/// The nSequence a complete transaction must carry to satisfy the vault's CSV check.
+/// Rejects timelocks that would not actually lock anything and double checks the encoding round trips,
+/// so a bad value can't silently produce a sequence with the disable flag or the wrong type flag set.
+pub(crate) fn csv_sequence(timelock: TimelockKind) -> Result<Sequence> {
+    let sequence = timelock.to_sequence();
+    let round_trips = match (timelock, sequence.to_relative_lock_time()) {
+        (TimelockKind::Blocks(0) | TimelockKind::Seconds(0), _) => {
+            return Err(anyhow!(
+                "a timelock of {} does not lock the withdrawal",
+                timelock
+            ));
+        }
+        (TimelockKind::Blocks(blocks), Some(relative::LockTime::Blocks(height))) => {
+            height.value() == blocks
+        }
+        (TimelockKind::Seconds(intervals), Some(relative::LockTime::Time(time))) => {
+            time.value() == intervals
+        }
+        _ => false,
+    };
+    if !round_trips {
+        return Err(anyhow!(
+            "timelock of {} encoded to an invalid sequence {}",
+            timelock,
+            sequence
+        ));
+    }
+    Ok(sequence)
+}
+
+/// Check that input `input_index` of `txn` satisfies the CHECKSEQUENCEVERIFY of a leaf locked for `timelock`.
+/// BIP68 only applies to version 2 transactions, and the input's sequence must lock in the same unit
+/// for at least as long, otherwise the node rejects the spend as non-final.
+pub(crate) fn require_csv_satisfied(
+    txn: &Transaction,
+    input_index: usize,
+    timelock: TimelockKind,
+) -> Result<()> {
+    let required = csv_sequence(timelock)?
+        .to_relative_lock_time()
+        .ok_or(anyhow!(
+            "timelock of {} has no relative lock time",
+            timelock
+        ))?;
+    if txn.version < Version::TWO {
+        return Err(anyhow!(
+            "a version {} transaction can't satisfy a relative timelock",
+            txn.version.0
+        ));
+    }
+    let sequence = txn
+        .input
+        .get(input_index)
+        .ok_or(anyhow!("transaction has no input {}", input_index))?
+        .sequence;
+    match sequence.to_relative_lock_time() {
+        Some(lock_time) if required.is_implied_by(lock_time) => Ok(()),
+        _ => Err(anyhow!(
+            "input {} has sequence {}, which doesn't satisfy the timelock of {}",
+            input_index,
+            sequence,
+            timelock
+        )),
+    }
+}
+
+/// Convert a desired unlock date into the relative timelock that expires closest to (but not before) it,
+/// assuming the vault is triggered now, with the chain at `current_height` and the trigger confirming in the next
+/// block. Prefers a block-based timelock and falls back to a time-based one when the block count doesn't fit.
+/// Errors for a target the next block would already reach, no timelock is that short.
+pub(crate) fn timelock_for_target_date(
+    target: SystemTime,
+    current_height: u32,
+    current_time: SystemTime,
+    avg_block_secs: u64,
+) -> Result<TimelockKind> {
+    if avg_block_secs == 0 {
+        return Err(anyhow!("average block time must be greater than zero"));
+    }
+    let wait = target
+        .duration_since(current_time)
+        .map_err(|_| anyhow!("target date is in the past"))?
+        .as_secs();
+
+    // the complete can be mined `blocks` after the block confirming the trigger, one block from now
+    let blocks = wait.div_ceil(avg_block_secs).saturating_sub(1);
+    if blocks == 0 {
+        return Err(anyhow!(
+            "target date is {} seconds away, the trigger's own block at height {} would already reach it",
+            wait,
+            current_height as u64 + 1
+        ));
+    }
+    if let Ok(blocks) = u16::try_from(blocks) {
+        debug!(
+            "a trigger confirming at height {} unlocks the complete at height {}",
+            current_height as u64 + 1,
+            current_height as u64 + 1 + blocks as u64
+        );
+        return Ok(TimelockKind::Blocks(blocks));
+    }
+    let intervals = wait.div_ceil(SECONDS_PER_INTERVAL);
+    if let Ok(intervals) = u16::try_from(intervals) {
+        return Ok(TimelockKind::Seconds(intervals));
+    }
+    Err(anyhow!(
+        "target date is {} seconds away, which is longer than any relative timelock allows",
+        wait
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultCovenant, VaultType};
+    use bitcoin::Amount;
+    use bitcoincore_rpc::jsonrpc::serde_json;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn blocks_away(blocks: u64, avg_block_secs: u64) -> Result<TimelockKind> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        timelock_for_target_date(
+            now + Duration::from_secs(blocks * avg_block_secs),
+            800_000,
+            now,
+            avg_block_secs,
+        )
+    }
+
+    #[test]
+    fn target_date_n_blocks_away_waits_n_blocks_after_the_trigger() {
+        // the trigger's block is the first of the N
+        assert_eq!(blocks_away(144, 600).unwrap(), TimelockKind::Blocks(143));
+        assert_eq!(blocks_away(2, 600).unwrap(), TimelockKind::Blocks(1));
+        assert_eq!(blocks_away(20, 30).unwrap(), TimelockKind::Blocks(19));
+    }
+
+    #[test]
+    fn target_date_past_the_block_limit_is_time_based() {
+        // with 30 second blocks 512 second intervals reach further than the most blocks a timelock can count
+        let timelock = blocks_away(u16::MAX as u64 + 2, 30).unwrap();
+        assert!(matches!(timelock, TimelockKind::Seconds(_)));
+    }
+
+    #[test]
+    fn target_date_within_a_block_is_refused() {
+        assert!(blocks_away(0, 600).is_err());
+        assert!(blocks_away(1, 600).is_err());
+    }
+
+    #[test]
+    fn target_date_in_the_past_is_refused() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        assert!(
+            timelock_for_target_date(now - Duration::from_secs(600), 800_000, now, 600).is_err()
+        );
+    }
+
+    fn spend_with_sequence(sequence: Sequence) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                sequence,
+                ..Default::default()
+            }],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn largest_block_timelock_converts_without_wrapping() {
+        let sequence = csv_sequence(TimelockKind::Blocks(u16::MAX)).unwrap();
+        assert_eq!(sequence.to_consensus_u32(), u16::MAX as u32);
+        assert!(sequence.is_relative_lock_time());
+        let timelock = TimelockKind::Blocks(u16::MAX);
+        require_csv_satisfied(&spend_with_sequence(sequence), 0, timelock).unwrap();
+    }
+
+    #[test]
+    fn sequence_with_the_disable_bit_is_rejected() {
+        // bit 31 turns the relative lock time off
+        let disabled = Sequence::from_consensus(1 << 31 | u16::MAX as u32);
+        assert!(!disabled.is_relative_lock_time());
+        let timelock = TimelockKind::Blocks(u16::MAX);
+        assert!(require_csv_satisfied(&spend_with_sequence(disabled), 0, timelock).is_err());
+    }
+
+    #[test]
+    fn zero_timelock_is_rejected() {
+        assert!(csv_sequence(TimelockKind::Blocks(0)).is_err());
+        assert!(csv_sequence(TimelockKind::Seconds(0)).is_err());
+        let vault = VaultCovenant::new_seeded(
+            VaultType::CAT,
+            TimelockKind::Blocks(0),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        );
+        assert!(vault.is_err());
+    }
+
+    #[test]
+    fn timelock_past_the_16_bit_height_field_is_rejected() {
+        let stored =
+            |json: &str| deserialize_timelock(&mut serde_json::Deserializer::from_str(json));
+        assert_eq!(stored("65535").unwrap(), TimelockKind::Blocks(u16::MAX));
+        assert!(stored("70000").is_err());
+        assert!(stored(r#"{"Blocks": 65536}"#).is_err());
+        assert!(stored(r#"{"Seconds": 70000}"#).is_err());
+    }
+
+    #[test]
+    fn shorter_or_version_1_spends_fail_the_csv_check() {
+        let timelock = TimelockKind::Blocks(10);
+        require_csv_satisfied(&spend_with_sequence(Sequence::from_height(10)), 0, timelock)
+            .unwrap();
+        assert!(
+            require_csv_satisfied(&spend_with_sequence(Sequence::from_height(9)), 0, timelock)
+                .is_err()
+        );
+        let mut version_1 = spend_with_sequence(Sequence::from_height(10));
+        version_1.version = Version::ONE;
+        assert!(require_csv_satisfied(&version_1, 0, timelock).is_err());
+    }
+}
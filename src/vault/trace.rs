@@ -0,0 +1,155 @@
+use bitcoin::hex::DisplayHex;
+
+use crate::vault::contract::{SpendPath, VaultCovenant};
+
+/// Open while a vault transaction is being built. With the `tracing` feature it is a span carrying the vault
+/// address, type and spend path that everything logged during the build is attached to.
+pub(crate) struct BuildSpan {
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+impl VaultCovenant {
+    /// Enter the span of building this vault's `path` transaction, it closes when the guard is dropped
+    pub(crate) fn build_span(&self, path: SpendPath) -> BuildSpan {
+        let address = self
+            .address()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        #[cfg(feature = "tracing")]
+        return BuildSpan {
+            _span: tracing::debug_span!(
+                "build_tx",
+                kind = ?path,
+                vault_type = ?self.get_type(),
+                vault = %address
+            )
+            .entered(),
+        };
+        #[cfg(not(feature = "tracing"))]
+        {
+            log::debug!(
+                "building a {:?} transaction for {:?} vault {}",
+                path,
+                self.get_type(),
+                address
+            );
+            BuildSpan {}
+        }
+    }
+}
+
+/// Log the covenant witness element pushed at `index`, counting from the bottom of the stack
+pub(crate) fn witness_component(index: usize, component: &[u8]) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        index,
+        component = %component.to_lower_hex_string(),
+        "witness component"
+    );
+    #[cfg(not(feature = "tracing"))]
+    log::debug!(
+        "pushing component {} <0x{}> into the witness",
+        index,
+        component.to_lower_hex_string()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, TxOut, Txid};
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    thread_local! {
+        static RECORDS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Keeps the records logged on each test thread, so tests running side by side don't see each other's
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            RECORDS.with(|records| records.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn captured(build: impl FnOnce()) -> Vec<String> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        RECORDS.with(|records| records.borrow_mut().clear());
+        build();
+        RECORDS.with(|records| records.take())
+    }
+
+    #[test]
+    fn trigger_build_logs_its_span_and_indexed_components() {
+        let mut vault = VaultCovenant::new_seeded(
+            crate::vault::contract::VaultType::CAT,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let destination = vault.address().unwrap();
+        let fee_input = (
+            OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+            TxOut {
+                script_pubkey: destination.script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        );
+        let records = captured(|| {
+            vault
+                .create_trigger_tx(&[fee_input], std::slice::from_ref(&destination))
+                .unwrap();
+        });
+
+        #[cfg(feature = "tracing")]
+        let (span, component_prefix) = (
+            format!(
+                "build_tx; kind=Trigger vault_type=CAT vault={}",
+                destination
+            ),
+            "witness component index=",
+        );
+        #[cfg(not(feature = "tracing"))]
+        let (span, component_prefix) = (
+            format!(
+                "building a Trigger transaction for CAT vault {}",
+                destination
+            ),
+            "pushing component ",
+        );
+        assert_eq!(records[0], span);
+        // every pushed component is logged under its own index, in the order they go on the stack
+        let indexes = records
+            .iter()
+            .filter_map(|record| record.strip_prefix(component_prefix))
+            .map(|rest| {
+                rest.split(|c: char| !c.is_ascii_digit())
+                    .next()
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(indexes.len() > 2);
+        assert_eq!(indexes, (0..indexes.len()).collect::<Vec<_>>());
+    }
+}
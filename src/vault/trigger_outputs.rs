@@ -0,0 +1,231 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::script::PushBytesBuf;
+use bitcoin::{Address, Amount, ScriptBuf, TxOut};
+
+use crate::vault::contract::{VaultCovenant, VaultType};
+use crate::vault::fees::dust_limit;
+
+/// The most data Bitcoin Core relays in an OP_RETURN output
+const MAX_METADATA_LEN: usize = 80;
+
+impl VaultCovenant {
+    /// Commit a CTV vault's trigger to an OP_RETURN output carrying `data` after the triggered vault output.
+    /// It changes the vault address, so it has to be set before the vault is funded.
+    pub(crate) fn set_trigger_metadata(&mut self, data: &[u8]) -> Result<()> {
+        self.require_unfunded_ctv("metadata")?;
+        if data.len() > MAX_METADATA_LEN {
+            return Err(anyhow!(
+                "{} bytes of metadata is more than the {} an OP_RETURN output relays with",
+                data.len(),
+                MAX_METADATA_LEN
+            ));
+        }
+        self.trigger_metadata = Some(data.to_vec());
+        Ok(())
+    }
+
+    /// Commit a CTV vault's trigger to paying `amount` of change to `address`, last.
+    /// The trigger's fee input then has to hold exactly the change plus the fee.
+    pub(crate) fn set_trigger_change(&mut self, address: &Address, amount: Amount) -> Result<()> {
+        self.require_unfunded_ctv("change")?;
+        if self.trigger_fee.is_some() {
+            return Err(anyhow!(
+                "a trigger paying its fee out of the vault has no fee input to pay change from"
+            ));
+        }
+        self.require_network(address)?;
+        let limit = dust_limit(&address.script_pubkey());
+        if amount < limit {
+            return Err(anyhow!(
+                "trigger change of {} is below the dust limit of {}",
+                amount,
+                limit
+            ));
+        }
+        self.trigger_change = Some((address.to_string(), amount));
+        Ok(())
+    }
+
+    /// Commit a CTV vault's trigger to paying `fee` out of the vault amount, so it spends the vault input alone
+    /// and needs no other UTXO. The fee is fixed at deposit, and a cancel has to top the vault back up to its
+    /// full amount from its fee input. It changes the vault address, so it has to be set before the vault is funded.
+    pub(crate) fn set_trigger_fee(&mut self, fee: Amount) -> Result<()> {
+        self.require_unfunded_ctv("a fee")?;
+        if self.trigger_change.is_some() {
+            return Err(anyhow!(
+                "a trigger paying change needs a fee input, it can't pay its fee out of the vault"
+            ));
+        }
+        if self.vault_input_index != 0 {
+            return Err(anyhow!(
+                "a trigger paying its fee out of the vault only has the vault input, at index 0"
+            ));
+        }
+        let triggered = self.get_amount().checked_sub(fee).unwrap_or(Amount::ZERO);
+        let limit = dust_limit(&self.address()?.script_pubkey());
+        if triggered < limit {
+            return Err(anyhow!(
+                "a trigger fee of {} leaves {} of {} in the vault, below the dust limit of {}",
+                fee,
+                triggered,
+                self.get_amount(),
+                limit
+            ));
+        }
+        let payout = self
+            .payout
+            .iter()
+            .map(|(_, amount)| *amount)
+            .sum::<Amount>();
+        if payout > triggered {
+            return Err(anyhow!(
+                "the payout totals {} but only {} is left after a trigger fee of {}",
+                payout,
+                triggered,
+                fee
+            ));
+        }
+        self.trigger_fee = Some(fee);
+        Ok(())
+    }
+
+    /// What the triggered output of a CTV vault holds once the trigger fee is paid out of the vault amount
+    pub(crate) fn triggered_amount(&self) -> Amount {
+        self.get_amount() - self.trigger_fee.unwrap_or(Amount::ZERO)
+    }
+
+    /// The outputs a CTV trigger pays after the triggered vault output: the metadata, then the change
+    pub(crate) fn trigger_extra_outputs(&self) -> Result<Vec<TxOut>> {
+        let mut outputs = Vec::new();
+        if let Some(data) = &self.trigger_metadata {
+            outputs.push(TxOut {
+                script_pubkey: ScriptBuf::new_op_return(&PushBytesBuf::try_from(data.clone())?),
+                value: Amount::ZERO,
+            });
+        }
+        if let Some((address, amount)) = &self.trigger_change {
+            outputs.push(TxOut {
+                script_pubkey: Address::from_str(address)?
+                    .require_network(self.get_network())?
+                    .script_pubkey(),
+                value: *amount,
+            });
+        }
+        Ok(outputs)
+    }
+
+    fn require_unfunded_ctv(&self, what: &str) -> Result<()> {
+        if self.get_type() != VaultType::CTV {
+            return Err(anyhow!(
+                "only a CTV trigger can commit to {}, a CAT trigger's outputs are fixed by its script",
+                what
+            ));
+        }
+        if self.is_funded() {
+            return Err(anyhow!(
+                "can't commit the trigger of a vault that is already funded to {}",
+                what
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::fees::fee_paid;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn seeded_ctv_vault() -> VaultCovenant {
+        VaultCovenant::new_seeded(
+            VaultType::CTV,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_the_committed_output_set_matches_the_template() {
+        let mut vault = seeded_ctv_vault();
+        vault.set_trigger_metadata(b"vault 7").unwrap();
+        vault
+            .set_trigger_change(&test_address(3), Amount::from_sat(10_000))
+            .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let fee_input = OutPoint::new(Txid::from_byte_array([9; 32]), 0);
+        let trigger_tx = vault.create_ctv_trigger_tx(Some(&fee_input)).unwrap();
+        assert_eq!(trigger_tx.output.len(), 3);
+        assert!(trigger_tx.output[1].script_pubkey.is_op_return());
+        vault.verify_ctv_commitment(&trigger_tx).unwrap();
+
+        let mut other_metadata = trigger_tx.clone();
+        other_metadata.output[1].script_pubkey =
+            ScriptBuf::new_op_return(PushBytesBuf::try_from(b"vault 8".to_vec()).unwrap());
+        assert!(vault.verify_ctv_commitment(&other_metadata).is_err());
+
+        let mut more_change = trigger_tx.clone();
+        more_change.output[2].value = Amount::from_sat(10_001);
+        assert!(vault.verify_ctv_commitment(&more_change).is_err());
+
+        let mut no_metadata = trigger_tx;
+        no_metadata.output.remove(1);
+        assert!(vault.verify_ctv_commitment(&no_metadata).is_err());
+    }
+
+    #[test]
+    fn oversized_metadata_and_dust_change_are_refused() {
+        let mut vault = seeded_ctv_vault();
+        assert!(vault
+            .set_trigger_metadata(&[0; MAX_METADATA_LEN + 1])
+            .is_err());
+        assert!(vault
+            .set_trigger_change(&test_address(3), Amount::from_sat(100))
+            .is_err());
+    }
+
+    #[test]
+    fn trigger_paying_its_own_fee_needs_no_fee_input() {
+        let mut vault = seeded_ctv_vault();
+        vault.set_trigger_fee(Amount::from_sat(1_000)).unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        let trigger_tx = vault.create_ctv_trigger_tx(None).unwrap();
+        assert_eq!(trigger_tx.input.len(), 1);
+        assert_eq!(trigger_tx.output[0].value, Amount::from_sat(99_000));
+        assert_eq!(
+            fee_paid(&trigger_tx, &[vault.current_txout().unwrap()]).unwrap(),
+            Amount::from_sat(1_000)
+        );
+        vault.verify_ctv_spend(&trigger_tx).unwrap();
+
+        let fee_input = OutPoint::new(Txid::from_byte_array([9; 32]), 0);
+        assert!(vault.create_ctv_trigger_tx(Some(&fee_input)).is_err());
+    }
+
+    #[test]
+    fn trigger_fee_is_refused_with_change_or_when_it_leaves_dust() {
+        let mut vault = seeded_ctv_vault();
+        vault
+            .set_trigger_change(&test_address(3), Amount::from_sat(10_000))
+            .unwrap();
+        assert!(vault.set_trigger_fee(Amount::from_sat(1_000)).is_err());
+        assert!(seeded_ctv_vault()
+            .set_trigger_fee(Amount::from_sat(99_900))
+            .is_err());
+    }
+}
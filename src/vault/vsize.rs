@@ -0,0 +1,339 @@
+use anyhow::{anyhow, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::serialize;
+use bitcoin::taproot::LeafVersion;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxIn, TxOut, Weight,
+    Witness,
+};
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultType};
+use crate::vault::fees::signed_weight;
+use crate::vault::inputs::arrange_inputs;
+use crate::vault::script::witness_chunk_ranges;
+use crate::vault::signature_building::{get_sigmsg_components, TxCommitmentSpec};
+
+/// The mangled signature is pushed as its first 63 bytes and two candidates for the last one
+const MANGLED_SIGNATURE_LENS: [usize; 3] = [63, 1, 1];
+
+impl VaultCovenant {
+    /// The weight the `path` transaction will have once its one fee input, paying from `fee_script_pubkey`, is signed
+    /// with a single key. Worked out from the witness layout of the path's leaf without grinding or signing anything,
+    /// a CAT trigger needs the `destination` its first marker pays and a CAT complete the recorded trigger.
+    pub(crate) fn predicted_weight(
+        &self,
+        path: SpendPath,
+        fee_script_pubkey: ScriptBuf,
+        destination: Option<&Address>,
+    ) -> Result<Weight> {
+        let fee_prevout = TxOut {
+            script_pubkey: fee_script_pubkey,
+            value: Amount::ZERO,
+        };
+        let (mut skeleton, vault_index, witness_lens) = match self.get_type() {
+            VaultType::CAT => self.cat_skeleton(path, &fee_prevout, destination)?,
+            VaultType::CTV => self.ctv_skeleton(path)?,
+            #[cfg(feature = "op-vault")]
+            VaultType::OpVault => {
+                return Err(anyhow!(
+                    "predicting OP_VAULT transaction weights isn't supported"
+                ))
+            }
+        };
+        let (script, control_block) = self.leaf_for_path(path)?;
+        let witness = witness_lens
+            .into_iter()
+            .chain([script.len(), control_block])
+            .map(|len| vec![0u8; len])
+            .collect::<Vec<_>>();
+        skeleton.input[vault_index].witness = Witness::from_slice(&witness);
+        Ok(signed_weight(&skeleton))
+    }
+
+    /// [Self::predicted_weight] in virtual bytes
+    pub(crate) fn predicted_vsize(
+        &self,
+        path: SpendPath,
+        fee_script_pubkey: ScriptBuf,
+        destination: Option<&Address>,
+    ) -> Result<u64> {
+        Ok(self
+            .predicted_weight(path, fee_script_pubkey, destination)?
+            .to_vbytes_ceil())
+    }
+
+    /// The unsigned CAT transaction for `path` with the vault input first, and the lengths of the witness elements
+    /// its leaf takes before the script and control block
+    fn cat_skeleton(
+        &self,
+        path: SpendPath,
+        fee_prevout: &TxOut,
+        destination: Option<&Address>,
+    ) -> Result<(Transaction, usize, Vec<usize>)> {
+        let vault_txout = TxOut {
+            script_pubkey: self.address()?.script_pubkey(),
+            value: self.get_amount(),
+        };
+        let vault_spk_len = serialize(&vault_txout.script_pubkey).len();
+        let fee_spk_len = serialize(&fee_prevout.script_pubkey).len();
+        let prevouts = [vault_txout.clone(), fee_prevout.clone()];
+        let output = match path {
+            SpendPath::Trigger => {
+                let destination =
+                    destination.ok_or(anyhow!("a trigger needs a withdrawal address"))?;
+                std::iter::once(vault_txout.clone())
+                    .chain(
+                        std::iter::once(destination.clone())
+                            .chain(self.marker_addresses()?)
+                            .map(|address| TxOut {
+                                script_pubkey: address.script_pubkey(),
+                                value: self.marker_amount(),
+                            }),
+                    )
+                    .collect()
+            }
+            SpendPath::Complete => vec![TxOut {
+                script_pubkey: self.get_withdrawal_address()?.script_pubkey(),
+                value: self.get_amount(),
+            }],
+            SpendPath::Cancel => vec![vault_txout.clone()],
+        };
+        let skeleton = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output,
+        };
+        let (script, _) = self.leaf_for_path(path)?;
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        let spec = match path {
+            SpendPath::Trigger => TxCommitmentSpec::for_trigger(),
+            SpendPath::Complete => TxCommitmentSpec::for_complete(),
+            SpendPath::Cancel => TxCommitmentSpec::for_cancel(),
+        };
+        let mut lens = get_sigmsg_components(
+            &spec,
+            &skeleton,
+            0,
+            &prevouts,
+            None,
+            leaf_hash,
+            TapSighashType::Default,
+        )?
+        .iter()
+        .map(Vec::len)
+        .collect::<Vec<_>>();
+        match path {
+            SpendPath::Trigger => {
+                // without change the leaf still takes an empty change element
+                if self.fee_change() {
+                    lens.push(0);
+                }
+                lens.extend(
+                    skeleton.output[1..]
+                        .iter()
+                        .map(|output| serialize(&output.script_pubkey).len()),
+                );
+                lens.extend([8, vault_spk_len, 8, fee_spk_len]);
+            }
+            SpendPath::Complete => {
+                let trigger_tx = self.get_trigger_transaction()?;
+                lens.push(serialize(&trigger_tx.version).len());
+                lens.extend(
+                    witness_chunk_ranges(serialize(&trigger_tx.input).len())
+                        .into_iter()
+                        .map(|(start, end)| end - start),
+                );
+                lens.push(serialize(&trigger_tx.lock_time).len());
+                if self.fee_change() {
+                    lens.push(
+                        self.trigger_paid_change(&trigger_tx)
+                            .map_or(0, |change| serialize(change).len()),
+                    );
+                }
+                lens.extend(
+                    trigger_tx
+                        .output
+                        .iter()
+                        .skip(2)
+                        .take(self.trigger_markers() - 1)
+                        .map(|output| serialize(output).len()),
+                );
+                lens.extend([
+                    vault_spk_len,
+                    8,
+                    serialize(&skeleton.output[0].script_pubkey).len(),
+                    serialize(&skeleton.input[1].previous_output).len(),
+                ]);
+            }
+            SpendPath::Cancel => {
+                if self.fee_change() {
+                    lens.push(0);
+                }
+                lens.extend([8, vault_spk_len, 8, fee_spk_len]);
+            }
+        }
+        lens.extend(MANGLED_SIGNATURE_LENS);
+        // the leaf's own signatures are always SIGHASH_ALL, trigger keys past the threshold push an empty one
+        match path {
+            SpendPath::Trigger => {
+                let keys = self.trigger_keys().len();
+                lens.extend(std::iter::repeat_n(0, keys - self.trigger_threshold));
+                lens.extend(std::iter::repeat_n(65, self.trigger_threshold));
+            }
+            _ => lens.push(65),
+        }
+        Ok((skeleton, 0, lens))
+    }
+
+    /// The unsigned CTV transaction for `path`, where its vault input sits, and the lengths of the witness elements
+    /// its leaf takes before the script and control block
+    fn ctv_skeleton(&self, path: SpendPath) -> Result<(Transaction, usize, Vec<usize>)> {
+        let (input, vault_index) = arrange_inputs(
+            TxIn::default(),
+            Some(TxIn::default()),
+            self.vault_input_index,
+        );
+        let signature_len = match self.sighash_type() {
+            TapSighashType::Default => 64,
+            _ => 65,
+        };
+        let (output, lens) = match path {
+            // the deposit leaf only checks the template hash
+            SpendPath::Trigger => (self.ctv_trigger_tx_template()?.output, vec![]),
+            SpendPath::Complete => {
+                let mut output = self.withdrawal_outputs(Amount::ZERO)?;
+                if self.withdrawal_amount.is_some() {
+                    output.push(self.residual_vault()?.current_txout()?);
+                }
+                (output, vec![signature_len])
+            }
+            SpendPath::Cancel => (
+                vec![TxOut {
+                    script_pubkey: self.address()?.script_pubkey(),
+                    value: self.get_amount(),
+                }],
+                vec![signature_len],
+            ),
+        };
+        let skeleton = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output,
+        };
+        Ok((skeleton, vault_index, lens))
+    }
+
+    /// The script and control block length of the leaf the `path` transaction spends the vault through
+    fn leaf_for_path(&self, path: SpendPath) -> Result<(ScriptBuf, usize)> {
+        let name = match (path, self.get_type()) {
+            (SpendPath::Trigger, VaultType::CAT) => "trigger",
+            (SpendPath::Trigger, VaultType::CTV) => "deposit",
+            #[cfg(feature = "op-vault")]
+            (SpendPath::Trigger, VaultType::OpVault) => "trigger",
+            (SpendPath::Complete, _) => "complete",
+            (SpendPath::Cancel, _) => "cancel",
+        };
+        let script = self
+            .named_leaves()?
+            .into_iter()
+            .find_map(|(leaf, script)| (leaf == name).then_some(script))
+            .ok_or(anyhow!("the vault has no {} leaf", name))?;
+        let spend_info = match path {
+            SpendPath::Trigger => self.deposit_spend_info()?,
+            _ => self.triggered_spend_info()?,
+        };
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(anyhow!("the {} leaf is missing from its script tree", name))?;
+        Ok((script, control_block.size()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::{VaultState, SECP};
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Network, OutPoint, Txid};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn fee_input(vout: u32) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    /// Build each path of a seeded vault withdrawing to `destination` and compare its weight with the prediction
+    fn assert_predictions_match(vault_type: VaultType) {
+        let destination = test_address(2);
+        let fee_script = test_address(9).script_pubkey();
+        let mut vault = VaultCovenant::new_seeded(
+            vault_type.clone(),
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+            .set_withdrawal_address(Some(destination.clone()))
+            .unwrap();
+
+        let predicted = vault
+            .predicted_weight(SpendPath::Trigger, fee_script.clone(), Some(&destination))
+            .unwrap();
+        let (outpoint, output) = fee_input(0);
+        let trigger_tx = vault
+            .create_path_tx(SpendPath::Trigger, &outpoint, output, Some(&destination))
+            .unwrap();
+        assert_eq!(
+            predicted,
+            signed_weight(&trigger_tx),
+            "{:?} trigger",
+            vault_type
+        );
+
+        vault.set_current_outpoint(OutPoint::new(trigger_tx.txid(), 0));
+        vault.set_trigger_transaction(Some(trigger_tx));
+        vault.set_state(VaultState::Triggered);
+        for (vout, path) in [(1, SpendPath::Complete), (2, SpendPath::Cancel)] {
+            let predicted = vault
+                .predicted_weight(path, fee_script.clone(), None)
+                .unwrap();
+            let (outpoint, output) = fee_input(vout);
+            let txn = vault.create_path_tx(path, &outpoint, output, None).unwrap();
+            assert_eq!(
+                predicted,
+                signed_weight(&txn),
+                "{:?} {:?}",
+                vault_type,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn predicted_cat_weights_match_the_built_transactions() {
+        assert_predictions_match(VaultType::CAT);
+    }
+
+    #[test]
+    fn predicted_ctv_weights_match_the_built_transactions() {
+        assert_predictions_match(VaultType::CTV);
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use bitcoincore_rpc::Client;
+use log::{error, info};
+
+use crate::vault::contract::{SpendPath, VaultCovenant, VaultState, VaultType};
+
+/// Something the watchtower saw or did, for a front end to alert the user about
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum WatchEvent {
+    /// a trigger this vault built or one committing to the expected withdrawal address
+    Triggered(Txid),
+    /// a trigger paying somewhere else, the watchtower cancels it
+    UnauthorizedTrigger(Txid),
+    Cancelled(Txid),
+    /// the vault output was spent by something that needs no action, e.g. the complete of an authorized trigger
+    Spent(Txid, VaultState),
+}
+
+/// Where the watchtower reports what it sees, e.g. to page the vault owner
+pub(crate) trait WatchHook {
+    fn on_event(&self, event: &WatchEvent);
+}
+
+/// Writes every event to the log
+pub(crate) struct LogWatchHook;
+
+impl WatchHook for LogWatchHook {
+    fn on_event(&self, event: &WatchEvent) {
+        match event {
+            WatchEvent::UnauthorizedTrigger(txid) => {
+                error!("unauthorized trigger {}, cancelling it", txid)
+            }
+            event => info!("watchtower: {:?}", event),
+        }
+    }
+}
+
+impl VaultCovenant {
+    /// Watch the vault output and cancel any trigger that isn't authorized, paying the cancel fee from `fee_input`
+    /// once `sign_fee_input` has signed it. Spends are only acted on once they have the vault's min_confirmations. A trigger is authorized if this vault built it or, for a CAT vault,
+    /// if it commits to the withdrawal address. Returns the cancel txid, or None once the vault is spent some
+    /// other way. Gives up with an error once `timeout` has elapsed or `stop` has been set.
+    pub(crate) fn watch(
+        &mut self,
+        client: &Client,
+        timeout: Duration,
+        stop: &AtomicBool,
+        hook: &dyn WatchHook,
+        fee_input: (OutPoint, TxOut),
+        sign_fee_input: impl Fn(&Transaction) -> Result<Transaction>,
+    ) -> Result<Option<Txid>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let spending_tx = self.monitor(
+                client,
+                deadline.saturating_duration_since(Instant::now()),
+                stop,
+            )?;
+            let txid = spending_tx.txid();
+            // don't act on a spend that could still be reorged out, go back to watching if it disappears
+            if !self.await_confirmations(
+                client,
+                &txid,
+                deadline.saturating_duration_since(Instant::now()),
+                stop,
+            )? {
+                continue;
+            }
+            if self.get_state() == VaultState::Triggered {
+                // the trigger was authorized, whatever spends it now is its complete or a cancel
+                hook.on_event(&WatchEvent::Spent(txid, self.get_state()));
+                return Ok(None);
+            }
+            // checked first, the marker of an authorized CAT trigger pays the withdrawal address like a complete
+            if self.is_authorized_trigger(&spending_tx) {
+                hook.on_event(&WatchEvent::Triggered(txid));
+                self.set_current_outpoint(OutPoint { txid, vout: 0 });
+                self.set_trigger_transaction(Some(spending_tx));
+                self.set_state(VaultState::Triggered);
+                continue;
+            }
+            match self.outpoint_spender_state(&spending_tx)? {
+                VaultState::Triggered => {
+                    hook.on_event(&WatchEvent::UnauthorizedTrigger(txid));
+                    self.set_current_outpoint(OutPoint { txid, vout: 0 });
+                    self.set_state(VaultState::Triggered);
+                    let (fee_paying_utxo, fee_paying_output) = fee_input;
+                    let cancel_tx = self.create_path_tx(
+                        SpendPath::Cancel,
+                        &fee_paying_utxo,
+                        fee_paying_output,
+                        None,
+                    )?;
+                    let cancel_txid =
+                        self.broadcast(&sign_fee_input(&cancel_tx)?, SpendPath::Cancel, client)?;
+                    hook.on_event(&WatchEvent::Cancelled(cancel_txid));
+                    return Ok(Some(cancel_txid));
+                }
+                state => {
+                    hook.on_event(&WatchEvent::Spent(txid, state));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn is_authorized_trigger(&self, trigger_tx: &Transaction) -> bool {
+        if self.is_recorded_trigger(trigger_tx) {
+            return true;
+        }
+        // a CTV trigger doesn't commit to where the complete pays, only the owner's own triggers are known
+        self.get_type() == VaultType::CAT
+            && trigger_tx.output.len() > 1
+            && self.get_withdrawal_address().is_ok_and(|address| {
+                Self::trigger_committed_destination(trigger_tx)
+                    .is_ok_and(|destination| destination == address.script_pubkey())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::vault::contract::SECP;
+    use crate::vault::timelock::TimelockKind;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::{Address, Amount, Network};
+
+    fn test_address(byte: u8) -> Address {
+        let keypair = Keypair::from_seckey_slice(&SECP, &[byte; 32]).unwrap();
+        Address::p2tr(&SECP, keypair.x_only_public_key().0, None, Network::Regtest)
+    }
+
+    fn fee_input(vout: u32) -> (OutPoint, TxOut) {
+        (
+            OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+            TxOut {
+                script_pubkey: test_address(9).script_pubkey(),
+                value: Amount::from_sat(50_000),
+            },
+        )
+    }
+
+    fn funded_vault(vault_type: VaultType) -> VaultCovenant {
+        let mut vault = VaultCovenant::new_seeded(
+            vault_type,
+            TimelockKind::Blocks(10),
+            Amount::from_sat(100_000),
+            &Settings::default(),
+            7,
+        )
+        .unwrap();
+        vault.set_current_outpoint(OutPoint::new(Txid::from_byte_array([1; 32]), 0));
+        vault
+    }
+
+    fn cat_trigger(vault: &VaultCovenant, destination: &Address) -> Transaction {
+        vault
+            .create_trigger_tx(&[fee_input(0)], std::slice::from_ref(destination))
+            .unwrap()
+            .transaction
+    }
+
+    #[test]
+    fn cat_trigger_is_authorized_only_to_the_expected_address() {
+        let mut vault = funded_vault(VaultType::CAT);
+        let expected = test_address(2);
+        vault
+            .set_withdrawal_address(Some(expected.clone()))
+            .unwrap();
+        let owners = cat_trigger(&vault, &expected);
+        let attackers = cat_trigger(&vault, &test_address(3));
+        assert!(vault.is_authorized_trigger(&owners));
+        assert!(!vault.is_authorized_trigger(&attackers));
+        assert_eq!(
+            vault.outpoint_spender_state(&attackers).unwrap(),
+            VaultState::Triggered
+        );
+
+        // a trigger the vault recorded itself is trusted wherever it pays
+        vault.set_trigger_transaction(Some(attackers.clone()));
+        assert!(vault.is_authorized_trigger(&attackers));
+    }
+
+    #[test]
+    fn ctv_trigger_is_authorized_only_once_recorded() {
+        let mut vault = funded_vault(VaultType::CTV);
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+        let trigger_tx = vault.create_ctv_trigger_tx(Some(&fee_input(0).0)).unwrap();
+        assert!(!vault.is_authorized_trigger(&trigger_tx));
+        vault.set_trigger_transaction(Some(trigger_tx.clone()));
+        assert!(vault.is_authorized_trigger(&trigger_tx));
+    }
+
+    #[test]
+    fn cancel_of_an_unauthorized_trigger_spends_its_vault_output() {
+        let mut vault = funded_vault(VaultType::CAT);
+        vault.set_withdrawal_address(Some(test_address(2))).unwrap();
+        let attackers = cat_trigger(&vault, &test_address(3));
+        vault.set_current_outpoint(OutPoint::new(attackers.txid(), 0));
+        vault.set_state(VaultState::Triggered);
+        let (fee_paying_utxo, fee_paying_output) = fee_input(1);
+        let cancel_tx = vault
+            .create_path_tx(SpendPath::Cancel, &fee_paying_utxo, fee_paying_output, None)
+            .unwrap();
+        assert_eq!(
+            cancel_tx.input[0].previous_output,
+            OutPoint::new(attackers.txid(), 0)
+        );
+        assert_eq!(cancel_tx.input[1].previous_output, fee_paying_utxo);
+    }
+}
@@ -1,5 +1,9 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::{anyhow, Result};
-use bitcoin::{Address, Amount, Network, OutPoint, Transaction, Txid};
+use bitcoin::psbt::Psbt;
+use bitcoin::{Address, Amount, Network, OutPoint, Transaction, TxOut, Txid};
 use bitcoincore_rpc::jsonrpc::serde_json::{json, Value};
 use bitcoincore_rpc::{Auth, Client, RawTx, RpcApi};
 use log::{debug, info};
@@ -52,15 +56,8 @@ impl Wallet {
     }
 
     pub(crate) fn create_rpc_client(settings: &Settings, wallet_name: Option<&str>) -> Client {
-        let port = match settings.network {
-            Network::Bitcoin => 8332,
-            Network::Testnet => 18332,
-            Network::Regtest => 18443,
-            Network::Signet => 38332,
-            _ => {
-                unreachable!("unsupported network")
-            }
-        };
+        let host = &settings.rpc_host;
+        let port = settings.rpc_port();
         // TODO: allow for other authentication
         let auth = Auth::UserPass(
             settings.bitcoin_rpc_username.clone(),
@@ -70,8 +67,8 @@ impl Wallet {
         //let auth = bitcoincore_rpc::Auth::CookieFile("/Users/alex/Library/Application Support/Bitcoin/regtest/.cookie".to_string().parse().unwrap());
 
         let url = match wallet_name {
-            None => format!("http://127.0.0.1:{port}"),
-            Some(name) => format!("http://127.0.0.1:{}/wallet/{name}", port),
+            None => format!("http://{host}:{port}"),
+            Some(name) => format!("http://{host}:{port}/wallet/{name}"),
         };
 
         Client::new(&url, auth.clone()).unwrap()
@@ -111,11 +108,43 @@ impl Wallet {
         Ok(())
     }
 
+    /// Move the regtest node's clock `seconds` ahead and mine enough blocks for the median time past to catch up,
+    /// so a time-based relative timelock can expire without waiting for real
+    pub(crate) fn advance_median_time(&self, seconds: u64) -> Result<()> {
+        let median_time = self.client.get_blockchain_info()?.median_time;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mock_time = median_time.max(now) + seconds;
+        self.client
+            .call::<Value>("setmocktime", &[json!(mock_time)])?;
+        // the median time past is the median of the last 11 block timestamps
+        self.mine_blocks(Some(11))
+    }
+
     pub(crate) fn get_balance(&self) -> Result<Amount> {
         let balance = self.client.get_balance(None, None)?;
         Ok(balance)
     }
 
+    /// Find a confirmed, spendable UTXO worth at least `min_amount`
+    pub(crate) fn get_utxo(&self, min_amount: Amount) -> Result<(OutPoint, TxOut)> {
+        let utxo = self
+            .client
+            .list_unspent(Some(1), None, None, None, None)?
+            .into_iter()
+            .find(|utxo| utxo.spendable && utxo.amount >= min_amount)
+            .ok_or(anyhow!("no spendable utxo worth at least {}", min_amount))?;
+        Ok((
+            OutPoint {
+                txid: utxo.txid,
+                vout: utxo.vout,
+            },
+            TxOut {
+                script_pubkey: utxo.script_pub_key,
+                value: utxo.amount,
+            },
+        ))
+    }
+
     pub(crate) fn send(&self, address: &Address, amount: Amount) -> Result<OutPoint> {
         let output = json!([{
             address.to_string(): amount.to_float_in(bitcoin::Denomination::Bitcoin)
@@ -128,7 +157,7 @@ impl Wallet {
         debug!("sent txid: {}", txid);
         let transaction_info = self.client.get_transaction(&txid, None)?;
         let mut target_vout = 0;
-        for (_, details) in transaction_info.details.iter().enumerate() {
+        for details in transaction_info.details.iter() {
             if &details.address.clone().unwrap().assume_checked() == address {
                 target_vout = details.vout;
                 break;
@@ -140,6 +169,14 @@ impl Wallet {
         })
     }
 
+    /// Sign whatever inputs of the PSBT this wallet can
+    pub(crate) fn sign_psbt(&self, psbt: &Psbt) -> Result<Psbt> {
+        let processed =
+            self.client
+                .wallet_process_psbt(&psbt.to_string(), Some(true), None, None)?;
+        Ok(Psbt::from_str(&processed.psbt)?)
+    }
+
     pub(crate) fn sign_tx(&self, tx: &Transaction) -> Result<Transaction> {
         let signed = self
             .client